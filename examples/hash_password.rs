@@ -28,8 +28,8 @@ fn main() {
             println!();
             println!("You can now use this hash to create an admin user:");
             println!();
-            println!("INSERT INTO users (id, username, password_hash, is_admin)");
-            println!("VALUES (UUID(), 'admin', '{}', true);", hash);
+            println!("INSERT INTO users (id, username, password_hash, role)");
+            println!("VALUES (UUID(), 'admin', '{}', 'admin');", hash);
         }
         Err(e) => {
             eprintln!("Error generating hash: {}", e);
@@ -0,0 +1,4 @@
+-- Add details column to upload_logs
+-- Stores a JSON report for each job (e.g. tracks recovered via the YouTube fallback)
+
+ALTER TABLE upload_logs ADD COLUMN details TEXT;
@@ -0,0 +1,2 @@
+-- Per-user default download profile (name of a [profiles.*] entry in config.toml)
+ALTER TABLE users ADD COLUMN default_profile TEXT;
@@ -0,0 +1,17 @@
+-- Results of the fake-lossless spectral check, one row per analyzed file
+CREATE TABLE IF NOT EXISTS spectral_analyses (
+    id INTEGER PRIMARY KEY AUTOINCREMENT,
+    user_id TEXT NOT NULL,
+    upload_log_id INTEGER NOT NULL,
+    file_name TEXT NOT NULL,
+    codec TEXT NOT NULL,
+    cutoff_hz REAL,
+    confidence REAL NOT NULL,
+    verdict TEXT NOT NULL CHECK(verdict IN ('genuine', 'suspicious', 'inconclusive')),
+    quarantine_path TEXT,
+    created_at TEXT NOT NULL DEFAULT CURRENT_TIMESTAMP,
+    FOREIGN KEY (user_id) REFERENCES users(id) ON DELETE CASCADE,
+    FOREIGN KEY (upload_log_id) REFERENCES upload_logs(id) ON DELETE CASCADE
+);
+
+CREATE INDEX IF NOT EXISTS idx_spectral_analyses_user_verdict ON spectral_analyses(user_id, verdict);
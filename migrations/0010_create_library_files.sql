@@ -0,0 +1,21 @@
+-- Files imported into each user's library, with a byte hash and acoustic
+-- fingerprint used to detect duplicates at import time
+CREATE TABLE IF NOT EXISTS library_files (
+    id INTEGER PRIMARY KEY AUTOINCREMENT,
+    user_id TEXT NOT NULL,
+    path TEXT NOT NULL,
+    sha256 TEXT NOT NULL,
+    fingerprint BLOB,
+    duration_secs REAL,
+    codec TEXT NOT NULL,
+    bitrate_kbps INTEGER,
+    size_bytes INTEGER NOT NULL,
+    upload_log_id INTEGER,
+    created_at TEXT NOT NULL DEFAULT CURRENT_TIMESTAMP,
+    UNIQUE (user_id, path),
+    FOREIGN KEY (user_id) REFERENCES users(id) ON DELETE CASCADE,
+    FOREIGN KEY (upload_log_id) REFERENCES upload_logs(id) ON DELETE SET NULL
+);
+
+CREATE INDEX IF NOT EXISTS idx_library_files_user_sha256 ON library_files(user_id, sha256);
+CREATE INDEX IF NOT EXISTS idx_library_files_user_duration ON library_files(user_id, duration_secs);
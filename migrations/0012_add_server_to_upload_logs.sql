@@ -0,0 +1,48 @@
+-- Add 'server' (imports from server directories) to upload_type CHECK constraint
+-- SQLite doesn't support ALTER COLUMN, so we need to recreate the table.
+-- Foreign keys can't be turned off inside the migration's transaction, so
+-- dropping the old table cascades to spectral_analyses and clears
+-- library_files.upload_log_id; both are saved first and restored afterwards.
+
+CREATE TEMP TABLE spectral_analyses_backup AS SELECT * FROM spectral_analyses;
+CREATE TEMP TABLE library_files_log_ids AS
+SELECT id, upload_log_id FROM library_files WHERE upload_log_id IS NOT NULL;
+
+-- Create new table with updated constraint
+CREATE TABLE IF NOT EXISTS upload_logs_new (
+    id INTEGER PRIMARY KEY AUTOINCREMENT,
+    user_id TEXT NOT NULL,
+    upload_type TEXT NOT NULL CHECK(upload_type IN ('file', 'youtube', 'spotify', 'url', 'server')),
+    source TEXT NOT NULL,
+    status TEXT NOT NULL DEFAULT 'pending' CHECK(status IN ('pending', 'processing', 'completed', 'failed')),
+    file_count INTEGER NOT NULL DEFAULT 0,
+    error_message TEXT,
+    created_at TEXT NOT NULL DEFAULT CURRENT_TIMESTAMP,
+    completed_at TEXT,
+    details TEXT,
+    FOREIGN KEY (user_id) REFERENCES users(id) ON DELETE CASCADE
+);
+
+-- Copy existing data
+INSERT INTO upload_logs_new (id, user_id, upload_type, source, status, file_count, error_message, created_at, completed_at, details)
+SELECT id, user_id, upload_type, source, status, file_count, error_message, created_at, completed_at, details
+FROM upload_logs;
+
+-- Drop old table
+DROP TABLE upload_logs;
+
+-- Rename new table
+ALTER TABLE upload_logs_new RENAME TO upload_logs;
+
+-- Restore rows that referenced the old table
+INSERT INTO spectral_analyses SELECT * FROM spectral_analyses_backup;
+UPDATE library_files
+SET upload_log_id = (SELECT b.upload_log_id FROM library_files_log_ids b WHERE b.id = library_files.id)
+WHERE id IN (SELECT id FROM library_files_log_ids);
+
+DROP TABLE spectral_analyses_backup;
+DROP TABLE library_files_log_ids;
+
+-- Recreate indexes
+CREATE INDEX IF NOT EXISTS idx_upload_logs_user_id ON upload_logs(user_id);
+CREATE INDEX IF NOT EXISTS idx_upload_logs_created_at ON upload_logs(created_at);
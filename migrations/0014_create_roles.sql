@@ -0,0 +1,38 @@
+-- Roles and their permissions replace the is_admin flag
+CREATE TABLE IF NOT EXISTS roles (
+    name TEXT PRIMARY KEY,
+    description TEXT NOT NULL DEFAULT '',
+    created_at TEXT NOT NULL DEFAULT CURRENT_TIMESTAMP
+);
+
+CREATE TABLE IF NOT EXISTS role_permissions (
+    role TEXT NOT NULL,
+    permission TEXT NOT NULL,
+    PRIMARY KEY (role, permission),
+    FOREIGN KEY (role) REFERENCES roles(name) ON DELETE CASCADE
+);
+
+INSERT INTO roles (name, description) VALUES
+    ('admin', 'Full access'),
+    ('user', 'Upload and download music');
+
+INSERT INTO role_permissions (role, permission) VALUES
+    ('admin', 'upload'),
+    ('admin', 'youtube'),
+    ('admin', 'spotify'),
+    ('admin', 'url_import'),
+    ('admin', 'server_import'),
+    ('admin', 'manage_users'),
+    ('admin', 'manage_config'),
+    ('admin', 'view_all_logs'),
+    ('user', 'upload'),
+    ('user', 'youtube'),
+    ('user', 'spotify'),
+    ('user', 'url_import');
+
+-- Existing admins keep full access
+ALTER TABLE users ADD COLUMN role TEXT NOT NULL DEFAULT 'user';
+UPDATE users SET role = 'admin' WHERE is_admin = 1;
+ALTER TABLE users DROP COLUMN is_admin;
+
+CREATE INDEX IF NOT EXISTS idx_users_role ON users(role);
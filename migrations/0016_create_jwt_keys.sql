@@ -0,0 +1,9 @@
+-- Signing keys for login tokens, identified by the token header's kid.
+-- The key without expires_at signs new tokens; retired keys keep verifying
+-- until expires_at (unix timestamp), when their last token has expired.
+CREATE TABLE IF NOT EXISTS jwt_keys (
+    kid TEXT PRIMARY KEY,
+    secret TEXT NOT NULL,
+    created_at TEXT NOT NULL DEFAULT CURRENT_TIMESTAMP,
+    expires_at INTEGER
+);
@@ -0,0 +1,18 @@
+-- Long-lived personal tokens for scripts; only a SHA-256 hash of the token is kept
+CREATE TABLE IF NOT EXISTS api_tokens (
+    id TEXT PRIMARY KEY,
+    user_id TEXT NOT NULL,
+    name TEXT NOT NULL,
+    token_hash TEXT NOT NULL UNIQUE,
+    -- Start of the token, shown to tell tokens apart
+    prefix TEXT NOT NULL,
+    -- Space-separated permissions the token is limited to; NULL for all of the owner's
+    scopes TEXT,
+    -- Unix timestamp; NULL never expires
+    expires_at INTEGER,
+    created_at TEXT NOT NULL DEFAULT CURRENT_TIMESTAMP,
+    last_used_at TEXT,
+    FOREIGN KEY (user_id) REFERENCES users(id) ON DELETE CASCADE
+);
+
+CREATE INDEX IF NOT EXISTS idx_api_tokens_user_id ON api_tokens(user_id);
@@ -0,0 +1,22 @@
+-- Recent failed logins per key ('ip:<address>' or 'user:<username>'), for backoff and lockout
+CREATE TABLE IF NOT EXISTS login_failures (
+    key TEXT PRIMARY KEY,
+    failures INTEGER NOT NULL,
+    -- Unix timestamp of the latest failure
+    last_failure_at INTEGER NOT NULL
+);
+
+-- Audit trail of login attempts
+CREATE TABLE IF NOT EXISTS auth_events (
+    id INTEGER PRIMARY KEY AUTOINCREMENT,
+    username TEXT NOT NULL,
+    user_id TEXT,
+    ip_address TEXT,
+    user_agent TEXT,
+    success INTEGER NOT NULL,
+    -- Why it failed (invalid_credentials, throttled), or how it succeeded
+    reason TEXT NOT NULL,
+    created_at TEXT NOT NULL DEFAULT CURRENT_TIMESTAMP
+);
+
+CREATE INDEX IF NOT EXISTS idx_auth_events_created_at ON auth_events(created_at);
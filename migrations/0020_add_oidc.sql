@@ -0,0 +1,13 @@
+-- Single sign-on: the identity provider account (issuer and subject) a user is linked to
+ALTER TABLE users ADD COLUMN oidc_subject TEXT;
+
+CREATE UNIQUE INDEX IF NOT EXISTS idx_users_oidc_subject ON users(oidc_subject);
+
+-- Logins sent to the identity provider and not back yet
+CREATE TABLE IF NOT EXISTS oidc_logins (
+    state TEXT PRIMARY KEY,
+    nonce TEXT NOT NULL,
+    pkce_verifier TEXT NOT NULL,
+    -- Unix timestamp
+    expires_at INTEGER NOT NULL
+);
@@ -0,0 +1,29 @@
+-- Passkeys (WebAuthn credentials), for passwordless login or as a second factor
+CREATE TABLE IF NOT EXISTS passkeys (
+    id TEXT PRIMARY KEY,
+    user_id TEXT NOT NULL,
+    name TEXT NOT NULL,
+    -- base64url, as browsers report it
+    credential_id TEXT NOT NULL UNIQUE,
+    -- COSE_Key from the authenticator
+    public_key BLOB NOT NULL,
+    -- Signature counter, to notice cloned authenticators
+    sign_count INTEGER NOT NULL DEFAULT 0,
+    created_at TEXT NOT NULL DEFAULT CURRENT_TIMESTAMP,
+    last_used_at TEXT,
+    FOREIGN KEY (user_id) REFERENCES users(id) ON DELETE CASCADE
+);
+
+CREATE INDEX IF NOT EXISTS idx_passkeys_user_id ON passkeys(user_id);
+
+-- Registrations and logins waiting for the browser; each challenge is only good once
+CREATE TABLE IF NOT EXISTS passkey_challenges (
+    challenge TEXT PRIMARY KEY,
+    -- 'register' or 'login'
+    purpose TEXT NOT NULL,
+    -- Registering user, or the user whose passkeys may answer; NULL for passwordless logins
+    user_id TEXT,
+    -- Unix timestamp
+    expires_at INTEGER NOT NULL,
+    FOREIGN KEY (user_id) REFERENCES users(id) ON DELETE CASCADE
+);
@@ -31,8 +31,8 @@ echo "To complete the setup, you need to:"
 echo "1. Generate a password hash for: $PASSWORD"
 echo "2. Run this SQL command:"
 echo ""
-echo "INSERT INTO users (id, username, password_hash, is_admin)"
-echo "VALUES ('$USER_ID', '$USERNAME', '<HASH_HERE>', true);"
+echo "INSERT INTO users (id, username, password_hash, role)"
+echo "VALUES ('$USER_ID', '$USERNAME', '<HASH_HERE>', 'admin');"
 echo ""
 echo "You can generate the hash by:"
 echo "1. Using the application's API after one admin exists"
@@ -56,8 +56,8 @@ DB_PASSWORD=$(grep "MYSQL_PASSWORD:" docker-compose.yml | head -1 | awk '{print
 
 # Insert user into database
 docker exec -i music_upload_db mysql -u music_upload -p"$DB_PASSWORD" music_upload <<EOF
-INSERT INTO users (id, username, password_hash, is_admin)
-VALUES (UUID(), '$USERNAME', '$HASH_OUTPUT', true);
+INSERT INTO users (id, username, password_hash, role)
+VALUES (UUID(), '$USERNAME', '$HASH_OUTPUT', 'admin');
 EOF
 
 if [ $? -eq 0 ]; then
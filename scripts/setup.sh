@@ -164,16 +164,16 @@ sleep 1
 # Insert admin user into database
 if command -v sqlite3 &> /dev/null; then
     sqlite3 data/music_upload.db << SQL
-INSERT INTO users (id, username, password_hash, is_admin, created_at, updated_at)
-VALUES ('${ADMIN_ID}', '${ADMIN_USER}', '${PASS_HASH}', 1, CURRENT_TIMESTAMP, CURRENT_TIMESTAMP);
+INSERT INTO users (id, username, password_hash, role, created_at, updated_at)
+VALUES ('${ADMIN_ID}', '${ADMIN_USER}', '${PASS_HASH}', 'admin', CURRENT_TIMESTAMP, CURRENT_TIMESTAMP);
 SQL
     echo -e "${GREEN}✓ Admin user created successfully${NC}"
 else
     echo -e "${YELLOW}Warning: sqlite3 command not found. You'll need to create the admin user manually.${NC}"
     echo -e "${YELLOW}Install sqlite3 and run:${NC}"
     echo "sqlite3 data/music_upload.db << SQL"
-    echo "INSERT INTO users (id, username, password_hash, is_admin, created_at, updated_at)"
-    echo "VALUES ('${ADMIN_ID}', '${ADMIN_USER}', '${PASS_HASH}', 1, CURRENT_TIMESTAMP, CURRENT_TIMESTAMP);"
+    echo "INSERT INTO users (id, username, password_hash, role, created_at, updated_at)"
+    echo "VALUES ('${ADMIN_ID}', '${ADMIN_USER}', '${PASS_HASH}', 'admin', CURRENT_TIMESTAMP, CURRENT_TIMESTAMP);"
     echo "SQL"
 fi
 
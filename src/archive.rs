@@ -0,0 +1,378 @@
+use crate::config::Config;
+use crate::paths::{safe_relative_path, sanitize_path_component};
+use crate::pipeline::{is_allowed_audio, is_image_file, is_lyrics_file};
+use anyhow::{Context, Result};
+use std::fs::{self, File};
+use std::io::{self, Read, Write};
+use std::path::{Path, PathBuf};
+
+/// Archive formats accepted as uploads
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ArchiveKind {
+    Zip,
+    Tar,
+    TarGz,
+    SevenZip,
+}
+
+impl ArchiveKind {
+    /// Detect the format from a file name; also returns the name without the extension
+    pub fn from_file_name(name: &str) -> Option<(Self, &str)> {
+        [
+            (".tar.gz", Self::TarGz),
+            (".tgz", Self::TarGz),
+            (".tar", Self::Tar),
+            (".zip", Self::Zip),
+            (".7z", Self::SevenZip),
+        ]
+        .into_iter()
+        .find_map(|(ext, kind)| {
+            let split = name.len().checked_sub(ext.len())?;
+            let suffix = name.get(split..)?;
+            suffix.eq_ignore_ascii_case(ext).then(|| (kind, &name[..split]))
+        })
+    }
+
+    /// Detect the format from the first bytes (at least 262 for tar)
+    pub fn sniff(header: &[u8]) -> Option<Self> {
+        let at = |offset: usize, magic: &[u8]| header.get(offset..offset + magic.len()) == Some(magic);
+
+        if at(0, b"PK\x03\x04") || at(0, b"PK\x05\x06") {
+            Some(Self::Zip)
+        } else if at(0, &[b'7', b'z', 0xBC, 0xAF, 0x27, 0x1C]) {
+            Some(Self::SevenZip)
+        } else if at(0, &[0x1F, 0x8B]) {
+            Some(Self::TarGz)
+        } else if at(257, b"ustar") {
+            Some(Self::Tar)
+        } else {
+            None
+        }
+    }
+
+    pub fn extension(&self) -> &'static str {
+        match self {
+            Self::Zip => ".zip",
+            Self::Tar => ".tar",
+            Self::TarGz => ".tar.gz",
+            Self::SevenZip => ".7z",
+        }
+    }
+}
+
+/// Limits applied while unpacking, counted on the actual decompressed data
+/// (headers can lie about sizes)
+#[derive(Debug, Clone)]
+pub struct ArchiveLimits {
+    pub max_entries: usize,
+    pub max_total_bytes: u64,
+    pub max_entry_bytes: u64,
+}
+
+/// Result of unpacking an archive
+#[derive(Debug, Default)]
+pub struct Extraction {
+    /// Extracted files, relative to the job directory
+    pub files: Vec<PathBuf>,
+    /// Entries that were left out (unsupported type, hidden, links)
+    pub skipped: Vec<String>,
+}
+
+struct Extractor<'a> {
+    dest: &'a Path,
+    limits: &'a ArchiveLimits,
+    accept: &'a dyn Fn(&Path) -> bool,
+    entries: usize,
+    total_bytes: u64,
+    extraction: Extraction,
+}
+
+impl Extractor<'_> {
+    /// Handle one entry; `is_file` is false for directories, links and other special entries
+    fn entry(&mut self, name: &str, is_file: bool, reader: &mut dyn Read) -> Result<()> {
+        self.entries += 1;
+        if self.entries > self.limits.max_entries {
+            anyhow::bail!("Archive has more than {} entries", self.limits.max_entries);
+        }
+        if !is_file {
+            if !name.ends_with('/') {
+                self.extraction.skipped.push(name.to_string());
+            }
+            return Ok(());
+        }
+
+        let relative = safe_relative_path(name).context(format!("Unsafe path in archive: {}", name))?;
+        let hidden = relative.iter().any(|c| {
+            let c = c.to_string_lossy();
+            c.starts_with('.') || c == "__MACOSX"
+        });
+
+        // Skipped entries are still decompressed (streamed formats), so they count too
+        if hidden || !(self.accept)(&relative) {
+            self.copy(name, reader, &mut io::sink())?;
+            self.extraction.skipped.push(name.to_string());
+            return Ok(());
+        }
+
+        let path = self.dest.join(&relative);
+        if let Some(parent) = path.parent() {
+            fs::create_dir_all(parent).context(format!("Failed to create {}", parent.display()))?;
+        }
+        let mut file = File::options()
+            .write(true)
+            .create_new(true)
+            .open(&path)
+            .context(format!("Duplicate entry in archive: {}", name))?;
+        self.copy(name, reader, &mut file)?;
+        self.extraction.files.push(relative);
+        Ok(())
+    }
+
+    fn copy(&mut self, name: &str, reader: &mut dyn Read, writer: &mut dyn Write) -> Result<()> {
+        let remaining = self.limits.max_total_bytes.saturating_sub(self.total_bytes);
+        let limit = self.limits.max_entry_bytes.min(remaining);
+        let copied = io::copy(&mut reader.take(limit + 1), writer).context(format!("Failed to extract {}", name))?;
+        if copied > limit {
+            if limit == remaining {
+                anyhow::bail!("Archive unpacks to more than {} MB", self.limits.max_total_bytes / 1024 / 1024);
+            }
+            anyhow::bail!("{} is larger than {} MB", name, self.limits.max_entry_bytes / 1024 / 1024);
+        }
+        self.total_bytes += copied;
+        Ok(())
+    }
+}
+
+/// Extract the entries `accept` allows into `dest`, keeping their folder structure
+/// Paths are validated component by component and files are never overwritten;
+/// links and special entries are skipped.
+pub fn extract_archive(
+    kind: ArchiveKind,
+    archive: &Path,
+    dest: &Path,
+    limits: &ArchiveLimits,
+    accept: &dyn Fn(&Path) -> bool,
+) -> Result<Extraction> {
+    let mut extractor = Extractor {
+        dest,
+        limits,
+        accept,
+        entries: 0,
+        total_bytes: 0,
+        extraction: Extraction::default(),
+    };
+    let file = File::open(archive).context(format!("Failed to open {}", archive.display()))?;
+
+    match kind {
+        ArchiveKind::Zip => {
+            let mut zip = zip::ZipArchive::new(file).context("Invalid zip archive")?;
+            if zip.len() > limits.max_entries {
+                anyhow::bail!("Archive has more than {} entries", limits.max_entries);
+            }
+            for i in 0..zip.len() {
+                let mut entry = zip.by_index(i).context("Invalid zip archive")?;
+                let name = entry.name().to_string();
+                let is_file = entry.is_file() && !entry.is_symlink();
+                extractor.entry(&name, is_file, &mut entry)?;
+            }
+        }
+        ArchiveKind::Tar | ArchiveKind::TarGz => {
+            let reader: Box<dyn Read> = if kind == ArchiveKind::TarGz {
+                Box::new(flate2::read::MultiGzDecoder::new(io::BufReader::new(file)))
+            } else {
+                Box::new(io::BufReader::new(file))
+            };
+            let mut tar = tar::Archive::new(reader);
+            for entry in tar.entries().context("Invalid tar archive")? {
+                let mut entry = entry.context("Invalid tar archive")?;
+                let name = String::from_utf8_lossy(&entry.path_bytes()).to_string();
+                let is_file = entry.header().entry_type().is_file();
+                extractor.entry(&name, is_file, &mut entry)?;
+            }
+        }
+        ArchiveKind::SevenZip => {
+            drop(file);
+            let mut reader = sevenz_rust::SevenZReader::open(archive, sevenz_rust::Password::empty())
+                .context("Invalid 7z archive")?;
+            // The callback can only return the crate's error type; keep ours aside
+            let mut failure = None;
+            reader
+                .for_each_entries(|entry, data| {
+                    let is_file = !entry.is_directory() && !entry.is_anti_item();
+                    match extractor.entry(entry.name(), is_file, data) {
+                        Ok(()) => Ok(true),
+                        Err(e) => {
+                            failure = Some(e);
+                            Ok(false)
+                        }
+                    }
+                })
+                .context("Invalid 7z archive")?;
+            if let Some(e) = failure {
+                return Err(e);
+            }
+        }
+    }
+
+    Ok(extractor.extraction)
+}
+
+/// Unpack an uploaded archive into the job directory
+///
+/// The archive's folder structure is kept. An archive with only loose files
+/// (the usual store download) is unpacked into a folder named after the
+/// archive, so the album keeps its folder. Returned paths are relative to
+/// `job_dir`; nothing already in the job is overwritten.
+pub fn unpack_upload(
+    kind: ArchiveKind,
+    archive: &Path,
+    archive_stem: &str,
+    job_dir: &Path,
+    limits: &ArchiveLimits,
+    accept: &dyn Fn(&Path) -> bool,
+) -> Result<Extraction> {
+    let unpack_dir = job_dir.join(format!(".unpack-{}", uuid::Uuid::new_v4()));
+    let result = extract_archive(kind, archive, &unpack_dir, limits, accept)
+        .and_then(|extraction| place_extracted(extraction, &unpack_dir, archive_stem, job_dir));
+    fs::remove_dir_all(&unpack_dir).ok();
+    result
+}
+
+/// Unpack an archive staged in the job directory and delete it, keeping
+/// audio with an allowed extension, images and lyrics
+pub async fn unpack_staged_archive(
+    config: &Config,
+    kind: ArchiveKind,
+    archive: PathBuf,
+    archive_stem: &str,
+    job_dir: &Path,
+) -> Result<Extraction> {
+    let limits = ArchiveLimits {
+        max_entries: config.upload.max_archive_entries,
+        max_total_bytes: config.max_archive_size_bytes(),
+        max_entry_bytes: config.max_file_size_bytes() as u64,
+    };
+    let allowed = config.upload.allowed_extensions.clone();
+    let (stem, dest) = (archive_stem.to_string(), job_dir.to_path_buf());
+
+    tokio::task::spawn_blocking(move || {
+        let accept = |p: &Path| is_allowed_audio(&allowed, p) || is_image_file(p) || is_lyrics_file(p);
+        let result = unpack_upload(kind, &archive, &stem, &dest, &limits, &accept);
+        fs::remove_file(&archive).ok();
+        result
+    })
+    .await
+    .context("Archive extraction failed")?
+}
+
+fn place_extracted(mut extraction: Extraction, unpack_dir: &Path, archive_stem: &str, job_dir: &Path) -> Result<Extraction> {
+    if extraction.files.is_empty() {
+        return Ok(extraction);
+    }
+
+    let move_into_job = |name: &Path, to: &Path| -> Result<()> {
+        let dest = job_dir.join(to);
+        if dest.exists() {
+            anyhow::bail!("{} already exists in this upload", to.display());
+        }
+        fs::rename(unpack_dir.join(name), &dest).context(format!("Failed to move {}", to.display()))
+    };
+
+    let loose = extraction.files.iter().all(|f| f.components().count() == 1);
+    if loose {
+        let folder = PathBuf::from(sanitize_path_component(archive_stem));
+        fs::create_dir(job_dir.join(&folder)).context(format!("{} already exists in this upload", folder.display()))?;
+        for file in &mut extraction.files {
+            let to = folder.join(&*file);
+            move_into_job(file, &to)?;
+            *file = to;
+        }
+    } else {
+        for entry in fs::read_dir(unpack_dir)? {
+            let name = PathBuf::from(entry?.file_name());
+            move_into_job(&name, &name)?;
+        }
+    }
+
+    Ok(extraction)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use zip::write::SimpleFileOptions;
+
+    fn limits() -> ArchiveLimits {
+        ArchiveLimits {
+            max_entries: 10,
+            max_total_bytes: 1024,
+            max_entry_bytes: 512,
+        }
+    }
+
+    fn zip_archive(dir: &Path, entries: &[(&str, &[u8])]) -> PathBuf {
+        let path = dir.join("test.zip");
+        let mut zip = zip::ZipWriter::new(File::create(&path).unwrap());
+        let options = SimpleFileOptions::default().compression_method(zip::CompressionMethod::Stored);
+        for (name, data) in entries {
+            zip.start_file(*name, options).unwrap();
+            zip.write_all(data).unwrap();
+        }
+        zip.finish().unwrap();
+        path
+    }
+
+    fn test_dir(name: &str) -> PathBuf {
+        let dir = std::env::temp_dir().join(format!("archive-test-{}-{}", name, uuid::Uuid::new_v4()));
+        fs::create_dir_all(&dir).unwrap();
+        dir
+    }
+
+    fn accept_flac(path: &Path) -> bool {
+        path.extension().is_some_and(|e| e == "flac")
+    }
+
+    #[test]
+    fn detects_archive_kinds() {
+        assert_eq!(ArchiveKind::from_file_name("Album.TAR.GZ"), Some((ArchiveKind::TarGz, "Album")));
+        assert_eq!(ArchiveKind::from_file_name("a.b.zip"), Some((ArchiveKind::Zip, "a.b")));
+        assert_eq!(ArchiveKind::from_file_name("x.7z"), Some((ArchiveKind::SevenZip, "x")));
+        assert_eq!(ArchiveKind::from_file_name("track.flac"), None);
+    }
+
+    #[test]
+    fn unpacks_loose_files_into_album_folder() {
+        let dir = test_dir("loose");
+        let archive = zip_archive(&dir, &[("01.flac", b"one"), ("notes.pdf", b"skip"), (".DS_Store", b"x")]);
+        let job_dir = dir.join("job");
+        fs::create_dir(&job_dir).unwrap();
+
+        let extraction = unpack_upload(ArchiveKind::Zip, &archive, "Artist - Album", &job_dir, &limits(), &accept_flac).unwrap();
+        assert_eq!(extraction.files, vec![PathBuf::from("Artist - Album/01.flac")]);
+        assert_eq!(extraction.skipped, vec!["notes.pdf", ".DS_Store"]);
+        assert_eq!(fs::read(job_dir.join("Artist - Album/01.flac")).unwrap(), b"one");
+        fs::remove_dir_all(&dir).ok();
+    }
+
+    #[test]
+    fn rejects_traversal_and_oversized_archives() {
+        let dir = test_dir("unsafe");
+        let dest = dir.join("out");
+
+        let archive = zip_archive(&dir, &[("../evil.flac", b"x")]);
+        assert!(extract_archive(ArchiveKind::Zip, &archive, &dest, &limits(), &accept_flac).is_err());
+        assert!(!dir.join("evil.flac").exists());
+
+        let big = vec![0u8; 600];
+        let archive = zip_archive(&dir, &[("big.flac", &big)]);
+        let error = extract_archive(ArchiveKind::Zip, &archive, &dest, &limits(), &accept_flac).unwrap_err();
+        assert!(error.to_string().contains("larger than"), "{}", error);
+
+        let half = vec![0u8; 500];
+        let archive = zip_archive(&dir, &[("a/1.flac", &half), ("a/2.flac", &half), ("a/3.flac", &half)]);
+        fs::remove_dir_all(&dest).ok();
+        let error = extract_archive(ArchiveKind::Zip, &archive, &dest, &limits(), &accept_flac).unwrap_err();
+        assert!(error.to_string().contains("unpacks to more than"), "{}", error);
+        fs::remove_dir_all(&dir).ok();
+    }
+}
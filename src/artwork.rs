@@ -0,0 +1,202 @@
+/// Detect the MIME type of an image from its magic bytes
+/// Only formats that can be embedded as cover art are recognised
+pub fn image_mime(data: &[u8]) -> Option<&'static str> {
+    if data.starts_with(&[0xFF, 0xD8, 0xFF]) {
+        Some("image/jpeg")
+    } else if data.starts_with(b"\x89PNG\r\n\x1a\n") {
+        Some("image/png")
+    } else {
+        None
+    }
+}
+
+/// Read width and height from a JPEG or PNG header
+pub fn image_dimensions(data: &[u8]) -> Option<(u32, u32)> {
+    match image_mime(data)? {
+        "image/png" => {
+            // IHDR is always the first chunk: width/height at offsets 16 and 20
+            let width = u32::from_be_bytes(data.get(16..20)?.try_into().ok()?);
+            let height = u32::from_be_bytes(data.get(20..24)?.try_into().ok()?);
+            Some((width, height))
+        }
+        _ => jpeg_dimensions(data),
+    }
+}
+
+fn jpeg_dimensions(data: &[u8]) -> Option<(u32, u32)> {
+    let mut pos = 2;
+    while pos + 4 <= data.len() {
+        if data[pos] != 0xFF {
+            return None;
+        }
+        let marker = data[pos + 1];
+        // Fill bytes and standalone markers carry no length
+        if marker == 0xFF {
+            pos += 1;
+            continue;
+        }
+        if (0xD0..=0xD9).contains(&marker) || marker == 0x01 {
+            pos += 2;
+            continue;
+        }
+
+        let length = u16::from_be_bytes([data[pos + 2], data[pos + 3]]) as usize;
+        // SOF0..SOF15, excluding DHT (C4), JPG (C8) and DAC (CC)
+        if (0xC0..=0xCF).contains(&marker) && ![0xC4, 0xC8, 0xCC].contains(&marker) {
+            let height = u16::from_be_bytes([*data.get(pos + 5)?, *data.get(pos + 6)?]);
+            let width = u16::from_be_bytes([*data.get(pos + 7)?, *data.get(pos + 8)?]);
+            return Some((width as u32, height as u32));
+        }
+        pos += 2 + length;
+    }
+    None
+}
+
+/// Build a FLAC METADATA_BLOCK_PICTURE (front cover), as used for
+/// cover art in Vorbis comments of Ogg/Opus files
+pub fn flac_picture_block(data: &[u8]) -> Option<Vec<u8>> {
+    let mime = image_mime(data)?;
+    let (width, height) = image_dimensions(data).unwrap_or((0, 0));
+
+    let mut block = Vec::with_capacity(data.len() + 64);
+    block.extend_from_slice(&3u32.to_be_bytes()); // picture type: front cover
+    block.extend_from_slice(&(mime.len() as u32).to_be_bytes());
+    block.extend_from_slice(mime.as_bytes());
+    block.extend_from_slice(&0u32.to_be_bytes()); // empty description
+    block.extend_from_slice(&width.to_be_bytes());
+    block.extend_from_slice(&height.to_be_bytes());
+    block.extend_from_slice(&24u32.to_be_bytes()); // colour depth
+    block.extend_from_slice(&0u32.to_be_bytes()); // not indexed
+    block.extend_from_slice(&(data.len() as u32).to_be_bytes());
+    block.extend_from_slice(data);
+    Some(block)
+}
+
+/// Pixels at or below this level (all channels) count as a black border
+const BORDER_LEVEL: u8 = 24;
+
+/// Bounding box (x, y, width, height) of an image without its black
+/// letterbox/pillarbox bars; the full image if trimming would leave almost nothing
+pub fn content_bounds(image: &image::RgbImage) -> (u32, u32, u32, u32) {
+    let (width, height) = image.dimensions();
+    let dark_row = |y: u32| (0..width).all(|x| image.get_pixel(x, y).0.iter().all(|&c| c <= BORDER_LEVEL));
+    let dark_col = |x: u32, top: u32, bottom: u32| {
+        (top..bottom).all(|y| image.get_pixel(x, y).0.iter().all(|&c| c <= BORDER_LEVEL))
+    };
+
+    let top = (0..height).find(|&y| !dark_row(y)).unwrap_or(0);
+    let bottom = (0..height).rev().find(|&y| !dark_row(y)).map_or(height, |y| y + 1);
+    let left = (0..width).find(|&x| !dark_col(x, top, bottom)).unwrap_or(0);
+    let right = (0..width).rev().find(|&x| !dark_col(x, top, bottom)).map_or(width, |x| x + 1);
+
+    // A mostly dark picture is not letterboxed
+    if right <= left || bottom <= top || (right - left) * 4 < width || (bottom - top) * 4 < height {
+        return (0, 0, width, height);
+    }
+    (left, top, right - left, bottom - top)
+}
+
+/// Normalize cover art: strip black bars, center-crop to a square, shrink
+/// to at most `max_dimension` pixels and re-encode as JPEG
+/// Returns the JPEG data and its side length.
+pub fn normalize_cover(data: &[u8], max_dimension: u32, quality: u8) -> anyhow::Result<(Vec<u8>, u32)> {
+    let image = image::load_from_memory(data)?.to_rgb8();
+    let (x, y, width, height) = content_bounds(&image);
+
+    let side = width.min(height);
+    let (left, top) = (x + (width - side) / 2, y + (height - side) / 2);
+    let square = image::imageops::crop_imm(&image, left, top, side, side).to_image();
+    let square = if side > max_dimension {
+        image::imageops::resize(&square, max_dimension, max_dimension, image::imageops::FilterType::Lanczos3)
+    } else {
+        square
+    };
+
+    let mut out = Vec::new();
+    image::codecs::jpeg::JpegEncoder::new_with_quality(&mut out, quality.clamp(1, 100)).encode_image(&square)?;
+    Ok((out, square.width()))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn tiny_png(width: u32, height: u32) -> Vec<u8> {
+        let mut data = b"\x89PNG\r\n\x1a\n\0\0\0\rIHDR".to_vec();
+        data.extend_from_slice(&width.to_be_bytes());
+        data.extend_from_slice(&height.to_be_bytes());
+        data.extend_from_slice(&[8, 2, 0, 0, 0]);
+        data
+    }
+
+    #[test]
+    fn detects_image_types() {
+        assert_eq!(image_mime(&[0xFF, 0xD8, 0xFF, 0xE0]), Some("image/jpeg"));
+        assert_eq!(image_mime(&tiny_png(1, 1)), Some("image/png"));
+        assert_eq!(image_mime(b"GIF89a"), None);
+    }
+
+    #[test]
+    fn reads_png_and_jpeg_dimensions() {
+        assert_eq!(image_dimensions(&tiny_png(640, 480)), Some((640, 480)));
+
+        // SOI, APP0 (length 4), SOF0 with height 300 and width 500
+        let jpeg = [
+            0xFF, 0xD8, 0xFF, 0xE0, 0x00, 0x04, 0x00, 0x00, 0xFF, 0xC0, 0x00, 0x11, 0x08, 0x01,
+            0x2C, 0x01, 0xF4, 0x03,
+        ];
+        assert_eq!(image_dimensions(&jpeg), Some((500, 300)));
+    }
+
+    #[test]
+    fn picture_block_layout() {
+        let png = tiny_png(2, 3);
+        let block = flac_picture_block(&png).unwrap();
+
+        assert_eq!(&block[0..4], &3u32.to_be_bytes());
+        assert_eq!(&block[8..17], b"image/png");
+        assert_eq!(block.len(), 4 + 4 + 9 + 4 + 16 + 4 + png.len());
+        assert!(block.ends_with(&png));
+    }
+
+    /// 16:9 frame with a red square art in the middle and black bars on the sides
+    fn pillarboxed(width: u32, height: u32) -> image::RgbImage {
+        let margin = (width - height) / 2;
+        image::RgbImage::from_fn(width, height, |x, _| {
+            if x >= margin && x < margin + height {
+                image::Rgb([200, 30, 30])
+            } else {
+                image::Rgb([0, 0, 0])
+            }
+        })
+    }
+
+    #[test]
+    fn trims_black_bars() {
+        assert_eq!(content_bounds(&pillarboxed(160, 90)), (35, 0, 90, 90));
+        // A dark image is left alone
+        let dark = image::RgbImage::from_pixel(40, 30, image::Rgb([5, 5, 5]));
+        assert_eq!(content_bounds(&dark), (0, 0, 40, 30));
+    }
+
+    #[test]
+    fn normalizes_to_square_jpeg() {
+        let mut png = Vec::new();
+        image::DynamicImage::ImageRgb8(pillarboxed(320, 180))
+            .write_to(&mut std::io::Cursor::new(&mut png), image::ImageFormat::Png)
+            .unwrap();
+
+        let (jpeg, side) = normalize_cover(&png, 100, 90).unwrap();
+        assert_eq!(side, 100);
+        assert_eq!(image_mime(&jpeg), Some("image/jpeg"));
+        assert_eq!(image_dimensions(&jpeg), Some((100, 100)));
+
+        // No black left at the edges
+        let decoded = image::load_from_memory(&jpeg).unwrap().to_rgb8();
+        assert!(decoded.get_pixel(0, 50).0[0] > 150);
+
+        // Small art is not upscaled
+        let (_, side) = normalize_cover(&png, 1200, 90).unwrap();
+        assert_eq!(side, 180);
+    }
+}
@@ -1,53 +1,183 @@
-use crate::models::{Claims, User};
+use crate::db::Database;
+use crate::models::{Claims, JwtKey, User};
 use axum::{
-    extract::{Request, State},
-    http::{header, StatusCode},
+    extract::{ConnectInfo, Extension, Request, State},
+    http::{header, HeaderMap, StatusCode},
     middleware::Next,
     response::{IntoResponse, Response},
     Json,
 };
 use chrono::{Duration, Utc};
-use jsonwebtoken::{decode, encode, DecodingKey, EncodingKey, Header, Validation};
+use jsonwebtoken::{decode, decode_header, encode, DecodingKey, EncodingKey, Header, Validation};
+use serde::{Deserialize, Serialize};
 use serde_json::json;
+use ipnet::IpNet;
+use std::net::{IpAddr, SocketAddr};
+use sha2::{Digest, Sha256};
+use std::sync::{Arc, RwLock};
+
+/// What a role allows; checked per route group by `require_permission`
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, PartialOrd, Ord, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Permission {
+    Upload,
+    Youtube,
+    Spotify,
+    UrlImport,
+    ServerImport,
+    ManageUsers,
+    ManageConfig,
+    /// Upload logs and reports (spectral, duplicates) of every user
+    ViewAllLogs,
+}
+
+impl Permission {
+    pub const ALL: [Permission; 8] = [
+        Self::Upload,
+        Self::Youtube,
+        Self::Spotify,
+        Self::UrlImport,
+        Self::ServerImport,
+        Self::ManageUsers,
+        Self::ManageConfig,
+        Self::ViewAllLogs,
+    ];
+
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Self::Upload => "upload",
+            Self::Youtube => "youtube",
+            Self::Spotify => "spotify",
+            Self::UrlImport => "url_import",
+            Self::ServerImport => "server_import",
+            Self::ManageUsers => "manage_users",
+            Self::ManageConfig => "manage_config",
+            Self::ViewAllLogs => "view_all_logs",
+        }
+    }
+
+    pub fn parse(name: &str) -> Option<Self> {
+        Self::ALL.into_iter().find(|p| p.as_str() == name)
+    }
+
+    /// Permissions held back from users without 2FA when `require_admin_2fa` is on
+    pub fn is_admin(&self) -> bool {
+        matches!(self, Self::ManageUsers | Self::ManageConfig | Self::ViewAllLogs)
+    }
+}
 
 #[derive(Clone)]
 pub struct AuthState {
-    pub jwt_secret: String,
+    /// Keys that verify tokens; the one without `expires_at` signs new ones
+    keys: Arc<RwLock<Vec<JwtKey>>>,
+    /// Whether the signing key comes from `jwt_secret`/`JWT_SECRET(_FILE)`;
+    /// such keys are rotated by changing the secret, not from the admin panel
+    pub configured: bool,
     pub session_timeout_hours: i64,
 }
 
 impl AuthState {
+    /// A single in-memory signing key
     pub fn new(jwt_secret: String, session_timeout_hours: i64) -> Self {
+        let key = JwtKey {
+            kid: key_id(&jwt_secret),
+            secret: jwt_secret,
+            created_at: Utc::now(),
+            expires_at: None,
+        };
         Self {
-            jwt_secret,
+            keys: Arc::new(RwLock::new(vec![key])),
+            configured: true,
             session_timeout_hours,
         }
     }
 
-    pub fn create_token(&self, user: &User) -> Result<String, jsonwebtoken::errors::Error> {
-        let expiration = Utc::now()
+    /// Keys from the database. A configured secret becomes the signing key
+    /// (retiring the previous one when it changed); without one, a random key
+    /// is generated once and kept, so restarts don't log everyone out.
+    pub async fn load(db: &Database, configured_secret: Option<&str>, session_timeout_hours: i64) -> anyhow::Result<Self> {
+        let state = Self {
+            keys: Arc::new(RwLock::new(Vec::new())),
+            configured: configured_secret.is_some(),
+            session_timeout_hours,
+        };
+        db.prune_jwt_keys().await?;
+        match configured_secret {
+            Some(secret) => db.activate_jwt_key(&key_id(secret), secret, state.expiry()).await?,
+            None => {
+                if !db.list_jwt_keys().await?.iter().any(|k| k.expires_at.is_none()) {
+                    tracing::info!("No JWT secret configured, generating a signing key");
+                    let secret = generate_secret();
+                    db.activate_jwt_key(&key_id(&secret), &secret, state.expiry()).await?;
+                }
+            }
+        }
+        state.reload(db).await?;
+        Ok(state)
+    }
+
+    /// Re-read the keys after they changed in the database
+    pub async fn reload(&self, db: &Database) -> anyhow::Result<()> {
+        let keys = db.list_jwt_keys().await?;
+        *self.keys.write().expect("key lock") = keys;
+        Ok(())
+    }
+
+    /// Sign new tokens with a fresh random key; the old one keeps verifying
+    /// until its tokens have expired and is pruned after that
+    pub async fn rotate(&self, db: &Database) -> anyhow::Result<String> {
+        anyhow::ensure!(
+            !self.configured,
+            "The signing key comes from the configured jwt_secret; change the secret to rotate it"
+        );
+        db.prune_jwt_keys().await?;
+        let secret = generate_secret();
+        let kid = key_id(&secret);
+        db.activate_jwt_key(&kid, &secret, self.expiry()).await?;
+        self.reload(db).await?;
+        Ok(kid)
+    }
+
+    /// Expiry (unix timestamp) for a token issued now
+    pub fn expiry(&self) -> i64 {
+        Utc::now()
             .checked_add_signed(Duration::hours(self.session_timeout_hours))
             .expect("valid timestamp")
-            .timestamp();
+            .timestamp()
+    }
 
+    pub fn create_token(&self, user: &User, session_id: &str, expires_at: i64) -> Result<String, jsonwebtoken::errors::Error> {
         let claims = Claims {
             sub: user.id.clone(),
-            username: user.username.clone(),
-            is_admin: user.is_admin,
-            exp: expiration,
+            jti: session_id.to_string(),
+            exp: expires_at,
+        };
+
+        let keys = self.keys.read().expect("key lock");
+        let key = keys
+            .iter()
+            .find(|k| k.expires_at.is_none())
+            .ok_or(jsonwebtoken::errors::ErrorKind::InvalidKeyFormat)?;
+        let header = Header {
+            kid: Some(key.kid.clone()),
+            ..Header::default()
         };
 
-        encode(
-            &Header::default(),
-            &claims,
-            &EncodingKey::from_secret(self.jwt_secret.as_bytes()),
-        )
+        encode(&header, &claims, &EncodingKey::from_secret(key.secret.as_bytes()))
     }
 
     pub fn verify_token(&self, token: &str) -> Result<Claims, jsonwebtoken::errors::Error> {
+        let kid = decode_header(token)?.kid.ok_or(jsonwebtoken::errors::ErrorKind::InvalidToken)?;
+        let now = Utc::now().timestamp();
+        let keys = self.keys.read().expect("key lock");
+        let key = keys
+            .iter()
+            .find(|k| k.kid == kid && k.expires_at.is_none_or(|at| at > now))
+            .ok_or(jsonwebtoken::errors::ErrorKind::InvalidToken)?;
+
         let token_data = decode::<Claims>(
             token,
-            &DecodingKey::from_secret(self.jwt_secret.as_bytes()),
+            &DecodingKey::from_secret(key.secret.as_bytes()),
             &Validation::default(),
         )?;
 
@@ -55,30 +185,135 @@ impl AuthState {
     }
 }
 
+/// Start of every personal API token, telling them apart from JWTs
+pub const API_TOKEN_PREFIX: &str = "j3s_";
+
+/// A new random API token; only its hash is stored
+pub fn generate_api_token() -> String {
+    use rand::distributions::{Alphanumeric, DistString};
+    format!("{}{}", API_TOKEN_PREFIX, Alphanumeric.sample_string(&mut rand::thread_rng(), 40))
+}
+
+/// Tokens are random, so a plain SHA-256 is enough (unlike passwords)
+pub fn hash_api_token(token: &str) -> String {
+    Sha256::digest(token.as_bytes()).iter().map(|b| format!("{:02x}", b)).collect()
+}
+
+/// Key id of a secret: a short hash, so the same secret always gets the same id
+fn key_id(secret: &str) -> String {
+    let digest = Sha256::digest(secret.as_bytes());
+    digest[..8].iter().map(|b| format!("{:02x}", b)).collect()
+}
+
+fn generate_secret() -> String {
+    use rand::Rng;
+    const CHARSET: &[u8] = b"ABCDEFGHIJKLMNOPQRSTUVWXYZabcdefghijklmnopqrstuvwxyz0123456789+/";
+    let mut rng = rand::thread_rng();
+    (0..43) // Base64 encoded 32 bytes = 43 characters
+        .map(|_| {
+            let idx = rng.gen_range(0..CHARSET.len());
+            CHARSET[idx] as char
+        })
+        .collect()
+}
+
+/// Where a login came from, recorded on its session
+#[derive(Debug, Clone, Default)]
+pub struct ClientInfo {
+    pub ip: Option<String>,
+    pub user_agent: Option<String>,
+}
+
+impl ClientInfo {
+    pub fn from_request(headers: &HeaderMap, peer: Option<SocketAddr>, trusted_proxies: &[IpNet]) -> Self {
+        Self {
+            ip: client_ip(headers, peer.map(|addr| addr.ip()), trusted_proxies).map(|ip| ip.to_string()),
+            user_agent: headers
+                .get(header::USER_AGENT)
+                .and_then(|h| h.to_str().ok())
+                .map(|ua| ua.chars().take(256).collect()),
+        }
+    }
+}
+
+/// The peer address, or behind trusted proxies the last `X-Forwarded-For`
+/// hop that isn't one of them (earlier hops can be forged by the client)
+pub fn client_ip(headers: &HeaderMap, peer: Option<IpAddr>, trusted_proxies: &[IpNet]) -> Option<IpAddr> {
+    let is_trusted = |ip: &IpAddr| trusted_proxies.iter().any(|net| net.contains(ip));
+    let peer = peer?;
+    if !is_trusted(&peer) {
+        return Some(peer);
+    }
+    let forwarded: Vec<IpAddr> = headers
+        .get_all("x-forwarded-for")
+        .iter()
+        .filter_map(|h| h.to_str().ok())
+        .flat_map(|v| v.split(','))
+        .filter_map(|ip| ip.trim().parse().ok())
+        .collect();
+    forwarded
+        .iter()
+        .rev()
+        .find(|ip| !is_trusted(ip))
+        .or(forwarded.first())
+        .copied()
+        .or(Some(peer))
+}
+
+/// Whether a password login of `user` needs a second step (TOTP or passkey)
+pub fn has_second_factor(state: &crate::AppState, user: &User) -> bool {
+    user.totp_enabled || (user.passkeys_enabled && state.config.passkeys.enabled)
+}
+
+/// The role's permissions, minus the admin ones if 2FA is required for them
+/// and `user` hasn't set it up
+pub async fn effective_permissions(state: &crate::AppState, user: &User) -> anyhow::Result<Vec<Permission>> {
+    let mut permissions = state.db.get_role_permissions(&user.role).await?;
+    if !has_second_factor(state, user)
+        && permissions.iter().any(Permission::is_admin)
+        && state.db.get_require_admin_2fa(&state.config).await?
+    {
+        permissions.retain(|p| !p.is_admin());
+    }
+    Ok(permissions)
+}
+
+/// Record a new session for `user` and return its token
+pub async fn start_session(state: &crate::AppState, user: &User, client: &ClientInfo) -> anyhow::Result<String> {
+    let expires_at = state.auth.expiry();
+    let session_id = state.db.create_session(&user.id, client, expires_at).await?;
+    Ok(state.auth.create_token(user, &session_id, expires_at)?)
+}
+
 // Extension to store authenticated user info in request
 #[derive(Clone)]
 pub struct AuthUser {
     pub user_id: String,
     pub username: String,
-    pub is_admin: bool,
+    /// The `sessions` row of the JWT used; `None` for API tokens
+    pub session_id: Option<String>,
+    /// Permissions of the role, loaded on every request so changes apply at once
+    pub permissions: Vec<Permission>,
 }
 
 impl AuthUser {
-    pub fn from_claims(claims: Claims) -> Self {
-        Self {
-            user_id: claims.sub,
-            username: claims.username,
-            is_admin: claims.is_admin,
-        }
+    pub fn has(&self, permission: Permission) -> bool {
+        self.permissions.contains(&permission)
     }
 }
 
 // Middleware for authentication
 pub async fn auth_middleware(
-    State(auth_state): State<AuthState>,
+    State(state): State<Arc<crate::AppState>>,
     mut request: Request,
     next: Next,
 ) -> Result<Response, StatusCode> {
+    let peer = request
+        .extensions()
+        .get::<ConnectInfo<SocketAddr>>()
+        .map(|ConnectInfo(addr)| *addr);
+    let proxied = crate::proxy_auth::identify(&state, peer, request.headers()).await?;
+
     // Try to get token from Authorization header first
     let auth_header = request
         .headers()
@@ -99,48 +334,206 @@ pub async fn auth_middleware(
                 cookies
                     .split(';')
                     .find_map(|cookie| {
-                        let cookie = cookie.trim();
-                        if let Some(value) = cookie.strip_prefix("token=") {
-                            Some(value.to_string())
-                        } else {
-                            None
-                        }
+                        cookie.trim().strip_prefix("token=").map(|value| value.to_string())
                     })
             })
     };
 
+    // Behind an auth proxy its headers decide the user; a login session of
+    // that same user (from /api/login/proxy) is kept for session features
+    if let Some(user) = proxied {
+        let claims = token.as_deref().and_then(|t| state.auth.verify_token(t).ok());
+        let session_id = match claims {
+            Some(claims) if claims.sub == user.id => state
+                .db
+                .touch_session(&claims.jti, &claims.sub)
+                .await
+                .map_err(|_| StatusCode::INTERNAL_SERVER_ERROR)?
+                .then_some(claims.jti),
+            _ => None,
+        };
+        let permissions = effective_permissions(&state, &user)
+            .await
+            .map_err(|_| StatusCode::INTERNAL_SERVER_ERROR)?;
+        request.extensions_mut().insert(AuthUser {
+            user_id: user.id,
+            username: user.username,
+            session_id,
+            permissions,
+        });
+        return Ok(next.run(request).await);
+    }
+
     let token = token.ok_or(StatusCode::UNAUTHORIZED)?;
 
-    match auth_state.verify_token(&token) {
-        Ok(claims) => {
-            request
-                .extensions_mut()
-                .insert(AuthUser::from_claims(claims));
-            Ok(next.run(request).await)
+    let (user_id, session_id, api_token) = if token.starts_with(API_TOKEN_PREFIX) {
+        // Personal API token: looked up by hash, expired and revoked ones are not found
+        let api_token = state
+            .db
+            .use_api_token(&hash_api_token(&token))
+            .await
+            .map_err(|_| StatusCode::INTERNAL_SERVER_ERROR)?
+            .ok_or(StatusCode::UNAUTHORIZED)?;
+        (api_token.user_id.clone(), None, Some(api_token))
+    } else {
+        let claims = state.auth.verify_token(&token).map_err(|_| StatusCode::UNAUTHORIZED)?;
+
+        // Revoked (logged out, password changed) and expired sessions are gone from the table
+        let active = state
+            .db
+            .touch_session(&claims.jti, &claims.sub)
+            .await
+            .map_err(|_| StatusCode::INTERNAL_SERVER_ERROR)?;
+        if !active {
+            return Err(StatusCode::UNAUTHORIZED);
         }
-        Err(_) => Err(StatusCode::UNAUTHORIZED),
+        (claims.sub, Some(claims.jti), None)
+    };
+
+    // Deleted users' tokens stop working; the role is looked up fresh
+    let user = state
+        .db
+        .get_user_by_id(&user_id)
+        .await
+        .map_err(|_| StatusCode::UNAUTHORIZED)?;
+    let mut permissions = effective_permissions(&state, &user)
+        .await
+        .map_err(|_| StatusCode::INTERNAL_SERVER_ERROR)?;
+    // A scoped token can do at most what its owner's role allows
+    if let Some(scopes) = api_token.as_ref().and_then(|t| t.scopes.as_ref()) {
+        permissions.retain(|p| scopes.contains(p));
     }
+
+    request.extensions_mut().insert(AuthUser {
+        user_id: user.id,
+        username: user.username,
+        session_id,
+        permissions,
+    });
+    Ok(next.run(request).await)
 }
 
-// Middleware for admin-only routes
-pub async fn admin_middleware(
-    auth_user: Option<axum::extract::Extension<AuthUser>>,
-    request: Request,
-    next: Next,
-) -> Result<Response, StatusCode> {
-    match auth_user {
-        Some(axum::extract::Extension(user)) if user.is_admin => Ok(next.run(request).await),
-        _ => Err(StatusCode::FORBIDDEN),
+// Middleware for account routes that API tokens may not use (passwords, sessions, tokens)
+pub async fn require_session(Extension(user): Extension<AuthUser>, request: Request, next: Next) -> Response {
+    if user.session_id.is_some() {
+        return next.run(request).await;
     }
+    (
+        StatusCode::FORBIDDEN,
+        Json(json!({
+            "error": "This needs a login session; API tokens can't be used here"
+        })),
+    )
+        .into_response()
 }
 
-// Error response helper
-pub fn auth_error(message: &str) -> Response {
+// Middleware for route groups that need a permission (layered inside `auth_middleware`)
+pub async fn require_permission(
+    State(permission): State<Permission>,
+    Extension(user): Extension<AuthUser>,
+    request: Request,
+    next: Next,
+) -> Response {
+    if user.has(permission) {
+        return next.run(request).await;
+    }
     (
-        StatusCode::UNAUTHORIZED,
+        StatusCode::FORBIDDEN,
         Json(json!({
-            "error": message
+            "error": format!("Permission required: {}", permission.as_str())
         })),
     )
         .into_response()
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::models::CreateUser;
+
+    async fn test_db() -> Database {
+        let dir = std::env::temp_dir().join(format!("auth-test-{}", uuid::Uuid::new_v4()));
+        std::fs::create_dir_all(&dir).unwrap();
+        Database::new(&format!("sqlite:{}", dir.join("test.db").display()), 1)
+            .await
+            .unwrap()
+    }
+
+    async fn test_user(db: &Database) -> User {
+        db.create_user(CreateUser {
+            username: "keyholder".to_string(),
+            password: "password123".to_string(),
+            role: "user".to_string(),
+            library_path: None,
+        })
+        .await
+        .unwrap()
+    }
+
+    #[test]
+    fn forwarded_for_is_only_believed_from_trusted_proxies() {
+        let trusted: Vec<IpNet> = vec!["10.0.0.0/8".parse().unwrap()];
+        let mut headers = HeaderMap::new();
+        headers.insert("x-forwarded-for", "6.6.6.6, 203.0.113.7, 10.0.0.2".parse().unwrap());
+        let ip = |s: &str| Some(s.parse::<IpAddr>().unwrap());
+
+        // Direct clients can't claim another address
+        assert_eq!(client_ip(&headers, ip("198.51.100.1"), &trusted), ip("198.51.100.1"));
+        // Behind the proxy, the nearest untrusted hop is the client
+        assert_eq!(client_ip(&headers, ip("10.0.0.1"), &trusted), ip("203.0.113.7"));
+        assert_eq!(client_ip(&HeaderMap::new(), ip("10.0.0.1"), &trusted), ip("10.0.0.1"));
+        assert_eq!(client_ip(&headers, None, &trusted), None);
+    }
+
+    #[tokio::test]
+    async fn generated_key_survives_restarts() {
+        let db = test_db().await;
+        let user = test_user(&db).await;
+        let auth = AuthState::load(&db, None, 1).await.unwrap();
+        let token = auth.create_token(&user, "session", auth.expiry()).unwrap();
+
+        let restarted = AuthState::load(&db, None, 1).await.unwrap();
+        assert_eq!(restarted.verify_token(&token).unwrap().sub, user.id);
+        assert_eq!(db.list_jwt_keys().await.unwrap().len(), 1);
+    }
+
+    #[tokio::test]
+    async fn rotation_keeps_old_tokens_until_pruned() {
+        let db = test_db().await;
+        let user = test_user(&db).await;
+        let auth = AuthState::load(&db, None, 1).await.unwrap();
+        let old = auth.create_token(&user, "old", auth.expiry()).unwrap();
+
+        let kid = auth.rotate(&db).await.unwrap();
+        let new = auth.create_token(&user, "new", auth.expiry()).unwrap();
+        assert_eq!(decode_header(&new).unwrap().kid.as_deref(), Some(kid.as_str()));
+        assert!(auth.verify_token(&old).is_ok());
+        assert!(auth.verify_token(&new).is_ok());
+
+        // Once the retired key's tokens have expired, it is dropped
+        sqlx::query("UPDATE jwt_keys SET expires_at = 0 WHERE expires_at IS NOT NULL")
+            .execute(db.pool())
+            .await
+            .unwrap();
+        assert_eq!(db.prune_jwt_keys().await.unwrap(), 1);
+        auth.reload(&db).await.unwrap();
+        assert!(auth.verify_token(&old).is_err());
+        assert!(auth.verify_token(&new).is_ok());
+    }
+
+    #[tokio::test]
+    async fn changing_the_configured_secret_rotates() {
+        let db = test_db().await;
+        let user = test_user(&db).await;
+        let first = AuthState::load(&db, Some("first-secret"), 1).await.unwrap();
+        let token = first.create_token(&user, "session", first.expiry()).unwrap();
+        assert!(first.rotate(&db).await.is_err());
+
+        let second = AuthState::load(&db, Some("second-secret"), 1).await.unwrap();
+        assert!(second.verify_token(&token).is_ok());
+        let keys = db.list_jwt_keys().await.unwrap();
+        assert_eq!(keys.len(), 2);
+        assert_eq!(keys[0].kid, key_id("second-secret"));
+        assert!(keys[0].expires_at.is_none() && keys[1].expires_at.is_some());
+    }
+}
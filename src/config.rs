@@ -1,5 +1,7 @@
 use anyhow::{Context, Result};
 use serde::{Deserialize, Serialize};
+use ipnet::IpNet;
+use std::collections::BTreeMap;
 use std::path::PathBuf;
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
@@ -12,6 +14,39 @@ pub struct Config {
     pub youtube: YoutubeConfig,
     #[serde(default)]
     pub spotify: SpotifyConfig,
+    #[serde(default)]
+    pub url_import: UrlImportConfig,
+    /// Server directories (e.g. a Soulseek or torrent download folder) that
+    /// files can be imported from, by name
+    #[serde(default)]
+    pub import_roots: BTreeMap<String, ImportRoot>,
+    #[serde(default)]
+    pub watch: WatchConfig,
+    #[serde(default)]
+    pub ffmpeg: FfmpegConfig,
+    /// Named download profiles, selectable per request and per user
+    #[serde(default)]
+    pub profiles: BTreeMap<String, DownloadProfile>,
+    #[serde(default)]
+    pub transcode: TranscodeConfig,
+    #[serde(default)]
+    pub loudness: LoudnessConfig,
+    #[serde(default)]
+    pub validation: ValidationConfig,
+    #[serde(default)]
+    pub spectral: SpectralConfig,
+    #[serde(default)]
+    pub duplicates: DuplicatesConfig,
+    #[serde(default)]
+    pub cover_art: CoverArtConfig,
+    #[serde(default)]
+    pub lyrics: LyricsConfig,
+    #[serde(default)]
+    pub oidc: OidcConfig,
+    #[serde(default)]
+    pub proxy_auth: ProxyAuthConfig,
+    #[serde(default)]
+    pub passkeys: PasskeyConfig,
 }
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
@@ -37,14 +72,62 @@ pub struct PathsConfig {
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct SecurityConfig {
+    /// Left as the placeholder, a generated key stored in the database is used
     pub jwt_secret: String,
     pub session_timeout_hours: i64,
+    /// Reverse proxies (IPs or CIDRs) whose `X-Forwarded-For` is believed
+    #[serde(default)]
+    pub trusted_proxies: Vec<IpNet>,
+    #[serde(default)]
+    pub login_throttle: LoginThrottleConfig,
+    /// Admin permissions (manage_users, manage_config, view_all_logs) only
+    /// apply once the user has TOTP set up; overridable in the admin panel
+    #[serde(default)]
+    pub require_admin_2fa: bool,
+    /// Allow logging in with a username and password (turn off to only allow single sign-on)
+    #[serde(default = "SecurityConfig::default_true")]
+    pub password_login: bool,
+}
+
+/// Failed-login limits, counted per client IP and per username
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct LoginThrottleConfig {
+    /// Failures after which a username is locked
+    #[serde(default = "LoginThrottleConfig::default_max_account_failures")]
+    pub max_account_failures: u32,
+    /// Failures after which an IP is locked
+    #[serde(default = "LoginThrottleConfig::default_max_ip_failures")]
+    pub max_ip_failures: u32,
+    /// How long a lockout lasts; failures older than this are forgotten
+    #[serde(default = "LoginThrottleConfig::default_lockout_minutes")]
+    pub lockout_minutes: i64,
+    /// Longest wait between attempts before the lockout (doubles from 1s per failure)
+    #[serde(default = "LoginThrottleConfig::default_max_backoff_secs")]
+    pub max_backoff_secs: i64,
+}
+
+impl SecurityConfig {
+    fn default_true() -> bool {
+        true
+    }
+
+    /// The JWT secret, unless empty or still the example placeholder
+    pub fn configured_jwt_secret(&self) -> Option<&str> {
+        let secret = self.jwt_secret.as_str();
+        (!secret.is_empty() && secret != "your-secret-key-here-change-this").then_some(secret)
+    }
 }
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct UploadConfig {
     pub max_file_size_mb: u64,
     pub allowed_extensions: Vec<String>,
+    /// Most entries an uploaded archive (zip, tar, 7z) may contain
+    #[serde(default = "UploadConfig::default_max_archive_entries")]
+    pub max_archive_entries: usize,
+    /// Largest size of an uploaded archive in MB, both packed and unpacked
+    #[serde(default = "UploadConfig::default_max_archive_size_mb")]
+    pub max_archive_size_mb: u64,
 }
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
@@ -74,6 +157,362 @@ pub struct SpotifyConfig {
     pub client_secret: String,
     #[serde(default = "SpotifyConfig::default_redirect_uri")]
     pub redirect_uri: String,
+    /// Search YouTube for tracks spotdl could not match
+    #[serde(default)]
+    pub youtube_fallback: bool,
+    /// Maximum allowed duration difference (seconds) for a fallback candidate
+    #[serde(default = "SpotifyConfig::default_fallback_duration_tolerance_secs")]
+    pub fallback_duration_tolerance_secs: u64,
+    /// Number of YouTube search results considered per missing track
+    #[serde(default = "SpotifyConfig::default_fallback_search_results")]
+    pub fallback_search_results: u32,
+}
+
+/// Direct HTTP(S) downloads of audio files and archives
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct UrlImportConfig {
+    #[serde(default = "UrlImportConfig::default_true")]
+    pub enabled: bool,
+    /// Largest download in MB (defaults to [upload] max_archive_size_mb)
+    #[serde(default)]
+    pub max_size_mb: Option<u64>,
+    #[serde(default = "UrlImportConfig::default_max_redirects")]
+    pub max_redirects: usize,
+    #[serde(default = "UrlImportConfig::default_timeout_secs")]
+    pub timeout_secs: u64,
+    /// Allow loopback, private and link-local targets (only for testing)
+    #[serde(default)]
+    pub allow_private_addresses: bool,
+}
+
+/// A server directory files can be browsed and imported from
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ImportRoot {
+    pub path: PathBuf,
+    #[serde(default)]
+    pub description: String,
+    /// Usernames allowed to import from this root; empty allows every user
+    /// with the server_import permission (config managers can always)
+    #[serde(default)]
+    pub users: Vec<String>,
+    /// Allow move imports, which delete the source files after a successful import
+    #[serde(default = "ImportRoot::default_true")]
+    pub allow_move: bool,
+}
+
+/// Drop folders (e.g. synced with Syncthing) whose files are imported automatically
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct WatchConfig {
+    #[serde(default)]
+    pub enabled: bool,
+    /// Seconds a file's size must stay unchanged before it is imported
+    #[serde(default = "WatchConfig::default_stable_secs")]
+    pub stable_secs: u64,
+    /// Seconds between full rescans; the only trigger when inotify is off or unavailable
+    #[serde(default = "WatchConfig::default_poll_interval_secs")]
+    pub poll_interval_secs: u64,
+    /// Always poll instead of using inotify (e.g. for network filesystems)
+    #[serde(default)]
+    pub force_polling: bool,
+    #[serde(default)]
+    pub folders: BTreeMap<String, WatchFolder>,
+}
+
+/// A watched drop folder
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct WatchFolder {
+    pub path: PathBuf,
+    /// Username owning the folder; without one, each subfolder is the inbox
+    /// of the user with that name
+    #[serde(default)]
+    pub user: Option<String>,
+}
+
+/// Output settings for YouTube/Spotify downloads
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct DownloadProfile {
+    #[serde(default)]
+    pub description: String,
+    /// Target audio format (opus, mp3, m4a, flac, ...)
+    pub audio_format: String,
+    /// yt-dlp --audio-quality (0-10 VBR scale, or a bitrate such as "320K")
+    #[serde(default)]
+    pub audio_quality: Option<String>,
+    /// spotdl --bitrate (e.g. "320k", "auto", "disable")
+    #[serde(default)]
+    pub bitrate: Option<String>,
+    /// yt-dlp --format selector; falls back to youtube.format_selector
+    #[serde(default)]
+    pub format_selector: Option<String>,
+    /// Extra raw arguments for yt-dlp, appended after youtube.extra_args
+    #[serde(default)]
+    pub ytdlp_args: Vec<String>,
+    /// Extra raw arguments for spotdl
+    #[serde(default)]
+    pub spotdl_args: Vec<String>,
+    #[serde(default = "DownloadProfile::default_true")]
+    pub embed_metadata: bool,
+    #[serde(default = "DownloadProfile::default_true")]
+    pub embed_thumbnail: bool,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct FfmpegConfig {
+    #[serde(default = "FfmpegConfig::default_ffmpeg_path")]
+    pub ffmpeg_path: String,
+    #[serde(default = "FfmpegConfig::default_ffprobe_path")]
+    pub ffprobe_path: String,
+}
+
+/// Post-processing stage that converts staged files according to `rules`
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct TranscodeConfig {
+    #[serde(default)]
+    pub enabled: bool,
+    /// Keep the original file after a successful transcode
+    #[serde(default)]
+    pub keep_originals: bool,
+    /// Where kept originals go (relative folder structure preserved);
+    /// empty keeps them next to the transcoded file in the library
+    #[serde(default)]
+    pub originals_dir: Option<PathBuf>,
+    /// Checked in order, the first matching rule wins; files matching no rule are kept
+    #[serde(default = "TranscodeConfig::default_rules")]
+    pub rules: Vec<TranscodeRule>,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct TranscodeRule {
+    /// Input codecs: ffprobe codec names ("alac", "mp3"), "pcm", "lossless", "lossy" or "*"
+    pub from: Vec<String>,
+    /// Only match these file extensions (empty matches any)
+    #[serde(default)]
+    pub extensions: Vec<String>,
+    #[serde(default)]
+    pub min_bitrate_kbps: Option<u32>,
+    #[serde(default)]
+    pub max_bitrate_kbps: Option<u32>,
+    /// Target format (flac, alac, opus, mp3, m4a, ogg) or "keep"
+    pub to: String,
+    /// Bitrate for lossy targets (defaults per format)
+    #[serde(default)]
+    pub bitrate_kbps: Option<u32>,
+}
+
+/// Post-processing stage that measures EBU R128 loudness and writes ReplayGain tags
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct LoudnessConfig {
+    #[serde(default)]
+    pub enabled: bool,
+    /// Target loudness for ReplayGain values (ReplayGain 2.0 uses -18 LUFS)
+    #[serde(default = "LoudnessConfig::default_reference_lufs")]
+    pub reference_lufs: f64,
+    /// Write ReplayGain (and R128 for Opus) tags; when false values are only logged
+    #[serde(default = "LoudnessConfig::default_true")]
+    pub write_tags: bool,
+    /// Also write album gain/peak when a job contains a whole album
+    #[serde(default = "LoudnessConfig::default_true")]
+    pub album_gain: bool,
+}
+
+/// Checks run on staged audio files before anything else touches them
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ValidationConfig {
+    /// Probe container/codec and reject files whose content doesn't match the extension
+    #[serde(default = "ValidationConfig::default_true")]
+    pub enabled: bool,
+    /// Also decode every file completely to catch corruption and truncation
+    #[serde(default = "ValidationConfig::default_true")]
+    pub full_decode: bool,
+}
+
+/// Spectral check that flags "lossless" files transcoded from a lossy source
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct SpectralConfig {
+    #[serde(default)]
+    pub enabled: bool,
+    /// Confidence (0.0-1.0) at which a file is flagged as suspicious
+    #[serde(default = "SpectralConfig::default_threshold")]
+    pub threshold: f64,
+    /// Move suspicious files to the quarantine directory instead of importing them
+    #[serde(default)]
+    pub quarantine: bool,
+    /// Defaults to <temp_dir>/quarantine
+    #[serde(default)]
+    pub quarantine_dir: Option<PathBuf>,
+    /// Only the first N seconds of each file are analyzed
+    #[serde(default = "SpectralConfig::default_max_analysis_secs")]
+    pub max_analysis_secs: u32,
+}
+
+/// Duplicate detection at import time (byte hash + acoustic fingerprint)
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct DuplicatesConfig {
+    #[serde(default)]
+    pub enabled: bool,
+    /// Fingerprint similarity (0.0-1.0) at which two files count as the same recording
+    #[serde(default = "DuplicatesConfig::default_similarity_threshold")]
+    pub similarity_threshold: f64,
+    /// What happens to a file that is already in the user's library
+    #[serde(default)]
+    pub on_duplicate: DuplicateAction,
+    /// Only the first N seconds of each file are fingerprinted
+    #[serde(default = "DuplicatesConfig::default_max_fingerprint_secs")]
+    pub max_fingerprint_secs: u32,
+}
+
+/// Collision rule for an imported file that duplicates a library file
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum DuplicateAction {
+    /// Don't import the new file
+    #[default]
+    Skip,
+    /// Import it anyway (the duplicate is only reported)
+    KeepBoth,
+    /// Import it and delete the library copy
+    Replace,
+    /// Keep whichever is better: lossless over lossy, then higher bitrate
+    KeepBetter,
+}
+
+/// Cover art normalization: square crop, resize, folder image and embedded art
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct CoverArtConfig {
+    #[serde(default = "CoverArtConfig::default_true")]
+    pub enabled: bool,
+    /// Largest width/height of the normalized art in pixels
+    #[serde(default = "CoverArtConfig::default_max_dimension")]
+    pub max_dimension: u32,
+    /// JPEG quality (1-100) of the normalized art
+    #[serde(default = "CoverArtConfig::default_jpeg_quality")]
+    pub jpeg_quality: u8,
+    /// Image file written into each album folder
+    #[serde(default = "CoverArtConfig::default_folder_file_name")]
+    pub folder_file_name: String,
+}
+
+/// Lyrics sidecars and lookups from an LRCLIB-compatible service
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct LyricsConfig {
+    /// Look up lyrics for imported tracks that have none
+    #[serde(default)]
+    pub fetch: bool,
+    #[serde(default = "LyricsConfig::default_base_url")]
+    pub base_url: String,
+    /// Where lyrics end up: next to the track, in its tags, or both
+    #[serde(default)]
+    pub output: LyricsOutput,
+    #[serde(default = "LyricsConfig::default_timeout_secs")]
+    pub timeout_secs: u64,
+}
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum LyricsOutput {
+    /// .lrc (synced) or .txt (plain) file with the track's name
+    #[default]
+    Sidecar,
+    /// LYRICS tag in the audio file
+    Embed,
+    Both,
+}
+
+/// Single sign-on with an OpenID Connect provider (Authelia, Keycloak, ...)
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct OidcConfig {
+    #[serde(default)]
+    pub enabled: bool,
+    /// Issuer URL; the discovery document is read from `<issuer_url>/.well-known/openid-configuration`
+    #[serde(default)]
+    pub issuer_url: String,
+    #[serde(default)]
+    pub client_id: String,
+    /// Empty for a public client (PKCE only)
+    #[serde(default)]
+    pub client_secret: String,
+    /// This server's callback, `https://<host>/api/oidc/callback`, as registered with the provider
+    #[serde(default)]
+    pub redirect_url: String,
+    #[serde(default = "OidcConfig::default_scopes")]
+    pub scopes: Vec<String>,
+    /// Claim used as the username of provisioned users
+    #[serde(default = "OidcConfig::default_username_claim")]
+    pub username_claim: String,
+    /// Claim compared to existing usernames to link accounts on their first
+    /// single sign-on; unset never links existing accounts
+    #[serde(default)]
+    pub match_claim: Option<String>,
+    /// Create users that don't exist yet
+    #[serde(default = "OidcConfig::default_true")]
+    pub auto_provision: bool,
+    /// Claim listing the user's groups
+    #[serde(default = "OidcConfig::default_groups_claim")]
+    pub groups_claim: String,
+    /// Group to role mappings; the first group the user is in decides the
+    /// role, which is updated on every login
+    #[serde(default)]
+    pub role_mapping: Vec<RoleMapping>,
+    /// Role of users in none of the mapped groups; unset refuses them
+    #[serde(default = "OidcConfig::default_role")]
+    pub default_role: Option<String>,
+    /// Library path of provisioned users, `{username}` is replaced (e.g. "/srv/music/{username}")
+    #[serde(default)]
+    pub library_path_template: Option<String>,
+    /// Text of the login page button
+    #[serde(default = "OidcConfig::default_button_label")]
+    pub button_label: String,
+    #[serde(default = "OidcConfig::default_timeout_secs")]
+    pub timeout_secs: u64,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct RoleMapping {
+    pub group: String,
+    pub role: String,
+}
+
+/// Authentication by a reverse proxy (Authelia, authentik, ...) that sends
+/// the user in request headers; only believed from `security.trusted_proxies`
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ProxyAuthConfig {
+    #[serde(default)]
+    pub enabled: bool,
+    #[serde(default = "ProxyAuthConfig::default_user_header")]
+    pub user_header: String,
+    /// Comma-separated group names
+    #[serde(default = "ProxyAuthConfig::default_groups_header")]
+    pub groups_header: String,
+    /// Create users that don't exist yet
+    #[serde(default = "ProxyAuthConfig::default_true")]
+    pub auto_provision: bool,
+    /// Group to role mappings; the first group the user is in decides the
+    /// role, which is updated on every request
+    #[serde(default)]
+    pub role_mapping: Vec<RoleMapping>,
+    /// Role of users in none of the mapped groups; unset refuses them
+    #[serde(default = "ProxyAuthConfig::default_role")]
+    pub default_role: Option<String>,
+    /// Library path of provisioned users, `{username}` is replaced
+    #[serde(default)]
+    pub library_path_template: Option<String>,
+}
+
+/// Passkey (WebAuthn) login; browsers only offer passkeys to pages on
+/// `rp_id` or a subdomain of it
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct PasskeyConfig {
+    #[serde(default)]
+    pub enabled: bool,
+    /// Domain the passkeys belong to, e.g. "music.example.com"
+    #[serde(default)]
+    pub rp_id: String,
+    /// Name shown by the browser when creating a passkey
+    #[serde(default = "PasskeyConfig::default_rp_name")]
+    pub rp_name: String,
+    /// Origin of the web UI, e.g. "https://music.example.com"
+    #[serde(default)]
+    pub origin: String,
 }
 
 impl Config {
@@ -93,12 +532,16 @@ impl Config {
         // Override with environment variables if present
         config.apply_env_overrides();
 
-        // Auto-generate JWT secret if it's the default placeholder or empty
-        if config.security.jwt_secret.is_empty()
-            || config.security.jwt_secret == "your-secret-key-here-change-this"
-        {
-            tracing::warn!("JWT secret not configured, generating random secret");
-            config.security.jwt_secret = Self::generate_jwt_secret();
+        // Docker secrets: read the JWT secret from a file
+        if let Ok(path) = std::env::var("JWT_SECRET_FILE") {
+            let secret = std::fs::read_to_string(&path)
+                .context(format!("Failed to read JWT_SECRET_FILE at {}", path))?;
+            config.security.jwt_secret = secret.trim().to_string();
+        }
+        if let Ok(path) = std::env::var("OIDC_CLIENT_SECRET_FILE") {
+            let secret = std::fs::read_to_string(&path)
+                .context(format!("Failed to read OIDC_CLIENT_SECRET_FILE at {}", path))?;
+            config.oidc.client_secret = secret.trim().to_string();
         }
 
         // Validate paths exist or can be created
@@ -122,6 +565,9 @@ impl Config {
         if let Ok(jwt_secret) = std::env::var("JWT_SECRET") {
             self.security.jwt_secret = jwt_secret;
         }
+        if let Ok(client_secret) = std::env::var("OIDC_CLIENT_SECRET") {
+            self.oidc.client_secret = client_secret;
+        }
         if let Ok(music_dir) = std::env::var("MUSIC_DIR") {
             self.paths.music_dir = std::path::PathBuf::from(music_dir);
         }
@@ -130,18 +576,6 @@ impl Config {
         }
     }
 
-    fn generate_jwt_secret() -> String {
-        use rand::Rng;
-        const CHARSET: &[u8] = b"ABCDEFGHIJKLMNOPQRSTUVWXYZabcdefghijklmnopqrstuvwxyz0123456789+/";
-        let mut rng = rand::thread_rng();
-        (0..43) // Base64 encoded 32 bytes = 43 characters
-            .map(|_| {
-                let idx = rng.gen_range(0..CHARSET.len());
-                CHARSET[idx] as char
-            })
-            .collect()
-    }
-
     fn validate(&self) -> Result<()> {
         // Create temp_dir if it doesn't exist
         if !self.paths.temp_dir.exists() {
@@ -160,19 +594,88 @@ impl Config {
                 .context("Failed to create music directory")?;
         }
 
+        if self.oidc.enabled {
+            let oidc = &self.oidc;
+            if oidc.issuer_url.is_empty() || oidc.client_id.is_empty() || oidc.redirect_url.is_empty() {
+                anyhow::bail!("[oidc] needs issuer_url, client_id and redirect_url when enabled");
+            }
+        }
+        self.validate_proxy_auth()?;
+        self.validate_passkeys()?;
+        if !self.security.password_login && !self.oidc.enabled && !self.proxy_auth.enabled {
+            tracing::warn!("Password login is disabled and neither single sign-on nor proxy auth is enabled; nobody can log in");
+        }
+
+        Ok(())
+    }
+
+    /// Proxy auth without trusted proxies would let any client pick its user
+    fn validate_proxy_auth(&self) -> Result<()> {
+        if self.proxy_auth.enabled && self.security.trusted_proxies.is_empty() {
+            anyhow::bail!("[proxy_auth] is enabled but [security] trusted_proxies is empty");
+        }
+        Ok(())
+    }
+
+    fn validate_passkeys(&self) -> Result<()> {
+        let passkeys = &self.passkeys;
+        if !passkeys.enabled {
+            return Ok(());
+        }
+        let host = passkeys
+            .origin
+            .strip_prefix("https://")
+            .or_else(|| passkeys.origin.strip_prefix("http://"))
+            .map(|rest| rest.split([':', '/']).next().unwrap_or_default())
+            .unwrap_or_default();
+        let rp_id = passkeys.rp_id.as_str();
+        if rp_id.is_empty() || !(host == rp_id || host.ends_with(&format!(".{}", rp_id))) {
+            anyhow::bail!("[passkeys] needs an origin (\"https://host\") on rp_id or a subdomain of it");
+        }
         Ok(())
     }
 
     pub fn max_file_size_bytes(&self) -> usize {
         (self.upload.max_file_size_mb * 1024 * 1024) as usize
     }
-}
 
-// Database-stored configuration (for runtime editable settings)
-#[derive(Debug, Clone, Serialize, Deserialize, sqlx::FromRow)]
-pub struct DbConfig {
-    pub key: String,
-    pub value: String,
+    pub fn max_archive_size_bytes(&self) -> u64 {
+        self.upload.max_archive_size_mb * 1024 * 1024
+    }
+
+    /// Profile equivalent to the global [youtube] settings (used when no profile is selected)
+    pub fn legacy_youtube_profile(&self) -> DownloadProfile {
+        DownloadProfile {
+            description: "Server default".to_string(),
+            audio_format: self.youtube.audio_format.clone(),
+            audio_quality: None,
+            bitrate: None,
+            format_selector: None,
+            ytdlp_args: Vec::new(),
+            spotdl_args: Vec::new(),
+            embed_metadata: true,
+            embed_thumbnail: true,
+        }
+    }
+
+    /// Profile equivalent to the global [spotify] settings (used when no profile is selected)
+    pub fn legacy_spotify_profile(&self) -> DownloadProfile {
+        DownloadProfile {
+            audio_format: self.spotify.audio_format.clone(),
+            ..self.legacy_youtube_profile()
+        }
+    }
+
+    /// Whether the config grants something to whoever is named `username`:
+    /// an import root listing them, or a drop folder or per-user inbox of theirs
+    pub fn names_user(&self, username: &str) -> bool {
+        let import_root = self.import_roots.values().any(|root| root.users.iter().any(|u| u == username));
+        let drop_folder = self.watch.folders.values().any(|folder| match &folder.user {
+            Some(user) => user == username,
+            None => folder.path.join(username).is_dir(),
+        });
+        import_root || drop_folder
+    }
 }
 
 impl Default for Config {
@@ -195,6 +698,10 @@ impl Default for Config {
             security: SecurityConfig {
                 jwt_secret: "your-secret-key-here-change-this".to_string(),
                 session_timeout_hours: 24,
+                trusted_proxies: Vec::new(),
+                login_throttle: LoginThrottleConfig::default(),
+                require_admin_2fa: false,
+                password_login: true,
             },
             upload: UploadConfig {
                 max_file_size_mb: 500,
@@ -207,6 +714,8 @@ impl Default for Config {
                     "wav".to_string(),
                     "aac".to_string(),
                 ],
+                max_archive_entries: UploadConfig::default_max_archive_entries(),
+                max_archive_size_mb: UploadConfig::default_max_archive_size_mb(),
             },
             youtube: YoutubeConfig {
                 enabled: true,
@@ -217,10 +726,35 @@ impl Default for Config {
                 extra_args: Vec::new(),
             },
             spotify: SpotifyConfig::default(),
+            url_import: UrlImportConfig::default(),
+            import_roots: BTreeMap::new(),
+            watch: WatchConfig::default(),
+            ffmpeg: FfmpegConfig::default(),
+            profiles: BTreeMap::new(),
+            transcode: TranscodeConfig::default(),
+            loudness: LoudnessConfig::default(),
+            validation: ValidationConfig::default(),
+            spectral: SpectralConfig::default(),
+            duplicates: DuplicatesConfig::default(),
+            cover_art: CoverArtConfig::default(),
+            lyrics: LyricsConfig::default(),
+            oidc: OidcConfig::default(),
+            proxy_auth: ProxyAuthConfig::default(),
+            passkeys: PasskeyConfig::default(),
         }
     }
 }
 
+impl UploadConfig {
+    fn default_max_archive_entries() -> usize {
+        1000
+    }
+
+    fn default_max_archive_size_mb() -> u64 {
+        4096
+    }
+}
+
 impl YoutubeConfig {
     fn default_format_selector() -> String {
         "bestaudio/best".to_string()
@@ -240,6 +774,9 @@ impl Default for SpotifyConfig {
             client_id: String::new(),
             client_secret: String::new(),
             redirect_uri: Self::default_redirect_uri(),
+            youtube_fallback: false,
+            fallback_duration_tolerance_secs: Self::default_fallback_duration_tolerance_secs(),
+            fallback_search_results: Self::default_fallback_search_results(),
         }
     }
 }
@@ -256,4 +793,412 @@ impl SpotifyConfig {
     fn default_redirect_uri() -> String {
         "http://localhost:8080/api/spotify/callback".to_string()
     }
+
+    fn default_fallback_duration_tolerance_secs() -> u64 {
+        10
+    }
+
+    fn default_fallback_search_results() -> u32 {
+        5
+    }
+}
+
+impl DownloadProfile {
+    fn default_true() -> bool {
+        true
+    }
+}
+
+impl ImportRoot {
+    fn default_true() -> bool {
+        true
+    }
+
+    /// Whether a user (who has the server_import permission) may use this root
+    pub fn allows(&self, username: &str, manages_config: bool) -> bool {
+        manages_config || self.users.is_empty() || self.users.iter().any(|u| u == username)
+    }
+}
+
+impl Default for UrlImportConfig {
+    fn default() -> Self {
+        Self {
+            enabled: true,
+            max_size_mb: None,
+            max_redirects: Self::default_max_redirects(),
+            timeout_secs: Self::default_timeout_secs(),
+            allow_private_addresses: false,
+        }
+    }
+}
+
+impl UrlImportConfig {
+    fn default_true() -> bool {
+        true
+    }
+
+    fn default_max_redirects() -> usize {
+        5
+    }
+
+    fn default_timeout_secs() -> u64 {
+        600
+    }
+}
+
+impl Default for FfmpegConfig {
+    fn default() -> Self {
+        Self {
+            ffmpeg_path: Self::default_ffmpeg_path(),
+            ffprobe_path: Self::default_ffprobe_path(),
+        }
+    }
+}
+
+impl FfmpegConfig {
+    fn default_ffmpeg_path() -> String {
+        "ffmpeg".to_string()
+    }
+
+    fn default_ffprobe_path() -> String {
+        "ffprobe".to_string()
+    }
+}
+
+impl Default for TranscodeConfig {
+    fn default() -> Self {
+        Self {
+            enabled: false,
+            keep_originals: false,
+            originals_dir: None,
+            rules: Self::default_rules(),
+        }
+    }
+}
+
+impl TranscodeConfig {
+    /// wav/aiff → flac, alac → flac, everything else is kept
+    fn default_rules() -> Vec<TranscodeRule> {
+        let rule = |from: &str, to: &str| TranscodeRule {
+            from: vec![from.to_string()],
+            extensions: Vec::new(),
+            min_bitrate_kbps: None,
+            max_bitrate_kbps: None,
+            to: to.to_string(),
+            bitrate_kbps: None,
+        };
+        vec![rule("pcm", "flac"), rule("alac", "flac"), rule("lossy", "keep")]
+    }
+}
+
+impl Default for LoudnessConfig {
+    fn default() -> Self {
+        Self {
+            enabled: false,
+            reference_lufs: Self::default_reference_lufs(),
+            write_tags: true,
+            album_gain: true,
+        }
+    }
+}
+
+impl LoudnessConfig {
+    fn default_reference_lufs() -> f64 {
+        -18.0
+    }
+
+    fn default_true() -> bool {
+        true
+    }
+}
+
+impl Default for ValidationConfig {
+    fn default() -> Self {
+        Self {
+            enabled: true,
+            full_decode: true,
+        }
+    }
+}
+
+impl ValidationConfig {
+    fn default_true() -> bool {
+        true
+    }
+}
+
+impl Default for SpectralConfig {
+    fn default() -> Self {
+        Self {
+            enabled: false,
+            threshold: Self::default_threshold(),
+            quarantine: false,
+            quarantine_dir: None,
+            max_analysis_secs: Self::default_max_analysis_secs(),
+        }
+    }
+}
+
+impl SpectralConfig {
+    fn default_threshold() -> f64 {
+        0.6
+    }
+
+    fn default_max_analysis_secs() -> u32 {
+        120
+    }
+}
+
+impl Default for DuplicatesConfig {
+    fn default() -> Self {
+        Self {
+            enabled: false,
+            similarity_threshold: Self::default_similarity_threshold(),
+            on_duplicate: DuplicateAction::default(),
+            max_fingerprint_secs: Self::default_max_fingerprint_secs(),
+        }
+    }
+}
+
+impl DuplicatesConfig {
+    fn default_similarity_threshold() -> f64 {
+        0.85
+    }
+
+    fn default_max_fingerprint_secs() -> u32 {
+        120
+    }
+}
+
+impl Default for CoverArtConfig {
+    fn default() -> Self {
+        Self {
+            enabled: true,
+            max_dimension: Self::default_max_dimension(),
+            jpeg_quality: Self::default_jpeg_quality(),
+            folder_file_name: Self::default_folder_file_name(),
+        }
+    }
+}
+
+impl CoverArtConfig {
+    fn default_true() -> bool {
+        true
+    }
+
+    fn default_max_dimension() -> u32 {
+        1200
+    }
+
+    fn default_jpeg_quality() -> u8 {
+        90
+    }
+
+    fn default_folder_file_name() -> String {
+        "cover.jpg".to_string()
+    }
+}
+
+impl Default for LyricsConfig {
+    fn default() -> Self {
+        Self {
+            fetch: false,
+            base_url: Self::default_base_url(),
+            output: LyricsOutput::default(),
+            timeout_secs: Self::default_timeout_secs(),
+        }
+    }
+}
+
+impl Default for WatchConfig {
+    fn default() -> Self {
+        Self {
+            enabled: false,
+            stable_secs: Self::default_stable_secs(),
+            poll_interval_secs: Self::default_poll_interval_secs(),
+            force_polling: false,
+            folders: BTreeMap::new(),
+        }
+    }
+}
+
+impl Default for LoginThrottleConfig {
+    fn default() -> Self {
+        Self {
+            max_account_failures: Self::default_max_account_failures(),
+            max_ip_failures: Self::default_max_ip_failures(),
+            lockout_minutes: Self::default_lockout_minutes(),
+            max_backoff_secs: Self::default_max_backoff_secs(),
+        }
+    }
+}
+
+impl LoginThrottleConfig {
+    fn default_max_account_failures() -> u32 {
+        5
+    }
+
+    fn default_max_ip_failures() -> u32 {
+        20
+    }
+
+    fn default_lockout_minutes() -> i64 {
+        15
+    }
+
+    fn default_max_backoff_secs() -> i64 {
+        30
+    }
+}
+
+impl WatchConfig {
+    fn default_stable_secs() -> u64 {
+        30
+    }
+
+    fn default_poll_interval_secs() -> u64 {
+        300
+    }
+}
+
+impl LyricsConfig {
+    fn default_base_url() -> String {
+        "https://lrclib.net".to_string()
+    }
+
+    fn default_timeout_secs() -> u64 {
+        10
+    }
+}
+
+impl Default for OidcConfig {
+    fn default() -> Self {
+        Self {
+            enabled: false,
+            issuer_url: String::new(),
+            client_id: String::new(),
+            client_secret: String::new(),
+            redirect_url: String::new(),
+            scopes: Self::default_scopes(),
+            username_claim: Self::default_username_claim(),
+            match_claim: None,
+            auto_provision: true,
+            groups_claim: Self::default_groups_claim(),
+            role_mapping: Vec::new(),
+            default_role: Self::default_role(),
+            library_path_template: None,
+            button_label: Self::default_button_label(),
+            timeout_secs: Self::default_timeout_secs(),
+        }
+    }
+}
+
+impl OidcConfig {
+    fn default_true() -> bool {
+        true
+    }
+
+    fn default_scopes() -> Vec<String> {
+        vec!["openid".to_string(), "profile".to_string(), "email".to_string()]
+    }
+
+    fn default_username_claim() -> String {
+        "preferred_username".to_string()
+    }
+
+    fn default_groups_claim() -> String {
+        "groups".to_string()
+    }
+
+    fn default_role() -> Option<String> {
+        Some("user".to_string())
+    }
+
+    fn default_button_label() -> String {
+        "Log in with single sign-on".to_string()
+    }
+
+    fn default_timeout_secs() -> u64 {
+        10
+    }
+}
+
+impl Default for ProxyAuthConfig {
+    fn default() -> Self {
+        Self {
+            enabled: false,
+            user_header: Self::default_user_header(),
+            groups_header: Self::default_groups_header(),
+            auto_provision: true,
+            role_mapping: Vec::new(),
+            default_role: Self::default_role(),
+            library_path_template: None,
+        }
+    }
+}
+
+impl ProxyAuthConfig {
+    fn default_true() -> bool {
+        true
+    }
+
+    fn default_user_header() -> String {
+        "Remote-User".to_string()
+    }
+
+    fn default_groups_header() -> String {
+        "Remote-Groups".to_string()
+    }
+
+    fn default_role() -> Option<String> {
+        Some("user".to_string())
+    }
+}
+
+impl Default for PasskeyConfig {
+    fn default() -> Self {
+        Self {
+            enabled: false,
+            rp_id: String::new(),
+            rp_name: Self::default_rp_name(),
+            origin: String::new(),
+        }
+    }
+}
+
+impl PasskeyConfig {
+    fn default_rp_name() -> String {
+        "Music Upload".to_string()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn proxy_auth_needs_trusted_proxies() {
+        let mut config = Config::default();
+        config.proxy_auth.enabled = true;
+        assert!(config.validate_proxy_auth().is_err());
+
+        config.security.trusted_proxies = vec!["10.0.0.0/8".parse().unwrap()];
+        assert!(config.validate_proxy_auth().is_ok());
+    }
+
+    #[test]
+    fn passkey_origin_must_match_rp_id() {
+        let mut config = Config::default();
+        config.passkeys.enabled = true;
+        config.passkeys.rp_id = "example.com".to_string();
+        for (origin, ok) in [
+            ("https://example.com", true),
+            ("https://music.example.com:8443/", true),
+            ("https://badexample.com", false),
+            ("example.com", false),
+        ] {
+            config.passkeys.origin = origin.to_string();
+            assert_eq!(config.validate_passkeys().is_ok(), ok, "{}", origin);
+        }
+    }
 }
@@ -1,3 +1,4 @@
+use crate::auth::{ClientInfo, Permission};
 use crate::models::*;
 use anyhow::{Context, Result};
 use argon2::{
@@ -42,14 +43,14 @@ impl Database {
 
         sqlx::query(
             r#"
-            INSERT INTO users (id, username, password_hash, is_admin, library_path)
+            INSERT INTO users (id, username, password_hash, role, library_path)
             VALUES (?, ?, ?, ?, ?)
             "#,
         )
         .bind(&id)
         .bind(&user.username)
         .bind(&password_hash)
-        .bind(user.is_admin)
+        .bind(&user.role)
         .bind(&user.library_path)
         .execute(&self.pool)
         .await
@@ -61,7 +62,9 @@ impl Database {
     pub async fn get_user_by_id(&self, id: &str) -> Result<User> {
         let user = sqlx::query_as::<_, User>(
             r#"
-            SELECT id, username, password_hash, is_admin, library_path, created_at, updated_at
+            SELECT id, username, password_hash, role, library_path, default_profile, created_at, updated_at,
+                EXISTS(SELECT 1 FROM user_totp t WHERE t.user_id = users.id AND t.enabled) AS totp_enabled,
+                EXISTS(SELECT 1 FROM passkeys p WHERE p.user_id = users.id) AS passkeys_enabled
             FROM users
             WHERE id = ?
             "#,
@@ -77,7 +80,9 @@ impl Database {
     pub async fn get_user_by_username(&self, username: &str) -> Result<User> {
         let user = sqlx::query_as::<_, User>(
             r#"
-            SELECT id, username, password_hash, is_admin, library_path, created_at, updated_at
+            SELECT id, username, password_hash, role, library_path, default_profile, created_at, updated_at,
+                EXISTS(SELECT 1 FROM user_totp t WHERE t.user_id = users.id AND t.enabled) AS totp_enabled,
+                EXISTS(SELECT 1 FROM passkeys p WHERE p.user_id = users.id) AS passkeys_enabled
             FROM users
             WHERE username = ?
             "#,
@@ -93,7 +98,9 @@ impl Database {
     pub async fn list_users(&self) -> Result<Vec<User>> {
         let users = sqlx::query_as::<_, User>(
             r#"
-            SELECT id, username, password_hash, is_admin, library_path, created_at, updated_at
+            SELECT id, username, password_hash, role, library_path, default_profile, created_at, updated_at,
+                EXISTS(SELECT 1 FROM user_totp t WHERE t.user_id = users.id AND t.enabled) AS totp_enabled,
+                EXISTS(SELECT 1 FROM passkeys p WHERE p.user_id = users.id) AS passkeys_enabled
             FROM users
             ORDER BY created_at DESC
             "#,
@@ -155,6 +162,882 @@ impl Database {
         Ok(())
     }
 
+    pub async fn update_user_role(&self, user_id: &str, role: &str) -> Result<()> {
+        sqlx::query(
+            r#"
+            UPDATE users SET role = ? WHERE id = ?
+            "#,
+        )
+        .bind(role)
+        .bind(user_id)
+        .execute(&self.pool)
+        .await
+        .context("Failed to update role")?;
+
+        Ok(())
+    }
+
+    // Role operations
+    pub async fn list_roles(&self) -> Result<Vec<Role>> {
+        let rows = sqlx::query(
+            r#"
+            SELECT r.name, r.description, p.permission
+            FROM roles r
+            LEFT JOIN role_permissions p ON p.role = r.name
+            ORDER BY r.name
+            "#,
+        )
+        .fetch_all(&self.pool)
+        .await
+        .context("Failed to list roles")?;
+
+        let mut roles: Vec<Role> = Vec::new();
+        for row in rows {
+            let name: String = row.get("name");
+            if roles.last().map(|r| r.name != name).unwrap_or(true) {
+                roles.push(Role {
+                    name,
+                    description: row.get("description"),
+                    permissions: Vec::new(),
+                });
+            }
+            let permission: Option<String> = row.get("permission");
+            if let Some(permission) = permission.as_deref().and_then(Permission::parse) {
+                roles.last_mut().expect("role was just pushed").permissions.push(permission);
+            }
+        }
+        for role in &mut roles {
+            role.permissions.sort();
+        }
+
+        Ok(roles)
+    }
+
+    pub async fn role_exists(&self, name: &str) -> Result<bool> {
+        let row = sqlx::query("SELECT 1 FROM roles WHERE name = ?")
+            .bind(name)
+            .fetch_optional(&self.pool)
+            .await
+            .context("Failed to look up role")?;
+
+        Ok(row.is_some())
+    }
+
+    /// Permissions of a role; unknown roles and permission names grant nothing
+    pub async fn get_role_permissions(&self, role: &str) -> Result<Vec<Permission>> {
+        let rows = sqlx::query("SELECT permission FROM role_permissions WHERE role = ?")
+            .bind(role)
+            .fetch_all(&self.pool)
+            .await
+            .context("Failed to load role permissions")?;
+
+        Ok(rows
+            .iter()
+            .filter_map(|row| Permission::parse(row.get::<&str, _>("permission")))
+            .collect())
+    }
+
+    /// Create a role or replace its description and permissions
+    pub async fn save_role(&self, role: &Role) -> Result<()> {
+        let mut tx = self.pool.begin().await?;
+
+        sqlx::query(
+            r#"
+            INSERT INTO roles (name, description)
+            VALUES (?, ?)
+            ON CONFLICT(name) DO UPDATE SET description = excluded.description
+            "#,
+        )
+        .bind(&role.name)
+        .bind(&role.description)
+        .execute(&mut *tx)
+        .await
+        .context("Failed to save role")?;
+
+        sqlx::query("DELETE FROM role_permissions WHERE role = ?")
+            .bind(&role.name)
+            .execute(&mut *tx)
+            .await?;
+        for permission in &role.permissions {
+            sqlx::query("INSERT OR IGNORE INTO role_permissions (role, permission) VALUES (?, ?)")
+                .bind(&role.name)
+                .bind(permission.as_str())
+                .execute(&mut *tx)
+                .await?;
+        }
+
+        tx.commit().await.context("Failed to save role")?;
+        Ok(())
+    }
+
+    /// Delete a role; fails while users still have it
+    pub async fn delete_role(&self, name: &str) -> Result<()> {
+        let (in_use,): (i64,) = sqlx::query_as("SELECT COUNT(*) FROM users WHERE role = ?")
+            .bind(name)
+            .fetch_one(&self.pool)
+            .await?;
+        if in_use > 0 {
+            anyhow::bail!("Role {} is assigned to {} user(s)", name, in_use);
+        }
+
+        sqlx::query("DELETE FROM roles WHERE name = ?")
+            .bind(name)
+            .execute(&self.pool)
+            .await
+            .context("Failed to delete role")?;
+
+        Ok(())
+    }
+
+    // Session operations
+    pub async fn create_session(&self, user_id: &str, client: &ClientInfo, expires_at: i64) -> Result<String> {
+        let id = Uuid::new_v4().to_string();
+
+        sqlx::query(
+            r#"
+            INSERT INTO sessions (id, user_id, ip_address, user_agent, expires_at)
+            VALUES (?, ?, ?, ?, ?)
+            "#,
+        )
+        .bind(&id)
+        .bind(user_id)
+        .bind(&client.ip)
+        .bind(&client.user_agent)
+        .bind(expires_at)
+        .execute(&self.pool)
+        .await
+        .context("Failed to create session")?;
+
+        Ok(id)
+    }
+
+    /// Mark a session as seen; false if it was revoked or has expired
+    pub async fn touch_session(&self, id: &str, user_id: &str) -> Result<bool> {
+        let result = sqlx::query(
+            r#"
+            UPDATE sessions SET last_seen_at = CURRENT_TIMESTAMP
+            WHERE id = ? AND user_id = ? AND expires_at > ?
+            "#,
+        )
+        .bind(id)
+        .bind(user_id)
+        .bind(chrono::Utc::now().timestamp())
+        .execute(&self.pool)
+        .await
+        .context("Failed to update session")?;
+
+        Ok(result.rows_affected() > 0)
+    }
+
+    pub async fn list_sessions(&self, user_id: &str) -> Result<Vec<Session>> {
+        let sessions = sqlx::query_as::<_, Session>(
+            r#"
+            SELECT id, user_id, ip_address, user_agent, created_at, last_seen_at, expires_at
+            FROM sessions
+            WHERE user_id = ? AND expires_at > ?
+            ORDER BY last_seen_at DESC
+            "#,
+        )
+        .bind(user_id)
+        .bind(chrono::Utc::now().timestamp())
+        .fetch_all(&self.pool)
+        .await
+        .context("Failed to list sessions")?;
+
+        Ok(sessions)
+    }
+
+    /// Revoke one of a user's sessions; false if there was no such session
+    pub async fn delete_session(&self, user_id: &str, id: &str) -> Result<bool> {
+        let result = sqlx::query("DELETE FROM sessions WHERE id = ? AND user_id = ?")
+            .bind(id)
+            .bind(user_id)
+            .execute(&self.pool)
+            .await
+            .context("Failed to delete session")?;
+
+        Ok(result.rows_affected() > 0)
+    }
+
+    /// Revoke all of a user's sessions, except `keep` if given
+    pub async fn delete_user_sessions(&self, user_id: &str, keep: Option<&str>) -> Result<u64> {
+        let result = sqlx::query("DELETE FROM sessions WHERE user_id = ? AND id IS NOT ?")
+            .bind(user_id)
+            .bind(keep)
+            .execute(&self.pool)
+            .await
+            .context("Failed to delete sessions")?;
+
+        Ok(result.rows_affected())
+    }
+
+    pub async fn delete_expired_sessions(&self) -> Result<()> {
+        sqlx::query("DELETE FROM sessions WHERE expires_at <= ?")
+            .bind(chrono::Utc::now().timestamp())
+            .execute(&self.pool)
+            .await
+            .context("Failed to delete expired sessions")?;
+
+        Ok(())
+    }
+
+    // API token operations
+    pub async fn create_api_token(
+        &self,
+        user_id: &str,
+        name: &str,
+        token_hash: &str,
+        prefix: &str,
+        scopes: Option<&[Permission]>,
+        expires_at: Option<i64>,
+    ) -> Result<String> {
+        let id = Uuid::new_v4().to_string();
+        let scopes = scopes.map(|s| s.iter().map(|p| p.as_str()).collect::<Vec<_>>().join(" "));
+
+        sqlx::query(
+            r#"
+            INSERT INTO api_tokens (id, user_id, name, token_hash, prefix, scopes, expires_at)
+            VALUES (?, ?, ?, ?, ?, ?, ?)
+            "#,
+        )
+        .bind(&id)
+        .bind(user_id)
+        .bind(name)
+        .bind(token_hash)
+        .bind(prefix)
+        .bind(scopes)
+        .bind(expires_at)
+        .execute(&self.pool)
+        .await
+        .context("Failed to create API token")?;
+
+        Ok(id)
+    }
+
+    /// Tokens of one user, or of everyone
+    pub async fn list_api_tokens(&self, user_id: Option<&str>) -> Result<Vec<ApiToken>> {
+        let rows = sqlx::query(
+            r#"
+            SELECT t.id, t.user_id, u.username, t.name, t.prefix, t.scopes, t.expires_at, t.created_at, t.last_used_at
+            FROM api_tokens t
+            JOIN users u ON u.id = t.user_id
+            WHERE ? IS NULL OR t.user_id = ?
+            ORDER BY t.created_at DESC
+            "#,
+        )
+        .bind(user_id)
+        .bind(user_id)
+        .fetch_all(&self.pool)
+        .await
+        .context("Failed to list API tokens")?;
+
+        Ok(rows.iter().map(api_token_from_row).collect())
+    }
+
+    /// Look up an unexpired token by hash and record that it was used
+    pub async fn use_api_token(&self, token_hash: &str) -> Result<Option<ApiToken>> {
+        let row = sqlx::query(
+            r#"
+            UPDATE api_tokens SET last_used_at = CURRENT_TIMESTAMP
+            WHERE token_hash = ? AND (expires_at IS NULL OR expires_at > ?)
+            RETURNING id, user_id, '' AS username, name, prefix, scopes, expires_at, created_at, last_used_at
+            "#,
+        )
+        .bind(token_hash)
+        .bind(chrono::Utc::now().timestamp())
+        .fetch_optional(&self.pool)
+        .await
+        .context("Failed to look up API token")?;
+
+        Ok(row.as_ref().map(api_token_from_row))
+    }
+
+    /// Revoke a token, only if it belongs to `user_id` when given; false if there was none
+    pub async fn delete_api_token(&self, id: &str, user_id: Option<&str>) -> Result<bool> {
+        let result = sqlx::query("DELETE FROM api_tokens WHERE id = ? AND (? IS NULL OR user_id = ?)")
+            .bind(id)
+            .bind(user_id)
+            .bind(user_id)
+            .execute(&self.pool)
+            .await
+            .context("Failed to delete API token")?;
+
+        Ok(result.rows_affected() > 0)
+    }
+
+    // TOTP operations
+    pub async fn get_user_totp(&self, user_id: &str) -> Result<Option<UserTotp>> {
+        let totp = sqlx::query_as::<_, UserTotp>(
+            "SELECT user_id, secret, enabled, last_step FROM user_totp WHERE user_id = ?",
+        )
+        .bind(user_id)
+        .fetch_optional(&self.pool)
+        .await
+        .context("Failed to load TOTP settings")?;
+
+        Ok(totp)
+    }
+
+    /// Store a new, not yet enabled secret (replacing an unfinished enrollment)
+    pub async fn start_totp_enrollment(&self, user_id: &str, secret: &str) -> Result<()> {
+        sqlx::query(
+            r#"
+            INSERT INTO user_totp (user_id, secret) VALUES (?, ?)
+            ON CONFLICT(user_id) DO UPDATE SET secret = excluded.secret, enabled = 0, last_step = NULL
+            "#,
+        )
+        .bind(user_id)
+        .bind(secret)
+        .execute(&self.pool)
+        .await
+        .context("Failed to start TOTP enrollment")?;
+
+        Ok(())
+    }
+
+    /// Finish enrollment with the step of the verified code and new recovery codes
+    pub async fn enable_totp(&self, user_id: &str, step: i64, code_hashes: &[String]) -> Result<()> {
+        let mut tx = self.pool.begin().await?;
+
+        sqlx::query("UPDATE user_totp SET enabled = 1, last_step = ? WHERE user_id = ?")
+            .bind(step)
+            .bind(user_id)
+            .execute(&mut *tx)
+            .await
+            .context("Failed to enable TOTP")?;
+
+        replace_recovery_codes(&mut tx, user_id, code_hashes).await?;
+
+        tx.commit().await?;
+        Ok(())
+    }
+
+    /// Record the step of an accepted code; false if it (or a later one) was already used
+    pub async fn use_totp_step(&self, user_id: &str, step: i64) -> Result<bool> {
+        let result = sqlx::query(
+            "UPDATE user_totp SET last_step = ? WHERE user_id = ? AND (last_step IS NULL OR last_step < ?)",
+        )
+        .bind(step)
+        .bind(user_id)
+        .bind(step)
+        .execute(&self.pool)
+        .await
+        .context("Failed to record TOTP use")?;
+
+        Ok(result.rows_affected() > 0)
+    }
+
+    pub async fn set_recovery_codes(&self, user_id: &str, code_hashes: &[String]) -> Result<()> {
+        let mut tx = self.pool.begin().await?;
+        replace_recovery_codes(&mut tx, user_id, code_hashes).await?;
+        tx.commit().await?;
+        Ok(())
+    }
+
+    /// Spend a recovery code; false if it doesn't exist or was used
+    pub async fn use_recovery_code(&self, user_id: &str, code_hash: &str) -> Result<bool> {
+        let result = sqlx::query(
+            r#"
+            UPDATE totp_recovery_codes SET used_at = CURRENT_TIMESTAMP
+            WHERE user_id = ? AND code_hash = ? AND used_at IS NULL
+            "#,
+        )
+        .bind(user_id)
+        .bind(code_hash)
+        .execute(&self.pool)
+        .await
+        .context("Failed to use recovery code")?;
+
+        Ok(result.rows_affected() > 0)
+    }
+
+    pub async fn count_recovery_codes(&self, user_id: &str) -> Result<i64> {
+        let count: (i64,) =
+            sqlx::query_as("SELECT COUNT(*) FROM totp_recovery_codes WHERE user_id = ? AND used_at IS NULL")
+                .bind(user_id)
+                .fetch_one(&self.pool)
+                .await
+                .context("Failed to count recovery codes")?;
+
+        Ok(count.0)
+    }
+
+    /// Turn 2FA off, dropping the secret and recovery codes
+    pub async fn delete_user_totp(&self, user_id: &str) -> Result<()> {
+        let mut tx = self.pool.begin().await?;
+
+        sqlx::query("DELETE FROM user_totp WHERE user_id = ?")
+            .bind(user_id)
+            .execute(&mut *tx)
+            .await
+            .context("Failed to delete TOTP settings")?;
+        sqlx::query("DELETE FROM totp_recovery_codes WHERE user_id = ?")
+            .bind(user_id)
+            .execute(&mut *tx)
+            .await
+            .context("Failed to delete recovery codes")?;
+
+        tx.commit().await?;
+        Ok(())
+    }
+
+    // Second-factor login challenges
+    pub async fn create_login_challenge(&self, user_id: &str, expires_at: i64) -> Result<String> {
+        let id = Uuid::new_v4().to_string();
+
+        sqlx::query("INSERT INTO login_challenges (id, user_id, expires_at) VALUES (?, ?, ?)")
+            .bind(&id)
+            .bind(user_id)
+            .bind(expires_at)
+            .execute(&self.pool)
+            .await
+            .context("Failed to create login challenge")?;
+
+        Ok(id)
+    }
+
+    /// User of an unexpired challenge, without counting an attempt
+    pub async fn get_login_challenge_user(&self, id: &str) -> Result<Option<String>> {
+        let user_id = sqlx::query_scalar("SELECT user_id FROM login_challenges WHERE id = ? AND expires_at > ?")
+            .bind(id)
+            .bind(chrono::Utc::now().timestamp())
+            .fetch_optional(&self.pool)
+            .await
+            .context("Failed to load login challenge")?;
+
+        Ok(user_id)
+    }
+
+    /// Count an attempt on an unexpired challenge; returns its user and the attempts so far
+    pub async fn attempt_login_challenge(&self, id: &str) -> Result<Option<(String, i64)>> {
+        let row = sqlx::query(
+            r#"
+            UPDATE login_challenges SET attempts = attempts + 1
+            WHERE id = ? AND expires_at > ?
+            RETURNING user_id, attempts
+            "#,
+        )
+        .bind(id)
+        .bind(chrono::Utc::now().timestamp())
+        .fetch_optional(&self.pool)
+        .await
+        .context("Failed to load login challenge")?;
+
+        Ok(row.map(|row| (row.get("user_id"), row.get("attempts"))))
+    }
+
+    /// Remove a finished challenge, and any expired ones
+    pub async fn delete_login_challenge(&self, id: &str) -> Result<()> {
+        sqlx::query("DELETE FROM login_challenges WHERE id = ? OR expires_at <= ?")
+            .bind(id)
+            .bind(chrono::Utc::now().timestamp())
+            .execute(&self.pool)
+            .await
+            .context("Failed to delete login challenge")?;
+
+        Ok(())
+    }
+
+    // Passkeys
+    pub async fn create_passkey(
+        &self,
+        user_id: &str,
+        name: &str,
+        credential_id: &str,
+        public_key: &[u8],
+        sign_count: i64,
+    ) -> Result<String> {
+        let id = Uuid::new_v4().to_string();
+
+        sqlx::query(
+            r#"
+            INSERT INTO passkeys (id, user_id, name, credential_id, public_key, sign_count)
+            VALUES (?, ?, ?, ?, ?, ?)
+            "#,
+        )
+        .bind(&id)
+        .bind(user_id)
+        .bind(name)
+        .bind(credential_id)
+        .bind(public_key)
+        .bind(sign_count)
+        .execute(&self.pool)
+        .await
+        .context("Failed to save passkey")?;
+
+        Ok(id)
+    }
+
+    pub async fn list_passkeys(&self, user_id: &str) -> Result<Vec<Passkey>> {
+        let passkeys = sqlx::query_as::<_, Passkey>(
+            r#"
+            SELECT id, user_id, name, credential_id, public_key, sign_count, created_at, last_used_at
+            FROM passkeys
+            WHERE user_id = ?
+            ORDER BY created_at
+            "#,
+        )
+        .bind(user_id)
+        .fetch_all(&self.pool)
+        .await
+        .context("Failed to list passkeys")?;
+
+        Ok(passkeys)
+    }
+
+    pub async fn get_passkey_by_credential_id(&self, credential_id: &str) -> Result<Option<Passkey>> {
+        let passkey = sqlx::query_as::<_, Passkey>(
+            r#"
+            SELECT id, user_id, name, credential_id, public_key, sign_count, created_at, last_used_at
+            FROM passkeys
+            WHERE credential_id = ?
+            "#,
+        )
+        .bind(credential_id)
+        .fetch_optional(&self.pool)
+        .await
+        .context("Failed to load passkey")?;
+
+        Ok(passkey)
+    }
+
+    /// Record a login with the passkey and its new signature counter
+    pub async fn use_passkey(&self, id: &str, sign_count: i64) -> Result<()> {
+        sqlx::query("UPDATE passkeys SET sign_count = ?, last_used_at = CURRENT_TIMESTAMP WHERE id = ?")
+            .bind(sign_count)
+            .bind(id)
+            .execute(&self.pool)
+            .await
+            .context("Failed to update passkey")?;
+
+        Ok(())
+    }
+
+    /// False if `user_id` has no such passkey
+    pub async fn rename_passkey(&self, id: &str, user_id: &str, name: &str) -> Result<bool> {
+        let result = sqlx::query("UPDATE passkeys SET name = ? WHERE id = ? AND user_id = ?")
+            .bind(name)
+            .bind(id)
+            .bind(user_id)
+            .execute(&self.pool)
+            .await
+            .context("Failed to rename passkey")?;
+
+        Ok(result.rows_affected() > 0)
+    }
+
+    /// False if `user_id` has no such passkey
+    pub async fn delete_passkey(&self, id: &str, user_id: &str) -> Result<bool> {
+        let result = sqlx::query("DELETE FROM passkeys WHERE id = ? AND user_id = ?")
+            .bind(id)
+            .bind(user_id)
+            .execute(&self.pool)
+            .await
+            .context("Failed to delete passkey")?;
+
+        Ok(result.rows_affected() > 0)
+    }
+
+    pub async fn delete_user_passkeys(&self, user_id: &str) -> Result<()> {
+        sqlx::query("DELETE FROM passkeys WHERE user_id = ?")
+            .bind(user_id)
+            .execute(&self.pool)
+            .await
+            .context("Failed to delete passkeys")?;
+
+        Ok(())
+    }
+
+    /// Remember a registration or login challenge, dropping expired ones
+    pub async fn create_passkey_challenge(
+        &self,
+        challenge: &str,
+        purpose: &str,
+        user_id: Option<&str>,
+        expires_at: i64,
+    ) -> Result<()> {
+        sqlx::query("DELETE FROM passkey_challenges WHERE expires_at <= ?")
+            .bind(chrono::Utc::now().timestamp())
+            .execute(&self.pool)
+            .await
+            .context("Failed to delete expired passkey challenges")?;
+        sqlx::query("INSERT INTO passkey_challenges (challenge, purpose, user_id, expires_at) VALUES (?, ?, ?, ?)")
+            .bind(challenge)
+            .bind(purpose)
+            .bind(user_id)
+            .bind(expires_at)
+            .execute(&self.pool)
+            .await
+            .context("Failed to save passkey challenge")?;
+
+        Ok(())
+    }
+
+    /// Use up an unexpired challenge of `purpose`; `Some` holds the user it was made for, if any
+    pub async fn take_passkey_challenge(&self, challenge: &str, purpose: &str) -> Result<Option<Option<String>>> {
+        let row = sqlx::query(
+            r#"
+            DELETE FROM passkey_challenges
+            WHERE challenge = ? AND purpose = ? AND expires_at > ?
+            RETURNING user_id
+            "#,
+        )
+        .bind(challenge)
+        .bind(purpose)
+        .bind(chrono::Utc::now().timestamp())
+        .fetch_optional(&self.pool)
+        .await
+        .context("Failed to load passkey challenge")?;
+
+        Ok(row.map(|row| row.get("user_id")))
+    }
+
+    // Single sign-on
+    pub async fn get_user_by_oidc_subject(&self, subject: &str) -> Result<Option<User>> {
+        let user = sqlx::query_as::<_, User>(
+            r#"
+            SELECT id, username, password_hash, role, library_path, default_profile, created_at, updated_at,
+                EXISTS(SELECT 1 FROM user_totp t WHERE t.user_id = users.id AND t.enabled) AS totp_enabled,
+                EXISTS(SELECT 1 FROM passkeys p WHERE p.user_id = users.id) AS passkeys_enabled
+            FROM users
+            WHERE oidc_subject = ?
+            "#,
+        )
+        .bind(subject)
+        .fetch_optional(&self.pool)
+        .await
+        .context("Failed to load user")?;
+
+        Ok(user)
+    }
+
+    /// Link a user to an identity provider account; false if it is already linked to another one
+    pub async fn set_oidc_subject(&self, user_id: &str, subject: &str) -> Result<bool> {
+        let result = sqlx::query("UPDATE users SET oidc_subject = ? WHERE id = ? AND oidc_subject IS NULL")
+            .bind(subject)
+            .bind(user_id)
+            .execute(&self.pool)
+            .await
+            .context("Failed to link user")?;
+
+        Ok(result.rows_affected() > 0)
+    }
+
+    /// Remember a login sent to the identity provider; expired ones are dropped
+    pub async fn create_oidc_login(&self, state: &str, nonce: &str, pkce_verifier: &str, expires_at: i64) -> Result<()> {
+        sqlx::query("DELETE FROM oidc_logins WHERE expires_at <= ?")
+            .bind(chrono::Utc::now().timestamp())
+            .execute(&self.pool)
+            .await
+            .context("Failed to clean up pending logins")?;
+
+        sqlx::query("INSERT INTO oidc_logins (state, nonce, pkce_verifier, expires_at) VALUES (?, ?, ?, ?)")
+            .bind(state)
+            .bind(nonce)
+            .bind(pkce_verifier)
+            .bind(expires_at)
+            .execute(&self.pool)
+            .await
+            .context("Failed to store pending login")?;
+
+        Ok(())
+    }
+
+    /// Remove an unexpired pending login, returning its nonce and PKCE verifier
+    pub async fn take_oidc_login(&self, state: &str) -> Result<Option<(String, String)>> {
+        let row = sqlx::query(
+            r#"
+            DELETE FROM oidc_logins
+            WHERE state = ? AND expires_at > ?
+            RETURNING nonce, pkce_verifier
+            "#,
+        )
+        .bind(state)
+        .bind(chrono::Utc::now().timestamp())
+        .fetch_optional(&self.pool)
+        .await
+        .context("Failed to load pending login")?;
+
+        Ok(row.map(|row| (row.get("nonce"), row.get("pkce_verifier"))))
+    }
+
+    // Login throttling and audit
+    pub async fn get_login_failures(&self, key: &str) -> Result<Option<LoginFailures>> {
+        let entry = sqlx::query_as::<_, LoginFailures>(
+            "SELECT key, failures, last_failure_at FROM login_failures WHERE key = ?",
+        )
+        .bind(key)
+        .fetch_optional(&self.pool)
+        .await
+        .context("Failed to load login failures")?;
+
+        Ok(entry)
+    }
+
+    /// Count a failure, starting over if the previous one was before `forget_before`
+    pub async fn record_login_failure(&self, key: &str, now: i64, forget_before: i64) -> Result<()> {
+        sqlx::query(
+            r#"
+            INSERT INTO login_failures (key, failures, last_failure_at) VALUES (?, 1, ?)
+            ON CONFLICT(key) DO UPDATE SET
+                failures = CASE WHEN last_failure_at <= ? THEN 1 ELSE failures + 1 END,
+                last_failure_at = excluded.last_failure_at
+            "#,
+        )
+        .bind(key)
+        .bind(now)
+        .bind(forget_before)
+        .execute(&self.pool)
+        .await
+        .context("Failed to record login failure")?;
+
+        Ok(())
+    }
+
+    /// Reset a counter; false if there was none
+    pub async fn clear_login_failures(&self, key: &str) -> Result<bool> {
+        let result = sqlx::query("DELETE FROM login_failures WHERE key = ?")
+            .bind(key)
+            .execute(&self.pool)
+            .await
+            .context("Failed to clear login failures")?;
+
+        Ok(result.rows_affected() > 0)
+    }
+
+    /// Counters with failures after `since`
+    pub async fn list_login_failures(&self, since: i64) -> Result<Vec<LoginFailures>> {
+        let entries = sqlx::query_as::<_, LoginFailures>(
+            r#"
+            SELECT key, failures, last_failure_at FROM login_failures
+            WHERE last_failure_at > ?
+            ORDER BY last_failure_at DESC
+            "#,
+        )
+        .bind(since)
+        .fetch_all(&self.pool)
+        .await
+        .context("Failed to list login failures")?;
+
+        Ok(entries)
+    }
+
+    pub async fn create_auth_event(
+        &self,
+        username: &str,
+        user_id: Option<&str>,
+        client: &ClientInfo,
+        success: bool,
+        reason: &str,
+    ) -> Result<()> {
+        sqlx::query(
+            r#"
+            INSERT INTO auth_events (username, user_id, ip_address, user_agent, success, reason)
+            VALUES (?, ?, ?, ?, ?, ?)
+            "#,
+        )
+        .bind(username)
+        .bind(user_id)
+        .bind(&client.ip)
+        .bind(&client.user_agent)
+        .bind(success)
+        .bind(reason)
+        .execute(&self.pool)
+        .await
+        .context("Failed to record auth event")?;
+
+        Ok(())
+    }
+
+    pub async fn list_auth_events(&self, limit: i64) -> Result<Vec<AuthEvent>> {
+        let events = sqlx::query_as::<_, AuthEvent>(
+            r#"
+            SELECT id, username, user_id, ip_address, user_agent, success, reason, created_at
+            FROM auth_events
+            ORDER BY id DESC
+            LIMIT ?
+            "#,
+        )
+        .bind(limit)
+        .fetch_all(&self.pool)
+        .await
+        .context("Failed to list auth events")?;
+
+        Ok(events)
+    }
+
+    // JWT signing key operations
+    /// Unexpired keys, the signing key first
+    pub async fn list_jwt_keys(&self) -> Result<Vec<JwtKey>> {
+        let keys = sqlx::query_as::<_, JwtKey>(
+            r#"
+            SELECT kid, secret, created_at, expires_at
+            FROM jwt_keys
+            WHERE expires_at IS NULL OR expires_at > ?
+            ORDER BY expires_at IS NOT NULL, expires_at DESC
+            "#,
+        )
+        .bind(chrono::Utc::now().timestamp())
+        .fetch_all(&self.pool)
+        .await
+        .context("Failed to list signing keys")?;
+
+        Ok(keys)
+    }
+
+    /// Make `kid` the signing key, retiring the current one until `retired_until`
+    pub async fn activate_jwt_key(&self, kid: &str, secret: &str, retired_until: i64) -> Result<()> {
+        let mut tx = self.pool.begin().await?;
+
+        sqlx::query("UPDATE jwt_keys SET expires_at = ? WHERE expires_at IS NULL AND kid != ?")
+            .bind(retired_until)
+            .bind(kid)
+            .execute(&mut *tx)
+            .await
+            .context("Failed to retire signing key")?;
+
+        sqlx::query(
+            r#"
+            INSERT INTO jwt_keys (kid, secret) VALUES (?, ?)
+            ON CONFLICT(kid) DO UPDATE SET expires_at = NULL
+            "#,
+        )
+        .bind(kid)
+        .bind(secret)
+        .execute(&mut *tx)
+        .await
+        .context("Failed to store signing key")?;
+
+        tx.commit().await?;
+        Ok(())
+    }
+
+    /// Delete retired keys whose tokens have all expired
+    pub async fn prune_jwt_keys(&self) -> Result<u64> {
+        let result = sqlx::query("DELETE FROM jwt_keys WHERE expires_at <= ?")
+            .bind(chrono::Utc::now().timestamp())
+            .execute(&self.pool)
+            .await
+            .context("Failed to prune signing keys")?;
+
+        Ok(result.rows_affected())
+    }
+
+    pub async fn update_default_profile(&self, user_id: &str, profile: Option<&str>) -> Result<()> {
+        sqlx::query(
+            r#"
+            UPDATE users SET default_profile = ? WHERE id = ?
+            "#,
+        )
+        .bind(profile)
+        .bind(user_id)
+        .execute(&self.pool)
+        .await
+        .context("Failed to update default profile")?;
+
+        Ok(())
+    }
+
     pub async fn update_username(&self, user_id: &str, new_username: &str) -> Result<()> {
         sqlx::query(
             r#"
@@ -236,6 +1119,36 @@ impl Database {
         Ok(())
     }
 
+    pub async fn set_upload_log_details(&self, id: i32, details: &serde_json::Value) -> Result<()> {
+        sqlx::query("UPDATE upload_logs SET details = ? WHERE id = ?")
+            .bind(details.to_string())
+            .bind(id)
+            .execute(&self.pool)
+            .await
+            .context("Failed to update upload log details")?;
+
+        Ok(())
+    }
+
+    /// Set one key of the details JSON, keeping the others (used by processing stages)
+    pub async fn add_upload_log_detail(&self, id: i32, key: &str, value: &serde_json::Value) -> Result<()> {
+        sqlx::query(
+            r#"
+            UPDATE upload_logs
+            SET details = json_set(COALESCE(details, '{}'), '$.' || ?, json(?))
+            WHERE id = ?
+            "#,
+        )
+        .bind(key)
+        .bind(value.to_string())
+        .bind(id)
+        .execute(&self.pool)
+        .await
+        .context("Failed to update upload log details")?;
+
+        Ok(())
+    }
+
     pub async fn get_upload_logs(
         &self,
         user_id: Option<&str>,
@@ -244,7 +1157,7 @@ impl Database {
         let logs = if let Some(uid) = user_id {
             sqlx::query_as::<_, UploadLog>(
                 r#"
-                SELECT id, user_id, upload_type, source, status, file_count, error_message, created_at, completed_at
+                SELECT id, user_id, upload_type, source, status, file_count, error_message, created_at, completed_at, details
                 FROM upload_logs
                 WHERE user_id = ?
                 ORDER BY created_at DESC
@@ -258,7 +1171,7 @@ impl Database {
         } else {
             sqlx::query_as::<_, UploadLog>(
                 r#"
-                SELECT id, user_id, upload_type, source, status, file_count, error_message, created_at, completed_at
+                SELECT id, user_id, upload_type, source, status, file_count, error_message, created_at, completed_at, details
                 FROM upload_logs
                 ORDER BY created_at DESC
                 LIMIT ?
@@ -272,6 +1185,134 @@ impl Database {
         Ok(logs)
     }
 
+    // Spectral analysis operations
+    pub async fn create_spectral_analysis(&self, analysis: CreateSpectralAnalysis) -> Result<i32> {
+        let result = sqlx::query(
+            r#"
+            INSERT INTO spectral_analyses (user_id, upload_log_id, file_name, codec, cutoff_hz, confidence, verdict, quarantine_path)
+            VALUES (?, ?, ?, ?, ?, ?, ?, ?)
+            "#,
+        )
+        .bind(&analysis.user_id)
+        .bind(analysis.upload_log_id)
+        .bind(&analysis.file_name)
+        .bind(&analysis.codec)
+        .bind(analysis.cutoff_hz)
+        .bind(analysis.confidence)
+        .bind(&analysis.verdict)
+        .bind(&analysis.quarantine_path)
+        .execute(&self.pool)
+        .await
+        .context("Failed to store spectral analysis")?;
+
+        Ok(result.last_insert_rowid() as i32)
+    }
+
+    /// Files flagged as suspicious, grouped by user and most confident first
+    pub async fn list_suspicious_files(&self) -> Result<Vec<SpectralAnalysis>> {
+        let rows = sqlx::query_as::<_, SpectralAnalysis>(
+            r#"
+            SELECT s.id, s.user_id, u.username, s.upload_log_id, s.file_name, s.codec, s.cutoff_hz,
+                   s.confidence, s.verdict, s.quarantine_path, s.created_at
+            FROM spectral_analyses s
+            JOIN users u ON u.id = s.user_id
+            WHERE s.verdict = 'suspicious'
+            ORDER BY u.username, s.confidence DESC, s.created_at DESC
+            "#,
+        )
+        .fetch_all(&self.pool)
+        .await?;
+
+        Ok(rows)
+    }
+
+    // Library file operations
+    /// Record an imported file; a file imported to the same path replaces the old entry
+    pub async fn upsert_library_file(&self, file: CreateLibraryFile) -> Result<()> {
+        sqlx::query(
+            r#"
+            INSERT INTO library_files (user_id, path, sha256, fingerprint, duration_secs, codec, bitrate_kbps, size_bytes, upload_log_id)
+            VALUES (?, ?, ?, ?, ?, ?, ?, ?, ?)
+            ON CONFLICT(user_id, path) DO UPDATE SET
+                sha256 = excluded.sha256,
+                fingerprint = excluded.fingerprint,
+                duration_secs = excluded.duration_secs,
+                codec = excluded.codec,
+                bitrate_kbps = excluded.bitrate_kbps,
+                size_bytes = excluded.size_bytes,
+                upload_log_id = excluded.upload_log_id,
+                created_at = CURRENT_TIMESTAMP
+            "#,
+        )
+        .bind(&file.user_id)
+        .bind(&file.path)
+        .bind(&file.sha256)
+        .bind(&file.fingerprint)
+        .bind(file.duration_secs)
+        .bind(&file.codec)
+        .bind(file.bitrate_kbps)
+        .bind(file.size_bytes)
+        .bind(file.upload_log_id)
+        .execute(&self.pool)
+        .await
+        .context("Failed to record library file")?;
+
+        Ok(())
+    }
+
+    /// A user's library files with this hash or a duration in the given range
+    pub async fn find_duplicate_candidates(
+        &self,
+        user_id: &str,
+        sha256: &str,
+        duration_range: Option<(f64, f64)>,
+    ) -> Result<Vec<LibraryFile>> {
+        let (min, max) = duration_range.unzip();
+        let files = sqlx::query_as::<_, LibraryFile>(
+            r#"
+            SELECT l.id, l.user_id, u.username, l.path, l.sha256, l.fingerprint, l.duration_secs, l.codec,
+                   l.bitrate_kbps, l.size_bytes, l.upload_log_id, l.created_at
+            FROM library_files l
+            JOIN users u ON u.id = l.user_id
+            WHERE l.user_id = ? AND (l.sha256 = ? OR l.duration_secs BETWEEN ? AND ?)
+            "#,
+        )
+        .bind(user_id)
+        .bind(sha256)
+        .bind(min)
+        .bind(max)
+        .fetch_all(&self.pool)
+        .await?;
+
+        Ok(files)
+    }
+
+    pub async fn list_library_files(&self) -> Result<Vec<LibraryFile>> {
+        let files = sqlx::query_as::<_, LibraryFile>(
+            r#"
+            SELECT l.id, l.user_id, u.username, l.path, l.sha256, l.fingerprint, l.duration_secs, l.codec,
+                   l.bitrate_kbps, l.size_bytes, l.upload_log_id, l.created_at
+            FROM library_files l
+            JOIN users u ON u.id = l.user_id
+            ORDER BY u.username, l.path
+            "#,
+        )
+        .fetch_all(&self.pool)
+        .await?;
+
+        Ok(files)
+    }
+
+    pub async fn delete_library_file(&self, id: i32) -> Result<()> {
+        sqlx::query("DELETE FROM library_files WHERE id = ?")
+            .bind(id)
+            .execute(&self.pool)
+            .await
+            .context("Failed to delete library file")?;
+
+        Ok(())
+    }
+
     // Config operations
     pub async fn get_config(&self, key: &str) -> Result<Option<String>> {
         let result = sqlx::query(
@@ -330,6 +1371,91 @@ impl Database {
             None => Ok(config.paths.ferric_enabled),
         }
     }
+
+    /// Get spotify_youtube_fallback setting from database
+    /// Falls back to config file value if not set in database
+    pub async fn get_spotify_youtube_fallback(&self, config: &crate::config::Config) -> Result<bool> {
+        match self.get_config("spotify_youtube_fallback").await? {
+            Some(value) => Ok(value == "true"),
+            None => Ok(config.spotify.youtube_fallback),
+        }
+    }
+
+    /// Get transcode_enabled setting from database
+    /// Falls back to config file value if not set in database
+    pub async fn get_transcode_enabled(&self, config: &crate::config::Config) -> Result<bool> {
+        match self.get_config("transcode_enabled").await? {
+            Some(value) => Ok(value == "true"),
+            None => Ok(config.transcode.enabled),
+        }
+    }
+
+    /// Get loudness_enabled setting from database
+    /// Falls back to config file value if not set in database
+    pub async fn get_loudness_enabled(&self, config: &crate::config::Config) -> Result<bool> {
+        match self.get_config("loudness_enabled").await? {
+            Some(value) => Ok(value == "true"),
+            None => Ok(config.loudness.enabled),
+        }
+    }
+
+    /// Get spectral_enabled setting from database
+    /// Falls back to config file value if not set in database
+    pub async fn get_spectral_enabled(&self, config: &crate::config::Config) -> Result<bool> {
+        match self.get_config("spectral_enabled").await? {
+            Some(value) => Ok(value == "true"),
+            None => Ok(config.spectral.enabled),
+        }
+    }
+
+    /// Get duplicates_enabled setting from database
+    /// Falls back to config file value if not set in database
+    pub async fn get_duplicates_enabled(&self, config: &crate::config::Config) -> Result<bool> {
+        match self.get_config("duplicates_enabled").await? {
+            Some(value) => Ok(value == "true"),
+            None => Ok(config.duplicates.enabled),
+        }
+    }
+
+    /// Get cover_art_enabled setting from database
+    /// Falls back to config file value if not set in database
+    pub async fn get_cover_art_enabled(&self, config: &crate::config::Config) -> Result<bool> {
+        match self.get_config("cover_art_enabled").await? {
+            Some(value) => Ok(value == "true"),
+            None => Ok(config.cover_art.enabled),
+        }
+    }
+
+    /// Get require_admin_2fa setting from database
+    /// Falls back to config file value if not set in database
+    pub async fn get_require_admin_2fa(&self, config: &crate::config::Config) -> Result<bool> {
+        match self.get_config("require_admin_2fa").await? {
+            Some(value) => Ok(value == "true"),
+            None => Ok(config.security.require_admin_2fa),
+        }
+    }
+
+    /// Get lyrics_fetch_enabled setting from database
+    /// Falls back to config file value if not set in database
+    pub async fn get_lyrics_fetch_enabled(&self, config: &crate::config::Config) -> Result<bool> {
+        match self.get_config("lyrics_fetch_enabled").await? {
+            Some(value) => Ok(value == "true"),
+            None => Ok(config.lyrics.fetch),
+        }
+    }
+
+    /// Get the names of the download profiles users may pick (comma-separated in the database)
+    /// Falls back to every profile in the config file if not set in database
+    pub async fn get_allowed_profiles(&self, config: &crate::config::Config) -> Result<Vec<String>> {
+        match self.get_config("allowed_profiles").await? {
+            Some(value) => Ok(value
+                .split(',')
+                .map(|name| name.trim().to_string())
+                .filter(|name| config.profiles.contains_key(name))
+                .collect()),
+            None => Ok(config.profiles.keys().cloned().collect()),
+        }
+    }
 }
 
 // Password hashing utilities
@@ -351,3 +1477,39 @@ pub fn verify_password(password: &str, hash: &str) -> Result<()> {
         .map_err(|e| anyhow::anyhow!("Password verification failed: {}", e))?;
     Ok(())
 }
+
+fn api_token_from_row(row: &sqlx::sqlite::SqliteRow) -> ApiToken {
+    let scopes: Option<String> = row.get("scopes");
+    ApiToken {
+        id: row.get("id"),
+        user_id: row.get("user_id"),
+        username: row.get("username"),
+        name: row.get("name"),
+        prefix: row.get("prefix"),
+        scopes: scopes.map(|s| s.split_whitespace().filter_map(Permission::parse).collect()),
+        expires_at: row.get("expires_at"),
+        created_at: row.get("created_at"),
+        last_used_at: row.get("last_used_at"),
+    }
+}
+
+async fn replace_recovery_codes(
+    tx: &mut sqlx::Transaction<'_, sqlx::Sqlite>,
+    user_id: &str,
+    code_hashes: &[String],
+) -> Result<()> {
+    sqlx::query("DELETE FROM totp_recovery_codes WHERE user_id = ?")
+        .bind(user_id)
+        .execute(&mut **tx)
+        .await
+        .context("Failed to delete recovery codes")?;
+    for hash in code_hashes {
+        sqlx::query("INSERT INTO totp_recovery_codes (user_id, code_hash) VALUES (?, ?)")
+            .bind(user_id)
+            .bind(hash)
+            .execute(&mut **tx)
+            .await
+            .context("Failed to store recovery code")?;
+    }
+    Ok(())
+}
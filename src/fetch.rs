@@ -0,0 +1,254 @@
+use crate::config::UrlImportConfig;
+use anyhow::{Context, Result};
+use reqwest::dns::{Addrs, Name, Resolve, Resolving};
+use reqwest::{redirect, Url};
+use std::net::{IpAddr, Ipv4Addr, SocketAddr};
+use std::path::Path;
+use std::sync::Arc;
+use std::time::Duration;
+use tokio::io::AsyncWriteExt;
+
+/// Whether an address is on the public internet (not loopback, private,
+/// link-local, shared, documentation, multicast or otherwise reserved)
+pub fn is_public_ip(ip: IpAddr) -> bool {
+    match ip {
+        IpAddr::V4(v4) => is_public_ipv4(v4),
+        IpAddr::V6(v6) => {
+            if let Some(v4) = v6.to_ipv4_mapped() {
+                return is_public_ipv4(v4);
+            }
+            let first = v6.segments()[0];
+            !(v6.is_unspecified()
+                || v6.is_loopback()
+                || v6.is_multicast()
+                // Unique local fc00::/7, link-local fe80::/10, documentation 2001:db8::/32
+                || first & 0xfe00 == 0xfc00
+                || first & 0xffc0 == 0xfe80
+                || (first == 0x2001 && v6.segments()[1] == 0x0db8)
+                // NAT64 64:ff9b::/96 and 6to4 2002::/16 can wrap private IPv4 addresses
+                || (first == 0x0064 && v6.segments()[1] == 0xff9b)
+                || first == 0x2002)
+        }
+    }
+}
+
+fn is_public_ipv4(ip: Ipv4Addr) -> bool {
+    let [a, b, c, _] = ip.octets();
+    !(ip.is_unspecified()
+        || ip.is_loopback()
+        || ip.is_private()
+        || ip.is_link_local()
+        || ip.is_broadcast()
+        || ip.is_documentation()
+        || ip.is_multicast()
+        || a == 0
+        // Shared address space (carrier-grade NAT) 100.64.0.0/10
+        || (a == 100 && (64..128).contains(&b))
+        // IETF protocol assignments 192.0.0.0/24, benchmarking 198.18.0.0/15
+        || (a == 192 && b == 0 && c == 0)
+        || (a == 198 && (b == 18 || b == 19))
+        // Reserved 240.0.0.0/4
+        || a >= 240)
+}
+
+/// Check a URL before requesting it: http(s) only, and literal addresses must be public
+/// (host names are checked when they are resolved)
+pub fn check_url(url: &Url, allow_private: bool) -> Result<(), String> {
+    if url.scheme() != "http" && url.scheme() != "https" {
+        return Err("Only http and https URLs are supported".to_string());
+    }
+    let host = url.host_str().ok_or("URL has no host")?;
+    let literal = host.trim_start_matches('[').trim_end_matches(']').parse::<IpAddr>();
+    match literal {
+        Ok(ip) if !allow_private && !is_public_ip(ip) => Err(format!("Address {} is not allowed", ip)),
+        _ => Ok(()),
+    }
+}
+
+/// Resolver that refuses host names with any non-public address, so a
+/// request can't be pointed at the internal network (also on redirects and
+/// via DNS rebinding: the addresses checked are the ones connected to)
+struct PublicResolver;
+
+impl Resolve for PublicResolver {
+    fn resolve(&self, name: Name) -> Resolving {
+        Box::pin(async move {
+            let host = name.as_str().to_string();
+            let addrs: Vec<SocketAddr> = tokio::net::lookup_host((host.as_str(), 0)).await?.collect();
+            if let Some(blocked) = addrs.iter().find(|a| !is_public_ip(a.ip())) {
+                return Err(format!("{} resolves to a non-public address ({})", host, blocked.ip()).into());
+            }
+            Ok(Box::new(addrs.into_iter()) as Addrs)
+        })
+    }
+}
+
+/// A file downloaded by `download_to_file`
+pub struct Download {
+    /// File name from Content-Disposition or the last path segment of the final URL
+    pub file_name: Option<String>,
+    pub content_type: Option<String>,
+    pub size: u64,
+}
+
+/// Download `url` into `dest`, enforcing the size, redirect and address limits
+pub async fn download_to_file(settings: &UrlImportConfig, max_bytes: u64, url: &str, dest: &Path) -> Result<Download> {
+    let url = Url::parse(url.trim()).context("Invalid URL")?;
+    let allow_private = settings.allow_private_addresses;
+    check_url(&url, allow_private).map_err(anyhow::Error::msg)?;
+
+    let max_redirects = settings.max_redirects;
+    let policy = redirect::Policy::custom(move |attempt| {
+        if attempt.previous().len() > max_redirects {
+            return attempt.error(format!("More than {} redirects", max_redirects));
+        }
+        match check_url(attempt.url(), allow_private) {
+            Ok(()) => attempt.follow(),
+            Err(e) => attempt.error(format!("Redirect refused: {}", e)),
+        }
+    });
+
+    // No proxies: a proxy would resolve (and reach) host names for us
+    let mut builder = reqwest::Client::builder()
+        .no_proxy()
+        .redirect(policy)
+        .connect_timeout(Duration::from_secs(15))
+        .timeout(Duration::from_secs(settings.timeout_secs))
+        .user_agent(concat!(env!("CARGO_PKG_NAME"), "/", env!("CARGO_PKG_VERSION")));
+    if !allow_private {
+        builder = builder.dns_resolver(Arc::new(PublicResolver));
+    }
+    let client = builder.build().context("Failed to create HTTP client")?;
+
+    let mut response = client
+        .get(url)
+        .send()
+        .await
+        .map_err(|e| anyhow::anyhow!("Request failed: {}", error_chain(&e)))?
+        .error_for_status()
+        .context("Server returned an error")?;
+
+    let too_large = || anyhow::anyhow!("File is larger than {} MB", max_bytes / 1024 / 1024);
+    if response.content_length().is_some_and(|len| len > max_bytes) {
+        return Err(too_large());
+    }
+
+    let content_type = response
+        .headers()
+        .get(reqwest::header::CONTENT_TYPE)
+        .and_then(|v| v.to_str().ok())
+        .map(|v| v.split(';').next().unwrap_or("").trim().to_lowercase());
+    // File hosts answer with a landing page when the link isn't a direct download
+    if let Some(kind) = content_type.as_deref().filter(|t| t.starts_with("text/") || *t == "application/xhtml+xml") {
+        anyhow::bail!("URL returned a web page ({}), not a file; a direct download link is needed", kind);
+    }
+
+    let file_name = response
+        .headers()
+        .get(reqwest::header::CONTENT_DISPOSITION)
+        .and_then(|v| v.to_str().ok())
+        .and_then(content_disposition_file_name)
+        .or_else(|| url_file_name(response.url()));
+
+    let mut file = tokio::fs::File::create(dest)
+        .await
+        .context(format!("Failed to create {}", dest.display()))?;
+    let mut size = 0u64;
+    while let Some(chunk) = response.chunk().await.context("Download interrupted")? {
+        size += chunk.len() as u64;
+        if size > max_bytes {
+            return Err(too_large());
+        }
+        file.write_all(&chunk).await.context("Failed to write download")?;
+    }
+    file.flush().await?;
+
+    Ok(Download {
+        file_name,
+        content_type,
+        size,
+    })
+}
+
+/// reqwest hides the interesting part (e.g. the resolver's refusal) in the source chain
+fn error_chain(error: &dyn std::error::Error) -> String {
+    let mut message = error.to_string();
+    let mut source = error.source();
+    while let Some(e) = source {
+        message.push_str(": ");
+        message.push_str(&e.to_string());
+        source = e.source();
+    }
+    message
+}
+
+/// `filename` from a Content-Disposition header (RFC 5987 `filename*` preferred)
+pub fn content_disposition_file_name(header: &str) -> Option<String> {
+    let mut plain = None;
+    for part in header.split(';').map(str::trim) {
+        let Some((key, value)) = part.split_once('=') else {
+            continue;
+        };
+        match key.trim().to_lowercase().as_str() {
+            "filename*" => {
+                // charset'language'percent-encoded
+                let encoded = value.trim().splitn(3, '\'').nth(2)?;
+                let decoded = percent_encoding::percent_decode_str(encoded).decode_utf8().ok()?;
+                return Some(decoded.to_string()).filter(|n| !n.is_empty());
+            }
+            "filename" => plain = Some(value.trim().trim_matches('"').to_string()),
+            _ => {}
+        }
+    }
+    plain.filter(|n| !n.is_empty())
+}
+
+fn url_file_name(url: &Url) -> Option<String> {
+    let segment = url.path_segments()?.next_back()?;
+    let decoded = percent_encoding::percent_decode_str(segment).decode_utf8_lossy();
+    Some(decoded.to_string()).filter(|n| !n.is_empty())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn blocks_non_public_addresses() {
+        for ip in [
+            "127.0.0.1", "10.1.2.3", "172.16.0.1", "192.168.1.1", "169.254.169.254", "100.64.0.1",
+            "0.0.0.0", "255.255.255.255", "::1", "fd00::1", "fe80::1", "::ffff:127.0.0.1", "64:ff9b::a00:1",
+        ] {
+            assert!(!is_public_ip(ip.parse().unwrap()), "{} should be blocked", ip);
+        }
+        for ip in ["93.184.216.34", "1.1.1.1", "2606:4700:4700::1111"] {
+            assert!(is_public_ip(ip.parse().unwrap()), "{} should be allowed", ip);
+        }
+    }
+
+    #[test]
+    fn checks_urls() {
+        let check = |url: &str, allow_private| check_url(&Url::parse(url).unwrap(), allow_private);
+        assert!(check("https://example.com/a.flac", false).is_ok());
+        assert!(check("ftp://example.com/a.flac", false).is_err());
+        assert!(check("file:///etc/passwd", false).is_err());
+        assert!(check("http://127.0.0.1:8080/a.zip", false).is_err());
+        assert!(check("http://[::1]/a.zip", false).is_err());
+        assert!(check("http://127.0.0.1:8080/a.zip", true).is_ok());
+    }
+
+    #[test]
+    fn parses_file_names() {
+        assert_eq!(
+            content_disposition_file_name(r#"attachment; filename="Album (2020).zip""#),
+            Some("Album (2020).zip".to_string())
+        );
+        assert_eq!(
+            content_disposition_file_name("attachment; filename=x.zip; filename*=UTF-8''Caf%C3%A9.flac"),
+            Some("Café.flac".to_string())
+        );
+        assert_eq!(content_disposition_file_name("inline"), None);
+        let url = Url::parse("https://host/files/My%20Track.flac?dl=1").unwrap();
+        assert_eq!(url_file_name(&url), Some("My Track.flac".to_string()));
+    }
+}
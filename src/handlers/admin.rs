@@ -1,5 +1,9 @@
-use crate::auth::AuthUser;
-use crate::models::{AdminChangePasswordRequest, ChangePasswordRequest, CreateUser, UpdateLibraryPathRequest, User};
+use crate::auth::{AuthUser, Permission};
+use crate::login_throttle;
+use crate::models::{
+    AdminChangePasswordRequest, ChangePasswordRequest, CreateUser, Role, UpdateLibraryPathRequest, UpdateUserRoleRequest,
+    User,
+};
 use axum::{
     extract::{Extension, Path, State},
     http::StatusCode,
@@ -14,6 +18,10 @@ use std::sync::Arc;
 pub struct CreateUserRequest {
     pub username: String,
     pub password: String,
+    /// Role name; defaults to "admin" if `is_admin` is set, else "user"
+    #[serde(default)]
+    pub role: Option<String>,
+    #[serde(default)]
     pub is_admin: bool,
     pub library_path: Option<String>,
 }
@@ -32,7 +40,6 @@ pub struct UpdateUsernameRequest {
 // User management endpoints
 pub async fn list_users(
     State(state): State<Arc<crate::AppState>>,
-    Extension(_user): Extension<AuthUser>, // Ensures user is authenticated
 ) -> Result<Json<Vec<User>>, Response> {
     let users = state
         .db
@@ -45,7 +52,6 @@ pub async fn list_users(
 
 pub async fn create_user(
     State(state): State<Arc<crate::AppState>>,
-    Extension(_user): Extension<AuthUser>,
     Json(req): Json<CreateUserRequest>,
 ) -> Result<Json<User>, Response> {
     // Validate username
@@ -70,12 +76,17 @@ pub async fn create_user(
             .into_response());
     }
 
+    let role = req
+        .role
+        .unwrap_or_else(|| if req.is_admin { "admin" } else { "user" }.to_string());
+    ensure_role_exists(&state, &role).await?;
+
     let user = state
         .db
         .create_user(CreateUser {
             username: req.username,
             password: req.password,
-            is_admin: req.is_admin,
+            role,
             library_path: req.library_path,
         })
         .await
@@ -123,10 +134,256 @@ pub async fn delete_user(
     })))
 }
 
+// Clear a user's failed-login lockout (manage_users)
+pub async fn unlock_user(
+    State(state): State<Arc<crate::AppState>>,
+    Path(user_id): Path<String>,
+) -> Result<Json<serde_json::Value>, Response> {
+    let user = state.db.get_user_by_id(&user_id).await.map_err(|_| {
+        (
+            StatusCode::NOT_FOUND,
+            Json(json!({
+                "error": "User not found"
+            })),
+        )
+            .into_response()
+    })?;
+
+    state
+        .db
+        .clear_login_failures(&login_throttle::account_key(&user.username))
+        .await
+        .map_err(|e| internal_error(&format!("Failed to unlock user: {}", e)))?;
+
+    Ok(Json(json!({
+        "message": format!("{} can log in again", user.username)
+    })))
+}
+
+// Usernames and IPs with recent failed logins, and how long they must wait (manage_users)
+pub async fn list_lockouts(
+    State(state): State<Arc<crate::AppState>>,
+) -> Result<Json<serde_json::Value>, Response> {
+    let throttle = &state.config.security.login_throttle;
+    let now = chrono::Utc::now().timestamp();
+    let entries = state
+        .db
+        .list_login_failures(now - throttle.lockout_minutes * 60)
+        .await
+        .map_err(|e| internal_error(&format!("Failed to list lockouts: {}", e)))?;
+
+    let lockouts: Vec<serde_json::Value> = entries
+        .iter()
+        .map(|entry| {
+            let max_failures = if entry.key.starts_with("ip:") {
+                throttle.max_ip_failures
+            } else {
+                throttle.max_account_failures
+            };
+            json!({
+                "key": entry.key,
+                "failures": entry.failures,
+                "last_failure_at": entry.last_failure_at,
+                "locked": entry.failures >= i64::from(max_failures),
+                "retry_after": login_throttle::wait_secs(entry, max_failures, throttle, now)
+            })
+        })
+        .collect();
+
+    Ok(Json(json!({
+        "lockouts": lockouts
+    })))
+}
+
+// Reset a username's or IP's failure counter, e.g. "ip:203.0.113.7" (manage_users)
+pub async fn clear_lockout(
+    State(state): State<Arc<crate::AppState>>,
+    Path(key): Path<String>,
+) -> Result<Json<serde_json::Value>, Response> {
+    let cleared = state
+        .db
+        .clear_login_failures(&key)
+        .await
+        .map_err(|e| internal_error(&format!("Failed to clear lockout: {}", e)))?;
+    if !cleared {
+        return Err((
+            StatusCode::NOT_FOUND,
+            Json(json!({
+                "error": "No failed logins recorded for that key"
+            })),
+        )
+            .into_response());
+    }
+
+    Ok(Json(json!({
+        "message": "Lockout cleared"
+    })))
+}
+
+// Recent login attempts (manage_users)
+pub async fn get_auth_events(
+    State(state): State<Arc<crate::AppState>>,
+) -> Result<Json<serde_json::Value>, Response> {
+    let events = state
+        .db
+        .list_auth_events(200)
+        .await
+        .map_err(|e| internal_error(&format!("Failed to list login attempts: {}", e)))?;
+
+    Ok(Json(json!({
+        "events": events
+    })))
+}
+
+// Change a user's role
+pub async fn update_user_role(
+    State(state): State<Arc<crate::AppState>>,
+    Extension(admin): Extension<AuthUser>,
+    Path(user_id): Path<String>,
+    Json(req): Json<UpdateUserRoleRequest>,
+) -> Result<Json<serde_json::Value>, Response> {
+    // Prevent locking yourself out
+    if admin.user_id == user_id {
+        return Err((
+            StatusCode::BAD_REQUEST,
+            Json(json!({
+                "error": "Cannot change your own role"
+            })),
+        )
+            .into_response());
+    }
+
+    ensure_role_exists(&state, &req.role).await?;
+
+    // Verify user exists
+    state.db.get_user_by_id(&user_id).await.map_err(|_| {
+        (
+            StatusCode::NOT_FOUND,
+            Json(json!({
+                "error": "User not found"
+            })),
+        )
+            .into_response()
+    })?;
+
+    state
+        .db
+        .update_user_role(&user_id, &req.role)
+        .await
+        .map_err(|e| internal_error(&format!("Failed to update role: {}", e)))?;
+
+    Ok(Json(json!({
+        "message": "User role updated successfully",
+        "role": req.role
+    })))
+}
+
+// Role management endpoints
+pub async fn list_roles(
+    State(state): State<Arc<crate::AppState>>,
+) -> Result<Json<serde_json::Value>, Response> {
+    let roles = state
+        .db
+        .list_roles()
+        .await
+        .map_err(|e| internal_error(&format!("Failed to list roles: {}", e)))?;
+
+    Ok(Json(json!({
+        "roles": roles,
+        "permissions": Permission::ALL,
+    })))
+}
+
+// Create or update a role
+pub async fn save_role(
+    State(state): State<Arc<crate::AppState>>,
+    Json(role): Json<Role>,
+) -> Result<Json<serde_json::Value>, Response> {
+    let valid_name = !role.name.is_empty()
+        && role.name.len() <= 32
+        && role.name.chars().all(|c| c.is_ascii_alphanumeric() || c == '_' || c == '-');
+    if !valid_name {
+        return Err((
+            StatusCode::BAD_REQUEST,
+            Json(json!({
+                "error": "Role names use letters, digits, '-' and '_' (max 32)"
+            })),
+        )
+            .into_response());
+    }
+    // The built-in admin role always keeps every permission
+    if role.name == "admin" {
+        return Err((
+            StatusCode::BAD_REQUEST,
+            Json(json!({
+                "error": "The admin role can't be changed"
+            })),
+        )
+            .into_response());
+    }
+
+    state
+        .db
+        .save_role(&role)
+        .await
+        .map_err(|e| internal_error(&format!("Failed to save role: {}", e)))?;
+
+    Ok(Json(json!({
+        "message": "Role saved successfully",
+        "role": role
+    })))
+}
+
+pub async fn delete_role(
+    State(state): State<Arc<crate::AppState>>,
+    Path(name): Path<String>,
+) -> Result<Json<serde_json::Value>, Response> {
+    if name == "admin" || name == "user" {
+        return Err((
+            StatusCode::BAD_REQUEST,
+            Json(json!({
+                "error": "Built-in roles can't be deleted"
+            })),
+        )
+            .into_response());
+    }
+
+    state.db.delete_role(&name).await.map_err(|e| {
+        (
+            StatusCode::CONFLICT,
+            Json(json!({
+                "error": e.to_string()
+            })),
+        )
+            .into_response()
+    })?;
+
+    Ok(Json(json!({
+        "message": "Role deleted successfully"
+    })))
+}
+
+async fn ensure_role_exists(state: &crate::AppState, role: &str) -> Result<(), Response> {
+    let exists = state
+        .db
+        .role_exists(role)
+        .await
+        .map_err(|e| internal_error(&format!("Failed to look up role: {}", e)))?;
+    if !exists {
+        return Err((
+            StatusCode::BAD_REQUEST,
+            Json(json!({
+                "error": format!("Unknown role: {}", role)
+            })),
+        )
+            .into_response());
+    }
+    Ok(())
+}
+
 // Config management endpoints
 pub async fn list_config(
     State(state): State<Arc<crate::AppState>>,
-    Extension(_user): Extension<AuthUser>,
 ) -> Result<Json<Vec<(String, String)>>, Response> {
     let configs = state
         .db
@@ -139,7 +396,6 @@ pub async fn list_config(
 
 pub async fn update_config(
     State(state): State<Arc<crate::AppState>>,
-    Extension(_user): Extension<AuthUser>,
     Json(req): Json<UpdateConfigRequest>,
 ) -> Result<Json<serde_json::Value>, Response> {
     state
@@ -157,7 +413,6 @@ pub async fn update_config(
 
 pub async fn get_config(
     State(state): State<Arc<crate::AppState>>,
-    Extension(_user): Extension<AuthUser>,
     Path(key): Path<String>,
 ) -> Result<Json<serde_json::Value>, Response> {
     let value = state
@@ -181,19 +436,31 @@ pub async fn get_config(
     }
 }
 
-// Upload logs endpoint
+// Upload logs endpoints
 pub async fn get_upload_logs(
     State(state): State<Arc<crate::AppState>>,
     Extension(user): Extension<AuthUser>,
 ) -> Result<Json<serde_json::Value>, Response> {
-    let logs = if user.is_admin {
-        // Admin can see all logs
-        state.db.get_upload_logs(None, 100).await
-    } else {
-        // Regular users can only see their own logs
-        state.db.get_upload_logs(Some(&user.user_id), 100).await
-    }
-    .map_err(|e| internal_error(&format!("Failed to get upload logs: {}", e)))?;
+    // Users see their own logs here; every user's logs are under /api/admin/logs
+    let logs = state
+        .db
+        .get_upload_logs(Some(&user.user_id), 100)
+        .await
+        .map_err(|e| internal_error(&format!("Failed to get upload logs: {}", e)))?;
+
+    Ok(Json(json!({
+        "logs": logs
+    })))
+}
+
+pub async fn get_all_upload_logs(
+    State(state): State<Arc<crate::AppState>>,
+) -> Result<Json<serde_json::Value>, Response> {
+    let logs = state
+        .db
+        .get_upload_logs(None, 100)
+        .await
+        .map_err(|e| internal_error(&format!("Failed to get upload logs: {}", e)))?;
 
     Ok(Json(json!({
         "logs": logs
@@ -239,6 +506,13 @@ pub async fn change_own_password(
         .await
         .map_err(|e| internal_error(&format!("Failed to update password: {}", e)))?;
 
+    // Log out every other device; this one stays signed in
+    state
+        .db
+        .delete_user_sessions(&db_user.id, user.session_id.as_deref())
+        .await
+        .map_err(|e| internal_error(&format!("Failed to revoke sessions: {}", e)))?;
+
     Ok(Json(json!({
         "message": "Password changed successfully"
     })))
@@ -246,21 +520,9 @@ pub async fn change_own_password(
 
 pub async fn admin_change_user_password(
     State(state): State<Arc<crate::AppState>>,
-    Extension(admin): Extension<AuthUser>,
     Path(user_id): Path<String>,
     Json(req): Json<AdminChangePasswordRequest>,
 ) -> Result<Json<serde_json::Value>, Response> {
-    // Ensure requester is admin
-    if !admin.is_admin {
-        return Err((
-            StatusCode::FORBIDDEN,
-            Json(json!({
-                "error": "Admin privileges required"
-            })),
-        )
-            .into_response());
-    }
-
     // Validate new password
     if req.new_password.len() < 8 {
         return Err((
@@ -290,29 +552,24 @@ pub async fn admin_change_user_password(
         .await
         .map_err(|e| internal_error(&format!("Failed to update password: {}", e)))?;
 
+    // The user has to log in again everywhere with the new password
+    state
+        .db
+        .delete_user_sessions(&user_id, None)
+        .await
+        .map_err(|e| internal_error(&format!("Failed to revoke sessions: {}", e)))?;
+
     Ok(Json(json!({
         "message": "User password changed successfully"
     })))
 }
 
-// Update user's library path (admin only)
+// Update user's library path (manage_users)
 pub async fn update_user_library_path(
     State(state): State<Arc<crate::AppState>>,
-    Extension(admin): Extension<AuthUser>,
     Path(user_id): Path<String>,
     Json(req): Json<UpdateLibraryPathRequest>,
 ) -> Result<Json<serde_json::Value>, Response> {
-    // Ensure requester is admin
-    if !admin.is_admin {
-        return Err((
-            StatusCode::FORBIDDEN,
-            Json(json!({
-                "error": "Admin privileges required"
-            })),
-        )
-            .into_response());
-    }
-
     // Validate library path
     if req.library_path.is_empty() {
         return Err((
@@ -359,22 +616,54 @@ pub async fn update_user_library_path(
     })))
 }
 
-// System info endpoint (admin only)
-pub async fn get_system_info(
+// Token signing keys, the active one first (manage_config)
+pub async fn list_jwt_keys(
+    State(state): State<Arc<crate::AppState>>,
+) -> Result<Json<serde_json::Value>, Response> {
+    let keys = state
+        .db
+        .list_jwt_keys()
+        .await
+        .map_err(|e| internal_error(&format!("Failed to list signing keys: {}", e)))?;
+
+    Ok(Json(json!({
+        "keys": keys,
+        "configured": state.auth.configured
+    })))
+}
+
+// Sign new tokens with a new key; existing logins stay valid (manage_config)
+pub async fn rotate_jwt_key(
     State(state): State<Arc<crate::AppState>>,
     Extension(admin): Extension<AuthUser>,
 ) -> Result<Json<serde_json::Value>, Response> {
-    // Ensure requester is admin
-    if !admin.is_admin {
+    if state.auth.configured {
         return Err((
-            StatusCode::FORBIDDEN,
+            StatusCode::CONFLICT,
             Json(json!({
-                "error": "Admin privileges required"
+                "error": "The signing key comes from jwt_secret; change the configured secret to rotate it"
             })),
         )
             .into_response());
     }
 
+    let kid = state
+        .auth
+        .rotate(&state.db)
+        .await
+        .map_err(|e| internal_error(&format!("Failed to rotate signing key: {}", e)))?;
+    tracing::info!("{} rotated the JWT signing key to {}", admin.username, kid);
+
+    Ok(Json(json!({
+        "message": "Signing key rotated",
+        "kid": kid
+    })))
+}
+
+// System info endpoint (manage_config)
+pub async fn get_system_info(
+    State(state): State<Arc<crate::AppState>>,
+) -> Result<Json<serde_json::Value>, Response> {
     // Get ferric_enabled from database (overrides config file)
     let ferric_enabled = state
         .db
@@ -382,13 +671,130 @@ pub async fn get_system_info(
         .await
         .unwrap_or(state.config.paths.ferric_enabled);
 
+    let spotify_youtube_fallback = state
+        .db
+        .get_spotify_youtube_fallback(&state.config)
+        .await
+        .unwrap_or(state.config.spotify.youtube_fallback);
+
+    let transcode_enabled = state
+        .db
+        .get_transcode_enabled(&state.config)
+        .await
+        .unwrap_or(state.config.transcode.enabled);
+
+    let loudness_enabled = state
+        .db
+        .get_loudness_enabled(&state.config)
+        .await
+        .unwrap_or(state.config.loudness.enabled);
+
+    let spectral_enabled = state
+        .db
+        .get_spectral_enabled(&state.config)
+        .await
+        .unwrap_or(state.config.spectral.enabled);
+
+    let cover_art_enabled = state
+        .db
+        .get_cover_art_enabled(&state.config)
+        .await
+        .unwrap_or(state.config.cover_art.enabled);
+
+    let duplicates_enabled = state
+        .db
+        .get_duplicates_enabled(&state.config)
+        .await
+        .unwrap_or(state.config.duplicates.enabled);
+
+    let lyrics_fetch_enabled = state
+        .db
+        .get_lyrics_fetch_enabled(&state.config)
+        .await
+        .unwrap_or(state.config.lyrics.fetch);
+
+    let require_admin_2fa = state
+        .db
+        .get_require_admin_2fa(&state.config)
+        .await
+        .unwrap_or(state.config.security.require_admin_2fa);
+
     Ok(Json(json!({
         "ferric_enabled": ferric_enabled,
+        "transcode_enabled": transcode_enabled,
+        "loudness_enabled": loudness_enabled,
+        "spectral_enabled": spectral_enabled,
+        "cover_art_enabled": cover_art_enabled,
+        "duplicates_enabled": duplicates_enabled,
+        "lyrics_fetch_enabled": lyrics_fetch_enabled,
+        "require_admin_2fa": require_admin_2fa,
         "spotify_enabled": state.config.spotify.enabled,
+        "spotify_youtube_fallback": spotify_youtube_fallback,
         "youtube_enabled": state.config.youtube.enabled,
     })))
 }
 
+// Files flagged by the fake-lossless check, grouped per user (view_all_logs)
+pub async fn get_spectral_report(
+    State(state): State<Arc<crate::AppState>>,
+) -> Result<Json<serde_json::Value>, Response> {
+    let files = state
+        .db
+        .list_suspicious_files()
+        .await
+        .map_err(|e| internal_error(&format!("Failed to load spectral report: {}", e)))?;
+
+    // Rows come sorted by username, so each user's files are contiguous
+    let mut users: Vec<serde_json::Value> = Vec::new();
+    for chunk in files.chunk_by(|a, b| a.user_id == b.user_id) {
+        users.push(json!({
+            "user_id": chunk[0].user_id,
+            "username": chunk[0].username,
+            "count": chunk.len(),
+            "files": chunk,
+        }));
+    }
+
+    Ok(Json(json!({
+        "threshold": state.config.spectral.threshold,
+        "users": users,
+    })))
+}
+
+// Duplicate clusters across every user's library (view_all_logs)
+pub async fn get_duplicate_clusters(
+    State(state): State<Arc<crate::AppState>>,
+) -> Result<Json<serde_json::Value>, Response> {
+    let files = state
+        .db
+        .list_library_files()
+        .await
+        .map_err(|e| internal_error(&format!("Failed to load library files: {}", e)))?;
+    let total_files = files.len();
+
+    // Comparing fingerprints is CPU-bound
+    let threshold = state.config.duplicates.similarity_threshold;
+    let clusters = tokio::task::spawn_blocking(move || {
+        crate::pipeline::duplicates::cluster_duplicates(&files, threshold)
+            .into_iter()
+            .map(|cluster| {
+                let members: Vec<_> = cluster.iter().map(|&i| &files[i]).collect();
+                json!({
+                    "exact": members.iter().all(|f| f.sha256 == members[0].sha256),
+                    "files": members,
+                })
+            })
+            .collect::<Vec<_>>()
+    })
+    .await
+    .map_err(|e| internal_error(&format!("Failed to cluster duplicates: {}", e)))?;
+
+    Ok(Json(json!({
+        "total_files": total_files,
+        "clusters": clusters,
+    })))
+}
+
 // User info endpoint (for current user)
 pub async fn get_user_info(
     State(state): State<Arc<crate::AppState>>,
@@ -446,6 +852,18 @@ pub async fn change_own_username(
             .into_response());
     }
 
+    // Import roots and drop folders are granted by username, so taking a
+    // name the config mentions would take over its server directories
+    if req.new_username != user.username && state.config.names_user(&req.new_username) {
+        return Err((
+            StatusCode::CONFLICT,
+            Json(json!({
+                "error": "This username is reserved"
+            })),
+        )
+            .into_response());
+    }
+
     // Update username
     state
         .db
@@ -0,0 +1,150 @@
+use crate::auth::{generate_api_token, hash_api_token, AuthUser};
+use crate::models::CreateApiTokenRequest;
+use axum::{
+    extract::{Extension, Path, State},
+    http::StatusCode,
+    response::{IntoResponse, Response},
+    Json,
+};
+use chrono::{Duration, Utc};
+use serde_json::json;
+use std::sync::Arc;
+
+/// Characters of the token kept (unhashed) to recognise it in lists
+const PREFIX_LEN: usize = 12;
+
+/// Longest expiry a token can be created with (ten years)
+const MAX_EXPIRY_DAYS: u32 = 3650;
+
+// The caller's API tokens
+pub async fn list_api_tokens(
+    State(state): State<Arc<crate::AppState>>,
+    Extension(user): Extension<AuthUser>,
+) -> Result<Json<serde_json::Value>, Response> {
+    let tokens = state
+        .db
+        .list_api_tokens(Some(&user.user_id))
+        .await
+        .map_err(|e| internal_error(&format!("Failed to list API tokens: {}", e)))?;
+
+    Ok(Json(json!({
+        "tokens": tokens
+    })))
+}
+
+// Create a token; the only time the token itself is returned
+pub async fn create_api_token(
+    State(state): State<Arc<crate::AppState>>,
+    Extension(user): Extension<AuthUser>,
+    Json(req): Json<CreateApiTokenRequest>,
+) -> Result<Json<serde_json::Value>, Response> {
+    let name = req.name.trim();
+    if name.is_empty() || name.len() > 64 {
+        return Err(bad_request("Token name must be 1-64 characters"));
+    }
+    if let Some(missing) = req.scopes.iter().flatten().find(|p| !user.has(**p)) {
+        return Err(bad_request(&format!("You don't have the {} permission", missing.as_str())));
+    }
+    let expires_at = match req.expires_in_days {
+        Some(0) => return Err(bad_request("Expiry must be at least one day")),
+        Some(days) if days > MAX_EXPIRY_DAYS => {
+            return Err(bad_request(&format!("Expiry must be at most {} days", MAX_EXPIRY_DAYS)))
+        }
+        Some(days) => Some((Utc::now() + Duration::days(days.into())).timestamp()),
+        None => None,
+    };
+
+    let token = generate_api_token();
+    let id = state
+        .db
+        .create_api_token(
+            &user.user_id,
+            name,
+            &hash_api_token(&token),
+            &token[..PREFIX_LEN],
+            req.scopes.as_deref(),
+            expires_at,
+        )
+        .await
+        .map_err(|e| internal_error(&format!("Failed to create API token: {}", e)))?;
+    tracing::info!("User {} created API token {} ({})", user.username, name, id);
+
+    Ok(Json(json!({
+        "id": id,
+        "token": token,
+        "message": "Copy the token now; it won't be shown again"
+    })))
+}
+
+// Revoke one of the caller's tokens
+pub async fn delete_api_token(
+    State(state): State<Arc<crate::AppState>>,
+    Extension(user): Extension<AuthUser>,
+    Path(id): Path<String>,
+) -> Result<Json<serde_json::Value>, Response> {
+    revoke(&state, &id, Some(&user.user_id)).await
+}
+
+// Every user's tokens (manage_users)
+pub async fn admin_list_api_tokens(
+    State(state): State<Arc<crate::AppState>>,
+) -> Result<Json<serde_json::Value>, Response> {
+    let tokens = state
+        .db
+        .list_api_tokens(None)
+        .await
+        .map_err(|e| internal_error(&format!("Failed to list API tokens: {}", e)))?;
+
+    Ok(Json(json!({
+        "tokens": tokens
+    })))
+}
+
+// Revoke any user's token (manage_users)
+pub async fn admin_delete_api_token(
+    State(state): State<Arc<crate::AppState>>,
+    Path(id): Path<String>,
+) -> Result<Json<serde_json::Value>, Response> {
+    revoke(&state, &id, None).await
+}
+
+async fn revoke(state: &crate::AppState, id: &str, user_id: Option<&str>) -> Result<Json<serde_json::Value>, Response> {
+    let revoked = state
+        .db
+        .delete_api_token(id, user_id)
+        .await
+        .map_err(|e| internal_error(&format!("Failed to revoke API token: {}", e)))?;
+    if !revoked {
+        return Err((
+            StatusCode::NOT_FOUND,
+            Json(json!({
+                "error": "Token not found"
+            })),
+        )
+            .into_response());
+    }
+
+    Ok(Json(json!({
+        "message": "Token revoked"
+    })))
+}
+
+fn bad_request(message: &str) -> Response {
+    (
+        StatusCode::BAD_REQUEST,
+        Json(json!({
+            "error": message
+        })),
+    )
+        .into_response()
+}
+
+fn internal_error(message: &str) -> Response {
+    (
+        StatusCode::INTERNAL_SERVER_ERROR,
+        Json(json!({
+            "error": message
+        })),
+    )
+        .into_response()
+}
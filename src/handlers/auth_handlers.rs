@@ -1,35 +1,432 @@
-use crate::models::{LoginRequest, LoginResponse};
+use crate::auth::{effective_permissions, has_second_factor, start_session, AuthUser, ClientInfo, Permission};
+use crate::login_throttle;
+use crate::models::{LoginRequest, LoginResponse, PasskeyLoginOptionsRequest, PasskeyLoginRequest, TotpLoginRequest, User};
+use crate::totp;
+use crate::webauthn::{self, ClientData};
 use axum::{
-    extract::State,
-    http::{header, StatusCode},
+    extract::{ConnectInfo, Extension, Path, State},
+    http::{header, HeaderMap, StatusCode},
     response::{IntoResponse, Response},
     Json,
 };
 use axum_extra::extract::cookie::{Cookie, SameSite};
+use base64::engine::general_purpose::URL_SAFE_NO_PAD;
+use base64::Engine;
 use serde_json::json;
+use std::net::SocketAddr;
 use std::sync::Arc;
 
+/// How long the second login step may take
+const CHALLENGE_SECS: i64 = 300;
+/// Wrong codes allowed per challenge before the password has to be entered again
+const MAX_CHALLENGE_ATTEMPTS: i64 = 5;
+
 pub async fn login(
     State(state): State<Arc<crate::AppState>>,
+    peer: Option<ConnectInfo<SocketAddr>>,
+    headers: HeaderMap,
     Json(req): Json<LoginRequest>,
 ) -> Result<Response, Response> {
+    if !state.config.security.password_login {
+        return Err((
+            StatusCode::FORBIDDEN,
+            Json(json!({
+                "error": "Password login is disabled, use single sign-on"
+            })),
+        )
+            .into_response());
+    }
+
+    let client = ClientInfo::from_request(
+        &headers,
+        peer.map(|ConnectInfo(addr)| addr),
+        &state.config.security.trusted_proxies,
+    );
+    let throttle = &state.config.security.login_throttle;
+    let keys = login_throttle::login_keys(throttle, &req.username, client.ip.as_deref());
+
+    // Refuse without checking the password while backing off or locked out
+    check_throttle(&state, &req.username, &client, &keys).await?;
+
     // Verify credentials
-    let user = state
+    let user = match state.db.verify_password(&req.username, &req.password).await {
+        Ok(user) => user,
+        Err(_) => {
+            return Err(login_failed(&state, &req.username, None, &client, &keys, "invalid_credentials", "Invalid username or password").await);
+        }
+    };
+
+    // With 2FA, the session is only created once the code or passkey is checked too
+    if has_second_factor(&state, &user) {
+        let challenge = state
+            .db
+            .create_login_challenge(&user.id, chrono::Utc::now().timestamp() + CHALLENGE_SECS)
+            .await
+            .map_err(|e| internal_error(&format!("Failed to start two-factor login: {}", e)))?;
+        return Ok(Json(json!({
+            "totp_required": true,
+            "challenge": challenge,
+            "totp": user.totp_enabled,
+            "passkey": user.passkeys_enabled && state.config.passkeys.enabled
+        }))
+        .into_response());
+    }
+
+    finish_login(&state, user, &client, "password").await
+}
+
+// Second login step for users with 2FA: a TOTP or recovery code
+pub async fn login_totp(
+    State(state): State<Arc<crate::AppState>>,
+    peer: Option<ConnectInfo<SocketAddr>>,
+    headers: HeaderMap,
+    Json(req): Json<TotpLoginRequest>,
+) -> Result<Response, Response> {
+    let client = ClientInfo::from_request(
+        &headers,
+        peer.map(|ConnectInfo(addr)| addr),
+        &state.config.security.trusted_proxies,
+    );
+
+    let (user_id, attempts) = state
+        .db
+        .attempt_login_challenge(&req.challenge)
+        .await
+        .map_err(|e| internal_error(&format!("Failed to load login challenge: {}", e)))?
+        .ok_or_else(login_expired)?;
+    let user = state.db.get_user_by_id(&user_id).await.map_err(|_| login_expired())?;
+    let throttle = &state.config.security.login_throttle;
+    let keys = login_throttle::login_keys(throttle, &user.username, client.ip.as_deref());
+
+    check_throttle(&state, &user.username, &client, &keys).await?;
+    if attempts > MAX_CHALLENGE_ATTEMPTS {
+        let _ = state.db.delete_login_challenge(&req.challenge).await;
+        return Err(login_expired());
+    }
+
+    let method = check_second_factor(&state, &user.id, &req.code)
+        .await
+        .map_err(|e| internal_error(&format!("Failed to check code: {}", e)))?;
+    let Some(method) = method else {
+        return Err(login_failed(&state, &user.username, Some(&user.id), &client, &keys, "invalid_totp", "Invalid code").await);
+    };
+
+    if let Err(e) = state.db.delete_login_challenge(&req.challenge).await {
+        tracing::warn!("Failed to delete login challenge: {}", e);
+    }
+    finish_login(&state, user, &client, method).await
+}
+
+// Start a passkey login: options for navigator.credentials.get(). With the
+// challenge of a password login only that user's passkeys are asked for, as
+// second factor; without, any passkey logs in on its own.
+pub async fn passkey_login_options(
+    State(state): State<Arc<crate::AppState>>,
+    Json(req): Json<PasskeyLoginOptionsRequest>,
+) -> Result<Json<serde_json::Value>, Response> {
+    if !state.config.passkeys.enabled {
+        return Err(passkeys_disabled());
+    }
+
+    let user_id = match &req.challenge {
+        Some(challenge) => Some(
+            state
+                .db
+                .get_login_challenge_user(challenge)
+                .await
+                .map_err(|e| internal_error(&format!("Failed to load login challenge: {}", e)))?
+                .ok_or_else(login_expired)?,
+        ),
+        None => None,
+    };
+    let allowed: Vec<String> = match &user_id {
+        Some(user_id) => state
+            .db
+            .list_passkeys(user_id)
+            .await
+            .map_err(|e| internal_error(&format!("Failed to list passkeys: {}", e)))?
+            .into_iter()
+            .map(|p| p.credential_id)
+            .collect(),
+        None => Vec::new(),
+    };
+
+    let challenge = crate::oidc::random_token();
+    state
         .db
-        .verify_password(&req.username, &req.password)
+        .create_passkey_challenge(
+            &challenge,
+            "login",
+            user_id.as_deref(),
+            chrono::Utc::now().timestamp() + webauthn::CHALLENGE_SECS,
+        )
         .await
-        .map_err(|_| {
+        .map_err(|e| internal_error(&format!("Failed to start passkey login: {}", e)))?;
+
+    // A passkey alone has to prove who holds it (PIN, biometrics)
+    Ok(Json(webauthn::request_options(
+        &state.config.passkeys,
+        &challenge,
+        &allowed,
+        user_id.is_none(),
+    )))
+}
+
+// Finish a passkey login with the browser's signed answer
+pub async fn login_passkey(
+    State(state): State<Arc<crate::AppState>>,
+    peer: Option<ConnectInfo<SocketAddr>>,
+    headers: HeaderMap,
+    Json(req): Json<PasskeyLoginRequest>,
+) -> Result<Response, Response> {
+    let config = &state.config.passkeys;
+    if !config.enabled {
+        return Err(passkeys_disabled());
+    }
+    let client = ClientInfo::from_request(
+        &headers,
+        peer.map(|ConnectInfo(addr)| addr),
+        &state.config.security.trusted_proxies,
+    );
+    let invalid = || {
+        (
+            StatusCode::UNAUTHORIZED,
+            Json(json!({
+                "error": "Passkey login failed"
+            })),
+        )
+            .into_response()
+    };
+    let credential = &req.credential;
+    let (Ok(client_data_json), Ok(authenticator_data), Ok(signature)) = (
+        URL_SAFE_NO_PAD.decode(&credential.client_data_json),
+        URL_SAFE_NO_PAD.decode(&credential.authenticator_data),
+        URL_SAFE_NO_PAD.decode(&credential.signature),
+    ) else {
+        return Err(invalid());
+    };
+
+    let client_data = ClientData::parse(config, &client_data_json, "webauthn.get").map_err(|e| {
+        tracing::warn!("Passkey login refused: {:#}", e);
+        invalid()
+    })?;
+    let challenge_user = state
+        .db
+        .take_passkey_challenge(&client_data.challenge, "login")
+        .await
+        .map_err(|e| internal_error(&format!("Failed to load passkey login: {}", e)))?
+        .ok_or_else(login_expired)?;
+    let passkey = state
+        .db
+        .get_passkey_by_credential_id(&credential.credential_id)
+        .await
+        .map_err(|e| internal_error(&format!("Failed to load passkey: {}", e)))?
+        .ok_or_else(invalid)?;
+    let user = state.db.get_user_by_id(&passkey.user_id).await.map_err(|_| invalid())?;
+
+    // As second factor the passkey must belong to the user who gave the password
+    let second_factor = challenge_user.is_some();
+    if let Some(challenge_user) = &challenge_user {
+        let login_challenge = req.challenge.as_deref().ok_or_else(login_expired)?;
+        let (user_id, attempts) = state
+            .db
+            .attempt_login_challenge(login_challenge)
+            .await
+            .map_err(|e| internal_error(&format!("Failed to load login challenge: {}", e)))?
+            .ok_or_else(login_expired)?;
+        if &user_id != challenge_user || user_id != user.id || attempts > MAX_CHALLENGE_ATTEMPTS {
+            return Err(login_expired());
+        }
+    } else if credential
+        .user_handle
+        .as_deref()
+        .is_some_and(|handle| handle != URL_SAFE_NO_PAD.encode(&user.id))
+    {
+        return Err(invalid());
+    }
+
+    let throttle = &state.config.security.login_throttle;
+    let keys = login_throttle::login_keys(throttle, &user.username, client.ip.as_deref());
+    check_throttle(&state, &user.username, &client, &keys).await?;
+    let sign_count = match webauthn::verify_assertion(
+        config,
+        &client_data_json,
+        &authenticator_data,
+        &signature,
+        &passkey.public_key,
+        passkey.sign_count as u32,
+        !second_factor,
+    ) {
+        Ok(sign_count) => sign_count,
+        Err(e) => {
+            tracing::warn!("Passkey login of {} refused: {:#}", user.username, e);
+            return Err(login_failed(&state, &user.username, Some(&user.id), &client, &keys, "invalid_passkey", "Passkey login failed").await);
+        }
+    };
+    if let Err(e) = state.db.use_passkey(&passkey.id, sign_count.into()).await {
+        tracing::warn!("Failed to update passkey: {}", e);
+    }
+    if let Some(login_challenge) = req.challenge.as_deref().filter(|_| second_factor) {
+        if let Err(e) = state.db.delete_login_challenge(login_challenge).await {
+            tracing::warn!("Failed to delete login challenge: {}", e);
+        }
+    }
+
+    finish_login(&state, user, &client, "passkey").await
+}
+
+fn passkeys_disabled() -> Response {
+    (
+        StatusCode::NOT_FOUND,
+        Json(json!({
+            "error": "Passkeys are not enabled"
+        })),
+    )
+        .into_response()
+}
+
+fn login_expired() -> Response {
+    (
+        StatusCode::UNAUTHORIZED,
+        Json(json!({
+            "error": "Login expired, please sign in again"
+        })),
+    )
+        .into_response()
+}
+
+// Behind an auth proxy: a login session for the user its headers name
+pub async fn login_proxy(
+    State(state): State<Arc<crate::AppState>>,
+    peer: Option<ConnectInfo<SocketAddr>>,
+    headers: HeaderMap,
+) -> Result<Response, Response> {
+    let peer = peer.map(|ConnectInfo(addr)| addr);
+    let user = crate::proxy_auth::identify(&state, peer, &headers)
+        .await
+        .map_err(|status| {
+            (
+                status,
+                Json(json!({
+                    "error": "Your account is not allowed to use this service"
+                })),
+            )
+                .into_response()
+        })?
+        .ok_or_else(|| {
             (
                 StatusCode::UNAUTHORIZED,
                 Json(json!({
-                    "error": "Invalid username or password"
+                    "error": "Not logged in through the auth proxy"
                 })),
             )
                 .into_response()
         })?;
 
-    // Create token
-    let token = state.auth.create_token(&user).map_err(|e| {
+    let client = ClientInfo::from_request(&headers, peer, &state.config.security.trusted_proxies);
+    finish_login(&state, user, &client, "proxy").await
+}
+
+/// A TOTP code (not used before) or an unused recovery code of `user_id`;
+/// returns which of the two it was
+pub async fn check_second_factor(state: &crate::AppState, user_id: &str, code: &str) -> anyhow::Result<Option<&'static str>> {
+    let Some(settings) = state.db.get_user_totp(user_id).await?.filter(|t| t.enabled) else {
+        return Ok(None);
+    };
+    if totp::is_totp_code(code) {
+        let now = chrono::Utc::now().timestamp() as u64;
+        if let Some(step) = totp::verify(&settings.secret, code, now, settings.last_step) {
+            if state.db.use_totp_step(user_id, step).await? {
+                return Ok(Some("totp"));
+            }
+        }
+        return Ok(None);
+    }
+    if state.db.use_recovery_code(user_id, &totp::hash_recovery_code(code)).await? {
+        return Ok(Some("recovery_code"));
+    }
+    Ok(None)
+}
+
+/// 429 with Retry-After while `keys` are backing off or locked out
+async fn check_throttle(
+    state: &crate::AppState,
+    username: &str,
+    client: &ClientInfo,
+    keys: &[(String, u32)],
+) -> Result<(), Response> {
+    let wait = login_throttle::retry_after(&state.db, &state.config.security.login_throttle, keys)
+        .await
+        .map_err(|e| internal_error(&format!("Failed to check login attempts: {}", e)))?;
+    if wait == 0 {
+        return Ok(());
+    }
+    audit(state, username, None, client, false, "throttled").await;
+    Err((
+        StatusCode::TOO_MANY_REQUESTS,
+        [(header::RETRY_AFTER, wait.to_string())],
+        Json(json!({
+            "error": format!("Too many failed logins, try again in {} seconds", wait),
+            "retry_after": wait
+        })),
+    )
+        .into_response())
+}
+
+/// Count and audit a failed attempt, returning the 401 for it
+async fn login_failed(
+    state: &crate::AppState,
+    username: &str,
+    user_id: Option<&str>,
+    client: &ClientInfo,
+    keys: &[(String, u32)],
+    reason: &str,
+    message: &str,
+) -> Response {
+    if let Err(e) = login_throttle::record_failure(&state.db, &state.config.security.login_throttle, keys).await {
+        tracing::warn!("Failed to record login failure: {}", e);
+    }
+    audit(state, username, user_id, client, false, reason).await;
+    (
+        StatusCode::UNAUTHORIZED,
+        Json(json!({
+            "error": message
+        })),
+    )
+        .into_response()
+}
+
+/// Start a session for a fully authenticated user; `method` goes into the audit trail
+pub async fn finish_login(state: &crate::AppState, user: User, client: &ClientInfo, method: &str) -> Result<Response, Response> {
+    let (response, cookie) = create_login(state, user, client, method).await?;
+
+    // Return JSON response with Set-Cookie header
+    Ok((
+        [(header::SET_COOKIE, cookie)],
+        Json(response),
+    )
+        .into_response())
+}
+
+/// The session behind [`finish_login`]: its response body and Set-Cookie value
+pub async fn create_login(
+    state: &crate::AppState,
+    user: User,
+    client: &ClientInfo,
+    method: &str,
+) -> Result<(LoginResponse, String), Response> {
+    if let Err(e) = state.db.clear_login_failures(&login_throttle::account_key(&user.username)).await {
+        tracing::warn!("Failed to reset login failures: {}", e);
+    }
+    audit(state, &user.username, Some(&user.id), client, true, method).await;
+
+    // Drop expired sessions now and then; revoked ones are deleted right away
+    if let Err(e) = state.db.delete_expired_sessions().await {
+        tracing::warn!("Failed to clean up expired sessions: {}", e);
+    }
+
+    // Create session and its token
+    let token = start_session(state, &user, client).await.map_err(|e| {
         (
             StatusCode::INTERNAL_SERVER_ERROR,
             Json(json!({
@@ -39,6 +436,15 @@ pub async fn login(
             .into_response()
     })?;
 
+    let permissions = effective_permissions(state, &user)
+        .await
+        .map_err(|e| internal_error(&format!("Failed to load permissions: {}", e)))?;
+    let role_permissions = state
+        .db
+        .get_role_permissions(&user.role)
+        .await
+        .map_err(|e| internal_error(&format!("Failed to load permissions: {}", e)))?;
+
     // Create HTTP-only cookie for browser-based auth
     let cookie = Cookie::build(("token", token.clone()))
         .path("/")
@@ -47,21 +453,29 @@ pub async fn login(
         .http_only(true)
         .build();
 
-    // Return JSON response with Set-Cookie header
-    let response = Json(LoginResponse {
+    let response = LoginResponse {
         token: token.clone(),
         username: user.username,
-        is_admin: user.is_admin,
-    });
+        is_admin: permissions.contains(&Permission::ManageUsers) || permissions.contains(&Permission::ManageConfig),
+        role: user.role,
+        totp_setup_required: role_permissions.len() > permissions.len(),
+        permissions,
+    };
 
-    Ok((
-        [(header::SET_COOKIE, cookie.to_string())],
-        response,
-    )
-        .into_response())
+    Ok((response, cookie.to_string()))
 }
 
-pub async fn logout() -> impl IntoResponse {
+pub async fn logout(
+    State(state): State<Arc<crate::AppState>>,
+    Extension(user): Extension<AuthUser>,
+) -> impl IntoResponse {
+    // Revoke this token's session so it can't be reused
+    if let Some(session_id) = &user.session_id {
+        if let Err(e) = state.db.delete_session(&user.user_id, session_id).await {
+            tracing::warn!("Failed to revoke session on logout: {}", e);
+        }
+    }
+
     // Clear the authentication cookie
     let cookie = Cookie::build(("token", ""))
         .path("/")
@@ -77,3 +491,82 @@ pub async fn logout() -> impl IntoResponse {
         })),
     )
 }
+
+// The caller's active sessions, newest activity first
+pub async fn list_sessions(
+    State(state): State<Arc<crate::AppState>>,
+    Extension(user): Extension<AuthUser>,
+) -> Result<Json<serde_json::Value>, Response> {
+    let mut sessions = state
+        .db
+        .list_sessions(&user.user_id)
+        .await
+        .map_err(|e| internal_error(&format!("Failed to list sessions: {}", e)))?;
+    for session in &mut sessions {
+        session.current = user.session_id.as_ref() == Some(&session.id);
+    }
+
+    Ok(Json(json!({
+        "sessions": sessions
+    })))
+}
+
+// Log out one of the caller's devices
+pub async fn revoke_session(
+    State(state): State<Arc<crate::AppState>>,
+    Extension(user): Extension<AuthUser>,
+    Path(session_id): Path<String>,
+) -> Result<Json<serde_json::Value>, Response> {
+    let revoked = state
+        .db
+        .delete_session(&user.user_id, &session_id)
+        .await
+        .map_err(|e| internal_error(&format!("Failed to revoke session: {}", e)))?;
+    if !revoked {
+        return Err((
+            StatusCode::NOT_FOUND,
+            Json(json!({
+                "error": "Session not found"
+            })),
+        )
+            .into_response());
+    }
+
+    Ok(Json(json!({
+        "message": "Session revoked"
+    })))
+}
+
+// Log out everywhere, including this session
+pub async fn revoke_all_sessions(
+    State(state): State<Arc<crate::AppState>>,
+    Extension(user): Extension<AuthUser>,
+) -> Result<Json<serde_json::Value>, Response> {
+    let revoked = state
+        .db
+        .delete_user_sessions(&user.user_id, None)
+        .await
+        .map_err(|e| internal_error(&format!("Failed to revoke sessions: {}", e)))?;
+
+    Ok(Json(json!({
+        "message": "Logged out everywhere",
+        "revoked": revoked
+    })))
+}
+
+/// Record a login attempt in the audit trail; a failure to do so doesn't block the login
+pub async fn audit(state: &crate::AppState, username: &str, user_id: Option<&str>, client: &ClientInfo, success: bool, reason: &str) {
+    if let Err(e) = state.db.create_auth_event(username, user_id, client, success, reason).await {
+        tracing::warn!("Failed to record login attempt: {}", e);
+    }
+}
+
+fn internal_error(message: &str) -> Response {
+    (
+        StatusCode::INTERNAL_SERVER_ERROR,
+        Json(json!({
+            "error": message
+        })),
+    )
+        .into_response()
+}
@@ -1,5 +1,12 @@
 pub mod admin;
+pub mod api_tokens;
 pub mod auth_handlers;
+pub mod oidc;
+pub mod passkeys;
+pub mod profiles;
+pub mod server_import;
 pub mod spotify;
+pub mod totp;
 pub mod upload;
+pub mod url_import;
 pub mod youtube;
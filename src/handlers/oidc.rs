@@ -0,0 +1,255 @@
+use crate::auth::ClientInfo;
+use crate::handlers::auth_handlers::{audit, create_login};
+use crate::models::User;
+use crate::oidc::{self, Identity};
+use crate::provisioning;
+use crate::templates::SsoTemplate;
+use axum::{
+    extract::{ConnectInfo, Query, State},
+    http::{header, HeaderMap, StatusCode},
+    response::{AppendHeaders, IntoResponse, Redirect, Response},
+    Json,
+};
+use axum_extra::extract::cookie::{Cookie, CookieJar, SameSite};
+use percent_encoding::{utf8_percent_encode, NON_ALPHANUMERIC};
+use serde::Deserialize;
+use serde_json::json;
+use std::net::SocketAddr;
+use std::sync::Arc;
+
+/// Ties a login to the browser that started it
+const STATE_COOKIE: &str = "oidc_state";
+
+/// Query the identity provider redirects back with
+#[derive(Debug, Deserialize)]
+pub struct CallbackParams {
+    code: Option<String>,
+    state: Option<String>,
+    error: Option<String>,
+    error_description: Option<String>,
+}
+
+// Start single sign-on: send the browser to the identity provider
+pub async fn oidc_login(State(state): State<Arc<crate::AppState>>) -> Response {
+    if !state.config.oidc.enabled {
+        return not_enabled();
+    }
+
+    match authorization_url(&state).await {
+        Ok((url, login_state)) => {
+            let cookie = state_cookie(login_state, oidc::LOGIN_TIMEOUT_SECS);
+            ([(header::SET_COOKIE, cookie)], Redirect::to(&url)).into_response()
+        }
+        Err(e) => {
+            tracing::error!("Failed to start single sign-on: {:#}", e);
+            login_error("Single sign-on is unavailable, please try again later")
+        }
+    }
+}
+
+/// Where to send the browser, and the state it must come back with
+async fn authorization_url(state: &crate::AppState) -> anyhow::Result<(String, String)> {
+    let config = &state.config.oidc;
+    let client = oidc::http_client(config)?;
+    let metadata = oidc::discover(&client, config).await?;
+
+    let login_state = oidc::random_token();
+    let nonce = oidc::random_token();
+    let pkce_verifier = oidc::random_token();
+    state
+        .db
+        .create_oidc_login(
+            &login_state,
+            &nonce,
+            &pkce_verifier,
+            chrono::Utc::now().timestamp() + oidc::LOGIN_TIMEOUT_SECS,
+        )
+        .await?;
+
+    let url = oidc::authorization_url(&metadata, config, &login_state, &nonce, &pkce_verifier)?;
+    Ok((url, login_state))
+}
+
+fn state_cookie(value: String, max_age_secs: i64) -> String {
+    Cookie::build((STATE_COOKIE, value))
+        .path("/api/oidc")
+        .max_age(time::Duration::seconds(max_age_secs))
+        .same_site(SameSite::Lax)
+        .http_only(true)
+        .build()
+        .to_string()
+}
+
+// The identity provider sends the browser back here with an authorization code
+pub async fn oidc_callback(
+    State(state): State<Arc<crate::AppState>>,
+    peer: Option<ConnectInfo<SocketAddr>>,
+    headers: HeaderMap,
+    jar: CookieJar,
+    Query(params): Query<CallbackParams>,
+) -> Response {
+    if !state.config.oidc.enabled {
+        return not_enabled();
+    }
+    if let Some(error) = params.error {
+        tracing::warn!("Identity provider refused the login: {} {:?}", error, params.error_description);
+        return login_error("Single sign-on was cancelled or refused");
+    }
+    let (Some(code), Some(login_state)) = (params.code, params.state) else {
+        return login_error("Invalid single sign-on response");
+    };
+
+    // Someone else's callback URL must not log this browser in as them
+    if jar.get(STATE_COOKIE).map(Cookie::value) != Some(login_state.as_str()) {
+        tracing::warn!("Single sign-on callback without the state cookie of its login");
+        return login_error("Login expired, please try again");
+    }
+
+    // The state is only good once, and only for this server's own logins
+    let pending = match state.db.take_oidc_login(&login_state).await {
+        Ok(Some(pending)) => pending,
+        Ok(None) => return login_error("Login expired, please try again"),
+        Err(e) => {
+            tracing::error!("Failed to load pending login: {:#}", e);
+            return login_error("Single sign-on failed");
+        }
+    };
+    let (nonce, pkce_verifier) = pending;
+
+    let identity = match authenticate(&state, &code, &nonce, &pkce_verifier).await {
+        Ok(identity) => identity,
+        Err(e) => {
+            tracing::warn!("Single sign-on failed: {:#}", e);
+            return login_error("Single sign-on failed");
+        }
+    };
+
+    let client = ClientInfo::from_request(
+        &headers,
+        peer.map(|ConnectInfo(addr)| addr),
+        &state.config.security.trusted_proxies,
+    );
+    let user = match resolve_user(&state, &identity, &client).await {
+        Ok(user) => user,
+        Err(response) => return response,
+    };
+
+    match create_login(&state, user, &client, "oidc").await {
+        Ok((login, cookie)) => {
+            let login = serde_json::to_string(&login).unwrap_or_default();
+            // The page carries the token, so it must not be cached
+            (
+                AppendHeaders([
+                    (header::SET_COOKIE, cookie),
+                    (header::SET_COOKIE, state_cookie(String::new(), 0)),
+                    (header::CACHE_CONTROL, "no-store".to_string()),
+                ]),
+                SsoTemplate { login },
+            )
+                .into_response()
+        }
+        Err(_) => login_error("Failed to log in"),
+    }
+}
+
+/// Trade the code for an ID token and validate it
+async fn authenticate(state: &crate::AppState, code: &str, nonce: &str, pkce_verifier: &str) -> anyhow::Result<Identity> {
+    let config = &state.config.oidc;
+    let client = oidc::http_client(config)?;
+    let metadata = oidc::discover(&client, config).await?;
+    let id_token = oidc::exchange_code(&client, &metadata, config, code, pkce_verifier).await?;
+    oidc::verify_id_token(&client, &metadata, config, &id_token, nonce).await
+}
+
+/// The local user of a provider account: already linked, linked by
+/// `match_claim`, or provisioned. Mapped groups update the role.
+async fn resolve_user(state: &crate::AppState, identity: &Identity, client: &ClientInfo) -> Result<User, Response> {
+    let config = &state.config.oidc;
+
+    let groups = identity.groups(&config.groups_claim);
+    let Some(role) = provisioning::map_role(&config.role_mapping, config.default_role.as_deref(), &groups) else {
+        return Err(denied(state, identity, client, "Your account is not allowed to use this service").await);
+    };
+    if !state.db.role_exists(&role).await.map_err(failed)? {
+        return Err(failed(anyhow::anyhow!("Role {} from [oidc] doesn't exist", role)));
+    }
+
+    let mut user = match state.db.get_user_by_oidc_subject(&identity.subject).await.map_err(failed)? {
+        Some(user) => user,
+        None => link_or_provision(state, identity, client, &role).await?,
+    };
+
+    // With group mappings, the provider decides the role
+    if !config.role_mapping.is_empty() && user.role != role {
+        state.db.update_user_role(&user.id, &role).await.map_err(failed)?;
+        user.role = role;
+    }
+
+    Ok(user)
+}
+
+async fn link_or_provision(
+    state: &crate::AppState,
+    identity: &Identity,
+    client: &ClientInfo,
+    role: &str,
+) -> Result<User, Response> {
+    let config = &state.config.oidc;
+
+    // An existing account with the username the provider vouches for
+    if let Some(value) = config.match_claim.as_deref().and_then(|claim| identity.claim(claim)) {
+        if let Ok(user) = state.db.get_user_by_username(value).await {
+            if !state.db.set_oidc_subject(&user.id, &identity.subject).await.map_err(failed)? {
+                return Err(denied(state, identity, client, "This account is linked to another single sign-on login").await);
+            }
+            tracing::info!("Linked user {} to {}", user.username, identity.subject);
+            return Ok(user);
+        }
+    }
+
+    if !config.auto_provision {
+        return Err(denied(state, identity, client, "No account exists for this login, ask an administrator").await);
+    }
+    let Some(username) = identity.claim(&config.username_claim).filter(|name| name.len() >= 3) else {
+        return Err(denied(state, identity, client, "The identity provider did not send a usable username").await);
+    };
+    // Taking over a local account needs match_claim
+    if state.db.get_user_by_username(username).await.is_ok() {
+        return Err(denied(state, identity, client, "An account with this username already exists").await);
+    }
+
+    let user = provisioning::create_external_user(&state.db, username, role, config.library_path_template.as_deref())
+        .await
+        .map_err(failed)?;
+    state.db.set_oidc_subject(&user.id, &identity.subject).await.map_err(failed)?;
+    tracing::info!("Provisioned user {} for {}", user.username, identity.subject);
+
+    Ok(user)
+}
+
+/// Audit a refused login and send the browser back to the login page with `message`
+async fn denied(state: &crate::AppState, identity: &Identity, client: &ClientInfo, message: &str) -> Response {
+    let name = identity.claim(&state.config.oidc.username_claim).unwrap_or(&identity.subject);
+    audit(state, name, None, client, false, "oidc_denied").await;
+    login_error(message)
+}
+
+fn failed(e: anyhow::Error) -> Response {
+    tracing::error!("Single sign-on failed: {:#}", e);
+    login_error("Single sign-on failed")
+}
+
+/// Back to the login page, which shows `message`
+fn login_error(message: &str) -> Response {
+    Redirect::to(&format!("/?error={}", utf8_percent_encode(message, NON_ALPHANUMERIC))).into_response()
+}
+
+fn not_enabled() -> Response {
+    (
+        StatusCode::NOT_FOUND,
+        Json(json!({
+            "error": "Single sign-on is not enabled"
+        })),
+    )
+        .into_response()
+}
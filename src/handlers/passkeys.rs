@@ -0,0 +1,222 @@
+use crate::auth::AuthUser;
+use crate::models::{RegisterPasskeyRequest, RenamePasskeyRequest};
+use crate::webauthn::{self, ClientData};
+use axum::{
+    extract::{Extension, Path, State},
+    http::StatusCode,
+    response::{IntoResponse, Response},
+    Json,
+};
+use base64::engine::general_purpose::URL_SAFE_NO_PAD;
+use base64::Engine;
+use serde_json::json;
+use std::sync::Arc;
+
+// The caller's passkeys
+pub async fn list_passkeys(
+    State(state): State<Arc<crate::AppState>>,
+    Extension(user): Extension<AuthUser>,
+) -> Result<Json<serde_json::Value>, Response> {
+    let passkeys = state
+        .db
+        .list_passkeys(&user.user_id)
+        .await
+        .map_err(|e| internal_error(&format!("Failed to list passkeys: {}", e)))?;
+
+    Ok(Json(json!({
+        "enabled": state.config.passkeys.enabled,
+        "passkeys": passkeys
+    })))
+}
+
+// Start adding a passkey: options for navigator.credentials.create()
+pub async fn passkey_registration_options(
+    State(state): State<Arc<crate::AppState>>,
+    Extension(user): Extension<AuthUser>,
+) -> Result<Json<serde_json::Value>, Response> {
+    if !state.config.passkeys.enabled {
+        return Err(not_enabled());
+    }
+    let existing: Vec<String> = state
+        .db
+        .list_passkeys(&user.user_id)
+        .await
+        .map_err(|e| internal_error(&format!("Failed to list passkeys: {}", e)))?
+        .into_iter()
+        .map(|p| p.credential_id)
+        .collect();
+
+    let challenge = crate::oidc::random_token();
+    state
+        .db
+        .create_passkey_challenge(
+            &challenge,
+            "register",
+            Some(&user.user_id),
+            chrono::Utc::now().timestamp() + webauthn::CHALLENGE_SECS,
+        )
+        .await
+        .map_err(|e| internal_error(&format!("Failed to start registration: {}", e)))?;
+
+    Ok(Json(webauthn::creation_options(
+        &state.config.passkeys,
+        &challenge,
+        &user.user_id,
+        &user.username,
+        &existing,
+    )))
+}
+
+// Finish adding a passkey with the browser's answer
+pub async fn register_passkey(
+    State(state): State<Arc<crate::AppState>>,
+    Extension(user): Extension<AuthUser>,
+    Json(req): Json<RegisterPasskeyRequest>,
+) -> Result<Json<serde_json::Value>, Response> {
+    if !state.config.passkeys.enabled {
+        return Err(not_enabled());
+    }
+    let name = req.name.trim();
+    if name.is_empty() || name.len() > 64 {
+        return Err(bad_request("Passkey name must be 1-64 characters"));
+    }
+    let config = &state.config.passkeys;
+    let (Ok(client_data_json), Ok(attestation_object)) = (
+        URL_SAFE_NO_PAD.decode(&req.credential.client_data_json),
+        URL_SAFE_NO_PAD.decode(&req.credential.attestation_object),
+    ) else {
+        return Err(bad_request("Invalid passkey response"));
+    };
+
+    let client_data = ClientData::parse(config, &client_data_json, "webauthn.create")
+        .map_err(|e| bad_request(&format!("Passkey not added: {}", e)))?;
+    let challenge_user = state
+        .db
+        .take_passkey_challenge(&client_data.challenge, "register")
+        .await
+        .map_err(|e| internal_error(&format!("Failed to load registration: {}", e)))?;
+    if challenge_user != Some(Some(user.user_id.clone())) {
+        return Err(bad_request("Registration expired, please try again"));
+    }
+    let credential = webauthn::verify_registration(config, &attestation_object)
+        .map_err(|e| bad_request(&format!("Passkey not added: {}", e)))?;
+
+    let exists = state
+        .db
+        .get_passkey_by_credential_id(&credential.credential_id)
+        .await
+        .map_err(|e| internal_error(&format!("Failed to load passkey: {}", e)))?
+        .is_some();
+    if exists {
+        return Err((
+            StatusCode::CONFLICT,
+            Json(json!({
+                "error": "This passkey is already registered"
+            })),
+        )
+            .into_response());
+    }
+    let id = state
+        .db
+        .create_passkey(
+            &user.user_id,
+            name,
+            &credential.credential_id,
+            &credential.public_key,
+            credential.sign_count.into(),
+        )
+        .await
+        .map_err(|e| internal_error(&format!("Failed to save passkey: {}", e)))?;
+    tracing::info!("User {} added passkey {} ({})", user.username, name, id);
+
+    Ok(Json(json!({
+        "id": id,
+        "message": "Passkey added"
+    })))
+}
+
+// Rename one of the caller's passkeys
+pub async fn rename_passkey(
+    State(state): State<Arc<crate::AppState>>,
+    Extension(user): Extension<AuthUser>,
+    Path(id): Path<String>,
+    Json(req): Json<RenamePasskeyRequest>,
+) -> Result<Json<serde_json::Value>, Response> {
+    let name = req.name.trim();
+    if name.is_empty() || name.len() > 64 {
+        return Err(bad_request("Passkey name must be 1-64 characters"));
+    }
+    let renamed = state
+        .db
+        .rename_passkey(&id, &user.user_id, name)
+        .await
+        .map_err(|e| internal_error(&format!("Failed to rename passkey: {}", e)))?;
+    if !renamed {
+        return Err(not_found());
+    }
+
+    Ok(Json(json!({
+        "message": "Passkey renamed"
+    })))
+}
+
+// Remove one of the caller's passkeys
+pub async fn delete_passkey(
+    State(state): State<Arc<crate::AppState>>,
+    Extension(user): Extension<AuthUser>,
+    Path(id): Path<String>,
+) -> Result<Json<serde_json::Value>, Response> {
+    let deleted = state
+        .db
+        .delete_passkey(&id, &user.user_id)
+        .await
+        .map_err(|e| internal_error(&format!("Failed to delete passkey: {}", e)))?;
+    if !deleted {
+        return Err(not_found());
+    }
+    tracing::info!("User {} removed passkey {}", user.username, id);
+
+    Ok(Json(json!({
+        "message": "Passkey removed"
+    })))
+}
+
+fn not_enabled() -> Response {
+    (
+        StatusCode::NOT_FOUND,
+        Json(json!({
+            "error": "Passkeys are not enabled"
+        })),
+    )
+        .into_response()
+}
+
+fn not_found() -> Response {
+    (
+        StatusCode::NOT_FOUND,
+        Json(json!({
+            "error": "Passkey not found"
+        })),
+    )
+        .into_response()
+}
+
+fn bad_request(message: &str) -> Response {
+    (
+        StatusCode::BAD_REQUEST,
+        Json(json!({
+            "error": message
+        })),
+    )
+        .into_response()
+}
+
+fn internal_error(message: &str) -> Response {
+    (
+        StatusCode::INTERNAL_SERVER_ERROR,
+        Json(json!({
+            "error": message
+        })),
+    )
+        .into_response()
+}
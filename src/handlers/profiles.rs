@@ -0,0 +1,126 @@
+use crate::auth::{AuthUser, Permission};
+use crate::models::{UpdateAllowedProfilesRequest, UpdateDefaultProfileRequest};
+use axum::{
+    extract::{Extension, State},
+    http::StatusCode,
+    response::{IntoResponse, Response},
+    Json,
+};
+use serde_json::json;
+use std::sync::Arc;
+
+// List download profiles (config managers also see profiles that are not allowed)
+pub async fn list_profiles(
+    State(state): State<Arc<crate::AppState>>,
+    Extension(user): Extension<AuthUser>,
+) -> Result<Json<serde_json::Value>, Response> {
+    let db_user = state
+        .db
+        .get_user_by_id(&user.user_id)
+        .await
+        .map_err(|e| internal_error(&format!("Failed to get user: {}", e)))?;
+
+    let allowed = state
+        .db
+        .get_allowed_profiles(&state.config)
+        .await
+        .map_err(|e| internal_error(&format!("Failed to get allowed profiles: {}", e)))?;
+
+    let profiles: Vec<serde_json::Value> = state
+        .config
+        .profiles
+        .iter()
+        .filter(|(name, _)| user.has(Permission::ManageConfig) || allowed.contains(name))
+        .map(|(name, profile)| {
+            json!({
+                "name": name,
+                "description": profile.description,
+                "audio_format": profile.audio_format,
+                "audio_quality": profile.audio_quality,
+                "bitrate": profile.bitrate,
+                "allowed": allowed.contains(name),
+            })
+        })
+        .collect();
+
+    Ok(Json(json!({
+        "profiles": profiles,
+        "default_profile": db_user.default_profile,
+    })))
+}
+
+// Set own default download profile
+pub async fn update_default_profile(
+    State(state): State<Arc<crate::AppState>>,
+    Extension(user): Extension<AuthUser>,
+    Json(req): Json<UpdateDefaultProfileRequest>,
+) -> Result<Json<serde_json::Value>, Response> {
+    let profile = req
+        .profile
+        .as_deref()
+        .map(str::trim)
+        .filter(|name| !name.is_empty());
+
+    if let Some(name) = profile {
+        let allowed = state
+            .db
+            .get_allowed_profiles(&state.config)
+            .await
+            .map_err(|e| internal_error(&format!("Failed to get allowed profiles: {}", e)))?;
+
+        crate::profiles::select_profile_name(&state.config, &allowed, Some(name), None)
+            .map_err(|e| (e.status(), Json(json!({ "error": e.to_string() }))).into_response())?;
+    }
+
+    state
+        .db
+        .update_default_profile(&user.user_id, profile)
+        .await
+        .map_err(|e| internal_error(&format!("Failed to update default profile: {}", e)))?;
+
+    Ok(Json(json!({
+        "message": "Default profile updated successfully",
+        "default_profile": profile
+    })))
+}
+
+// Choose which profiles users may pick (manage_config)
+pub async fn update_allowed_profiles(
+    State(state): State<Arc<crate::AppState>>,
+    Json(req): Json<UpdateAllowedProfilesRequest>,
+) -> Result<Json<serde_json::Value>, Response> {
+    if let Some(unknown) = req
+        .allowed
+        .iter()
+        .find(|name| !state.config.profiles.contains_key(name.as_str()))
+    {
+        return Err((
+            StatusCode::BAD_REQUEST,
+            Json(json!({
+                "error": format!("Unknown download profile: {}", unknown)
+            })),
+        )
+            .into_response());
+    }
+
+    state
+        .db
+        .set_config("allowed_profiles", &req.allowed.join(","))
+        .await
+        .map_err(|e| internal_error(&format!("Failed to update allowed profiles: {}", e)))?;
+
+    Ok(Json(json!({
+        "message": "Allowed profiles updated successfully",
+        "allowed": req.allowed
+    })))
+}
+
+fn internal_error(message: &str) -> Response {
+    (
+        StatusCode::INTERNAL_SERVER_ERROR,
+        Json(json!({
+            "error": message
+        })),
+    )
+        .into_response()
+}
@@ -0,0 +1,277 @@
+use crate::auth::{AuthUser, Permission};
+use crate::config::ImportRoot;
+use crate::import_roots::{imported_sources, list_dir, remove_sources, resolve, stage_selection};
+use crate::models::{BrowseQuery, CreateUploadLog, ImportMode, ServerImportRequest, UploadResponse};
+use crate::paths::{ensure_directory_exists, get_user_directories};
+use crate::pipeline::{
+    apply_metadata_overrides, decode_cover_image, import_job_dir, run_post_processing, stage_cover_image,
+    validate_staged_files, JobContext,
+};
+use axum::{
+    extract::{Extension, Query, State},
+    http::StatusCode,
+    response::{IntoResponse, Response},
+    Json,
+};
+use serde_json::json;
+use std::sync::Arc;
+use tokio::fs;
+
+/// Import roots the user may use
+pub async fn list_import_roots(
+    State(state): State<Arc<crate::AppState>>,
+    Extension(user): Extension<AuthUser>,
+) -> Json<serde_json::Value> {
+    let roots: Vec<_> = state
+        .config
+        .import_roots
+        .iter()
+        .filter(|(_, root)| root.allows(&user.username, user.has(Permission::ManageConfig)))
+        .map(|(name, root)| {
+            json!({
+                "name": name,
+                "description": root.description,
+                "allow_move": root.allow_move,
+            })
+        })
+        .collect();
+
+    Json(json!({ "roots": roots }))
+}
+
+/// List a folder inside an import root
+pub async fn browse_import_root(
+    State(state): State<Arc<crate::AppState>>,
+    Extension(user): Extension<AuthUser>,
+    Query(query): Query<BrowseQuery>,
+) -> Result<Json<serde_json::Value>, Response> {
+    let root = find_root(&state, &user, &query.root).ok_or_else(|| root_not_found(&query.root))?;
+    let dir = resolve(root, &query.path).map_err(bad_request)?;
+    if !dir.is_dir() {
+        return Err(bad_request(format!("{} is not a folder", query.path)));
+    }
+
+    let base = resolve(root, "").map_err(bad_request)?;
+    let allowed = state.config.upload.allowed_extensions.clone();
+    let entries = tokio::task::spawn_blocking(move || list_dir(&base, &dir, &allowed))
+        .await
+        .map_err(|e| internal_error(&format!("Failed to list folder: {}", e)))?
+        .map_err(|e| internal_error(&format!("Failed to list folder: {:#}", e)))?;
+
+    Ok(Json(json!({
+        "root": query.root,
+        "path": query.path,
+        "entries": entries,
+    })))
+}
+
+/// Import files and folders from an import root through the normal ingest pipeline
+pub async fn import_from_root(
+    State(state): State<Arc<crate::AppState>>,
+    Extension(user): Extension<AuthUser>,
+    Json(req): Json<ServerImportRequest>,
+) -> Result<Json<UploadResponse>, Response> {
+    let root = find_root(&state, &user, &req.root).ok_or_else(|| root_not_found(&req.root))?;
+    if req.mode == ImportMode::Move && !root.allow_move {
+        return Err(bad_request(format!("Moving files out of {} is not allowed", req.root)));
+    }
+    if req.paths.is_empty() {
+        return Err(bad_request("No files selected"));
+    }
+
+    // Resolve every selected path up front; the root itself can't be imported
+    let base = resolve(root, "").map_err(bad_request)?;
+    let selection = req
+        .paths
+        .iter()
+        .map(|p| match resolve(root, p) {
+            Ok(path) if path == base => Err("Select files or folders inside the root".to_string()),
+            resolved => resolved,
+        })
+        .collect::<Result<Vec<_>, _>>()
+        .map_err(bad_request)?;
+
+    // Validate metadata overrides before doing any work
+    let overrides = req.metadata.clone().normalized().map_err(bad_request)?;
+    let cover_data = overrides
+        .cover_image
+        .as_deref()
+        .map(decode_cover_image)
+        .transpose()
+        .map_err(bad_request)?;
+
+    // Get user from database to access library_path
+    let db_user = state
+        .db
+        .get_user_by_id(&user.user_id)
+        .await
+        .map_err(|e| internal_error(&format!("Failed to get user: {}", e)))?;
+
+    // Get user-specific directories
+    let (music_dir, temp_dir) = get_user_directories(&state.config, &db_user.library_path)
+        .await
+        .map_err(|e| internal_error(&format!("Failed to get user directories: {}", e)))?;
+
+    let source = format!("{}:{}", req.root, req.paths.join(", "));
+    tracing::info!("User {} importing {} ({:?}) into {}", user.username, source, req.mode, music_dir.display());
+
+    // Create upload log
+    let log_id = state
+        .db
+        .create_upload_log(CreateUploadLog {
+            user_id: user.user_id.clone(),
+            upload_type: "server".to_string(),
+            source,
+        })
+        .await
+        .map_err(|e| internal_error(&format!("Failed to create upload log: {}", e)))?;
+
+    // Update status to processing
+    state
+        .db
+        .update_upload_log_status(log_id, "processing", None, None)
+        .await
+        .map_err(|e| internal_error(&format!("Failed to update log: {}", e)))?;
+
+    // Each job gets its own staging directory so only its files are processed
+    let job_dir = temp_dir.join(format!("server-{}", uuid::Uuid::new_v4()));
+    ensure_directory_exists(&job_dir)
+        .await
+        .map_err(|e| internal_error(&format!("Failed to create job directory: {}", e)))?;
+
+    let cover = match &cover_data {
+        Some(data) => Some(
+            stage_cover_image(&job_dir, data)
+                .await
+                .map_err(|e| internal_error(&format!("Failed to save cover image: {}", e)))?,
+        ),
+        None => None,
+    };
+
+    // Files are always copied; a move deletes the sources only after the import
+    // succeeded, so a failed job never loses them
+    let staged = {
+        let (base, selection, job_dir) = (base.clone(), selection.clone(), job_dir.clone());
+        let allowed = state.config.upload.allowed_extensions.clone();
+        tokio::task::spawn_blocking(move || stage_selection(&base, &selection, &job_dir, &allowed))
+            .await
+            .map_err(anyhow::Error::from)
+            .and_then(|r| r)
+    };
+
+    let response = match staged {
+        Ok(staged) if staged.audio == 0 => Err((StatusCode::BAD_REQUEST, "The selection contains no audio files".to_string())),
+        Ok(staged) => {
+            let details = json!({
+                "root": req.root,
+                "mode": req.mode,
+                "copied": staged.sources.len(),
+                "skipped": staged.skipped,
+            });
+            state.db.add_upload_log_detail(log_id, "server_import", &details).await.ok();
+
+            // Same validation and ingest path as uploaded files
+            let processed = async {
+                let job = JobContext {
+                    user_id: &user.user_id,
+                    log_id,
+                    job_dir: &job_dir,
+                    music_dir: &music_dir,
+                };
+                let validation = validate_staged_files(&state, &job_dir, log_id).await?;
+                apply_metadata_overrides(&state, &job_dir, &overrides, cover.as_deref()).await?;
+                run_post_processing(&state, &job).await?;
+                let imported = import_job_dir(&state, &job).await?;
+                Ok::<_, anyhow::Error>(((staged.audio - validation.rejected.len()) as i32, imported))
+            }
+            .await;
+
+            match processed {
+                Ok((file_count, imported)) => {
+                    let allowed = &state.config.upload.allowed_extensions;
+                    Ok((file_count, imported_sources(&staged, &imported, allowed)))
+                }
+                Err(e) => Err((StatusCode::INTERNAL_SERVER_ERROR, format!("Processing failed: {}", e))),
+            }
+        }
+        Err(e) => Err((StatusCode::BAD_REQUEST, format!("Import failed: {:#}", e))),
+    };
+
+    // Leftover files (failed jobs) are discarded with the job directory
+    fs::remove_dir_all(&job_dir).await.ok();
+
+    let (file_count, sources) = match response {
+        Ok(imported) => imported,
+        Err((status, error_msg)) => {
+            state
+                .db
+                .update_upload_log_status(log_id, "failed", Some(0), Some(error_msg.clone()))
+                .await
+                .ok();
+            return Err((status, Json(json!({ "error": error_msg }))).into_response());
+        }
+    };
+
+    let mut message = format!("Successfully imported and processed {} file(s)", file_count);
+    let mut note = None;
+    if req.mode == ImportMode::Move {
+        let removed = tokio::task::spawn_blocking(move || remove_sources(&selection, &sources))
+            .await
+            .map_err(anyhow::Error::from)
+            .and_then(|r| r);
+        if let Err(e) = removed {
+            tracing::warn!("Failed to remove imported source files: {:#}", e);
+            let warning = format!("Some source files could not be removed: {:#}", e);
+            message.push_str(&format!("; {}", warning));
+            note = Some(warning);
+        }
+    }
+
+    state
+        .db
+        .update_upload_log_status(log_id, "completed", Some(file_count), note)
+        .await
+        .map_err(|e| internal_error(&format!("Failed to update log: {}", e)))?;
+
+    Ok(Json(UploadResponse {
+        success: true,
+        message,
+        log_id: Some(log_id),
+        session_id: None,
+        details: None,
+    }))
+}
+
+/// Look up an import root the user may access
+fn find_root<'a>(state: &'a crate::AppState, user: &AuthUser, name: &str) -> Option<&'a ImportRoot> {
+    state
+        .config
+        .import_roots
+        .get(name)
+        .filter(|root| root.allows(&user.username, user.has(Permission::ManageConfig)))
+}
+
+/// Unknown and forbidden roots get the same answer
+fn root_not_found(name: &str) -> Response {
+    (
+        StatusCode::NOT_FOUND,
+        Json(json!({
+            "error": format!("Import root {} not found", name)
+        })),
+    )
+        .into_response()
+}
+
+fn bad_request(message: impl Into<String>) -> Response {
+    (StatusCode::BAD_REQUEST, Json(json!({ "error": message.into() }))).into_response()
+}
+
+fn internal_error(message: &str) -> Response {
+    (
+        StatusCode::INTERNAL_SERVER_ERROR,
+        Json(json!({
+            "error": message
+        })),
+    )
+        .into_response()
+}
@@ -1,15 +1,24 @@
 use crate::auth::AuthUser;
-use crate::config::Config;
+use crate::config::{Config, DownloadProfile};
 use crate::models::{CreateUploadLog, SpotifyDownloadRequest, UploadResponse};
-use crate::paths::get_user_directories;
+use crate::handlers::youtube::{
+    build_ytdlp_download_args, pick_best_candidate, run_ytdlp, search_ytdlp,
+};
+use crate::paths::{ensure_directory_exists, get_user_directories, sanitize_path_component};
+use crate::pipeline::{
+    apply_metadata_overrides, decode_cover_image, import_job_dir, run_post_processing,
+    stage_cover_image, validate_staged_files, JobContext,
+};
+use crate::tagging::{write_tags, TrackTags};
 use axum::{
     extract::{Extension, State},
     http::StatusCode,
     response::{IntoResponse, Response},
     Json,
 };
+use serde::Deserialize;
 use serde_json::json;
-use std::path::PathBuf;
+use std::path::{Path, PathBuf};
 use std::sync::Arc;
 use tokio::fs;
 
@@ -76,6 +85,27 @@ pub async fn download_spotify(
             .into_response());
     }
 
+    // Validate metadata overrides before doing any work
+    let overrides = req.metadata.clone().normalized().map_err(|e| {
+        (StatusCode::BAD_REQUEST, Json(json!({ "error": e }))).into_response()
+    })?;
+    let cover_data = overrides
+        .cover_image
+        .as_deref()
+        .map(decode_cover_image)
+        .transpose()
+        .map_err(|e| (StatusCode::BAD_REQUEST, Json(json!({ "error": e }))).into_response())?;
+
+    // Pick the download profile (request, then the user's default, then [spotify] settings)
+    let selected = crate::profiles::resolve_profile(
+        &state,
+        &db_user,
+        req.profile.as_deref(),
+        state.config.legacy_spotify_profile(),
+    )
+    .await
+    .map_err(|e| (e.status(), Json(json!({ "error": e.to_string() }))).into_response())?;
+
     // Send initial progress
     crate::progress::send_progress(&state.progress_store, &session_id, "Starting Spotify download...".to_string()).await;
 
@@ -97,19 +127,75 @@ pub async fn download_spotify(
         .await
         .map_err(|e| internal_error(&format!("Failed to update log: {}", e)))?;
 
+    // Each job gets its own staging directory so only its files are processed
+    let job_dir = temp_dir.join(format!("spotify-{}", session_id));
+    ensure_directory_exists(&job_dir)
+        .await
+        .map_err(|e| internal_error(&format!("Failed to create job directory: {}", e)))?;
+
+    let cover = match &cover_data {
+        Some(data) => Some(
+            stage_cover_image(&job_dir, data)
+                .await
+                .map_err(|e| internal_error(&format!("Failed to save cover image: {}", e)))?,
+        ),
+        None => None,
+    };
+
+    let fallback_enabled = spotify_fallback_enabled(&state, &req).await;
+
     // Download with spotdl
     crate::progress::send_progress(&state.progress_store, &session_id, "Downloading from Spotify...".to_string()).await;
-    let result = download_with_spotdl(&state.config, &temp_dir, &req.url).await;
+    let outcome = download_with_spotdl(
+        &state,
+        &selected.profile,
+        &temp_dir,
+        &job_dir,
+        url,
+        &session_id,
+        fallback_enabled,
+    )
+    .await;
+    let result = match outcome {
+        Ok(outcome) if outcome.file_count > 0 => Ok(outcome),
+        Ok(outcome) => Err(anyhow::anyhow!(
+            "{}",
+            outcome
+                .spotdl_error
+                .unwrap_or_else(|| "spotdl produced no files".to_string())
+        )),
+        Err(e) => Err(e),
+    };
+
+    let response = match result {
+        Ok(outcome) => {
+            let file_count = outcome.file_count;
+            let mut details = outcome.details();
+            if let Some(name) = &selected.name {
+                details["profile"] = json!(name);
+            }
+            state.db.set_upload_log_details(log_id, &details).await.ok();
 
-    match result {
-        Ok(file_count) => {
             // Process with Ferric (check database for ferric_enabled setting)
             crate::progress::send_progress(&state.progress_store, &session_id, format!("Downloaded {} file(s), now processing...", file_count)).await;
-            match process_temp_dir(&state, &temp_dir, &music_dir).await {
+            let processed = async {
+                let job = JobContext {
+                    user_id: &user.user_id,
+                    log_id,
+                    job_dir: &job_dir,
+                    music_dir: &music_dir,
+                };
+                validate_staged_files(&state, &job_dir, log_id).await?;
+                apply_metadata_overrides(&state, &job_dir, &overrides, cover.as_deref()).await?;
+                run_post_processing(&state, &job).await?;
+                import_job_dir(&state, &job).await
+            }
+            .await;
+            match processed {
                 Ok(_) => {
                     state
                         .db
-                        .update_upload_log_status(log_id, "completed", Some(file_count), None)
+                        .update_upload_log_status(log_id, "completed", Some(file_count), outcome.note())
                         .await
                         .map_err(|e| internal_error(&format!("Failed to update log: {}", e)))?;
 
@@ -122,14 +208,31 @@ pub async fn download_spotify(
                         crate::progress::unregister_session(&store, &sid).await;
                     });
 
+                    let mut message = format!(
+                        "Successfully downloaded and processed {} file(s)",
+                        file_count
+                    );
+                    if !outcome.fallback_tracks.is_empty() {
+                        message.push_str(&format!(
+                            " ({} via YouTube fallback)",
+                            outcome.fallback_tracks.len()
+                        ));
+                    }
+                    if !outcome.unmatched_tracks.is_empty() {
+                        message.push_str(&format!(
+                            "; {} track(s) could not be found",
+                            outcome.unmatched_tracks.len()
+                        ));
+                    } else if outcome.note().is_some() {
+                        message.push_str("; spotdl reported errors, some tracks may be missing");
+                    }
+
                     Ok(Json(UploadResponse {
                         success: true,
-                        message: format!(
-                            "Successfully downloaded and processed {} file(s)",
-                            file_count
-                        ),
+                        message,
                         log_id: Some(log_id),
                         session_id: Some(session_id),
+                        details: Some(details),
                     }))
                 }
                 Err(e) => {
@@ -167,104 +270,339 @@ pub async fn download_spotify(
             )
                 .into_response())
         }
+    };
+
+    // Leftover files (failed jobs) are discarded with the job directory
+    fs::remove_dir_all(&job_dir).await.ok();
+
+    response
+}
+
+/// Whether to use the YouTube fallback for this request
+/// The request field wins, then the database setting, then config.toml.
+/// YouTube downloads must be enabled for the fallback to run at all.
+async fn spotify_fallback_enabled(
+    state: &Arc<crate::AppState>,
+    req: &SpotifyDownloadRequest,
+) -> bool {
+    if !state.config.youtube.enabled {
+        return false;
+    }
+
+    match req.youtube_fallback {
+        Some(enabled) => enabled,
+        None => state
+            .db
+            .get_spotify_youtube_fallback(&state.config)
+            .await
+            .unwrap_or(state.config.spotify.youtube_fallback),
+    }
+}
+
+/// Result of a Spotify download job, including fallback bookkeeping
+#[derive(Debug, Default)]
+struct SpotifyDownloadOutcome {
+    file_count: i32,
+    /// Tracks that were fetched from YouTube instead of by spotdl
+    fallback_tracks: Vec<String>,
+    /// Tracks neither spotdl nor the fallback could fetch
+    unmatched_tracks: Vec<String>,
+    /// spotdl's error output, if it exited unsuccessfully
+    spotdl_error: Option<String>,
+}
+
+impl SpotifyDownloadOutcome {
+    fn details(&self) -> serde_json::Value {
+        json!({
+            "fallback_tracks": self.fallback_tracks,
+            "unmatched_tracks": self.unmatched_tracks,
+            "spotdl_error": self.spotdl_error,
+        })
+    }
+
+    /// Note for the log of a completed job: the tracks that are missing, or
+    /// spotdl's error when the track list wasn't checked (fallback off)
+    fn note(&self) -> Option<String> {
+        if !self.unmatched_tracks.is_empty() {
+            Some(format!(
+                "Could not download {} track(s): {}",
+                self.unmatched_tracks.len(),
+                self.unmatched_tracks.join(", ")
+            ))
+        } else if self.fallback_tracks.is_empty() {
+            self.spotdl_error.clone()
+        } else {
+            None
+        }
+    }
+}
+
+/// Track metadata as written by `spotdl save`
+#[derive(Debug, Clone, Deserialize)]
+struct SpotifyTrack {
+    name: String,
+    #[serde(default)]
+    artist: String,
+    #[serde(default)]
+    artists: Vec<String>,
+    #[serde(default)]
+    album_name: Option<String>,
+    #[serde(default)]
+    album_artist: Option<String>,
+    #[serde(default)]
+    duration: f64,
+    #[serde(default)]
+    year: Option<i64>,
+    #[serde(default)]
+    track_number: Option<u32>,
+    #[serde(default)]
+    tracks_count: Option<u32>,
+    #[serde(default)]
+    disc_number: Option<u32>,
+    #[serde(default)]
+    genres: Vec<String>,
+}
+
+impl SpotifyTrack {
+    fn main_artist(&self) -> &str {
+        if !self.artist.is_empty() {
+            &self.artist
+        } else {
+            self.artists.first().map(String::as_str).unwrap_or("")
+        }
+    }
+
+    /// "Artist - Title", matching spotdl's output pattern and the search query
+    fn display_name(&self) -> String {
+        format!("{} - {}", self.main_artist(), self.name)
+    }
+
+    fn tags(&self) -> TrackTags {
+        TrackTags {
+            title: Some(self.name.clone()),
+            artist: Some(if self.artists.is_empty() {
+                self.main_artist().to_string()
+            } else {
+                self.artists.join(", ")
+            }),
+            album: self.album_name.clone(),
+            album_artist: self.album_artist.clone(),
+            year: self.year.map(|y| y.to_string()),
+            genre: self.genres.first().cloned(),
+            track_number: self.track_number,
+            track_total: self.tracks_count,
+            disc_number: self.disc_number,
+        }
     }
 }
 
 async fn download_with_spotdl(
-    config: &Config,
-    temp_dir: &PathBuf,
+    state: &Arc<crate::AppState>,
+    profile: &DownloadProfile,
+    temp_dir: &Path,
+    job_dir: &Path,
     url: &str,
-) -> anyhow::Result<i32> {
-    let args = build_spotdl_args(config, temp_dir, url);
+    session_id: &str,
+    fallback_enabled: bool,
+) -> anyhow::Result<SpotifyDownloadOutcome> {
+    let config = &state.config;
+
+    // Fetch the track list up front so missing tracks can be identified afterwards
+    let tracks = if fallback_enabled {
+        let save_file = temp_dir.join(format!(".spotify-{}.spotdl", session_id));
+        let tracks = fetch_spotify_tracks(config, url, &save_file).await;
+        fs::remove_file(&save_file).await.ok();
+        match tracks {
+            Ok(tracks) => tracks,
+            Err(e) => {
+                tracing::warn!("Could not fetch Spotify track list, fallback disabled: {}", e);
+                Vec::new()
+            }
+        }
+    } else {
+        Vec::new()
+    };
+
+    let args = build_spotdl_args(profile, job_dir, url);
 
     let output = tokio::process::Command::new(&config.spotify.spotdl_path)
         .args(&args)
         .output()
         .await?;
 
+    let mut outcome = SpotifyDownloadOutcome::default();
     if !output.status.success() {
         let stderr = String::from_utf8_lossy(&output.stderr);
         let stdout = String::from_utf8_lossy(&output.stdout);
-        anyhow::bail!("spotdl failed: {}\n{}", stderr, stdout);
+        // Keep going: tracks that did download are still processed
+        tracing::warn!("spotdl exited with {}: {}", output.status, stderr);
+        outcome.spotdl_error = Some(format!("spotdl failed: {}\n{}", stderr, stdout));
+    }
+
+    let downloaded = list_file_stems(job_dir).await?;
+    let missing: Vec<&SpotifyTrack> = tracks
+        .iter()
+        .filter(|track| !is_track_downloaded(track, &downloaded))
+        .collect();
+
+    for track in missing {
+        let name = track.display_name();
+        crate::progress::send_progress(
+            &state.progress_store,
+            session_id,
+            format!("Searching YouTube for {}...", name),
+        )
+        .await;
+
+        match download_track_from_youtube(config, profile, job_dir, track).await {
+            Ok(()) => {
+                tracing::info!("Fetched {} via YouTube fallback", name);
+                outcome.fallback_tracks.push(name);
+            }
+            Err(e) => {
+                tracing::warn!("YouTube fallback failed for {}: {}", name, e);
+                outcome.unmatched_tracks.push(name);
+            }
+        }
     }
 
     // Count downloaded files
-    let mut count = 0;
-    let mut entries = fs::read_dir(temp_dir).await?;
+    outcome.file_count = list_file_stems(job_dir).await?.len() as i32;
+
+    Ok(outcome)
+}
+
+/// Run `spotdl save` to get metadata for every track behind the URL
+async fn fetch_spotify_tracks(
+    config: &Config,
+    url: &str,
+    save_file: &Path,
+) -> anyhow::Result<Vec<SpotifyTrack>> {
+    let output = tokio::process::Command::new(&config.spotify.spotdl_path)
+        .arg("save")
+        .arg(url)
+        .arg("--save-file")
+        .arg(save_file)
+        .output()
+        .await?;
+
+    if !output.status.success() {
+        let stderr = String::from_utf8_lossy(&output.stderr);
+        anyhow::bail!("spotdl save failed: {}", stderr);
+    }
+
+    let content = fs::read_to_string(save_file).await?;
+    Ok(serde_json::from_str(&content)?)
+}
+
+/// Search YouTube for a track, download the closest match by duration
+/// and tag it with the Spotify metadata
+async fn download_track_from_youtube(
+    config: &Config,
+    profile: &DownloadProfile,
+    job_dir: &Path,
+    track: &SpotifyTrack,
+) -> anyhow::Result<()> {
+    let query = track.display_name();
+    let candidates = search_ytdlp(config, &query, config.spotify.fallback_search_results).await?;
+    let best = pick_best_candidate(
+        &candidates,
+        track.duration,
+        config.spotify.fallback_duration_tolerance_secs as f64,
+    )
+    .ok_or_else(|| anyhow::anyhow!("no YouTube result within the duration tolerance"))?;
+
+    tracing::info!("Fallback match for {}: {} ({})", query, best.title, best.id);
+
+    let stem = fallback_file_stem(track);
+    // '%' starts a yt-dlp template field, so it has to be escaped
+    let template = format!("{}/{}.%(ext)s", job_dir.display(), stem.replace('%', "%%"));
+    let args = build_ytdlp_download_args(
+        config,
+        profile,
+        &template,
+        &best.watch_url(),
+    );
+    run_ytdlp(config, &args).await?;
+
+    let file = find_file_by_stem(job_dir, &stem)
+        .await?
+        .ok_or_else(|| anyhow::anyhow!("yt-dlp did not produce an output file"))?;
+    write_tags(&config.ffmpeg.ffmpeg_path, &file, &track.tags()).await?;
+
+    Ok(())
+}
+
+/// File stem for a fallback download, mirroring spotdl's "{artist} - {title}"
+fn fallback_file_stem(track: &SpotifyTrack) -> String {
+    sanitize_path_component(&track.display_name())
+}
+
+/// Lowercased alphanumerics only, so "AC/DC - T.N.T." matches spotdl's sanitized "ACDC - TNT"
+fn normalize_name(name: &str) -> String {
+    name.chars()
+        .filter(|c| c.is_alphanumeric())
+        .flat_map(char::to_lowercase)
+        .collect()
+}
+
+fn is_track_downloaded(track: &SpotifyTrack, stems: &[String]) -> bool {
+    let expected = normalize_name(&track.display_name());
+    let title = normalize_name(&track.name);
+    let artist = normalize_name(track.main_artist());
+
+    stems.iter().map(|s| normalize_name(s)).any(|stem| {
+        stem == expected || (!title.is_empty() && stem.contains(&title) && stem.contains(&artist))
+    })
+}
+
+async fn list_file_stems(dir: &Path) -> anyhow::Result<Vec<String>> {
+    let mut stems = Vec::new();
+    let mut entries = fs::read_dir(dir).await?;
     while let Some(entry) = entries.next_entry().await? {
         if entry.file_type().await?.is_file() {
-            count += 1;
+            if let Some(stem) = entry.path().file_stem().and_then(|s| s.to_str()) {
+                stems.push(stem.to_string());
+            }
         }
     }
+    Ok(stems)
+}
 
-    Ok(count)
+async fn find_file_by_stem(dir: &Path, stem: &str) -> anyhow::Result<Option<PathBuf>> {
+    let mut entries = fs::read_dir(dir).await?;
+    while let Some(entry) = entries.next_entry().await? {
+        let path = entry.path();
+        if entry.file_type().await?.is_file()
+            && path.file_stem().and_then(|s| s.to_str()) == Some(stem)
+        {
+            return Ok(Some(path));
+        }
+    }
+    Ok(None)
 }
 
-fn build_spotdl_args(config: &Config, temp_dir: &PathBuf, url: &str) -> Vec<String> {
+fn build_spotdl_args(profile: &DownloadProfile, temp_dir: &Path, url: &str) -> Vec<String> {
     // SpotDL expects a file pattern, not just a directory
     // Pattern: {output_dir}/{artist} - {title}.{output-ext}
     let output_pattern = format!("{}/{{artist}} - {{title}}.{{output-ext}}", temp_dir.display());
 
-    vec![
+    let mut args = vec![
         "download".to_string(),
         url.to_string(),
         "--output".to_string(),
         output_pattern,
         "--format".to_string(),
-        config.spotify.audio_format.clone(),
-    ]
-}
+        profile.audio_format.clone(),
+    ];
 
-async fn process_temp_dir(
-    state: &Arc<crate::AppState>,
-    temp_dir: &PathBuf,
-    music_dir: &PathBuf,
-) -> anyhow::Result<()> {
-    // Check database for ferric_enabled setting (overrides config file)
-    let ferric_enabled = state
-        .db
-        .get_ferric_enabled(&state.config)
-        .await
-        .unwrap_or(state.config.paths.ferric_enabled);
-
-    if ferric_enabled {
-        // Call Ferric to process the files in temp dir
-        tracing::info!("Ferric enabled: processing files");
-        let output = tokio::process::Command::new(&state.config.paths.ferric_path)
-            .arg("--input-dir")
-            .arg(temp_dir)
-            .arg("--output-dir")
-            .arg(music_dir)
-            .output()
-            .await?;
-
-        if !output.status.success() {
-            let stderr = String::from_utf8_lossy(&output.stderr);
-            anyhow::bail!("Ferric processing failed: {}", stderr);
-        }
-    } else {
-        // Ferric disabled: just move files directly to music_dir
-        tracing::info!("Ferric disabled: moving files directly to music directory");
-        let mut entries = fs::read_dir(temp_dir).await?;
-        while let Some(entry) = entries.next_entry().await? {
-            if entry.file_type().await?.is_file() {
-                let dest = music_dir.join(entry.file_name());
-                // Use copy+remove instead of rename to handle cross-filesystem moves
-                fs::copy(entry.path(), &dest).await?;
-                fs::remove_file(entry.path()).await?;
-            }
-        }
+    if let Some(bitrate) = profile.bitrate.as_deref().filter(|b| !b.trim().is_empty()) {
+        args.push("--bitrate".to_string());
+        args.push(bitrate.trim().to_string());
     }
+    args.extend(profile.spotdl_args.iter().cloned());
 
-    // Clean up temp directory
-    let mut entries = fs::read_dir(temp_dir).await?;
-    while let Some(entry) = entries.next_entry().await? {
-        if entry.file_type().await?.is_file() {
-            fs::remove_file(entry.path()).await.ok();
-        }
-    }
-
-    Ok(())
+    args
 }
 
 fn internal_error(message: &str) -> Response {
@@ -286,7 +624,7 @@ mod tests {
         let config = Config::default();
         let temp_dir = PathBuf::from("/tmp/test");
         let url = "https://open.spotify.com/track/example";
-        let args = build_spotdl_args(&config, &temp_dir, url);
+        let args = build_spotdl_args(&config.legacy_spotify_profile(), &temp_dir, url);
 
         assert_eq!(args[0], "download");
         assert_eq!(args[1], url);
@@ -307,7 +645,7 @@ mod tests {
         ];
 
         for url in urls {
-            let args = build_spotdl_args(&config, &temp_dir, url);
+            let args = build_spotdl_args(&config.legacy_spotify_profile(), &temp_dir, url);
             assert_eq!(args.len(), 6); // Now includes --format opus
             assert_eq!(args[0], "download");
             assert_eq!(args[1], url);
@@ -315,4 +653,57 @@ mod tests {
             assert_eq!(args[5], "opus");
         }
     }
+
+    #[test]
+    fn build_args_apply_profile_bitrate_and_extra_args() {
+        let config = Config::default();
+        let profile = DownloadProfile {
+            audio_format: "mp3".to_string(),
+            bitrate: Some("320k".to_string()),
+            spotdl_args: vec!["--lyrics".to_string(), "genius".to_string()],
+            ..config.legacy_spotify_profile()
+        };
+        let args = build_spotdl_args(&profile, &PathBuf::from("/tmp/test"), "https://open.spotify.com/track/1");
+
+        assert_eq!(&args[4..], ["--format", "mp3", "--bitrate", "320k", "--lyrics", "genius"]);
+    }
+
+    fn track(artist: &str, name: &str) -> SpotifyTrack {
+        serde_json::from_value(json!({
+            "name": name,
+            "artist": artist,
+            "artists": [artist],
+            "album_name": "Album",
+            "duration": 200,
+            "year": 1999,
+            "track_number": 3,
+            "tracks_count": 12
+        }))
+        .unwrap()
+    }
+
+    #[test]
+    fn downloaded_tracks_match_sanitized_names() {
+        let stems = vec!["ACDC - TNT".to_string(), "Daft Punk - One More Time".to_string()];
+
+        assert!(is_track_downloaded(&track("AC/DC", "T.N.T."), &stems));
+        assert!(is_track_downloaded(&track("Daft Punk", "One More Time"), &stems));
+        assert!(!is_track_downloaded(&track("Daft Punk", "Aerodynamic"), &stems));
+    }
+
+    #[test]
+    fn fallback_stem_replaces_path_characters() {
+        assert_eq!(fallback_file_stem(&track("AC/DC", "T.N.T.")), "AC_DC - T.N.T");
+    }
+
+    #[test]
+    fn spotify_track_tags_include_track_numbers() {
+        let tags = track("Daft Punk", "Aerodynamic").tags();
+
+        assert_eq!(tags.title.as_deref(), Some("Aerodynamic"));
+        assert_eq!(tags.year.as_deref(), Some("1999"));
+        assert!(tags
+            .ffmpeg_metadata()
+            .contains(&("track", "3/12".to_string())));
+    }
 }
@@ -0,0 +1,243 @@
+use crate::auth::{AuthUser, Permission};
+use crate::handlers::auth_handlers::check_second_factor;
+use crate::models::TotpCodeRequest;
+use crate::totp;
+use axum::{
+    extract::{Extension, Path, State},
+    http::StatusCode,
+    response::{IntoResponse, Response},
+    Json,
+};
+use serde_json::json;
+use std::sync::Arc;
+
+// The caller's 2FA status
+pub async fn get_totp_status(
+    State(state): State<Arc<crate::AppState>>,
+    Extension(user): Extension<AuthUser>,
+) -> Result<Json<serde_json::Value>, Response> {
+    let settings = state
+        .db
+        .get_user_totp(&user.user_id)
+        .await
+        .map_err(|e| internal_error(&format!("Failed to load 2FA settings: {}", e)))?;
+    let enabled = settings.is_some_and(|t| t.enabled);
+    let recovery_codes_left = state
+        .db
+        .count_recovery_codes(&user.user_id)
+        .await
+        .map_err(|e| internal_error(&format!("Failed to count recovery codes: {}", e)))?;
+    let role_permissions = state
+        .db
+        .get_role_permissions(&user_role(&state, &user).await?)
+        .await
+        .map_err(|e| internal_error(&format!("Failed to load permissions: {}", e)))?;
+    let required = role_permissions.iter().any(Permission::is_admin)
+        && state
+            .db
+            .get_require_admin_2fa(&state.config)
+            .await
+            .map_err(|e| internal_error(&format!("Failed to load setting: {}", e)))?;
+
+    Ok(Json(json!({
+        "enabled": enabled,
+        "required": required,
+        "recovery_codes_left": recovery_codes_left
+    })))
+}
+
+// Start enrollment: a new secret, as text and QR code, to add to an authenticator app
+pub async fn setup_totp(
+    State(state): State<Arc<crate::AppState>>,
+    Extension(user): Extension<AuthUser>,
+) -> Result<Json<serde_json::Value>, Response> {
+    if is_enabled(&state, &user.user_id).await? {
+        return Err(conflict("Two-factor authentication is already enabled"));
+    }
+
+    let secret = totp::generate_secret();
+    let uri = totp::provisioning_uri(&secret, &user.username)
+        .map_err(|e| internal_error(&format!("Failed to create provisioning URI: {}", e)))?;
+    let qr_svg = totp::qr_svg(&uri).map_err(|e| internal_error(&format!("Failed to render QR code: {}", e)))?;
+    state
+        .db
+        .start_totp_enrollment(&user.user_id, &secret)
+        .await
+        .map_err(|e| internal_error(&format!("Failed to start enrollment: {}", e)))?;
+
+    Ok(Json(json!({
+        "secret": secret,
+        "uri": uri,
+        "qr_svg": qr_svg
+    })))
+}
+
+// Finish enrollment with a code from the app; returns the recovery codes (only this once)
+pub async fn enable_totp(
+    State(state): State<Arc<crate::AppState>>,
+    Extension(user): Extension<AuthUser>,
+    Json(req): Json<TotpCodeRequest>,
+) -> Result<Json<serde_json::Value>, Response> {
+    let settings = state
+        .db
+        .get_user_totp(&user.user_id)
+        .await
+        .map_err(|e| internal_error(&format!("Failed to load 2FA settings: {}", e)))?;
+    let Some(settings) = settings else {
+        return Err(bad_request("Start the setup first"));
+    };
+    if settings.enabled {
+        return Err(conflict("Two-factor authentication is already enabled"));
+    }
+
+    let now = chrono::Utc::now().timestamp() as u64;
+    let Some(step) = totp::verify(&settings.secret, &req.code, now, None) else {
+        return Err(bad_request("Invalid code; check the time on your device"));
+    };
+    let codes = totp::generate_recovery_codes();
+    let hashes: Vec<String> = codes.iter().map(|c| totp::hash_recovery_code(c)).collect();
+    state
+        .db
+        .enable_totp(&user.user_id, step, &hashes)
+        .await
+        .map_err(|e| internal_error(&format!("Failed to enable 2FA: {}", e)))?;
+    tracing::info!("User {} enabled two-factor authentication", user.username);
+
+    Ok(Json(json!({
+        "message": "Two-factor authentication enabled",
+        "recovery_codes": codes
+    })))
+}
+
+// Turn 2FA off; needs a current code (or a recovery code)
+pub async fn disable_totp(
+    State(state): State<Arc<crate::AppState>>,
+    Extension(user): Extension<AuthUser>,
+    Json(req): Json<TotpCodeRequest>,
+) -> Result<Json<serde_json::Value>, Response> {
+    require_code(&state, &user, &req.code).await?;
+    state
+        .db
+        .delete_user_totp(&user.user_id)
+        .await
+        .map_err(|e| internal_error(&format!("Failed to disable 2FA: {}", e)))?;
+    tracing::info!("User {} disabled two-factor authentication", user.username);
+
+    Ok(Json(json!({
+        "message": "Two-factor authentication disabled"
+    })))
+}
+
+// Replace the recovery codes; needs a current code
+pub async fn regenerate_recovery_codes(
+    State(state): State<Arc<crate::AppState>>,
+    Extension(user): Extension<AuthUser>,
+    Json(req): Json<TotpCodeRequest>,
+) -> Result<Json<serde_json::Value>, Response> {
+    require_code(&state, &user, &req.code).await?;
+    let codes = totp::generate_recovery_codes();
+    let hashes: Vec<String> = codes.iter().map(|c| totp::hash_recovery_code(c)).collect();
+    state
+        .db
+        .set_recovery_codes(&user.user_id, &hashes)
+        .await
+        .map_err(|e| internal_error(&format!("Failed to store recovery codes: {}", e)))?;
+
+    Ok(Json(json!({
+        "message": "New recovery codes created; the old ones no longer work",
+        "recovery_codes": codes
+    })))
+}
+
+// Remove a user's 2FA (TOTP and passkeys), e.g. after they lost their device (manage_users)
+pub async fn reset_user_totp(
+    State(state): State<Arc<crate::AppState>>,
+    Extension(admin): Extension<AuthUser>,
+    Path(user_id): Path<String>,
+) -> Result<Json<serde_json::Value>, Response> {
+    let user = state.db.get_user_by_id(&user_id).await.map_err(|_| {
+        (
+            StatusCode::NOT_FOUND,
+            Json(json!({
+                "error": "User not found"
+            })),
+        )
+            .into_response()
+    })?;
+    state
+        .db
+        .delete_user_totp(&user.id)
+        .await
+        .map_err(|e| internal_error(&format!("Failed to reset 2FA: {}", e)))?;
+    state
+        .db
+        .delete_user_passkeys(&user.id)
+        .await
+        .map_err(|e| internal_error(&format!("Failed to remove passkeys: {}", e)))?;
+    tracing::info!("{} reset two-factor authentication of {}", admin.username, user.username);
+
+    Ok(Json(json!({
+        "message": format!("Two-factor authentication of {} was reset", user.username)
+    })))
+}
+
+async fn is_enabled(state: &crate::AppState, user_id: &str) -> Result<bool, Response> {
+    let settings = state
+        .db
+        .get_user_totp(user_id)
+        .await
+        .map_err(|e| internal_error(&format!("Failed to load 2FA settings: {}", e)))?;
+    Ok(settings.is_some_and(|t| t.enabled))
+}
+
+async fn require_code(state: &crate::AppState, user: &AuthUser, code: &str) -> Result<(), Response> {
+    if !is_enabled(state, &user.user_id).await? {
+        return Err(bad_request("Two-factor authentication is not enabled"));
+    }
+    let method = check_second_factor(state, &user.user_id, code)
+        .await
+        .map_err(|e| internal_error(&format!("Failed to check code: {}", e)))?;
+    if method.is_none() {
+        return Err(bad_request("Invalid code"));
+    }
+    Ok(())
+}
+
+async fn user_role(state: &crate::AppState, user: &AuthUser) -> Result<String, Response> {
+    let user = state
+        .db
+        .get_user_by_id(&user.user_id)
+        .await
+        .map_err(|e| internal_error(&format!("Failed to load user: {}", e)))?;
+    Ok(user.role)
+}
+
+fn bad_request(message: &str) -> Response {
+    (
+        StatusCode::BAD_REQUEST,
+        Json(json!({
+            "error": message
+        })),
+    )
+        .into_response()
+}
+
+fn conflict(message: &str) -> Response {
+    (
+        StatusCode::CONFLICT,
+        Json(json!({
+            "error": message
+        })),
+    )
+        .into_response()
+}
+
+fn internal_error(message: &str) -> Response {
+    (
+        StatusCode::INTERNAL_SERVER_ERROR,
+        Json(json!({
+            "error": message
+        })),
+    )
+        .into_response()
+}
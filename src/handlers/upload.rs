@@ -1,6 +1,11 @@
+use crate::archive::{unpack_staged_archive, ArchiveKind};
 use crate::auth::AuthUser;
-use crate::models::{CreateUploadLog, UploadResponse};
-use crate::paths::get_user_directories;
+use crate::models::{CreateUploadLog, MetadataOverrides, UploadResponse};
+use crate::paths::{ensure_directory_exists, get_user_directories, safe_relative_path};
+use crate::pipeline::{
+    apply_metadata_overrides, import_job_dir, is_allowed_audio, is_image_file, is_lyrics_file, lyrics::validate_lyrics_file,
+    run_post_processing, stage_cover_image, validate_cover_image, validate_staged_files, JobContext,
+};
 use axum::{
     extract::{Extension, Multipart, State},
     http::StatusCode,
@@ -18,9 +23,6 @@ pub async fn upload_files(
     Extension(user): Extension<AuthUser>,
     mut multipart: Multipart,
 ) -> Result<Json<UploadResponse>, Response> {
-    let mut uploaded_files = Vec::new();
-    let mut file_count = 0;
-
     // Get user from database to access library_path
     let db_user = state
         .db
@@ -60,150 +62,103 @@ pub async fn upload_files(
         .await
         .map_err(|e| internal_error(&format!("Failed to update log: {}", e)))?;
 
-    // Process each file in the multipart upload
-    while let Some(field) = multipart
-        .next_field()
+    // Each job gets its own staging directory so only its files are processed
+    let job_dir = temp_dir.join(format!("upload-{}", uuid::Uuid::new_v4()));
+    ensure_directory_exists(&job_dir)
         .await
-        .map_err(|e| internal_error(&format!("Failed to read field: {}", e)))?
-    {
-        let file_name = match field.file_name() {
-            Some(name) => name.to_string(),
-            None => continue,
-        };
+        .map_err(|e| internal_error(&format!("Failed to create job directory: {}", e)))?;
 
-        // SECURITY: Sanitize filename to prevent path traversal attacks
-        // Remove any path components and only keep the filename
-        let sanitized_name = Path::new(&file_name)
-            .file_name()
-            .and_then(|n| n.to_str())
-            .ok_or_else(|| internal_error("Invalid filename"))?
-            .to_string();
-
-        // Additional check: reject files with suspicious characters
-        if sanitized_name.contains("..")
-            || sanitized_name.contains('/')
-            || sanitized_name.contains('\\')
-        {
-            let error_msg = "Invalid filename: path traversal attempt detected";
-            state
-                .db
-                .update_upload_log_status(
-                    log_id,
-                    "failed",
-                    Some(file_count),
-                    Some(error_msg.to_string()),
-                )
-                .await
-                .ok();
-            return Err(
-                (StatusCode::BAD_REQUEST, Json(json!({ "error": error_msg }))).into_response(),
-            );
-        }
-
-        // Check file extension
-        let extension = Path::new(&sanitized_name)
-            .extension()
-            .and_then(|e| e.to_str())
-            .unwrap_or("");
+    let response = handle_upload(&state, &user, &mut multipart, &job_dir, &music_dir, log_id).await;
 
-        if !state
-            .config
-            .upload
-            .allowed_extensions
-            .contains(&extension.to_string())
-        {
-            let error_msg = format!("File type .{} not allowed", extension);
-            state
-                .db
-                .update_upload_log_status(
-                    log_id,
-                    "failed",
-                    Some(file_count),
-                    Some(error_msg.clone()),
-                )
-                .await
-                .ok();
-            return Err(
-                (StatusCode::BAD_REQUEST, Json(json!({ "error": error_msg }))).into_response(),
-            );
-        }
+    // Leftover files (failed jobs) are discarded with the job directory
+    fs::remove_dir_all(&job_dir).await.ok();
 
-        // Read file data
-        let data = field
-            .bytes()
-            .await
-            .map_err(|e| internal_error(&format!("Failed to read file: {}", e)))?;
+    response
+}
 
-        // Check file size
-        if data.len() > state.config.max_file_size_bytes() {
-            let error_msg = format!(
-                "File too large: {} MB (max: {} MB)",
-                data.len() / 1024 / 1024,
-                state.config.upload.max_file_size_mb
-            );
+async fn handle_upload(
+    state: &Arc<crate::AppState>,
+    user: &AuthUser,
+    multipart: &mut Multipart,
+    job_dir: &Path,
+    music_dir: &Path,
+    log_id: i32,
+) -> Result<Json<UploadResponse>, Response> {
+    let upload = match receive_upload(state, multipart, job_dir).await {
+        Ok(upload) => upload,
+        Err(UploadError { status, message, file_count }) => {
             state
                 .db
-                .update_upload_log_status(
-                    log_id,
-                    "failed",
-                    Some(file_count),
-                    Some(error_msg.clone()),
-                )
+                .update_upload_log_status(log_id, "failed", Some(file_count), Some(message.clone()))
                 .await
                 .ok();
-            return Err(
-                (StatusCode::BAD_REQUEST, Json(json!({ "error": error_msg }))).into_response(),
-            );
+            return Err((status, Json(json!({ "error": message }))).into_response());
         }
+    };
 
-        // Save to temp directory (using sanitized filename)
-        let temp_path = temp_dir.join(&sanitized_name);
-        let mut file = File::create(&temp_path)
-            .await
-            .map_err(|e| internal_error(&format!("Failed to create file: {}", e)))?;
-
-        file.write_all(&data)
+    if !upload.skipped.is_empty() {
+        state
+            .db
+            .add_upload_log_detail(log_id, "folder", &json!({ "skipped": upload.skipped }))
             .await
-            .map_err(|e| internal_error(&format!("Failed to write file: {}", e)))?;
-
-        uploaded_files.push(temp_path);
-        file_count += 1;
+            .ok();
     }
-
-    if uploaded_files.is_empty() {
+    if !upload.archives.is_empty() {
         state
             .db
-            .update_upload_log_status(
-                log_id,
-                "failed",
-                Some(0),
-                Some("No files uploaded".to_string()),
-            )
+            .add_upload_log_detail(log_id, "archives", &json!(upload.archives))
             .await
             .ok();
-        return Err((
-            StatusCode::BAD_REQUEST,
-            Json(json!({ "error": "No files uploaded" })),
-        )
-            .into_response());
     }
 
-    // Process files with Ferric (check database for ferric_enabled setting)
-    let result = process_with_ferric(&state, &temp_dir, &music_dir, &uploaded_files).await;
+    // Probe and decode every file; bad ones are dropped with a per-file reason
+    let validation = match validate_staged_files(state, job_dir, log_id).await {
+        Ok(validation) => validation,
+        Err(e) => {
+            let message = e.to_string();
+            state
+                .db
+                .update_upload_log_status(log_id, "failed", Some(0), Some(message.clone()))
+                .await
+                .ok();
+            return Err((StatusCode::BAD_REQUEST, Json(json!({ "error": message }))).into_response());
+        }
+    };
+    let file_count = (upload.files.len() - validation.rejected.len()) as i32;
+
+    // Apply metadata overrides, then process files with Ferric (check database for ferric_enabled setting)
+    let job = JobContext { user_id: &user.user_id, log_id, job_dir, music_dir };
+    let result = async {
+        apply_metadata_overrides(state, job_dir, &upload.overrides, upload.cover.as_deref()).await?;
+        run_post_processing(state, &job).await?;
+        import_job_dir(state, &job).await
+    }
+    .await;
 
     match result {
         Ok(_) => {
+            let rejected_note = if validation.rejected.is_empty() {
+                None
+            } else {
+                Some(format!("Rejected {} file(s): {}", validation.rejected.len(), validation.summary()))
+            };
             state
                 .db
-                .update_upload_log_status(log_id, "completed", Some(file_count), None)
+                .update_upload_log_status(log_id, "completed", Some(file_count), rejected_note)
                 .await
                 .map_err(|e| internal_error(&format!("Failed to update log: {}", e)))?;
 
+            let mut message = format!("Successfully uploaded and processed {} file(s)", file_count);
+            if !validation.rejected.is_empty() {
+                message.push_str(&format!("; rejected {}", validation.summary()));
+            }
+
             Ok(Json(UploadResponse {
                 success: true,
-                message: format!("Successfully uploaded and processed {} file(s)", file_count),
+                message,
                 log_id: Some(log_id),
                 session_id: None,  // TODO: Add progress tracking to upload
+                details: Some(validation.to_json()).filter(|_| !validation.rejected.is_empty()),
             }))
         }
         Err(e) => {
@@ -228,55 +183,282 @@ pub async fn upload_files(
     }
 }
 
-async fn process_with_ferric(
+/// Everything received in a multipart upload
+struct ReceivedUpload {
+    files: Vec<PathBuf>,
+    overrides: MetadataOverrides,
+    cover: Option<PathBuf>,
+    /// What was unpacked from each uploaded archive
+    archives: Vec<serde_json::Value>,
+    /// Files of a folder upload that were left out (hidden or unsupported)
+    skipped: Vec<String>,
+}
+
+struct UploadError {
+    status: StatusCode,
+    message: String,
+    file_count: i32,
+}
+
+impl UploadError {
+    fn bad_request(message: impl Into<String>, file_count: i32) -> Self {
+        Self {
+            status: StatusCode::BAD_REQUEST,
+            message: message.into(),
+            file_count,
+        }
+    }
+
+    fn internal(message: impl Into<String>, file_count: i32) -> Self {
+        Self {
+            status: StatusCode::INTERNAL_SERVER_ERROR,
+            message: message.into(),
+            file_count,
+        }
+    }
+}
+
+/// Read the multipart body: audio, image and lyrics files are saved into `job_dir`,
+/// archives are unpacked into it, text fields named like `MetadataOverrides`
+/// fields become overrides and a `cover` file field is used as cover art
+///
+/// A `preserve_paths=true` field (sent before the files) turns on folder
+/// uploads: file names are taken as relative paths and the folders are
+/// recreated in `job_dir`; hidden and unsupported files are skipped instead
+/// of failing the upload.
+async fn receive_upload(
     state: &Arc<crate::AppState>,
-    temp_dir: &PathBuf,
-    music_dir: &PathBuf,
-    files: &[std::path::PathBuf],
-) -> anyhow::Result<()> {
-    // Check database for ferric_enabled setting (overrides config file)
-    let ferric_enabled = state
-        .db
-        .get_ferric_enabled(&state.config)
+    multipart: &mut Multipart,
+    job_dir: &Path,
+) -> Result<ReceivedUpload, UploadError> {
+    let mut files = Vec::new();
+    let mut overrides = MetadataOverrides::default();
+    let mut cover = None;
+    let mut archives = Vec::new();
+    let mut skipped = Vec::new();
+    let mut preserve_paths = false;
+
+    // Process each field in the multipart upload
+    while let Some(mut field) = multipart
+        .next_field()
         .await
-        .unwrap_or(state.config.paths.ferric_enabled);
-
-    if ferric_enabled {
-        // Call Ferric to process the files
-        tracing::info!("Ferric enabled: processing files");
-        let output = tokio::process::Command::new(&state.config.paths.ferric_path)
-            .arg("--input-dir")
-            .arg(temp_dir)
-            .arg("--output-dir")
-            .arg(music_dir)
-            .output()
-            .await?;
-
-        if !output.status.success() {
-            let stderr = String::from_utf8_lossy(&output.stderr);
-            anyhow::bail!("Ferric processing failed: {}", stderr);
+        .map_err(|e| UploadError::internal(format!("Failed to read field: {}", e), files.len() as i32))?
+    {
+        let file_count = files.len() as i32;
+        let field_name = field.name().unwrap_or_default().to_string();
+
+        let file_name = match field.file_name() {
+            Some(name) => name.to_string(),
+            None => {
+                // Plain form field: metadata override
+                let value = field.text().await.map_err(|e| {
+                    UploadError::bad_request(format!("Failed to read field {}: {}", field_name, e), file_count)
+                })?;
+                if field_name == "preserve_paths" {
+                    preserve_paths = value.trim() == "true";
+                } else {
+                    overrides.set_field(&field_name, value);
+                }
+                continue;
+            }
+        };
+
+        if field_name == "cover" {
+            let data = field.bytes().await.map_err(|e| {
+                UploadError::internal(format!("Failed to read cover image: {}", e), file_count)
+            })?;
+            validate_cover_image(&data).map_err(|e| UploadError::bad_request(e, file_count))?;
+            let path = stage_cover_image(job_dir, &data).await.map_err(|e| {
+                UploadError::internal(format!("Failed to save cover image: {}", e), file_count)
+            })?;
+            cover = Some(path);
+            continue;
         }
-    } else {
-        // Ferric disabled: just move files directly to music_dir
-        tracing::info!("Ferric disabled: moving files directly to music directory");
-        for file in files {
-            if let Some(filename) = file.file_name() {
-                let dest = music_dir.join(filename);
-                // Use copy+remove instead of rename to handle cross-filesystem moves
-                fs::copy(file, &dest).await?;
-                fs::remove_file(file).await?;
+
+        let relative = if preserve_paths {
+            // SECURITY: every component of the client's path is validated
+            safe_relative_path(&file_name)
+                .ok_or_else(|| UploadError::bad_request(format!("Invalid path: {}", file_name), file_count))?
+        } else {
+            // SECURITY: Sanitize filename to prevent path traversal attacks
+            // Remove any path components and only keep the filename
+            let sanitized_name = Path::new(&file_name)
+                .file_name()
+                .and_then(|n| n.to_str())
+                .ok_or_else(|| UploadError::bad_request("Invalid filename", file_count))?
+                .to_string();
+
+            // Additional check: reject files with suspicious characters
+            if sanitized_name.contains("..")
+                || sanitized_name.contains('/')
+                || sanitized_name.contains('\\')
+            {
+                return Err(UploadError::bad_request(
+                    "Invalid filename: path traversal attempt detected",
+                    file_count,
+                ));
+            }
+            PathBuf::from(sanitized_name)
+        };
+        let sanitized_name = relative.file_name().unwrap_or_default().to_string_lossy().to_string();
+
+        if preserve_paths {
+            // Folder picks bring along .DS_Store, playlists, scans as PDF, ...
+            let hidden = relative.iter().any(|c| c.to_string_lossy().starts_with('.'));
+            let supported = is_allowed_audio(&state.config.upload.allowed_extensions, &relative)
+                || is_image_file(&relative)
+                || is_lyrics_file(&relative)
+                || ArchiveKind::from_file_name(&sanitized_name).is_some();
+            if hidden || !supported {
+                skipped.push(relative.display().to_string());
+                continue;
+            }
+            if let Some(parent) = job_dir.join(&relative).parent() {
+                fs::create_dir_all(parent).await.map_err(|e| {
+                    UploadError::internal(format!("Failed to create folder: {}", e), file_count)
+                })?;
+            }
+            // Same-named files from different folders must not overwrite each other
+            if job_dir.join(&relative).exists() {
+                return Err(UploadError::bad_request(
+                    format!("{} was uploaded twice", relative.display()),
+                    file_count,
+                ));
             }
         }
-    }
 
-    // Clean up remaining temp files
-    for file in files {
-        if file.exists() {
-            fs::remove_file(file).await.ok();
+        // Archives are unpacked with their folder structure; only supported entries are kept
+        if let Some((kind, stem)) = ArchiveKind::from_file_name(&sanitized_name) {
+            let max_bytes = state.config.max_archive_size_bytes();
+            let archive_path = job_dir.join(format!(".archive-{}", uuid::Uuid::new_v4()));
+            let mut archive_file = File::create(&archive_path)
+                .await
+                .map_err(|e| UploadError::internal(format!("Failed to create file: {}", e), file_count))?;
+            let mut size = 0u64;
+            while let Some(chunk) = field
+                .chunk()
+                .await
+                .map_err(|e| UploadError::internal(format!("Failed to read archive: {}", e), file_count))?
+            {
+                size += chunk.len() as u64;
+                if size > max_bytes {
+                    return Err(UploadError::bad_request(
+                        format!("Archive too large (max: {} MB)", state.config.upload.max_archive_size_mb),
+                        file_count,
+                    ));
+                }
+                archive_file
+                    .write_all(&chunk)
+                    .await
+                    .map_err(|e| UploadError::internal(format!("Failed to write file: {}", e), file_count))?;
+            }
+            drop(archive_file);
+
+            let unpacked = unpack_staged_archive(&state.config, kind, archive_path, stem, job_dir)
+                .await
+                .map_err(|e| UploadError::bad_request(format!("{}: {:#}", sanitized_name, e), file_count))?;
+
+            let audio: Vec<PathBuf> = unpacked
+                .files
+                .iter()
+                .filter(|f| is_allowed_audio(&state.config.upload.allowed_extensions, f))
+                .map(|f| job_dir.join(f))
+                .collect();
+            archives.push(json!({
+                "archive": sanitized_name,
+                "extracted": unpacked.files.len(),
+                "audio": audio.len(),
+                "skipped": unpacked.skipped,
+            }));
+            files.extend(audio);
+            continue;
+        }
+
+        // Images uploaded alongside the audio become the album art
+        if is_image_file(Path::new(&sanitized_name)) {
+            let data = field.bytes().await.map_err(|e| {
+                UploadError::internal(format!("Failed to read image: {}", e), file_count)
+            })?;
+            validate_cover_image(&data)
+                .map_err(|e| UploadError::bad_request(format!("{}: {}", sanitized_name, e), file_count))?;
+            fs::write(job_dir.join(&relative), &data).await.map_err(|e| {
+                UploadError::internal(format!("Failed to save image: {}", e), file_count)
+            })?;
+            continue;
+        }
+
+        // Lyrics files are paired with their tracks during post-processing
+        if is_lyrics_file(Path::new(&sanitized_name)) {
+            let data = field.bytes().await.map_err(|e| {
+                UploadError::internal(format!("Failed to read lyrics: {}", e), file_count)
+            })?;
+            validate_lyrics_file(&data)
+                .map_err(|e| UploadError::bad_request(format!("{}: {}", sanitized_name, e), file_count))?;
+            fs::write(job_dir.join(&relative), &data).await.map_err(|e| {
+                UploadError::internal(format!("Failed to save lyrics: {}", e), file_count)
+            })?;
+            continue;
+        }
+
+        // Check file extension
+        let extension = Path::new(&sanitized_name)
+            .extension()
+            .and_then(|e| e.to_str())
+            .unwrap_or("");
+
+        if !is_allowed_audio(&state.config.upload.allowed_extensions, &relative) {
+            return Err(UploadError::bad_request(
+                format!("File type .{} not allowed", extension),
+                file_count,
+            ));
+        }
+
+        // Read file data
+        let data = field
+            .bytes()
+            .await
+            .map_err(|e| UploadError::internal(format!("Failed to read file: {}", e), file_count))?;
+
+        // Check file size
+        if data.len() > state.config.max_file_size_bytes() {
+            return Err(UploadError::bad_request(
+                format!(
+                    "File too large: {} MB (max: {} MB)",
+                    data.len() / 1024 / 1024,
+                    state.config.upload.max_file_size_mb
+                ),
+                file_count,
+            ));
         }
+
+        // Save to job directory (using the sanitized name or validated relative path)
+        let temp_path = job_dir.join(&relative);
+        let mut file = File::create(&temp_path)
+            .await
+            .map_err(|e| UploadError::internal(format!("Failed to create file: {}", e), file_count))?;
+
+        file.write_all(&data)
+            .await
+            .map_err(|e| UploadError::internal(format!("Failed to write file: {}", e), file_count))?;
+
+        files.push(temp_path);
     }
 
-    Ok(())
+    if files.is_empty() {
+        return Err(UploadError::bad_request("No audio files uploaded", 0));
+    }
+
+    let overrides = overrides
+        .normalized()
+        .map_err(|e| UploadError::bad_request(e, files.len() as i32))?;
+
+    Ok(ReceivedUpload {
+        files,
+        overrides,
+        cover,
+        archives,
+        skipped,
+    })
 }
 
 fn internal_error(message: &str) -> Response {
@@ -288,3 +470,128 @@ fn internal_error(message: &str) -> Response {
     )
         .into_response()
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::auth::AuthState;
+    use crate::config::Config;
+    use crate::db::Database;
+    use axum::body::Body;
+    use axum::extract::FromRequest;
+    use axum::http::Request;
+
+    const BOUNDARY: &str = "folder-upload-test";
+
+    /// Run `receive_upload` on a folder upload of `files` (path, contents),
+    /// returning the job directory it wrote into
+    async fn receive_folder(files: &[(&str, &[u8])]) -> (PathBuf, Result<ReceivedUpload, UploadError>) {
+        let dir = std::env::temp_dir().join(format!("upload-test-{}", uuid::Uuid::new_v4()));
+        let job_dir = dir.join("job");
+        std::fs::create_dir_all(&job_dir).unwrap();
+        let state = Arc::new(crate::AppState {
+            db: Database::new(&format!("sqlite:{}", dir.join("test.db").display()), 1)
+                .await
+                .unwrap(),
+            config: Config::default(),
+            auth: AuthState::new("test-secret".to_string(), 1),
+            progress_store: crate::progress::create_progress_store(),
+        });
+
+        let mut body = format!(
+            "--{BOUNDARY}\r\nContent-Disposition: form-data; name=\"preserve_paths\"\r\n\r\ntrue\r\n"
+        )
+        .into_bytes();
+        for (path, contents) in files {
+            body.extend_from_slice(
+                format!(
+                    "--{BOUNDARY}\r\nContent-Disposition: form-data; name=\"files\"; filename=\"{path}\"\r\n\
+                     Content-Type: application/octet-stream\r\n\r\n"
+                )
+                .as_bytes(),
+            );
+            body.extend_from_slice(contents);
+            body.extend_from_slice(b"\r\n");
+        }
+        body.extend_from_slice(format!("--{BOUNDARY}--\r\n").as_bytes());
+
+        let request = Request::post("/api/upload")
+            .header("content-type", format!("multipart/form-data; boundary={BOUNDARY}"))
+            .body(Body::from(body))
+            .unwrap();
+        let mut multipart = Multipart::from_request(request, &()).await.unwrap();
+        let received = receive_upload(&state, &mut multipart, &job_dir).await;
+        (job_dir, received)
+    }
+
+    fn rejection(received: Result<ReceivedUpload, UploadError>) -> UploadError {
+        match received {
+            Ok(upload) => panic!("upload of {:?} was accepted", upload.files),
+            Err(e) => e,
+        }
+    }
+
+    #[tokio::test]
+    async fn folder_uploads_recreate_nested_folders() {
+        let (job_dir, received) = receive_folder(&[
+            ("Album/CD1/01 Intro.mp3", b"one"),
+            ("Album/CD2/01 Intro.mp3", b"two"),
+            ("Album/CD1/cover.jpg", b"\xFF\xD8\xFF\xE0 not checked past the magic"),
+        ])
+        .await;
+        let upload = received.ok().unwrap();
+
+        let cd1 = job_dir.join("Album/CD1/01 Intro.mp3");
+        let cd2 = job_dir.join("Album/CD2/01 Intro.mp3");
+        assert_eq!(upload.files, vec![cd1.clone(), cd2.clone()]);
+        assert_eq!(std::fs::read(cd1).unwrap(), b"one");
+        assert_eq!(std::fs::read(cd2).unwrap(), b"two");
+        assert!(upload.skipped.is_empty());
+    }
+
+    #[tokio::test]
+    async fn folder_uploads_skip_hidden_and_unsupported_files() {
+        let (job_dir, received) = receive_folder(&[
+            ("Album/.DS_Store", b"junk"),
+            ("Album/.sync/01 Intro.mp3", b"junk"),
+            ("Album/playlist.m3u", b"junk"),
+            ("Album/booklet.pdf", b"junk"),
+            ("Album/01 Intro.mp3", b"one"),
+        ])
+        .await;
+        let upload = received.ok().unwrap();
+
+        assert_eq!(upload.files, vec![job_dir.join("Album/01 Intro.mp3")]);
+        let mut skipped = upload.skipped;
+        skipped.sort();
+        assert_eq!(
+            skipped,
+            ["Album/.DS_Store", "Album/.sync/01 Intro.mp3", "Album/booklet.pdf", "Album/playlist.m3u"]
+        );
+        assert!(!job_dir.join("Album/.sync").exists());
+        assert!(!job_dir.join("Album/.DS_Store").exists());
+    }
+
+    #[tokio::test]
+    async fn folder_uploads_reject_the_same_path_twice() {
+        let (job_dir, received) =
+            receive_folder(&[("Album/01 Intro.mp3", b"first"), ("Album/01 Intro.mp3", b"second")]).await;
+        let error = rejection(received);
+
+        assert_eq!(error.status, StatusCode::BAD_REQUEST);
+        assert!(error.message.contains("uploaded twice"), "{}", error.message);
+        assert_eq!(std::fs::read(job_dir.join("Album/01 Intro.mp3")).unwrap(), b"first");
+    }
+
+    #[tokio::test]
+    async fn folder_uploads_reject_paths_leaving_the_job_directory() {
+        for path in ["../escape.mp3", "Album/../../escape.mp3", "/tmp/escape.mp3", "\\\\server\\escape.mp3"] {
+            let (job_dir, received) = receive_folder(&[(path, b"data")]).await;
+            let error = rejection(received);
+
+            assert_eq!(error.status, StatusCode::BAD_REQUEST, "{}", path);
+            assert!(error.message.starts_with("Invalid path"), "{}: {}", path, error.message);
+            assert!(!job_dir.parent().unwrap().join("escape.mp3").exists());
+        }
+    }
+}
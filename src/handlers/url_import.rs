@@ -0,0 +1,282 @@
+use crate::archive::{unpack_staged_archive, ArchiveKind};
+use crate::auth::AuthUser;
+use crate::fetch::{check_url, download_to_file};
+use crate::models::{CreateUploadLog, UploadResponse, UrlImportRequest};
+use crate::paths::{ensure_directory_exists, get_user_directories, sanitize_path_component};
+use crate::pipeline::validate::{expected_containers, sniff_container};
+use crate::pipeline::{
+    apply_metadata_overrides, decode_cover_image, import_job_dir, is_allowed_audio, run_post_processing,
+    stage_cover_image, validate_staged_files, JobContext,
+};
+use axum::{
+    extract::{Extension, State},
+    http::StatusCode,
+    response::{IntoResponse, Response},
+    Json,
+};
+use serde_json::json;
+use std::path::Path;
+use std::sync::Arc;
+use tokio::fs;
+use tokio::io::AsyncReadExt;
+
+pub async fn import_url(
+    State(state): State<Arc<crate::AppState>>,
+    Extension(user): Extension<AuthUser>,
+    Json(req): Json<UrlImportRequest>,
+) -> Result<Json<UploadResponse>, Response> {
+    if !state.config.url_import.enabled {
+        return Err((
+            StatusCode::FORBIDDEN,
+            Json(json!({
+                "error": "URL imports are disabled"
+            })),
+        )
+            .into_response());
+    }
+
+    // Reject malformed and obviously internal URLs before doing any work
+    // (host names are checked again when they are resolved)
+    let url = req.url.trim();
+    let checked = match reqwest::Url::parse(url) {
+        _ if url.len() > 2000 => Err("URL too long".to_string()),
+        Ok(parsed) => check_url(&parsed, state.config.url_import.allow_private_addresses),
+        Err(_) => Err("Invalid URL".to_string()),
+    };
+    if let Err(e) = checked {
+        return Err((StatusCode::BAD_REQUEST, Json(json!({ "error": e }))).into_response());
+    }
+
+    // Validate metadata overrides before doing any work
+    let overrides = req.metadata.clone().normalized().map_err(|e| {
+        (StatusCode::BAD_REQUEST, Json(json!({ "error": e }))).into_response()
+    })?;
+    let cover_data = overrides
+        .cover_image
+        .as_deref()
+        .map(decode_cover_image)
+        .transpose()
+        .map_err(|e| (StatusCode::BAD_REQUEST, Json(json!({ "error": e }))).into_response())?;
+
+    // Get user from database to access library_path
+    let db_user = state
+        .db
+        .get_user_by_id(&user.user_id)
+        .await
+        .map_err(|e| internal_error(&format!("Failed to get user: {}", e)))?;
+
+    // Get user-specific directories
+    let (music_dir, temp_dir) = get_user_directories(&state.config, &db_user.library_path)
+        .await
+        .map_err(|e| internal_error(&format!("Failed to get user directories: {}", e)))?;
+
+    tracing::info!("User {} importing {} into {}", user.username, url, music_dir.display());
+
+    // Create upload log
+    let log_id = state
+        .db
+        .create_upload_log(CreateUploadLog {
+            user_id: user.user_id.clone(),
+            upload_type: "url".to_string(),
+            source: url.to_string(),
+        })
+        .await
+        .map_err(|e| internal_error(&format!("Failed to create upload log: {}", e)))?;
+
+    // Update status to processing
+    state
+        .db
+        .update_upload_log_status(log_id, "processing", None, None)
+        .await
+        .map_err(|e| internal_error(&format!("Failed to update log: {}", e)))?;
+
+    // Each job gets its own staging directory so only its files are processed
+    let job_dir = temp_dir.join(format!("url-{}", uuid::Uuid::new_v4()));
+    ensure_directory_exists(&job_dir)
+        .await
+        .map_err(|e| internal_error(&format!("Failed to create job directory: {}", e)))?;
+
+    let cover = match &cover_data {
+        Some(data) => Some(
+            stage_cover_image(&job_dir, data)
+                .await
+                .map_err(|e| internal_error(&format!("Failed to save cover image: {}", e)))?,
+        ),
+        None => None,
+    };
+
+    let response = match fetch_into_job(&state, url, &job_dir).await {
+        Ok((file_count, details)) => {
+            state.db.add_upload_log_detail(log_id, "download", &details).await.ok();
+
+            // Same validation and ingest path as uploaded files
+            let processed = async {
+                let job = JobContext {
+                    user_id: &user.user_id,
+                    log_id,
+                    job_dir: &job_dir,
+                    music_dir: &music_dir,
+                };
+                let validation = validate_staged_files(&state, &job_dir, log_id).await?;
+                apply_metadata_overrides(&state, &job_dir, &overrides, cover.as_deref()).await?;
+                run_post_processing(&state, &job).await?;
+                import_job_dir(&state, &job).await?;
+                Ok::<_, anyhow::Error>(file_count - validation.rejected.len() as i32)
+            }
+            .await;
+            match processed {
+                Ok(file_count) => {
+                    state
+                        .db
+                        .update_upload_log_status(log_id, "completed", Some(file_count), None)
+                        .await
+                        .map_err(|e| internal_error(&format!("Failed to update log: {}", e)))?;
+
+                    Ok(Json(UploadResponse {
+                        success: true,
+                        message: format!("Successfully downloaded and processed {} file(s)", file_count),
+                        log_id: Some(log_id),
+                        session_id: None,
+                        details: None,
+                    }))
+                }
+                Err(e) => {
+                    let error_msg = format!("Processing failed: {}", e);
+                    state
+                        .db
+                        .update_upload_log_status(log_id, "failed", Some(file_count), Some(error_msg.clone()))
+                        .await
+                        .ok();
+
+                    Err((StatusCode::INTERNAL_SERVER_ERROR, Json(json!({ "error": error_msg }))).into_response())
+                }
+            }
+        }
+        Err(e) => {
+            let error_msg = format!("Download failed: {:#}", e);
+            state
+                .db
+                .update_upload_log_status(log_id, "failed", Some(0), Some(error_msg.clone()))
+                .await
+                .ok();
+
+            Err((StatusCode::BAD_GATEWAY, Json(json!({ "error": error_msg }))).into_response())
+        }
+    };
+
+    // Leftover files (failed jobs) are discarded with the job directory
+    fs::remove_dir_all(&job_dir).await.ok();
+
+    response
+}
+
+/// Download the URL into the job directory, unpacking archives
+/// Returns the number of audio files staged and the report stored in the
+/// upload log's details under "download".
+async fn fetch_into_job(state: &Arc<crate::AppState>, url: &str, job_dir: &Path) -> anyhow::Result<(i32, serde_json::Value)> {
+    let config = &state.config;
+    let max_bytes = config
+        .url_import
+        .max_size_mb
+        .map(|mb| mb * 1024 * 1024)
+        .unwrap_or(config.max_archive_size_bytes());
+
+    let download_path = job_dir.join(".download");
+    let download = download_to_file(&config.url_import, max_bytes, url, &download_path).await?;
+
+    let mut header = Vec::new();
+    fs::File::open(&download_path).await?.take(512).read_to_end(&mut header).await?;
+    let name = staged_name(download.file_name.as_deref(), &header).ok_or_else(|| {
+        anyhow::anyhow!(
+            "Not an audio file or a supported archive (content type {})",
+            download.content_type.as_deref().unwrap_or("unknown")
+        )
+    })?;
+    let mut details = json!({
+        "file": name,
+        "size": download.size,
+        "content_type": download.content_type,
+    });
+
+    if let Some((kind, stem)) = ArchiveKind::from_file_name(&name) {
+        let unpacked = unpack_staged_archive(config, kind, download_path, stem, job_dir).await?;
+        let audio = unpacked
+            .files
+            .iter()
+            .filter(|f| is_allowed_audio(&config.upload.allowed_extensions, f))
+            .count();
+        if audio == 0 {
+            anyhow::bail!("The archive contains no audio files");
+        }
+        details["extracted"] = json!(unpacked.files.len());
+        details["skipped"] = json!(unpacked.skipped);
+        return Ok((audio as i32, details));
+    }
+
+    if !is_allowed_audio(&config.upload.allowed_extensions, Path::new(&name)) {
+        anyhow::bail!("File type of {} not allowed", name);
+    }
+    fs::rename(&download_path, job_dir.join(&name)).await?;
+    Ok((1, details))
+}
+
+/// File name to stage a download under: the server's name if its extension
+/// matches the content, else the name's stem with an extension from the magic bytes
+fn staged_name(server_name: Option<&str>, header: &[u8]) -> Option<String> {
+    let name = server_name.map(sanitize_path_component).filter(|n| n != "_");
+
+    if let Some(name) = &name {
+        if let Some((kind, _)) = ArchiveKind::from_file_name(name) {
+            if ArchiveKind::sniff(header) == Some(kind) {
+                return Some(name.clone());
+            }
+        } else if let Some(ext) = Path::new(name).extension().and_then(|e| e.to_str()) {
+            let expected = expected_containers(&ext.to_lowercase());
+            if sniff_container(header).is_some_and(|c| expected.contains(&c)) {
+                return Some(name.clone());
+            }
+        }
+    }
+
+    let stem = match &name {
+        Some(name) => match ArchiveKind::from_file_name(name) {
+            Some((_, stem)) => stem.to_string(),
+            None => Path::new(name).file_stem().unwrap_or_default().to_string_lossy().to_string(),
+        },
+        None => "download".to_string(),
+    };
+    if let Some(kind) = ArchiveKind::sniff(header) {
+        return Some(format!("{}{}", stem, kind.extension()));
+    }
+    sniff_container(header).map(|c| format!("{}.{}", stem, c.extension()))
+}
+
+fn internal_error(message: &str) -> Response {
+    (
+        StatusCode::INTERNAL_SERVER_ERROR,
+        Json(json!({
+            "error": message
+        })),
+    )
+        .into_response()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn staged_name_trusts_matching_extensions_only() {
+        let flac = b"fLaC\0\0\0\x22";
+        let zip = b"PK\x03\x04\x14\0";
+
+        assert_eq!(staged_name(Some("Track.flac"), flac), Some("Track.flac".to_string()));
+        assert_eq!(staged_name(Some("Album.zip"), zip), Some("Album.zip".to_string()));
+        // Wrong or missing extensions are fixed from the content
+        assert_eq!(staged_name(Some("Track.mp3"), flac), Some("Track.flac".to_string()));
+        assert_eq!(staged_name(Some("download.php"), zip), Some("download.zip".to_string()));
+        assert_eq!(staged_name(None, zip), Some("download.zip".to_string()));
+        // Unknown content is refused
+        assert_eq!(staged_name(Some("page.html"), b"<!DOCTYPE html>"), None);
+    }
+}
@@ -1,15 +1,23 @@
 use crate::auth::AuthUser;
-use crate::config::Config;
-use crate::models::{CreateUploadLog, UploadResponse, YoutubeDownloadRequest};
-use crate::paths::get_user_directories;
+use crate::config::{Config, DownloadProfile};
+use crate::models::{CreateUploadLog, MetadataOverrides, UploadResponse, YoutubeDownloadRequest};
+use crate::paths::{ensure_directory_exists, get_user_directories, sanitize_path_component};
+use crate::pipeline::{
+    apply_metadata_overrides, clean_youtube_metadata, decode_cover_image, import_job_dir,
+    move_file, run_post_processing, stage_cover_image, staged_files, validate_staged_files,
+    JobContext,
+};
+use crate::tagging::TrackTags;
+use crate::tracklist::{parse_tracklist, segments_from_chapters, split_into_tracks, Chapter};
 use axum::{
     extract::{Extension, State},
     http::StatusCode,
     response::{IntoResponse, Response},
     Json,
 };
+use serde::Deserialize;
 use serde_json::json;
-use std::path::PathBuf;
+use std::path::Path;
 use std::sync::Arc;
 use tokio::fs;
 
@@ -74,6 +82,28 @@ pub async fn download_youtube(
             .into_response());
     }
 
+    // Validate metadata overrides before doing any work
+    let overrides = req.metadata.clone().normalized().map_err(|e| {
+        (StatusCode::BAD_REQUEST, Json(json!({ "error": e }))).into_response()
+    })?;
+    let cover_data = overrides
+        .cover_image
+        .as_deref()
+        .map(decode_cover_image)
+        .transpose()
+        .map_err(|e| (StatusCode::BAD_REQUEST, Json(json!({ "error": e }))).into_response())?;
+
+    // Pick the download profile (request, then the user's default, then [youtube] settings)
+    let selected = crate::profiles::resolve_profile(
+        &state,
+        &db_user,
+        req.profile.as_deref(),
+        state.config.legacy_youtube_profile(),
+    )
+    .await
+    .map_err(|e| (e.status(), Json(json!({ "error": e.to_string() }))).into_response())?;
+    let profile = &selected.profile;
+
     // Create upload log
     let log_id = state
         .db
@@ -92,13 +122,61 @@ pub async fn download_youtube(
         .await
         .map_err(|e| internal_error(&format!("Failed to update log: {}", e)))?;
 
+    if let Some(name) = &selected.name {
+        state.db.add_upload_log_detail(log_id, "profile", &json!(name)).await.ok();
+    }
+
+    // Each job gets its own staging directory so only its files are processed
+    let job_dir = temp_dir.join(format!("youtube-{}", uuid::Uuid::new_v4()));
+    ensure_directory_exists(&job_dir)
+        .await
+        .map_err(|e| internal_error(&format!("Failed to create job directory: {}", e)))?;
+
+    let cover = match &cover_data {
+        Some(data) => Some(
+            stage_cover_image(&job_dir, data)
+                .await
+                .map_err(|e| internal_error(&format!("Failed to save cover image: {}", e)))?,
+        ),
+        None => None,
+    };
+
     // Download with yt-dlp
-    let result = download_with_ytdlp(&state.config, &temp_dir, &req.url).await;
+    let result = if req.album_mode {
+        download_album(
+            &state.config,
+            profile,
+            &job_dir,
+            url,
+            req.tracklist.as_deref(),
+            &overrides,
+            cover.as_deref(),
+        )
+        .await
+    } else {
+        download_with_ytdlp(&state.config, profile, &job_dir, url).await
+    };
 
-    match result {
+    let response = match result {
         Ok(file_count) => {
-            // Process with Ferric (check database for ferric_enabled setting)
-            match process_temp_dir(&state, &temp_dir, &music_dir).await {
+            // Validate and fix up metadata, then process with Ferric (check database for ferric_enabled setting)
+            let processed = async {
+                let job = JobContext {
+                    user_id: &user.user_id,
+                    log_id,
+                    job_dir: &job_dir,
+                    music_dir: &music_dir,
+                };
+                validate_staged_files(&state, &job_dir, log_id).await?;
+                if !req.album_mode {
+                    clean_youtube_metadata(&state, &job_dir, &overrides).await?;
+                }
+                apply_metadata_overrides(&state, &job_dir, &overrides, cover.as_deref()).await?;
+                run_post_processing(&state, &job).await?;
+                import_job_dir(&state, &job).await
+            }
+            .await;
+            match processed {
                 Ok(_) => {
                     state
                         .db
@@ -114,6 +192,7 @@ pub async fn download_youtube(
                         ),
                         log_id: Some(log_id),
                         session_id: None,  // TODO: Add progress tracking to YouTube
+                        details: None,
                     }))
                 }
                 Err(e) => {
@@ -151,11 +230,87 @@ pub async fn download_youtube(
             )
                 .into_response())
         }
+    };
+
+    // Leftover files (failed jobs) are discarded with the job directory
+    fs::remove_dir_all(&job_dir).await.ok();
+
+    response
+}
+
+async fn download_with_ytdlp(
+    config: &Config,
+    profile: &DownloadProfile,
+    temp_dir: &Path,
+    url: &str,
+) -> anyhow::Result<i32> {
+    let args = build_ytdlp_args(config, profile, temp_dir, url);
+    run_ytdlp(config, &args).await?;
+
+    // Count downloaded files
+    Ok(staged_files(temp_dir).len() as i32)
+}
+
+/// Subset of yt-dlp's info JSON used for album mode
+#[derive(Debug, Deserialize)]
+struct VideoInfo {
+    #[serde(default)]
+    title: String,
+    #[serde(default)]
+    uploader: Option<String>,
+    #[serde(default)]
+    artist: Option<String>,
+    #[serde(default)]
+    album: Option<String>,
+    #[serde(default)]
+    release_year: Option<i64>,
+    #[serde(default)]
+    upload_date: Option<String>,
+    #[serde(default)]
+    chapters: Option<Vec<Chapter>>,
+}
+
+impl VideoInfo {
+    /// Album-level tags: explicit music metadata wins over the video title/channel
+    fn album_tags(&self) -> TrackTags {
+        let album_artist = self
+            .artist
+            .clone()
+            .or_else(|| {
+                self.uploader
+                    .as_deref()
+                    .map(|u| u.trim_end_matches(" - Topic").to_string())
+            })
+            .filter(|a| !a.trim().is_empty())
+            .unwrap_or_else(|| "Unknown Artist".to_string());
+        let album = self
+            .album
+            .clone()
+            .filter(|a| !a.trim().is_empty())
+            .unwrap_or_else(|| self.title.clone());
+        let year = self
+            .release_year
+            .map(|y| y.to_string())
+            .or_else(|| self.upload_date.as_ref().map(|d| d.chars().take(4).collect()));
+
+        TrackTags {
+            artist: Some(album_artist.clone()),
+            album_artist: Some(album_artist),
+            album: Some(album),
+            year,
+            ..TrackTags::default()
+        }
     }
 }
 
-async fn download_with_ytdlp(config: &Config, temp_dir: &PathBuf, url: &str) -> anyhow::Result<i32> {
-    let args = build_ytdlp_args(config, temp_dir, url);
+async fn fetch_video_info(config: &Config, url: &str) -> anyhow::Result<VideoInfo> {
+    let mut args = vec![
+        "--no-warnings".to_string(),
+        "--dump-single-json".to_string(),
+        "--no-playlist".to_string(),
+    ];
+    push_player_client_args(config, &mut args);
+    args.push(url.to_string());
 
     let output = tokio::process::Command::new(&config.youtube.ytdlp_path)
         .args(&args)
@@ -164,44 +319,167 @@ async fn download_with_ytdlp(config: &Config, temp_dir: &PathBuf, url: &str) ->
 
     if !output.status.success() {
         let stderr = String::from_utf8_lossy(&output.stderr);
-        anyhow::bail!("yt-dlp failed: {}", stderr);
+        anyhow::bail!("yt-dlp failed to read video info: {}", stderr);
     }
 
-    // Count downloaded files
-    let mut count = 0;
-    let mut entries = fs::read_dir(temp_dir).await?;
-    while let Some(entry) = entries.next_entry().await? {
-        if entry.file_type().await?.is_file() {
-            count += 1;
+    Ok(serde_json::from_slice(&output.stdout)?)
+}
+
+/// Album mode: download the video once, then split it into tracks using the
+/// pasted tracklist (if any) or the video's chapters. Tracks are written to
+/// "{album artist}/{album}/" inside the job dir, with the thumbnail (or the
+/// user's cover override) as cover.jpg.
+async fn download_album(
+    config: &Config,
+    profile: &DownloadProfile,
+    job_dir: &Path,
+    url: &str,
+    tracklist: Option<&str>,
+    overrides: &MetadataOverrides,
+    cover: Option<&Path>,
+) -> anyhow::Result<i32> {
+    let info = fetch_video_info(config, url).await?;
+
+    let segments = match tracklist.filter(|t| !t.trim().is_empty()) {
+        Some(text) => parse_tracklist(text)?,
+        None => segments_from_chapters(info.chapters.as_deref().unwrap_or_default()),
+    };
+    if segments.is_empty() {
+        anyhow::bail!("Video has no chapters; paste a tracklist with timestamps to split it");
+    }
+
+    // Download the full recording once (hidden dir so it is never imported)
+    let source_dir = job_dir.join(".source");
+    ensure_directory_exists(&source_dir).await?;
+    let mut args = build_ytdlp_download_args(
+        config,
+        profile,
+        &format!("{}/source.%(ext)s", source_dir.display()),
+        url,
+    );
+    let url_arg = args.pop().unwrap_or_default();
+    args.extend([
+        "--write-thumbnail".to_string(),
+        "--convert-thumbnails".to_string(),
+        "jpg".to_string(),
+        url_arg,
+    ]);
+    run_ytdlp(config, &args).await?;
+
+    let thumbnail = source_dir.join("source.jpg");
+    let source = staged_files(&source_dir)
+        .into_iter()
+        .find(|p| *p != thumbnail)
+        .ok_or_else(|| anyhow::anyhow!("yt-dlp did not produce an audio file"))?;
+
+    let tags = info
+        .album_tags()
+        .merged_with(&TrackTags::from_overrides(overrides, false));
+    let album_dir = job_dir
+        .join(sanitize_path_component(tags.album_artist.as_deref().unwrap_or_default()))
+        .join(sanitize_path_component(tags.album.as_deref().unwrap_or_default()));
+    ensure_directory_exists(&album_dir).await?;
+
+    let tracks =
+        split_into_tracks(&config.ffmpeg.ffmpeg_path, &source, &segments, &tags, &album_dir)
+            .await?;
+
+    match cover {
+        Some(cover) => {
+            fs::copy(cover, album_dir.join("cover.jpg")).await?;
         }
+        None if thumbnail.exists() => move_file(&thumbnail, &album_dir.join("cover.jpg")).await?,
+        None => {}
+    }
+    fs::remove_dir_all(&source_dir).await.ok();
+
+    Ok(tracks.len() as i32)
+}
+
+pub(crate) async fn run_ytdlp(config: &Config, args: &[String]) -> anyhow::Result<()> {
+    let output = tokio::process::Command::new(&config.youtube.ytdlp_path)
+        .args(args)
+        .output()
+        .await?;
+
+    if !output.status.success() {
+        let stderr = String::from_utf8_lossy(&output.stderr);
+        anyhow::bail!("yt-dlp failed: {}", stderr);
     }
 
-    Ok(count)
+    Ok(())
 }
 
-fn build_ytdlp_args(config: &Config, temp_dir: &PathBuf, url: &str) -> Vec<String> {
+fn build_ytdlp_args(
+    config: &Config,
+    profile: &DownloadProfile,
+    temp_dir: &Path,
+    url: &str,
+) -> Vec<String> {
+    build_ytdlp_download_args(
+        config,
+        profile,
+        &format!("{}/%(title)s.%(ext)s", temp_dir.display()),
+        url,
+    )
+}
+
+/// yt-dlp arguments for downloading a single URL as audio
+/// `output_template` is passed to --output as-is (yt-dlp template syntax)
+pub(crate) fn build_ytdlp_download_args(
+    config: &Config,
+    profile: &DownloadProfile,
+    output_template: &str,
+    url: &str,
+) -> Vec<String> {
     let mut args = vec![
         "--no-warnings".to_string(),
         "--extract-audio".to_string(),
         "--audio-format".to_string(),
-        config.youtube.audio_format.clone(),
+        profile.audio_format.clone(),
         "--output".to_string(),
-        format!("{}/%(title)s.%(ext)s", temp_dir.display()),
+        output_template.to_string(),
         "--no-playlist".to_string(),
         "--ignore-no-formats-error".to_string(),
-        // Add embed metadata for better processing
-        "--embed-metadata".to_string(),
-        "--embed-thumbnail".to_string(),
         // Handle age-restricted and certificate issues better
         "--no-check-certificates".to_string(),
     ];
 
-    let format_selector = config.youtube.format_selector.trim();
+    if let Some(quality) = profile.audio_quality.as_deref().filter(|q| !q.trim().is_empty()) {
+        args.push("--audio-quality".to_string());
+        args.push(quality.trim().to_string());
+    }
+
+    // Add embed metadata for better processing
+    if profile.embed_metadata {
+        args.push("--embed-metadata".to_string());
+    }
+    if profile.embed_thumbnail {
+        args.push("--embed-thumbnail".to_string());
+    }
+
+    let format_selector = profile
+        .format_selector
+        .as_deref()
+        .unwrap_or(&config.youtube.format_selector)
+        .trim();
     if !format_selector.is_empty() {
         args.push("--format".to_string());
         args.push(format_selector.to_string());
     }
 
+    push_player_client_args(config, &mut args);
+
+    if !config.youtube.extra_args.is_empty() {
+        args.extend(config.youtube.extra_args.iter().cloned());
+    }
+    args.extend(profile.ytdlp_args.iter().cloned());
+
+    args.push(url.to_string());
+    args
+}
+
+fn push_player_client_args(config: &Config, args: &mut Vec<String>) {
     if let Some(client) = config.youtube.player_client.as_deref() {
         let trimmed = client.trim();
         if !trimmed.is_empty() {
@@ -210,65 +488,77 @@ fn build_ytdlp_args(config: &Config, temp_dir: &PathBuf, url: &str) -> Vec<Strin
             args.push(format!("youtube:player_client={}", trimmed));
         }
     }
+}
 
-    if !config.youtube.extra_args.is_empty() {
-        args.extend(config.youtube.extra_args.iter().cloned());
+/// A single result from a yt-dlp `ytsearch` query
+#[derive(Debug, Clone, Deserialize)]
+pub(crate) struct SearchCandidate {
+    pub id: String,
+    #[serde(default)]
+    pub title: String,
+    #[serde(default)]
+    pub duration: Option<f64>,
+}
+
+impl SearchCandidate {
+    pub fn watch_url(&self) -> String {
+        format!("https://www.youtube.com/watch?v={}", self.id)
     }
+}
 
-    args.push(url.to_string());
+fn build_ytdlp_search_args(config: &Config, query: &str, limit: u32) -> Vec<String> {
+    let mut args = vec![
+        "--no-warnings".to_string(),
+        "--flat-playlist".to_string(),
+        "--dump-json".to_string(),
+    ];
+    push_player_client_args(config, &mut args);
+    args.push(format!("ytsearch{}:{}", limit.max(1), query));
     args
 }
 
-async fn process_temp_dir(
-    state: &Arc<crate::AppState>,
-    temp_dir: &PathBuf,
-    music_dir: &PathBuf,
-) -> anyhow::Result<()> {
-    // Check database for ferric_enabled setting (overrides config file)
-    let ferric_enabled = state
-        .db
-        .get_ferric_enabled(&state.config)
-        .await
-        .unwrap_or(state.config.paths.ferric_enabled);
-
-    if ferric_enabled {
-        // Call Ferric to process the files in temp dir
-        tracing::info!("Ferric enabled: processing files");
-        let output = tokio::process::Command::new(&state.config.paths.ferric_path)
-            .arg("--input-dir")
-            .arg(temp_dir)
-            .arg("--output-dir")
-            .arg(music_dir)
-            .output()
-            .await?;
+/// Search YouTube with yt-dlp and return the candidates (no download)
+pub(crate) async fn search_ytdlp(
+    config: &Config,
+    query: &str,
+    limit: u32,
+) -> anyhow::Result<Vec<SearchCandidate>> {
+    let args = build_ytdlp_search_args(config, query, limit);
+    let output = tokio::process::Command::new(&config.youtube.ytdlp_path)
+        .args(&args)
+        .output()
+        .await?;
 
-        if !output.status.success() {
-            let stderr = String::from_utf8_lossy(&output.stderr);
-            anyhow::bail!("Ferric processing failed: {}", stderr);
-        }
-    } else {
-        // Ferric disabled: just move files directly to music_dir
-        tracing::info!("Ferric disabled: moving files directly to music directory");
-        let mut entries = fs::read_dir(temp_dir).await?;
-        while let Some(entry) = entries.next_entry().await? {
-            if entry.file_type().await?.is_file() {
-                let dest = music_dir.join(entry.file_name());
-                // Use copy+remove instead of rename to handle cross-filesystem moves
-                fs::copy(entry.path(), &dest).await?;
-                fs::remove_file(entry.path()).await?;
-            }
-        }
+    if !output.status.success() {
+        let stderr = String::from_utf8_lossy(&output.stderr);
+        anyhow::bail!("yt-dlp search failed: {}", stderr);
     }
 
-    // Clean up temp directory
-    let mut entries = fs::read_dir(temp_dir).await?;
-    while let Some(entry) = entries.next_entry().await? {
-        if entry.file_type().await?.is_file() {
-            fs::remove_file(entry.path()).await.ok();
-        }
-    }
+    Ok(parse_search_output(&String::from_utf8_lossy(&output.stdout)))
+}
 
-    Ok(())
+/// yt-dlp --dump-json prints one JSON object per line; unparseable lines are skipped
+fn parse_search_output(stdout: &str) -> Vec<SearchCandidate> {
+    stdout
+        .lines()
+        .filter(|line| !line.trim().is_empty())
+        .filter_map(|line| serde_json::from_str(line).ok())
+        .collect()
+}
+
+/// Pick the candidate whose duration is closest to `target_secs`
+/// Candidates without a duration or outside `tolerance_secs` are ignored
+pub(crate) fn pick_best_candidate(
+    candidates: &[SearchCandidate],
+    target_secs: f64,
+    tolerance_secs: f64,
+) -> Option<&SearchCandidate> {
+    candidates
+        .iter()
+        .filter_map(|c| c.duration.map(|d| (c, (d - target_secs).abs())))
+        .filter(|(_, diff)| *diff <= tolerance_secs)
+        .min_by(|a, b| a.1.total_cmp(&b.1))
+        .map(|(c, _)| c)
 }
 
 fn internal_error(message: &str) -> Response {
@@ -284,13 +574,14 @@ fn internal_error(message: &str) -> Response {
 #[cfg(test)]
 mod tests {
     use super::*;
+    use std::path::PathBuf;
 
     #[test]
     fn build_args_include_default_player_client() {
         let config = Config::default();
         let temp_dir = PathBuf::from("/tmp/test");
         let url = "https://www.youtube.com/watch?v=dQw4w9WgXcQ";
-        let args = build_ytdlp_args(&config, &temp_dir, url);
+        let args = build_ytdlp_args(&config, &config.legacy_youtube_profile(), &temp_dir, url);
 
         assert!(
             args.windows(2)
@@ -308,9 +599,88 @@ mod tests {
         let temp_dir = PathBuf::from("/tmp/test");
         let url = "https://youtu.be/example";
 
-        let args = build_ytdlp_args(&config, &temp_dir, url);
+        let args = build_ytdlp_args(&config, &config.legacy_youtube_profile(), &temp_dir, url);
 
         assert!(args.contains(&"--throttled-rate=100K".to_string()));
         assert_eq!(args.last().unwrap(), url);
     }
+
+    #[test]
+    fn build_args_apply_profile_settings() {
+        let config = Config::default();
+        let profile = DownloadProfile {
+            audio_format: "mp3".to_string(),
+            audio_quality: Some("320K".to_string()),
+            format_selector: Some("bestaudio[ext=m4a]".to_string()),
+            ytdlp_args: vec!["--sponsorblock-remove=music_offtopic".to_string()],
+            embed_thumbnail: false,
+            ..config.legacy_youtube_profile()
+        };
+        let url = "https://youtu.be/example";
+
+        let args = build_ytdlp_args(&config, &profile, &PathBuf::from("/tmp/test"), url);
+
+        let has_pair = |a: &str, b: &str| args.windows(2).any(|p| p[0] == a && p[1] == b);
+        assert!(has_pair("--audio-format", "mp3"));
+        assert!(has_pair("--audio-quality", "320K"));
+        assert!(has_pair("--format", "bestaudio[ext=m4a]"));
+        assert!(args.contains(&"--embed-metadata".to_string()));
+        assert!(!args.contains(&"--embed-thumbnail".to_string()));
+        assert!(args.contains(&"--sponsorblock-remove=music_offtopic".to_string()));
+        assert_eq!(args.last().unwrap(), url);
+    }
+
+    #[test]
+    fn search_args_use_ytsearch_prefix() {
+        let config = Config::default();
+        let args = build_ytdlp_search_args(&config, "Daft Punk - Harder Better Faster Stronger", 5);
+
+        assert!(args.contains(&"--flat-playlist".to_string()));
+        assert_eq!(
+            args.last().unwrap(),
+            "ytsearch5:Daft Punk - Harder Better Faster Stronger"
+        );
+    }
+
+    #[test]
+    fn parse_search_output_skips_bad_lines() {
+        let stdout = r#"{"id": "abc", "title": "Song (Official Audio)", "duration": 215.0}
+not json
+{"id": "def", "title": "Song (Live)"}
+"#;
+        let candidates = parse_search_output(stdout);
+
+        assert_eq!(candidates.len(), 2);
+        assert_eq!(candidates[0].id, "abc");
+        assert_eq!(candidates[1].duration, None);
+    }
+
+    #[test]
+    fn pick_best_candidate_prefers_closest_duration() {
+        let candidates = vec![
+            SearchCandidate { id: "live".into(), title: "Live".into(), duration: Some(260.0) },
+            SearchCandidate { id: "close".into(), title: "Audio".into(), duration: Some(212.0) },
+            SearchCandidate { id: "exact".into(), title: "Topic".into(), duration: Some(214.0) },
+            SearchCandidate { id: "unknown".into(), title: "?".into(), duration: None },
+        ];
+
+        let best = pick_best_candidate(&candidates, 214.5, 10.0).unwrap();
+        assert_eq!(best.id, "exact");
+        assert!(pick_best_candidate(&candidates[..1], 214.5, 10.0).is_none());
+    }
+
+    #[test]
+    fn album_tags_prefer_music_metadata_over_channel() {
+        let info: VideoInfo = serde_json::from_value(json!({
+            "title": "Full Album Stream",
+            "uploader": "Some Band - Topic",
+            "upload_date": "20190412"
+        }))
+        .unwrap();
+        let tags = info.album_tags();
+
+        assert_eq!(tags.album_artist.as_deref(), Some("Some Band"));
+        assert_eq!(tags.album.as_deref(), Some("Full Album Stream"));
+        assert_eq!(tags.year.as_deref(), Some("2019"));
+    }
 }
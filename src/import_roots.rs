@@ -0,0 +1,293 @@
+use crate::config::ImportRoot;
+use crate::paths::safe_relative_path;
+use crate::pipeline::{is_allowed_audio, is_image_file, is_lyrics_file};
+use anyhow::{Context, Result};
+use serde::Serialize;
+use std::collections::HashSet;
+use std::fs;
+use std::path::{Path, PathBuf};
+
+/// Resolve a client-supplied path inside an import root
+/// The path is validated like an archive entry, then canonicalized so that
+/// symlinks can't lead outside the root. An empty path is the root itself.
+pub fn resolve(root: &ImportRoot, relative: &str) -> Result<PathBuf, String> {
+    let base = root
+        .path
+        .canonicalize()
+        .map_err(|e| format!("Import root is not available: {}", e))?;
+    if relative.trim_matches(['/', '\\', '.']).is_empty() {
+        return Ok(base);
+    }
+
+    let relative = safe_relative_path(relative).ok_or_else(|| format!("Invalid path: {}", relative))?;
+    let path = base
+        .join(&relative)
+        .canonicalize()
+        .map_err(|_| format!("{} not found", relative.display()))?;
+    if !path.starts_with(&base) {
+        return Err(format!("{} is outside the import root", relative.display()));
+    }
+    Ok(path)
+}
+
+/// What kind of file an entry is, as far as importing goes
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum EntryKind {
+    Directory,
+    Audio,
+    Image,
+    Lyrics,
+    Other,
+}
+
+impl EntryKind {
+    fn of_file(allowed_extensions: &[String], path: &Path) -> Self {
+        if is_allowed_audio(allowed_extensions, path) {
+            Self::Audio
+        } else if is_image_file(path) {
+            Self::Image
+        } else if is_lyrics_file(path) {
+            Self::Lyrics
+        } else {
+            Self::Other
+        }
+    }
+}
+
+#[derive(Debug, Serialize)]
+pub struct Entry {
+    pub name: String,
+    /// Path relative to the root, as accepted by `resolve`
+    pub path: String,
+    pub kind: EntryKind,
+    pub size: u64,
+}
+
+/// List a directory inside a root: folders first, then files, both by name
+/// Hidden entries and symlinks are left out (they are not imported either).
+pub fn list_dir(base: &Path, dir: &Path, allowed_extensions: &[String]) -> Result<Vec<Entry>> {
+    let mut entries = Vec::new();
+    for entry in fs::read_dir(dir).with_context(|| format!("Failed to read {}", dir.display()))? {
+        let entry = entry?;
+        let name = entry.file_name().to_string_lossy().to_string();
+        let file_type = entry.file_type()?;
+        if name.starts_with('.') || file_type.is_symlink() {
+            continue;
+        }
+        let path = entry.path();
+        let (kind, size) = if file_type.is_dir() {
+            (EntryKind::Directory, 0)
+        } else {
+            (EntryKind::of_file(allowed_extensions, &path), entry.metadata()?.len())
+        };
+        let relative = path.strip_prefix(base).unwrap_or(&path);
+        entries.push(Entry {
+            name,
+            path: relative.to_string_lossy().replace('\\', "/"),
+            kind,
+            size,
+        });
+    }
+    entries.sort_by(|a, b| {
+        (a.kind != EntryKind::Directory, a.name.to_lowercase()).cmp(&(b.kind != EntryKind::Directory, b.name.to_lowercase()))
+    });
+    Ok(entries)
+}
+
+/// Files copied into a job directory by `stage_selection`
+#[derive(Debug, Default)]
+pub struct Staged {
+    /// Source files that were copied, with their path relative to the job directory
+    pub sources: Vec<(PathBuf, PathBuf)>,
+    /// Number of audio files among them
+    pub audio: usize,
+    /// Files left out (hidden, symlinked or unsupported), relative to the root
+    pub skipped: Vec<String>,
+}
+
+/// Copy the selected files and folders into `job_dir`
+/// A selected folder keeps its name and structure, a selected file is placed
+/// at the top level; only audio, images and lyrics are copied.
+pub fn stage_selection(base: &Path, selection: &[PathBuf], job_dir: &Path, allowed_extensions: &[String]) -> Result<Staged> {
+    let mut staged = Staged::default();
+
+    for selected in selection {
+        let name = selected.file_name().context("Can't import the root itself")?;
+        let relative_to_root = |p: &Path| p.strip_prefix(base).unwrap_or(p).display().to_string();
+
+        for entry in walkdir::WalkDir::new(selected) {
+            let entry = entry?;
+            let path = entry.path();
+            let inside = path.strip_prefix(selected).unwrap_or(Path::new(""));
+            if entry.file_type().is_dir() {
+                continue;
+            }
+
+            let hidden = inside.iter().any(|c| c.to_string_lossy().starts_with('.'));
+            let kind = EntryKind::of_file(allowed_extensions, path);
+            if hidden || entry.path_is_symlink() || kind == EntryKind::Other {
+                staged.skipped.push(relative_to_root(path));
+                continue;
+            }
+
+            // (joining an empty path would add a trailing slash to a selected file)
+            let target = if inside.as_os_str().is_empty() {
+                job_dir.join(name)
+            } else {
+                job_dir.join(name).join(inside)
+            };
+            // Two selected items with the same name would overwrite each other
+            if target.exists() {
+                anyhow::bail!("{} was selected twice", target.strip_prefix(job_dir).unwrap_or(&target).display());
+            }
+            if let Some(parent) = target.parent() {
+                fs::create_dir_all(parent)?;
+            }
+            fs::copy(path, &target).with_context(|| format!("Failed to copy {}", relative_to_root(path)))?;
+
+            if kind == EntryKind::Audio {
+                staged.audio += 1;
+            }
+            let relative = target.strip_prefix(job_dir).unwrap_or(&target).to_path_buf();
+            staged.sources.push((path.to_path_buf(), relative));
+        }
+    }
+
+    Ok(staged)
+}
+
+/// The sources a move may delete once the job is done: audio files the
+/// pipeline left out (rejected, quarantined or skipped as duplicates) and
+/// their lyrics sidecars stay where they are
+/// `imported` are the files `import_job_dir` imported, relative to the job directory.
+pub fn imported_sources(staged: &Staged, imported: &[PathBuf], allowed_extensions: &[String]) -> Vec<PathBuf> {
+    // Transcoding changes the extension, so tracks are compared without it
+    let imported: HashSet<PathBuf> = imported.iter().map(|p| p.with_extension("")).collect();
+    let left_out: HashSet<PathBuf> = staged
+        .sources
+        .iter()
+        .map(|(_, staged)| staged)
+        .filter(|staged| is_allowed_audio(allowed_extensions, staged))
+        .map(|staged| staged.with_extension(""))
+        .filter(|track| !imported.contains(track))
+        .collect();
+
+    staged
+        .sources
+        .iter()
+        .filter(|(_, staged)| !left_out.contains(&staged.with_extension("")))
+        .map(|(source, _)| source.clone())
+        .collect()
+}
+
+/// Delete imported source files, then the selected folders if nothing else is left in them
+pub fn remove_sources(selection: &[PathBuf], sources: &[PathBuf]) -> Result<()> {
+    for source in sources {
+        fs::remove_file(source).with_context(|| format!("Failed to remove {}", source.display()))?;
+    }
+    for selected in selection.iter().filter(|p| p.is_dir()) {
+        // Deepest first; remove_dir fails (and is ignored) on non-empty folders
+        for dir in walkdir::WalkDir::new(selected).contents_first(true).into_iter().flatten() {
+            if dir.file_type().is_dir() {
+                fs::remove_dir(dir.path()).ok();
+            }
+        }
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn resolve_stays_inside_the_root() {
+        let temp = std::env::temp_dir().join(format!("import-root-{}", uuid::Uuid::new_v4()));
+        let outside = temp.join("outside");
+        let base = temp.join("root");
+        fs::create_dir_all(base.join("Album")).unwrap();
+        fs::create_dir_all(&outside).unwrap();
+        #[cfg(unix)]
+        std::os::unix::fs::symlink(&outside, base.join("escape")).unwrap();
+
+        let root = ImportRoot {
+            path: base.clone(),
+            description: String::new(),
+            users: Vec::new(),
+            allow_move: true,
+        };
+        let base = base.canonicalize().unwrap();
+        assert_eq!(resolve(&root, ""), Ok(base.clone()));
+        assert_eq!(resolve(&root, "Album/"), Ok(base.join("Album")));
+        assert!(resolve(&root, "../outside").is_err());
+        assert!(resolve(&root, "/etc").is_err());
+        assert!(resolve(&root, "Missing").is_err());
+        #[cfg(unix)]
+        assert!(resolve(&root, "escape").is_err());
+
+        fs::remove_dir_all(&temp).ok();
+    }
+
+    #[test]
+    fn stages_supported_files_and_removes_moved_sources() {
+        let temp = std::env::temp_dir().join(format!("import-stage-{}", uuid::Uuid::new_v4()));
+        let base = temp.join("root");
+        let job_dir = temp.join("job");
+        fs::create_dir_all(base.join("Album/CD1")).unwrap();
+        fs::create_dir_all(&job_dir).unwrap();
+        for file in ["Album/CD1/01.flac", "Album/cover.jpg", "Album/info.nfo", "Album/.hidden.flac", "single.mp3"] {
+            fs::write(base.join(file), b"data").unwrap();
+        }
+
+        let allowed = vec!["flac".to_string(), "mp3".to_string()];
+        let selection = vec![base.join("Album"), base.join("single.mp3")];
+        let staged = stage_selection(&base, &selection, &job_dir, &allowed).unwrap();
+        assert_eq!(staged.audio, 2);
+        assert_eq!(staged.sources.len(), 3);
+        assert!(job_dir.join("Album/CD1/01.flac").exists());
+        assert!(job_dir.join("Album/cover.jpg").exists());
+        assert!(job_dir.join("single.mp3").exists());
+        let mut skipped = staged.skipped.clone();
+        skipped.sort();
+        assert_eq!(skipped, ["Album/.hidden.flac", "Album/info.nfo"]);
+
+        // Selecting the same name twice is refused
+        assert!(stage_selection(&base, &[base.join("single.mp3")], &job_dir, &allowed).is_err());
+
+        let sources: Vec<PathBuf> = staged.sources.iter().map(|(source, _)| source.clone()).collect();
+        remove_sources(&selection, &sources).unwrap();
+        assert!(!base.join("single.mp3").exists());
+        assert!(!base.join("Album/CD1").exists());
+        // Skipped files stay where they are
+        assert!(base.join("Album/info.nfo").exists());
+
+        fs::remove_dir_all(&temp).ok();
+    }
+
+    #[test]
+    fn tracks_left_out_of_the_import_are_kept() {
+        let staged = Staged {
+            sources: [
+                ("Album/01.flac", "Album/01.flac"),
+                ("Album/02.flac", "Album/02.flac"),
+                ("Album/02.lrc", "Album/02.lrc"),
+                ("Album/03.wav", "Album/03.wav"),
+                ("Album/cover.jpg", "Album/cover.jpg"),
+            ]
+            .iter()
+            .map(|(source, staged)| (Path::new("/root").join(source), PathBuf::from(staged)))
+            .collect(),
+            audio: 3,
+            skipped: Vec::new(),
+        };
+        let allowed = vec!["flac".to_string(), "wav".to_string()];
+        // 02 was rejected, 03 was transcoded to FLAC
+        let imported = [PathBuf::from("Album/01.flac"), PathBuf::from("Album/03.flac"), PathBuf::from("Album/cover.jpg")];
+
+        assert_eq!(
+            imported_sources(&staged, &imported, &allowed),
+            [Path::new("/root/Album/01.flac"), Path::new("/root/Album/03.wav"), Path::new("/root/Album/cover.jpg")]
+        );
+    }
+}
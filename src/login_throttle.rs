@@ -0,0 +1,89 @@
+use crate::config::LoginThrottleConfig;
+use crate::db::Database;
+use crate::models::LoginFailures;
+use anyhow::Result;
+
+/// Counter key of a username
+pub fn account_key(username: &str) -> String {
+    format!("user:{}", username)
+}
+
+/// Counter key of a client address
+pub fn ip_key(ip: &str) -> String {
+    format!("ip:{}", ip)
+}
+
+/// The counters a login attempt is checked against, with their failure limits
+pub fn login_keys(config: &LoginThrottleConfig, username: &str, ip: Option<&str>) -> Vec<(String, u32)> {
+    let mut keys = vec![(account_key(username), config.max_account_failures)];
+    if let Some(ip) = ip {
+        keys.push((ip_key(ip), config.max_ip_failures));
+    }
+    keys
+}
+
+/// Seconds `entry` has to wait before its next attempt (0: may try now)
+/// Each failure doubles the wait from one second up to `max_backoff_secs`;
+/// at `max_failures` it is locked out for `lockout_minutes` after the last
+/// failure, which is also when earlier failures are forgotten.
+pub fn wait_secs(entry: &LoginFailures, max_failures: u32, config: &LoginThrottleConfig, now: i64) -> i64 {
+    let forgotten_at = entry.last_failure_at + config.lockout_minutes * 60;
+    if now >= forgotten_at || entry.failures < 1 {
+        return 0;
+    }
+    if entry.failures >= i64::from(max_failures) {
+        return forgotten_at - now;
+    }
+    let backoff = 2i64
+        .saturating_pow((entry.failures - 1).min(62) as u32)
+        .min(config.max_backoff_secs);
+    (entry.last_failure_at + backoff - now).max(0)
+}
+
+/// Longest wait of any of the keys
+pub async fn retry_after(db: &Database, config: &LoginThrottleConfig, keys: &[(String, u32)]) -> Result<i64> {
+    let now = chrono::Utc::now().timestamp();
+    let mut wait = 0;
+    for (key, max_failures) in keys {
+        if let Some(entry) = db.get_login_failures(key).await? {
+            wait = wait.max(wait_secs(&entry, *max_failures, config, now));
+        }
+    }
+    Ok(wait)
+}
+
+pub async fn record_failure(db: &Database, config: &LoginThrottleConfig, keys: &[(String, u32)]) -> Result<()> {
+    let now = chrono::Utc::now().timestamp();
+    for (key, _) in keys {
+        db.record_login_failure(key, now, now - config.lockout_minutes * 60).await?;
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn entry(failures: i64, last_failure_at: i64) -> LoginFailures {
+        LoginFailures {
+            key: "user:test".to_string(),
+            failures,
+            last_failure_at,
+        }
+    }
+
+    #[test]
+    fn backoff_doubles_then_locks_out() {
+        let config = LoginThrottleConfig::default();
+        assert_eq!(wait_secs(&entry(1, 1000), 5, &config, 1000), 1);
+        assert_eq!(wait_secs(&entry(3, 1000), 5, &config, 1000), 4);
+        assert_eq!(wait_secs(&entry(3, 1000), 5, &config, 1010), 0);
+        assert_eq!(wait_secs(&entry(4, 1000), 50, &config, 1000), 8);
+        assert_eq!(wait_secs(&entry(40, 1000), 50, &config, 1000), config.max_backoff_secs);
+
+        // Locked out until the lockout has passed, then forgotten
+        let lockout = config.lockout_minutes * 60;
+        assert_eq!(wait_secs(&entry(5, 1000), 5, &config, 1100), lockout - 100);
+        assert_eq!(wait_secs(&entry(5, 1000), 5, &config, 1000 + lockout), 0);
+    }
+}
@@ -1,36 +1,33 @@
+mod archive;
+mod artwork;
 mod auth;
 mod config;
 mod db;
+mod fetch;
 mod handlers;
+mod import_roots;
+mod login_throttle;
 mod models;
+mod oidc;
 mod paths;
+mod pipeline;
+mod profiles;
 mod progress;
+mod provisioning;
+mod proxy_auth;
+mod routes;
+mod tagging;
 mod templates;
+mod totp;
+mod tracklist;
+mod watch;
+mod webauthn;
 
-use crate::auth::{auth_middleware, AuthState};
+use crate::auth::AuthState;
 use crate::config::Config;
 use crate::db::Database;
-use crate::handlers::admin::{
-    admin_change_user_password, change_own_password, change_own_username, create_user,
-    delete_user, get_config, get_system_info, get_upload_logs, get_user_directories_info,
-    get_user_info, list_config, list_users, update_config, update_user_library_path,
-};
-use crate::handlers::auth_handlers::{login, logout};
-use crate::handlers::spotify::download_spotify;
-use crate::handlers::upload::upload_files;
-use crate::handlers::youtube::download_youtube;
-use crate::templates::{AdminTemplate, LoginTemplate, LogsTemplate, SettingsTemplate, UploadTemplate};
-use axum::{
-    middleware,
-    routing::{delete, get, post},
-    Router,
-};
+use std::net::SocketAddr;
 use std::sync::Arc;
-use tower_http::{
-    cors::{Any, CorsLayer},
-    limit::RequestBodyLimitLayer,
-    trace::TraceLayer,
-};
 use tracing_subscriber::{layer::SubscriberExt, util::SubscriberInitExt};
 
 // Shared application state
@@ -84,29 +81,39 @@ async fn main() -> anyhow::Result<()> {
     tracing::info!("Connecting to database...");
     let db = Database::new(&config.database.url, config.database.max_connections).await?;
 
-    // Check if this is first run and create default admin if needed
+    // First run: create an admin with INITIAL_ADMIN_PASSWORD, or a random
+    // password that is only ever shown in this log
     if !db.user_exists().await? {
-        tracing::warn!("No users found in database. Creating default admin user...");
-        tracing::warn!("DEFAULT CREDENTIALS - Username: admin, Password: admin");
-        tracing::warn!("PLEASE CHANGE THE DEFAULT PASSWORD IMMEDIATELY!");
+        let (password, generated) = match std::env::var("INITIAL_ADMIN_PASSWORD") {
+            Ok(password) if password.len() >= 8 => (password, false),
+            Ok(_) => anyhow::bail!("INITIAL_ADMIN_PASSWORD must be at least 8 characters"),
+            Err(_) => (crate::oidc::random_token(), true),
+        };
 
         use crate::models::CreateUser;
         db.create_user(CreateUser {
             username: "admin".to_string(),
-            password: "admin".to_string(),
-            is_admin: true,
+            password: password.clone(),
+            role: "admin".to_string(),
             library_path: None, // Admin can set this later via admin panel
         })
         .await?;
 
-        tracing::info!("Default admin user created successfully");
+        if generated {
+            tracing::warn!("No users found in database. Created admin user 'admin' with password: {}", password);
+            tracing::warn!("This password is not shown again, change it after logging in");
+        } else {
+            tracing::info!("No users found in database. Created admin user 'admin' with INITIAL_ADMIN_PASSWORD");
+        }
     }
 
-    // Create auth state
-    let auth_state = AuthState::new(
-        config.security.jwt_secret.clone(),
+    // Load (or create) the token signing keys
+    let auth_state = AuthState::load(
+        &db,
+        config.security.configured_jwt_secret(),
         config.security.session_timeout_hours,
-    );
+    )
+    .await?;
 
     // Create progress store for tracking upload/download progress
     let progress_store = progress::create_progress_store();
@@ -115,47 +122,12 @@ async fn main() -> anyhow::Result<()> {
     let app_state = Arc::new(AppState {
         db,
         config,
-        auth: auth_state.clone(),
+        auth: auth_state,
         progress_store,
     });
 
-    // Protected routes (require authentication)
-    let protected_routes = Router::new()
-        // API routes
-        .route("/api/upload", post(upload_files))
-        .route("/api/youtube", post(download_youtube))
-        .route("/api/spotify", post(download_spotify))
-        .route("/api/progress/:session_id", get(stream_progress))
-        .route("/api/admin/users", get(list_users).post(create_user))
-        .route("/api/admin/users/:id", delete(delete_user))
-        .route(
-            "/api/admin/users/:id/password",
-            post(admin_change_user_password),
-        )
-        .route(
-            "/api/admin/users/:id/library",
-            post(update_user_library_path),
-        )
-        .route("/api/user/change-password", post(change_own_password))
-        .route("/api/user/change-username", post(change_own_username))
-        .route("/api/user/info", get(get_user_info))
-        .route("/api/user/directories", get(get_user_directories_info))
-        .route("/api/admin/system-info", get(get_system_info))
-        .route("/api/admin/config", get(list_config).post(update_config))
-        .route("/api/admin/config/:key", get(get_config))
-        .route("/api/admin/logs", get(get_upload_logs))
-        .route("/api/logout", post(logout))
-        // Template routes (PROTECTED - require login)
-        .route("/upload", get(|| async { UploadTemplate }))
-        .route("/settings", get(|| async { SettingsTemplate }))
-        .route("/admin", get(|| async { AdminTemplate }))
-        .route("/logs", get(|| async { LogsTemplate }))
-        .layer(middleware::from_fn_with_state(auth_state, auth_middleware));
-
-    // Public routes (only login page and API endpoint)
-    let public_routes = Router::new()
-        .route("/", get(|| async { LoginTemplate }))
-        .route("/api/login", post(login));
+    // Import files dropped into the watched folders in the background
+    watch::spawn(app_state.clone());
 
     // Start server address
     let addr = format!(
@@ -163,34 +135,13 @@ async fn main() -> anyhow::Result<()> {
         app_state.config.server.host, app_state.config.server.port
     );
 
-    // Configure CORS - only allow same-origin by default (restrictive for security)
-    // If you need to allow different origins, configure this appropriately
-    let cors = CorsLayer::new()
-        .allow_origin(Any) // In production, specify your domain(s)
-        .allow_methods([
-            axum::http::Method::GET,
-            axum::http::Method::POST,
-            axum::http::Method::DELETE,
-        ])
-        .allow_headers(Any);
-
-    // Max request body size from config
-    let max_body_size = app_state.config.max_file_size_bytes();
-
-    // Combine routes
-    let app = Router::new()
-        .merge(protected_routes)
-        .merge(public_routes)
-        .with_state(app_state)
-        .layer(cors)
-        .layer(RequestBodyLimitLayer::new(max_body_size))
-        .layer(TraceLayer::new_for_http());
+    let app = routes::app(app_state);
     let listener = tokio::net::TcpListener::bind(&addr).await?;
 
     tracing::info!("Server listening on {}", addr);
     tracing::info!("Visit http://{} to access the application", addr);
 
-    axum::serve(listener, app).await?;
+    axum::serve(listener, app.into_make_service_with_connect_info::<SocketAddr>()).await?;
 
     Ok(())
 }
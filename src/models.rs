@@ -1,3 +1,4 @@
+use crate::auth::Permission;
 use chrono::{DateTime, Utc};
 use serde::{Deserialize, Serialize};
 use sqlx::FromRow;
@@ -8,17 +9,26 @@ pub struct User {
     pub username: String,
     #[serde(skip_serializing)]
     pub password_hash: String,
-    pub is_admin: bool,
+    /// Name of a row in `roles`
+    pub role: String,
     pub library_path: Option<String>,
+    /// Download profile used when a request does not name one
+    pub default_profile: Option<String>,
     pub created_at: DateTime<Utc>,
     pub updated_at: DateTime<Utc>,
+    /// Whether TOTP two-factor authentication is set up
+    #[sqlx(default)]
+    pub totp_enabled: bool,
+    /// Whether the user has passkeys, which also serve as a second factor
+    #[sqlx(default)]
+    pub passkeys_enabled: bool,
 }
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct CreateUser {
     pub username: String,
     pub password: String,
-    pub is_admin: bool,
+    pub role: String,
     pub library_path: Option<String>,
 }
 
@@ -32,7 +42,181 @@ pub struct LoginRequest {
 pub struct LoginResponse {
     pub token: String,
     pub username: String,
+    /// Whether the admin panel is of any use (users or config can be managed)
     pub is_admin: bool,
+    pub role: String,
+    pub permissions: Vec<Permission>,
+    /// The role has admin permissions that need 2FA to be set up first
+    pub totp_setup_required: bool,
+}
+
+/// A named set of permissions assigned to users
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Role {
+    pub name: String,
+    #[serde(default)]
+    pub description: String,
+    pub permissions: Vec<Permission>,
+}
+
+/// A logged-in device, one per issued token
+#[derive(Debug, Clone, Serialize, Deserialize, FromRow)]
+pub struct Session {
+    pub id: String,
+    #[serde(skip_serializing)]
+    pub user_id: String,
+    pub ip_address: Option<String>,
+    pub user_agent: Option<String>,
+    pub created_at: DateTime<Utc>,
+    pub last_seen_at: DateTime<Utc>,
+    pub expires_at: i64,
+    /// Whether this is the session making the request
+    #[sqlx(default)]
+    #[serde(default)]
+    pub current: bool,
+}
+
+/// A personal API token (the token itself is only returned on creation)
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ApiToken {
+    pub id: String,
+    pub user_id: String,
+    pub username: String,
+    pub name: String,
+    pub prefix: String,
+    /// Permissions the token is limited to; `None` for all of the owner's
+    pub scopes: Option<Vec<Permission>>,
+    pub expires_at: Option<i64>,
+    pub created_at: DateTime<Utc>,
+    pub last_used_at: Option<DateTime<Utc>>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct CreateApiTokenRequest {
+    pub name: String,
+    #[serde(default)]
+    pub expires_in_days: Option<u32>,
+    #[serde(default)]
+    pub scopes: Option<Vec<Permission>>,
+}
+
+/// A user's TOTP secret; see `crate::totp`
+#[derive(Debug, Clone, FromRow)]
+pub struct UserTotp {
+    pub user_id: String,
+    pub secret: String,
+    pub enabled: bool,
+    pub last_step: Option<i64>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct TotpCodeRequest {
+    pub code: String,
+}
+
+/// Second login step: the challenge from the first step and a TOTP or recovery code
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct TotpLoginRequest {
+    pub challenge: String,
+    pub code: String,
+}
+
+/// A registered passkey; see `crate::webauthn`
+#[derive(Debug, Clone, Serialize, Deserialize, FromRow)]
+pub struct Passkey {
+    pub id: String,
+    #[serde(skip_serializing)]
+    pub user_id: String,
+    pub name: String,
+    #[serde(skip_serializing)]
+    pub credential_id: String,
+    #[serde(skip_serializing)]
+    pub public_key: Vec<u8>,
+    #[serde(skip_serializing)]
+    pub sign_count: i64,
+    pub created_at: DateTime<Utc>,
+    pub last_used_at: Option<DateTime<Utc>>,
+}
+
+/// A new passkey as `navigator.credentials.create()` returns it (binary fields base64url)
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct PasskeyCredential {
+    pub client_data_json: String,
+    pub attestation_object: String,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct RegisterPasskeyRequest {
+    pub name: String,
+    pub credential: PasskeyCredential,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct RenamePasskeyRequest {
+    pub name: String,
+}
+
+/// A passkey login as `navigator.credentials.get()` returns it (binary fields base64url)
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct PasskeyAssertion {
+    pub credential_id: String,
+    pub client_data_json: String,
+    pub authenticator_data: String,
+    pub signature: String,
+    #[serde(default)]
+    pub user_handle: Option<String>,
+}
+
+/// Start a passkey login; with the challenge of a password login, the
+/// passkey is its second factor
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct PasskeyLoginOptionsRequest {
+    #[serde(default)]
+    pub challenge: Option<String>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct PasskeyLoginRequest {
+    #[serde(default)]
+    pub challenge: Option<String>,
+    pub credential: PasskeyAssertion,
+}
+
+/// Recent failed logins of an IP or username; see `login_throttle`
+#[derive(Debug, Clone, Serialize, Deserialize, FromRow)]
+pub struct LoginFailures {
+    pub key: String,
+    pub failures: i64,
+    pub last_failure_at: i64,
+}
+
+/// A login attempt in the audit trail
+#[derive(Debug, Clone, Serialize, Deserialize, FromRow)]
+pub struct AuthEvent {
+    pub id: i64,
+    pub username: String,
+    pub user_id: Option<String>,
+    pub ip_address: Option<String>,
+    pub user_agent: Option<String>,
+    pub success: bool,
+    pub reason: String,
+    pub created_at: DateTime<Utc>,
+}
+
+/// A token signing key; see `AuthState`
+#[derive(Debug, Clone, Serialize, Deserialize, FromRow)]
+pub struct JwtKey {
+    pub kid: String,
+    #[serde(skip_serializing)]
+    pub secret: String,
+    pub created_at: DateTime<Utc>,
+    /// Set once retired: when the last token it signed expires (unix timestamp)
+    pub expires_at: Option<i64>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct UpdateUserRoleRequest {
+    pub role: String,
 }
 
 #[derive(Debug, Clone, Serialize, Deserialize, FromRow)]
@@ -46,6 +230,8 @@ pub struct UploadLog {
     pub error_message: Option<String>,
     pub created_at: DateTime<Utc>,
     pub completed_at: Option<DateTime<Utc>>,
+    /// JSON report with per-job details (e.g. tracks recovered by a fallback)
+    pub details: Option<String>,
 }
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
@@ -55,6 +241,67 @@ pub struct CreateUploadLog {
     pub source: String,
 }
 
+/// A file flagged by the fake-lossless check, with the owner's username
+#[derive(Debug, Clone, Serialize, Deserialize, FromRow)]
+pub struct SpectralAnalysis {
+    pub id: i32,
+    pub user_id: String,
+    pub username: String,
+    pub upload_log_id: i32,
+    pub file_name: String,
+    pub codec: String,
+    pub cutoff_hz: Option<f64>,
+    pub confidence: f64,
+    pub verdict: String,
+    pub quarantine_path: Option<String>,
+    pub created_at: DateTime<Utc>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct CreateSpectralAnalysis {
+    pub user_id: String,
+    pub upload_log_id: i32,
+    pub file_name: String,
+    pub codec: String,
+    pub cutoff_hz: Option<f64>,
+    pub confidence: f64,
+    pub verdict: String,
+    pub quarantine_path: Option<String>,
+}
+
+/// A file in a user's library, with the owner's username
+#[derive(Debug, Clone, Serialize, Deserialize, FromRow)]
+pub struct LibraryFile {
+    pub id: i32,
+    pub user_id: String,
+    pub username: String,
+    /// Relative to the user's music directory (as staged, for Ferric imports)
+    pub path: String,
+    pub sha256: String,
+    /// Chromaprint sub-fingerprints as little-endian u32s
+    #[serde(skip_serializing, default)]
+    pub fingerprint: Option<Vec<u8>>,
+    pub duration_secs: Option<f64>,
+    pub codec: String,
+    pub bitrate_kbps: Option<i64>,
+    pub size_bytes: i64,
+    pub upload_log_id: Option<i32>,
+    pub created_at: DateTime<Utc>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct CreateLibraryFile {
+    pub user_id: String,
+    pub path: String,
+    pub sha256: String,
+    pub fingerprint: Option<Vec<u8>>,
+    pub duration_secs: Option<f64>,
+    pub codec: String,
+    pub bitrate_kbps: Option<i64>,
+    pub size_bytes: i64,
+    pub upload_log_id: Option<i32>,
+}
+
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct UploadResponse {
     pub success: bool,
@@ -62,24 +309,154 @@ pub struct UploadResponse {
     pub log_id: Option<i32>,
     #[serde(skip_serializing_if = "Option::is_none")]
     pub session_id: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub details: Option<serde_json::Value>,
+}
+
+/// Optional tags supplied by the user at submission time
+/// Applied to the resulting files before processing
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct MetadataOverrides {
+    #[serde(default)]
+    pub artist: Option<String>,
+    #[serde(default)]
+    pub album_artist: Option<String>,
+    #[serde(default)]
+    pub album: Option<String>,
+    #[serde(default)]
+    pub title: Option<String>,
+    #[serde(default)]
+    pub year: Option<String>,
+    #[serde(default)]
+    pub genre: Option<String>,
+    /// Base64-encoded JPEG or PNG (a "data:image/...;base64," prefix is accepted)
+    #[serde(default, skip_serializing)]
+    pub cover_image: Option<String>,
+}
+
+impl MetadataOverrides {
+    const MAX_FIELD_LEN: usize = 200;
+
+    /// Set a text field by its form/JSON name; blank values are ignored
+    /// Returns false for unknown field names
+    pub fn set_field(&mut self, name: &str, value: String) -> bool {
+        let value = Some(value.trim().to_string()).filter(|v| !v.is_empty());
+        match name {
+            "artist" => self.artist = value,
+            "album_artist" => self.album_artist = value,
+            "album" => self.album = value,
+            "title" => self.title = value,
+            "year" => self.year = value,
+            "genre" => self.genre = value,
+            _ => return false,
+        }
+        true
+    }
+
+    /// Trim fields, drop blank ones and check lengths/year format
+    pub fn normalized(self) -> Result<Self, String> {
+        let clean = |value: Option<String>, name: &str| -> Result<Option<String>, String> {
+            match value.map(|v| v.trim().to_string()).filter(|v| !v.is_empty()) {
+                Some(v) if v.chars().count() > Self::MAX_FIELD_LEN => Err(format!(
+                    "{} must be at most {} characters",
+                    name,
+                    Self::MAX_FIELD_LEN
+                )),
+                other => Ok(other),
+            }
+        };
+
+        let year = clean(self.year, "year")?;
+        if let Some(y) = &year {
+            if y.len() != 4 || !y.chars().all(|c| c.is_ascii_digit()) {
+                return Err("year must be a four-digit year".to_string());
+            }
+        }
+
+        Ok(Self {
+            artist: clean(self.artist, "artist")?,
+            album_artist: clean(self.album_artist, "album_artist")?,
+            album: clean(self.album, "album")?,
+            title: clean(self.title, "title")?,
+            year,
+            genre: clean(self.genre, "genre")?,
+            cover_image: self.cover_image.filter(|c| !c.trim().is_empty()),
+        })
+    }
 }
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct YoutubeDownloadRequest {
     pub url: String,
+    #[serde(default)]
+    pub metadata: MetadataOverrides,
+    /// Split the video into an album (by chapters or `tracklist`)
+    #[serde(default)]
+    pub album_mode: bool,
+    /// Pasted "timestamp title" lines, used instead of the video's chapters
+    #[serde(default)]
+    pub tracklist: Option<String>,
+    /// Download profile name; defaults to the user's default profile
+    #[serde(default)]
+    pub profile: Option<String>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct UrlImportRequest {
+    /// Direct http(s) link to an audio file or an archive
+    pub url: String,
+    #[serde(default)]
+    pub metadata: MetadataOverrides,
+}
+
+/// Whether server-side imports leave the source files in place
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum ImportMode {
+    #[default]
+    Copy,
+    /// Source files are deleted once the import succeeded
+    Move,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ServerImportRequest {
+    /// Name of a configured import root
+    pub root: String,
+    /// Files and folders to import, relative to the root
+    pub paths: Vec<String>,
+    #[serde(default)]
+    pub mode: ImportMode,
+    #[serde(default)]
+    pub metadata: MetadataOverrides,
+}
+
+#[derive(Debug, Clone, Deserialize)]
+pub struct BrowseQuery {
+    pub root: String,
+    #[serde(default)]
+    pub path: String,
 }
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct SpotifyDownloadRequest {
     pub url: String,
+    #[serde(default)]
+    pub metadata: MetadataOverrides,
+    /// Override the global YouTube fallback setting for this request
+    #[serde(default)]
+    pub youtube_fallback: Option<bool>,
+    /// Download profile name; defaults to the user's default profile
+    #[serde(default)]
+    pub profile: Option<String>,
 }
 
 // Claims for JWT tokens
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct Claims {
     pub sub: String, // user_id
-    pub username: String,
-    pub is_admin: bool,
+    /// Id of the row in `sessions` this token belongs to
+    pub jti: String,
     pub exp: i64,
 }
 
@@ -100,3 +477,15 @@ pub struct AdminChangePasswordRequest {
 pub struct UpdateLibraryPathRequest {
     pub library_path: String,
 }
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct UpdateDefaultProfileRequest {
+    /// None (or an empty string) clears the default
+    #[serde(default)]
+    pub profile: Option<String>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct UpdateAllowedProfilesRequest {
+    pub allowed: Vec<String>,
+}
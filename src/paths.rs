@@ -77,6 +77,65 @@ pub async fn get_user_directories(
     Ok((music_dir, temp_dir))
 }
 
+/// Make a string safe to use as a single file or directory name
+/// Replaces path separators and characters that are invalid on common filesystems
+pub fn sanitize_path_component(name: &str) -> String {
+    let cleaned: String = name
+        .chars()
+        .map(|c| match c {
+            '/' | '\\' | ':' | '*' | '?' | '"' | '<' | '>' | '|' => '_',
+            c if c.is_control() => '_',
+            c => c,
+        })
+        .collect();
+
+    // Leading/trailing dots and spaces cause trouble (hidden files, "..", Windows shares)
+    let trimmed: String = cleaned
+        .trim_matches(|c: char| c == '.' || c.is_whitespace())
+        .chars()
+        .take(200)
+        .collect();
+
+    if trimmed.is_empty() {
+        "_".to_string()
+    } else {
+        trimmed
+    }
+}
+
+/// Validate a relative path supplied by a client (archive entry, folder upload)
+/// Each component is checked on its own: no `..`, no absolute paths or drive
+/// prefixes, no Windows reserved names, no control characters.
+/// Empty and `.` components are dropped; returns None if the path is unsafe or empty.
+pub fn safe_relative_path(name: &str) -> Option<PathBuf> {
+    const RESERVED: &[&str] = &["con", "prn", "aux", "nul"];
+
+    if name.starts_with('/') || name.starts_with('\\') {
+        return None;
+    }
+
+    let mut path = PathBuf::new();
+    for component in name.split(['/', '\\']) {
+        if component.is_empty() || component == "." {
+            continue;
+        }
+        if component == ".." || component.contains(':') || component.chars().any(|c| c.is_control()) {
+            return None;
+        }
+        // Windows ignores trailing dots/spaces, so "con .txt" is still CON
+        let base = component.split('.').next().unwrap_or("").trim_end().to_lowercase();
+        let numbered = (base.starts_with("com") || base.starts_with("lpt"))
+            && base.len() == 4
+            && base.as_bytes()[3].is_ascii_digit();
+        if RESERVED.contains(&base.as_str()) || numbered {
+            return None;
+        }
+        path.push(component);
+    }
+
+    path.components().next().is_some().then_some(path)
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
@@ -140,4 +199,29 @@ mod tests {
         let result = get_user_temp_dir(&config, &library_path);
         assert_eq!(result, config.paths.temp_dir);
     }
+
+    #[test]
+    fn test_sanitize_path_component() {
+        assert_eq!(sanitize_path_component("AC/DC"), "AC_DC");
+        assert_eq!(sanitize_path_component(".."), "_");
+        assert_eq!(sanitize_path_component("  Album: Live?  "), "Album_ Live_");
+        assert_eq!(sanitize_path_component("Normal Name"), "Normal Name");
+    }
+
+    #[test]
+    fn test_safe_relative_path() {
+        assert_eq!(
+            safe_relative_path("Artist/Album/CD1/01 Track.flac"),
+            Some(PathBuf::from("Artist/Album/CD1/01 Track.flac"))
+        );
+        assert_eq!(safe_relative_path("./Album\\cover.jpg"), Some(PathBuf::from("Album/cover.jpg")));
+        assert_eq!(safe_relative_path("../etc/passwd"), None);
+        assert_eq!(safe_relative_path("Album/../../x.mp3"), None);
+        assert_eq!(safe_relative_path("/etc/passwd"), None);
+        assert_eq!(safe_relative_path("C:/Windows/x.mp3"), None);
+        assert_eq!(safe_relative_path("Album/CON.mp3"), None);
+        assert_eq!(safe_relative_path("Album/lpt1"), None);
+        assert!(safe_relative_path("Album/Console.mp3").is_some());
+        assert_eq!(safe_relative_path("./"), None);
+    }
 }
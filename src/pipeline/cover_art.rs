@@ -0,0 +1,180 @@
+use super::probe::probe_audio;
+use super::{is_audio_file, is_image_file, staged_files};
+use crate::artwork::normalize_cover;
+use crate::config::Config;
+use crate::tagging::{embed_cover_art, extract_cover_art, sibling_temp_path};
+use anyhow::{Context, Result};
+use std::collections::BTreeMap;
+use std::path::{Path, PathBuf};
+use tokio::fs;
+
+/// File stems preferred when a folder contains several images
+const COVER_NAMES: &[&str] = &["cover", "folder", "front", "album"];
+
+/// Pick the image to use as album art: a conventional name first, else the largest file
+pub fn pick_folder_image(images: &[(PathBuf, u64)]) -> Option<&Path> {
+    let named = images.iter().find(|(path, _)| {
+        path.file_stem()
+            .and_then(|s| s.to_str())
+            .is_some_and(|stem| COVER_NAMES.contains(&stem.to_lowercase().as_str()))
+    });
+    named
+        .or_else(|| images.iter().max_by_key(|(_, size)| *size))
+        .map(|(path, _)| path.as_path())
+}
+
+/// Audio files and images (with their sizes) staged in one folder
+#[derive(Default)]
+struct FolderContents {
+    tracks: Vec<PathBuf>,
+    images: Vec<(PathBuf, u64)>,
+}
+
+/// Normalize the cover art of every folder in the job and embed it in each track
+///
+/// The art comes from the user's cover override, an image uploaded into the
+/// folder, or the first track with embedded art, in that order. Album
+/// folders get it as `folder_file_name`; at the top level of the job (no
+/// album folder) it is only embedded and the source images are dropped.
+/// Returns the report stored in the upload log's details under "cover_art".
+pub async fn process_job(config: &Config, job_dir: &Path) -> serde_json::Value {
+    let override_cover = ["jpg", "png"]
+        .iter()
+        .map(|ext| job_dir.join(format!(".override-cover.{}", ext)))
+        .find(|p| p.exists());
+
+    let mut folders: BTreeMap<PathBuf, FolderContents> = BTreeMap::new();
+    for file in staged_files(job_dir) {
+        let folder = folders.entry(file.parent().unwrap_or(job_dir).to_path_buf()).or_default();
+        if is_audio_file(&file) {
+            folder.tracks.push(file);
+        } else if is_image_file(&file) {
+            let size = fs::metadata(&file).await.map(|m| m.len()).unwrap_or(0);
+            folder.images.push((file, size));
+        }
+    }
+
+    let mut reports = Vec::new();
+    for (folder, contents) in &folders {
+        if contents.tracks.is_empty() {
+            continue;
+        }
+        let relative = folder.strip_prefix(job_dir).unwrap_or(folder).display().to_string();
+        let result = process_folder(config, job_dir, folder, contents, override_cover.as_deref()).await;
+        let mut report = match result {
+            Ok(report) => report,
+            Err(e) => {
+                tracing::warn!("Cover art processing failed for {}: {:#}", folder.display(), e);
+                serde_json::json!({ "error": format!("{:#}", e) })
+            }
+        };
+        report["folder"] = serde_json::json!(relative);
+        reports.push(report);
+    }
+
+    serde_json::json!({ "folders": reports })
+}
+
+async fn process_folder(
+    config: &Config,
+    job_dir: &Path,
+    folder: &Path,
+    contents: &FolderContents,
+    override_cover: Option<&Path>,
+) -> Result<serde_json::Value> {
+    let FolderContents { tracks, images } = contents;
+    let settings = &config.cover_art;
+    let ffmpeg = &config.ffmpeg;
+    let is_album_folder = folder != job_dir;
+    let name = |p: &Path| p.file_name().unwrap_or_default().to_string_lossy().to_string();
+
+    // Find the source art
+    let mut extracted = None;
+    let (source, source_label) = if let Some(cover) = override_cover {
+        (cover.to_path_buf(), "override".to_string())
+    } else if let Some(image) = pick_folder_image(images) {
+        (image.to_path_buf(), format!("image {}", name(image)))
+    } else {
+        let mut found = None;
+        for track in tracks {
+            let has_cover = probe_audio(&ffmpeg.ffprobe_path, track).await.is_ok_and(|p| p.has_cover);
+            if !has_cover {
+                continue;
+            }
+            let dest_stem = sibling_temp_path(track, "art");
+            if let Some(art) = extract_cover_art(&ffmpeg.ffmpeg_path, track, &dest_stem).await? {
+                found = Some((art, format!("embedded in {}", name(track))));
+                break;
+            }
+        }
+        match found {
+            Some((art, label)) => {
+                extracted = Some(art.clone());
+                (art, label)
+            }
+            None => return Ok(serde_json::json!({ "source": null })),
+        }
+    };
+
+    let data = fs::read(&source).await.context(format!("Failed to read {}", source.display()))?;
+    if let Some(art) = &extracted {
+        fs::remove_file(art).await.ok();
+    }
+    let (jpeg, side) = normalize_cover(&data, settings.max_dimension, settings.jpeg_quality)?;
+
+    // Album folders keep the art as a file; at the top level it is a hidden temp file
+    let cover_path = if is_album_folder {
+        folder.join(&settings.folder_file_name)
+    } else {
+        job_dir.join(".normalized-cover.jpg")
+    };
+    fs::write(&cover_path, &jpeg)
+        .await
+        .context(format!("Failed to write {}", cover_path.display()))?;
+
+    let mut embedded = 0;
+    let mut unsupported = Vec::new();
+    for track in tracks {
+        if embed_cover_art(ffmpeg, track, &cover_path).await? {
+            embedded += 1;
+        } else {
+            unsupported.push(name(track));
+        }
+    }
+
+    // Uploaded images have been turned into the folder art (or embedded)
+    for (image, _) in images {
+        let consumed = !is_album_folder || (*image == source && *image != cover_path);
+        if consumed {
+            fs::remove_file(image).await.ok();
+        }
+    }
+    if !is_album_folder {
+        fs::remove_file(&cover_path).await.ok();
+    }
+
+    Ok(serde_json::json!({
+        "source": source_label,
+        "size": side,
+        "folder_file": is_album_folder.then(|| settings.folder_file_name.clone()),
+        "embedded": embedded,
+        "unsupported": unsupported,
+    }))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn prefers_conventional_names_then_size() {
+        let images = vec![
+            (PathBuf::from("a/scan1.jpg"), 900_000),
+            (PathBuf::from("a/Folder.png"), 100_000),
+            (PathBuf::from("a/scan2.jpg"), 800_000),
+        ];
+        assert_eq!(pick_folder_image(&images), Some(Path::new("a/Folder.png")));
+        assert_eq!(pick_folder_image(&[images[0].clone(), images[2].clone()]), Some(Path::new("a/scan1.jpg")));
+        assert_eq!(pick_folder_image(&[]), None);
+    }
+}
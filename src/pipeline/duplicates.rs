@@ -0,0 +1,513 @@
+use super::fingerprint::{self, fingerprint_file};
+use super::probe::{codec_is_lossless, probe_audio};
+use super::{is_audio_file, staged_files, JobContext};
+use crate::config::{Config, DuplicateAction};
+use crate::models::{CreateLibraryFile, LibraryFile};
+use anyhow::{Context, Result};
+use sha2::{Digest, Sha256};
+use std::collections::HashMap;
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+use std::time::SystemTime;
+use tokio::fs;
+use tokio::io::AsyncReadExt;
+
+/// Fingerprints are compared at alignments up to this many sub-fingerprints apart (~5 s)
+const MAX_ALIGN_OFFSET: usize = 40;
+
+/// Durations further apart than this can't be the same recording
+pub fn duration_tolerance(secs: f64) -> f64 {
+    (secs * 0.03).max(3.0)
+}
+
+/// A staged file with everything needed to compare and record it
+pub struct InspectedFile {
+    pub path: PathBuf,
+    /// Path relative to the job directory
+    pub relative: String,
+    pub sha256: String,
+    pub fingerprint: Option<Vec<u32>>,
+    pub duration_secs: Option<f64>,
+    pub codec: String,
+    pub bitrate_kbps: Option<u32>,
+    pub size_bytes: u64,
+}
+
+/// How a new file compares to a library file
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Match {
+    /// The file bytes are identical
+    pub exact: bool,
+    pub similarity: f64,
+}
+
+/// Compare a staged file with a library file; None if they are not duplicates
+pub fn compare(file: &InspectedFile, existing: &LibraryFile, threshold: f64) -> Option<Match> {
+    if file.sha256 == existing.sha256 {
+        return Some(Match { exact: true, similarity: 1.0 });
+    }
+
+    let (Some(a), Some(b)) = (&file.fingerprint, &existing.fingerprint) else {
+        return None;
+    };
+    let (Some(d1), Some(d2)) = (file.duration_secs, existing.duration_secs) else {
+        return None;
+    };
+    if (d1 - d2).abs() > duration_tolerance(d1.min(d2)) {
+        return None;
+    }
+
+    let similarity = fingerprint::similarity(a, &fingerprint::from_bytes(b), MAX_ALIGN_OFFSET);
+    (similarity >= threshold).then_some(Match { exact: false, similarity })
+}
+
+/// Lossless beats lossy; otherwise the higher bitrate wins (unknown counts as 0)
+pub fn is_better(codec: &str, bitrate_kbps: Option<u32>, other_codec: &str, other_bitrate_kbps: Option<u32>) -> bool {
+    match (codec_is_lossless(codec), codec_is_lossless(other_codec)) {
+        (true, false) => true,
+        (false, true) => false,
+        _ => bitrate_kbps.unwrap_or(0) > other_bitrate_kbps.unwrap_or(0),
+    }
+}
+
+/// SHA-256 of the file contents, hex encoded
+pub async fn hash_file(path: &Path) -> Result<String> {
+    let mut file = fs::File::open(path)
+        .await
+        .context(format!("Failed to open {}", path.display()))?;
+    let mut hasher = Sha256::new();
+    let mut buffer = vec![0u8; 64 * 1024];
+    loop {
+        let read = file.read(&mut buffer).await?;
+        if read == 0 {
+            break;
+        }
+        hasher.update(&buffer[..read]);
+    }
+    Ok(format!("{:x}", hasher.finalize()))
+}
+
+/// Hash, probe and fingerprint one staged file
+/// Only the hash is required; probe and fingerprint failures leave those fields empty
+pub async fn inspect_file(config: &Config, job_dir: &Path, path: &Path) -> Result<InspectedFile> {
+    let ffmpeg = &config.ffmpeg;
+    let relative = path.strip_prefix(job_dir).unwrap_or(path).display().to_string();
+    let sha256 = hash_file(path).await?;
+    let size_bytes = fs::metadata(path).await?.len();
+
+    let probe = probe_audio(&ffmpeg.ffprobe_path, path).await;
+    let fingerprint = fingerprint_file(&ffmpeg.ffmpeg_path, path, config.duplicates.max_fingerprint_secs)
+        .await
+        .inspect_err(|e| tracing::warn!("Fingerprinting {} failed: {:#}", relative, e))
+        .ok()
+        .filter(|fp| !fp.is_empty());
+
+    let (codec, bitrate_kbps, duration_secs) = match probe {
+        Ok(probe) => (probe.codec, probe.bitrate_kbps, probe.duration_secs),
+        Err(_) => (String::new(), None, None),
+    };
+
+    Ok(InspectedFile {
+        path: path.to_path_buf(),
+        relative,
+        sha256,
+        fingerprint,
+        duration_secs,
+        codec,
+        bitrate_kbps,
+        size_bytes,
+    })
+}
+
+/// A library file the collision rule replaces
+pub struct Replacement {
+    pub existing: LibraryFile,
+    /// Its modification time at the check; a different one later means the
+    /// import wrote the new copy over it
+    modified: Option<SystemTime>,
+}
+
+/// Result of checking a job against the user's library
+#[derive(Default)]
+pub struct DuplicateCheck {
+    pub checked: usize,
+    /// Files that will be imported, to be recorded afterwards
+    pub files: Vec<InspectedFile>,
+    /// Library files to delete once the job is imported
+    pub replaced: Vec<Replacement>,
+    /// One entry per file that matched a library file
+    pub matches: Vec<serde_json::Value>,
+}
+
+impl DuplicateCheck {
+    pub fn to_json(&self, action: DuplicateAction) -> serde_json::Value {
+        serde_json::json!({
+            "on_duplicate": action,
+            "checked": self.checked,
+            "matches": self.matches,
+        })
+    }
+}
+
+/// Inspect every staged audio file and apply the collision rule to the ones
+/// already in the user's library: skipped files are removed from the job,
+/// replaced library files are only collected (see `remove_replaced`).
+pub async fn check_job(state: &Arc<crate::AppState>, job: &JobContext<'_>) -> DuplicateCheck {
+    let settings = &state.config.duplicates;
+    let mut check = DuplicateCheck::default();
+
+    for path in staged_files(job.job_dir).into_iter().filter(|f| is_audio_file(f)) {
+        let file = match inspect_file(&state.config, job.job_dir, &path).await {
+            Ok(file) => file,
+            Err(e) => {
+                tracing::warn!("Duplicate check failed for {}: {:#}", path.display(), e);
+                continue;
+            }
+        };
+        check.checked += 1;
+
+        let range = file
+            .duration_secs
+            .map(|d| (d - duration_tolerance(d), d + duration_tolerance(d)));
+        let candidates = match state
+            .db
+            .find_duplicate_candidates(job.user_id, &file.sha256, range)
+            .await
+        {
+            Ok(candidates) => candidates,
+            Err(e) => {
+                tracing::warn!("Duplicate lookup failed for {}: {:#}", file.relative, e);
+                check.files.push(file);
+                continue;
+            }
+        };
+
+        // Exact copies first, then the most similar recording
+        let best = candidates
+            .iter()
+            .filter_map(|existing| compare(&file, existing, settings.similarity_threshold).map(|m| (existing, m)))
+            .max_by(|(_, a), (_, b)| a.exact.cmp(&b.exact).then(a.similarity.total_cmp(&b.similarity)));
+        let Some((existing, matched)) = best else {
+            check.files.push(file);
+            continue;
+        };
+
+        let replace = match settings.on_duplicate {
+            DuplicateAction::Skip => Some(false),
+            DuplicateAction::KeepBoth => None,
+            DuplicateAction::Replace => Some(true),
+            DuplicateAction::KeepBetter => Some(is_better(
+                &file.codec,
+                file.bitrate_kbps,
+                &existing.codec,
+                existing.bitrate_kbps.map(|b| b as u32),
+            )),
+        };
+        let action = match replace {
+            None => "kept_both",
+            Some(true) => {
+                check.replaced.push(Replacement {
+                    existing: existing.clone(),
+                    modified: modified(&job.music_dir.join(&existing.path)).await,
+                });
+                "replaced"
+            }
+            Some(false) => {
+                fs::remove_file(&file.path).await.ok();
+                for sidecar in super::lyrics::sidecars(&file.path) {
+                    fs::remove_file(sidecar).await.ok();
+                }
+                "skipped"
+            }
+        };
+        tracing::info!("{} duplicates {} ({}): {}", file.relative, existing.path, matched.similarity, action);
+
+        check.matches.push(serde_json::json!({
+            "file": file.relative,
+            "existing": existing.path,
+            "exact": matched.exact,
+            "similarity": matched.similarity,
+            "action": action,
+        }));
+        if action != "skipped" {
+            check.files.push(file);
+        }
+    }
+
+    check
+}
+
+async fn modified(path: &Path) -> Option<SystemTime> {
+    fs::metadata(path).await.and_then(|m| m.modified()).ok()
+}
+
+/// Delete the library files a job replaced, once its files are imported
+/// `destinations` are the imported paths relative to the music directory;
+/// a library file the import wrote over (same path, or modified since the
+/// check) is kept and only its old record is dropped.
+pub async fn remove_replaced(
+    state: &Arc<crate::AppState>,
+    job: &JobContext<'_>,
+    replaced: &[Replacement],
+    destinations: &[PathBuf],
+) {
+    for Replacement { existing, modified: checked } in replaced {
+        let path = job.music_dir.join(&existing.path);
+        let overwritten =
+            destinations.iter().any(|d| d == Path::new(&existing.path)) || modified(&path).await != *checked;
+        let removed = async {
+            if !overwritten && path.is_file() {
+                fs::remove_file(&path)
+                    .await
+                    .context(format!("Failed to delete {}", path.display()))?;
+            }
+            state.db.delete_library_file(existing.id).await
+        }
+        .await;
+        if let Err(e) = removed {
+            tracing::warn!("Replacing {} failed: {:#}", existing.path, e);
+        }
+    }
+}
+
+/// Record imported files in the library (paths relative to the music directory)
+pub async fn record_imports(state: &Arc<crate::AppState>, job: &JobContext<'_>, files: &[InspectedFile]) -> Result<()> {
+    for file in files {
+        state
+            .db
+            .upsert_library_file(CreateLibraryFile {
+                user_id: job.user_id.to_string(),
+                path: file.relative.clone(),
+                sha256: file.sha256.clone(),
+                fingerprint: file.fingerprint.as_deref().map(fingerprint::to_bytes),
+                duration_secs: file.duration_secs,
+                codec: file.codec.clone(),
+                bitrate_kbps: file.bitrate_kbps.map(i64::from),
+                size_bytes: file.size_bytes as i64,
+                upload_log_id: Some(job.log_id),
+            })
+            .await?;
+    }
+    Ok(())
+}
+
+/// Group library files into clusters of duplicates (indices into `files`)
+/// Files are linked when their bytes are identical or their fingerprints match.
+pub fn cluster_duplicates(files: &[LibraryFile], threshold: f64) -> Vec<Vec<usize>> {
+    fn root(parent: &mut [usize], mut i: usize) -> usize {
+        while parent[i] != i {
+            parent[i] = parent[parent[i]];
+            i = parent[i];
+        }
+        i
+    }
+    fn union(parent: &mut [usize], a: usize, b: usize) {
+        let (ra, rb) = (root(parent, a), root(parent, b));
+        parent[ra] = rb;
+    }
+    let mut parent: Vec<usize> = (0..files.len()).collect();
+
+    let mut by_hash: HashMap<&str, usize> = HashMap::new();
+    for (i, file) in files.iter().enumerate() {
+        if let Some(&first) = by_hash.get(file.sha256.as_str()) {
+            union(&mut parent, i, first);
+        } else {
+            by_hash.insert(&file.sha256, i);
+        }
+    }
+
+    // Only files of similar length can match; walk them in duration order
+    let fingerprints: Vec<Option<Vec<u32>>> = files
+        .iter()
+        .map(|f| f.fingerprint.as_deref().map(fingerprint::from_bytes))
+        .collect();
+    let mut order: Vec<(usize, f64)> = files
+        .iter()
+        .enumerate()
+        .filter(|(i, _)| fingerprints[*i].is_some())
+        .filter_map(|(i, f)| f.duration_secs.map(|d| (i, d)))
+        .collect();
+    order.sort_by(|a, b| a.1.total_cmp(&b.1));
+
+    for (pos, &(i, duration)) in order.iter().enumerate() {
+        for &(j, other) in &order[pos + 1..] {
+            if other - duration > duration_tolerance(duration) {
+                break;
+            }
+            if root(&mut parent, i) == root(&mut parent, j) {
+                continue;
+            }
+            let (Some(a), Some(b)) = (&fingerprints[i], &fingerprints[j]) else {
+                continue;
+            };
+            if fingerprint::similarity(a, b, MAX_ALIGN_OFFSET) >= threshold {
+                union(&mut parent, i, j);
+            }
+        }
+    }
+
+    let mut clusters: HashMap<usize, Vec<usize>> = HashMap::new();
+    for i in 0..files.len() {
+        clusters.entry(root(&mut parent, i)).or_default().push(i);
+    }
+    let mut clusters: Vec<Vec<usize>> = clusters.into_values().filter(|c| c.len() > 1).collect();
+    clusters.sort();
+    clusters
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn library_file(id: i32, sha256: &str, duration_secs: f64, fingerprint: &[u32]) -> LibraryFile {
+        LibraryFile {
+            id,
+            user_id: "u1".to_string(),
+            username: "alice".to_string(),
+            path: format!("track{}.flac", id),
+            sha256: sha256.to_string(),
+            fingerprint: Some(fingerprint::to_bytes(fingerprint)),
+            duration_secs: Some(duration_secs),
+            codec: "flac".to_string(),
+            bitrate_kbps: None,
+            size_bytes: 0,
+            upload_log_id: None,
+            created_at: chrono::Utc::now(),
+        }
+    }
+
+    /// Pseudo-random fingerprint; `flip` bits of every value inverted
+    fn fp(seed: u32, flip: u32) -> Vec<u32> {
+        let mut x = seed;
+        (0..200)
+            .map(|_| {
+                x = x.wrapping_mul(1_664_525).wrapping_add(1_013_904_223);
+                x ^ ((1u32 << flip) - 1)
+            })
+            .collect()
+    }
+
+    #[test]
+    fn better_copy_wins() {
+        assert!(is_better("flac", None, "mp3", Some(320)));
+        assert!(!is_better("opus", Some(160), "alac", None));
+        assert!(is_better("mp3", Some(320), "aac", Some(256)));
+        assert!(!is_better("mp3", None, "mp3", Some(128)));
+    }
+
+    #[test]
+    fn compares_hash_then_fingerprint() {
+        let existing = library_file(1, "abc", 200.0, &fp(1, 0));
+        let file = |sha256: &str, duration: f64, fingerprint: Vec<u32>| InspectedFile {
+            path: PathBuf::from("new.opus"),
+            relative: "new.opus".to_string(),
+            sha256: sha256.to_string(),
+            fingerprint: Some(fingerprint),
+            duration_secs: Some(duration),
+            codec: "opus".to_string(),
+            bitrate_kbps: Some(160),
+            size_bytes: 0,
+        };
+
+        assert!(compare(&file("abc", 10.0, vec![]), &existing, 0.85).unwrap().exact);
+        // 3 of 32 bits differ: ~0.91 similarity
+        let near = compare(&file("def", 201.0, fp(1, 3)), &existing, 0.85).unwrap();
+        assert!(!near.exact && near.similarity > 0.9);
+        // Same audio but far too long, or unrelated audio
+        assert!(compare(&file("def", 260.0, fp(1, 3)), &existing, 0.85).is_none());
+        assert!(compare(&file("def", 200.0, fp(2, 0)), &existing, 0.85).is_none());
+    }
+
+    #[test]
+    fn clusters_exact_and_acoustic_duplicates() {
+        let files = vec![
+            library_file(1, "aaa", 200.0, &fp(1, 0)),
+            library_file(2, "bbb", 100.0, &fp(2, 0)),
+            library_file(3, "ccc", 201.5, &fp(1, 2)),
+            library_file(4, "bbb", 100.0, &fp(2, 0)),
+            library_file(5, "ddd", 300.0, &fp(3, 0)),
+        ];
+        assert_eq!(cluster_duplicates(&files, 0.85), vec![vec![0, 2], vec![1, 3]]);
+    }
+
+    #[tokio::test]
+    async fn replaced_files_survive_a_failed_import() {
+        use crate::auth::AuthState;
+        use crate::models::{CreateUploadLog, CreateUser};
+
+        let temp = std::env::temp_dir().join(format!("duplicates-replace-{}", uuid::Uuid::new_v4()));
+        let (music_dir, job_dir) = (temp.join("music"), temp.join("job"));
+        std::fs::create_dir_all(music_dir.join("Old")).unwrap();
+        std::fs::create_dir_all(job_dir.join("New")).unwrap();
+        std::fs::write(music_dir.join("Old/track.flac"), b"same audio").unwrap();
+        std::fs::write(job_dir.join("New/track.flac"), b"same audio").unwrap();
+
+        let mut config = Config::default();
+        config.duplicates.enabled = true;
+        config.duplicates.on_duplicate = DuplicateAction::Replace;
+        config.paths.ferric_path = PathBuf::from("false");
+        let state = Arc::new(crate::AppState {
+            db: crate::db::Database::new(&format!("sqlite:{}", temp.join("test.db").display()), 1)
+                .await
+                .unwrap(),
+            config,
+            auth: AuthState::new("test-secret".to_string(), 1),
+            progress_store: crate::progress::create_progress_store(),
+        });
+        state.db.set_config("ferric_enabled", "true").await.unwrap();
+        let user = state
+            .db
+            .create_user(CreateUser {
+                username: "alice".to_string(),
+                password: "password123".to_string(),
+                role: "user".to_string(),
+                library_path: None,
+            })
+            .await
+            .unwrap();
+        let log_id = state
+            .db
+            .create_upload_log(CreateUploadLog {
+                user_id: user.id.clone(),
+                upload_type: "file".to_string(),
+                source: "test".to_string(),
+            })
+            .await
+            .unwrap();
+        state
+            .db
+            .upsert_library_file(CreateLibraryFile {
+                user_id: user.id.clone(),
+                path: "Old/track.flac".to_string(),
+                sha256: hash_file(&music_dir.join("Old/track.flac")).await.unwrap(),
+                fingerprint: None,
+                duration_secs: None,
+                codec: "flac".to_string(),
+                bitrate_kbps: None,
+                size_bytes: 10,
+                upload_log_id: None,
+            })
+            .await
+            .unwrap();
+        let job = JobContext {
+            user_id: &user.id,
+            log_id,
+            job_dir: &job_dir,
+            music_dir: &music_dir,
+        };
+
+        // Ferric fails: the library copy and its record are still there
+        assert!(super::super::import_job_dir(&state, &job).await.is_err());
+        assert!(music_dir.join("Old/track.flac").exists());
+        assert_eq!(state.db.list_library_files().await.unwrap().len(), 1);
+
+        state.db.set_config("ferric_enabled", "false").await.unwrap();
+        super::super::import_job_dir(&state, &job).await.unwrap();
+        assert!(!music_dir.join("Old/track.flac").exists());
+        assert!(music_dir.join("New/track.flac").exists());
+        let paths: Vec<_> = state.db.list_library_files().await.unwrap().into_iter().map(|f| f.path).collect();
+        assert_eq!(paths, ["New/track.flac"]);
+
+        std::fs::remove_dir_all(&temp).ok();
+    }
+}
@@ -0,0 +1,313 @@
+use anyhow::{Context, Result};
+use rustfft::{num_complex::Complex, FftPlanner};
+use std::path::Path;
+
+pub const SAMPLE_RATE: u32 = 11025;
+const FRAME_SIZE: usize = 4096;
+const FRAME_STEP: usize = FRAME_SIZE / 3;
+const MIN_FREQ: f64 = 28.0;
+const MAX_FREQ: f64 = 3520.0;
+const NUM_BANDS: usize = 12;
+const CHROMA_FILTER: [f64; 5] = [0.25, 0.75, 1.0, 0.75, 0.25];
+const GRAY_CODE: [u32; 4] = [0, 1, 3, 2];
+
+/// Chromaprint filter: (type, y, height, width) over the chroma image
+/// y/height index chroma bands, width counts frames
+#[derive(Clone, Copy)]
+struct Classifier {
+    filter: (u8, usize, usize, usize),
+    thresholds: [f64; 3],
+}
+
+const fn classifier(kind: u8, y: usize, height: usize, width: usize, t: [f64; 3]) -> Classifier {
+    Classifier { filter: (kind, y, height, width), thresholds: t }
+}
+
+/// Classifiers of Chromaprint's default algorithm (CHROMAPRINT_ALGORITHM_TEST2)
+const CLASSIFIERS: [Classifier; 16] = [
+    classifier(0, 4, 3, 15, [1.98215, 2.35817, 2.63523]),
+    classifier(4, 4, 6, 15, [-1.03809, -0.651211, -0.282167]),
+    classifier(1, 0, 4, 16, [-0.298702, 0.119262, 0.558497]),
+    classifier(3, 8, 2, 12, [-0.105439, 0.0153946, 0.135898]),
+    classifier(3, 4, 4, 8, [-0.142891, 0.0258736, 0.200632]),
+    classifier(4, 0, 3, 5, [-0.826319, -0.590612, -0.368214]),
+    classifier(1, 2, 2, 9, [-0.557409, -0.233035, 0.0534525]),
+    classifier(2, 7, 3, 4, [-0.0646826, 0.00620476, 0.0784847]),
+    classifier(2, 6, 2, 16, [-0.192387, -0.029699, 0.215855]),
+    classifier(2, 1, 3, 2, [-0.0397818, -0.00568076, 0.0292026]),
+    classifier(5, 10, 1, 15, [-0.53823, -0.369934, -0.190235]),
+    classifier(3, 6, 2, 10, [-0.124877, 0.0296483, 0.139239]),
+    classifier(2, 1, 1, 14, [-0.101475, 0.0225617, 0.231971]),
+    classifier(3, 5, 6, 4, [-0.0799915, -0.00729616, 0.063262]),
+    classifier(1, 9, 2, 12, [-0.272556, 0.019424, 0.302559]),
+    classifier(3, 4, 2, 14, [-0.164292, -0.0321188, 0.0846339]),
+];
+
+const MAX_FILTER_WIDTH: usize = 16;
+
+/// Acoustic fingerprint compatible with Chromaprint's default algorithm
+///
+/// Mono samples (-1.0..1.0 at `SAMPLE_RATE`) are turned into 12-band chroma
+/// vectors and run through Chromaprint's 16 classifiers, giving one 32-bit
+/// sub-fingerprint per ~124 ms (the values `fpcalc -raw` prints).
+pub fn fingerprint(samples: &[f32]) -> Vec<u32> {
+    let image = chroma_image(samples);
+    if image.len() < MAX_FILTER_WIDTH {
+        return Vec::new();
+    }
+
+    let integral = IntegralImage::new(&image);
+    (0..=image.len() - MAX_FILTER_WIDTH)
+        .map(|offset| {
+            CLASSIFIERS.iter().fold(0u32, |bits, c| {
+                let value = apply_filter(&integral, c.filter, offset);
+                (bits << 2) | GRAY_CODE[quantize(value, &c.thresholds)]
+            })
+        })
+        .collect()
+}
+
+/// Filtered, normalized chroma vectors, one per frame
+fn chroma_image(samples: &[f32]) -> Vec<[f64; NUM_BANDS]> {
+    let fft = FftPlanner::<f64>::new().plan_fft_forward(FRAME_SIZE);
+    let window: Vec<f64> = (0..FRAME_SIZE)
+        .map(|i| 0.54 - 0.46 * (2.0 * std::f64::consts::PI * i as f64 / (FRAME_SIZE - 1) as f64).cos())
+        .collect();
+
+    let index = |freq: f64| (FRAME_SIZE as f64 * freq / SAMPLE_RATE as f64).round() as usize;
+    let min_index = index(MIN_FREQ).max(1);
+    let max_index = index(MAX_FREQ).min(FRAME_SIZE / 2);
+    let notes: Vec<usize> = (0..max_index)
+        .map(|i| {
+            let freq = i as f64 * SAMPLE_RATE as f64 / FRAME_SIZE as f64;
+            let octave = (freq / (440.0 / 16.0)).log2();
+            (NUM_BANDS as f64 * (octave - octave.floor())) as usize
+        })
+        .collect();
+
+    let mut chroma = Vec::new();
+    let mut buffer = vec![Complex::new(0f64, 0f64); FRAME_SIZE];
+    let mut start = 0;
+    while start + FRAME_SIZE <= samples.len() {
+        for (slot, (sample, w)) in buffer
+            .iter_mut()
+            .zip(samples[start..start + FRAME_SIZE].iter().zip(&window))
+        {
+            *slot = Complex::new(*sample as f64 * w, 0.0);
+        }
+        fft.process(&mut buffer);
+
+        let mut features = [0f64; NUM_BANDS];
+        for i in min_index..max_index {
+            features[notes[i]] += buffer[i].norm_sqr();
+        }
+        chroma.push(features);
+        start += FRAME_STEP;
+    }
+
+    // Smooth over time, then normalize each vector (near-silence becomes all zeros)
+    chroma
+        .windows(CHROMA_FILTER.len())
+        .map(|frames| {
+            let mut filtered = [0f64; NUM_BANDS];
+            for (frame, coefficient) in frames.iter().zip(CHROMA_FILTER) {
+                for (out, value) in filtered.iter_mut().zip(frame) {
+                    *out += coefficient * value;
+                }
+            }
+            let norm = filtered.iter().map(|v| v * v).sum::<f64>().sqrt();
+            if norm < 0.01 {
+                [0.0; NUM_BANDS]
+            } else {
+                filtered.map(|v| v / norm)
+            }
+        })
+        .collect()
+}
+
+/// Summed-area table over (frame, band)
+struct IntegralImage {
+    sums: Vec<f64>,
+}
+
+impl IntegralImage {
+    fn new(image: &[[f64; NUM_BANDS]]) -> Self {
+        let stride = NUM_BANDS + 1;
+        let mut sums = vec![0f64; (image.len() + 1) * stride];
+        for (r, row) in image.iter().enumerate() {
+            for (c, value) in row.iter().enumerate() {
+                sums[(r + 1) * stride + c + 1] =
+                    value + sums[r * stride + c + 1] + sums[(r + 1) * stride + c] - sums[r * stride + c];
+            }
+        }
+        Self { sums }
+    }
+
+    /// Sum of frames r1..r2 and bands c1..c2 (exclusive ends)
+    fn area(&self, r1: usize, c1: usize, r2: usize, c2: usize) -> f64 {
+        let stride = NUM_BANDS + 1;
+        self.sums[r2 * stride + c2] - self.sums[r1 * stride + c2] - self.sums[r2 * stride + c1]
+            + self.sums[r1 * stride + c1]
+    }
+}
+
+fn apply_filter(image: &IntegralImage, (kind, y, h, w): (u8, usize, usize, usize), x: usize) -> f64 {
+    let cmp = |a: f64, b: f64| (1.0 + a).ln() - (1.0 + b).ln();
+    let area = |x1, y1, x2, y2| image.area(x1, y1, x2, y2);
+    match kind {
+        0 => cmp(area(x, y, x + w, y + h), 0.0),
+        1 => {
+            let h2 = h / 2;
+            cmp(area(x, y + h2, x + w, y + h), area(x, y, x + w, y + h2))
+        }
+        2 => {
+            let w2 = w / 2;
+            cmp(area(x + w2, y, x + w, y + h), area(x, y, x + w2, y + h))
+        }
+        3 => {
+            let (w2, h2) = (w / 2, h / 2);
+            cmp(
+                area(x, y + h2, x + w2, y + h) + area(x + w2, y, x + w, y + h2),
+                area(x, y, x + w2, y + h2) + area(x + w2, y + h2, x + w, y + h),
+            )
+        }
+        4 => {
+            let h3 = h / 3;
+            cmp(
+                area(x, y + h3, x + w, y + 2 * h3),
+                area(x, y, x + w, y + h3) + area(x, y + 2 * h3, x + w, y + h),
+            )
+        }
+        _ => {
+            let w3 = w / 3;
+            cmp(
+                area(x + w3, y, x + 2 * w3, y + h),
+                area(x, y, x + w3, y + h) + area(x + 2 * w3, y, x + w, y + h),
+            )
+        }
+    }
+}
+
+fn quantize(value: f64, [t0, t1, t2]: &[f64; 3]) -> usize {
+    match value {
+        v if v < *t0 => 0,
+        v if v < *t1 => 1,
+        v if v < *t2 => 2,
+        _ => 3,
+    }
+}
+
+/// Best bit agreement (0.0-1.0) between two fingerprints, trying alignments
+/// up to `max_offset` sub-fingerprints apart. Unrelated audio scores ~0.5.
+pub fn similarity(a: &[u32], b: &[u32], max_offset: usize) -> f64 {
+    let min_overlap = (a.len().min(b.len()) / 2).max(1);
+    let mut best = 0.0f64;
+
+    for offset in -(max_offset as isize)..=max_offset as isize {
+        let (a, b) = if offset >= 0 {
+            (a.get(offset as usize..).unwrap_or_default(), b)
+        } else {
+            (a, b.get(offset.unsigned_abs()..).unwrap_or_default())
+        };
+        let overlap = a.len().min(b.len());
+        if overlap < min_overlap {
+            continue;
+        }
+        let errors: u32 = a.iter().zip(b).map(|(x, y)| (x ^ y).count_ones()).sum();
+        best = best.max(1.0 - errors as f64 / (32 * overlap) as f64);
+    }
+
+    best
+}
+
+/// Decode up to `max_secs` of `file` and fingerprint it
+pub async fn fingerprint_file(ffmpeg_path: &str, file: &Path, max_secs: u32) -> Result<Vec<u32>> {
+    let output = tokio::process::Command::new(ffmpeg_path)
+        .args(["-v", "error", "-nostdin", "-i"])
+        .arg(file)
+        .args(["-map", "0:a:0", "-t", &max_secs.to_string(), "-ac", "1"])
+        .args(["-ar", &SAMPLE_RATE.to_string(), "-f", "f32le", "-"])
+        .output()
+        .await
+        .context("Failed to run ffmpeg")?;
+
+    if !output.status.success() {
+        let stderr = String::from_utf8_lossy(&output.stderr);
+        anyhow::bail!("ffmpeg failed to decode {}: {}", file.display(), stderr.trim());
+    }
+
+    let samples: Vec<f32> = output
+        .stdout
+        .chunks_exact(4)
+        .map(|b| f32::from_le_bytes([b[0], b[1], b[2], b[3]]))
+        .collect();
+    Ok(fingerprint(&samples))
+}
+
+/// Fingerprints are stored as little-endian u32s
+pub fn to_bytes(fingerprint: &[u32]) -> Vec<u8> {
+    fingerprint.iter().flat_map(|v| v.to_le_bytes()).collect()
+}
+
+pub fn from_bytes(bytes: &[u8]) -> Vec<u32> {
+    bytes
+        .chunks_exact(4)
+        .map(|b| u32::from_le_bytes([b[0], b[1], b[2], b[3]]))
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// A few seconds of a simple melody: one note per `note_secs`
+    fn melody(notes: &[f64], note_secs: f64, gain: f32) -> Vec<f32> {
+        let per_note = (note_secs * SAMPLE_RATE as f64) as usize;
+        notes
+            .iter()
+            .flat_map(|hz| {
+                (0..per_note).map(move |n| {
+                    let t = n as f64 / SAMPLE_RATE as f64;
+                    let tone = (std::f64::consts::TAU * hz * t).sin() + 0.5 * (std::f64::consts::TAU * 2.0 * hz * t).sin();
+                    gain * (0.4 * tone) as f32
+                })
+            })
+            .collect()
+    }
+
+    const TUNE: [f64; 12] = [262.0, 330.0, 392.0, 523.0, 440.0, 349.0, 294.0, 247.0, 262.0, 392.0, 330.0, 262.0];
+
+    #[test]
+    fn same_audio_matches_regardless_of_level() {
+        let a = fingerprint(&melody(&TUNE, 0.5, 1.0));
+        let b = fingerprint(&melody(&TUNE, 0.5, 0.3));
+        assert!(!a.is_empty());
+        assert!(similarity(&a, &b, 10) > 0.95);
+    }
+
+    #[test]
+    fn offset_copies_are_aligned() {
+        let samples = melody(&TUNE, 0.5, 1.0);
+        let a = fingerprint(&samples);
+        // Drop the first ~0.5 s (4 sub-fingerprints)
+        let b = fingerprint(&samples[FRAME_STEP * 4..]);
+        assert!(similarity(&a, &b, 0) < 0.9);
+        assert!(similarity(&a, &b, 10) > 0.95);
+    }
+
+    #[test]
+    fn different_audio_does_not_match() {
+        let mut other = TUNE;
+        other.reverse();
+        other.iter_mut().for_each(|hz| *hz *= 1.19);
+        let a = fingerprint(&melody(&TUNE, 0.5, 1.0));
+        let b = fingerprint(&melody(&other, 0.5, 1.0));
+        assert!(similarity(&a, &b, 10) < 0.8);
+    }
+
+    #[test]
+    fn bytes_round_trip() {
+        let fp = vec![0xDEADBEEF, 1, u32::MAX];
+        assert_eq!(from_bytes(&to_bytes(&fp)), fp);
+    }
+}
@@ -0,0 +1,292 @@
+use super::probe::probe_audio;
+use super::{is_audio_file, staged_files};
+use crate::config::Config;
+use crate::tagging::{read_tags, write_metadata};
+use anyhow::{Context, Result};
+use serde::Serialize;
+use std::collections::BTreeMap;
+use std::path::{Path, PathBuf};
+
+/// Opus R128_* gains are relative to the EBU R128 target (RFC 7845)
+const R128_REFERENCE_LUFS: f64 = -23.0;
+
+/// EBU R128 measurement of one file (or one album)
+#[derive(Debug, Clone, Copy, PartialEq, Serialize)]
+pub struct Loudness {
+    pub integrated_lufs: f64,
+    pub true_peak_dbtp: f64,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub loudness_range_lu: Option<f64>,
+}
+
+impl Loudness {
+    /// ReplayGain 2.0 gain in dB for the given reference level
+    pub fn gain_db(&self, reference_lufs: f64) -> f64 {
+        reference_lufs - self.integrated_lufs
+    }
+
+    /// True peak as a linear sample value (1.0 = full scale)
+    pub fn peak_linear(&self) -> f64 {
+        10f64.powf(self.true_peak_dbtp / 20.0)
+    }
+
+    /// Opus R128 gain: Q7.8 fixed point relative to -23 LUFS
+    pub fn r128_gain(&self) -> i16 {
+        ((R128_REFERENCE_LUFS - self.integrated_lufs) * 256.0)
+            .round()
+            .clamp(i16::MIN as f64, i16::MAX as f64) as i16
+    }
+}
+
+/// Parse the summary ffmpeg's ebur128 filter prints at the end of a run
+fn parse_ebur128_summary(stderr: &str) -> Option<Loudness> {
+    let summary = &stderr[stderr.rfind("Summary:")?..];
+    let value = |label: &str| {
+        summary.lines().find_map(|line| {
+            let rest = line.trim().strip_prefix(label)?;
+            rest.split_whitespace().next()?.parse::<f64>().ok()
+        })
+    };
+
+    Some(Loudness {
+        integrated_lufs: value("I:")?,
+        true_peak_dbtp: value("Peak:")?,
+        loudness_range_lu: value("LRA:"),
+    })
+}
+
+/// Combine per-track measurements into album values
+/// Integrated loudness is the duration-weighted energy mean of the tracks
+/// (an approximation of measuring the concatenated album); peak is the maximum.
+pub fn album_loudness(tracks: &[(Loudness, f64)]) -> Option<Loudness> {
+    let total: f64 = tracks.iter().map(|(_, duration)| duration).sum();
+    if tracks.is_empty() || total <= 0.0 {
+        return None;
+    }
+
+    let energy: f64 = tracks
+        .iter()
+        .map(|(l, duration)| duration * 10f64.powf(l.integrated_lufs / 10.0))
+        .sum::<f64>()
+        / total;
+
+    Some(Loudness {
+        integrated_lufs: 10.0 * energy.log10(),
+        true_peak_dbtp: tracks
+            .iter()
+            .map(|(l, _)| l.true_peak_dbtp)
+            .fold(f64::NEG_INFINITY, f64::max),
+        loudness_range_lu: None,
+    })
+}
+
+/// Decode `file` once and measure integrated loudness, loudness range and true peak
+pub async fn measure_loudness(ffmpeg_path: &str, file: &Path) -> Result<Loudness> {
+    let output = tokio::process::Command::new(ffmpeg_path)
+        .args(["-hide_banner", "-nostats", "-i"])
+        .arg(file)
+        .args(["-map", "0:a:0", "-af", "ebur128=peak=true", "-f", "null", "-"])
+        .output()
+        .await
+        .context("Failed to run ffmpeg")?;
+
+    let stderr = String::from_utf8_lossy(&output.stderr);
+    if !output.status.success() {
+        anyhow::bail!("ffmpeg failed: {}", stderr.lines().last().unwrap_or_default());
+    }
+
+    parse_ebur128_summary(&stderr)
+        .ok_or_else(|| anyhow::anyhow!("ffmpeg did not report loudness for {}", file.display()))
+}
+
+/// ReplayGain metadata for a file; Opus gets R128_* tags instead (RFC 7845)
+fn gain_tags(
+    file: &Path,
+    track: &Loudness,
+    album: Option<&Loudness>,
+    reference_lufs: f64,
+) -> Vec<(&'static str, String)> {
+    let is_opus = file
+        .extension()
+        .and_then(|e| e.to_str())
+        .is_some_and(|e| e.eq_ignore_ascii_case("opus"));
+
+    let mut tags = Vec::new();
+    if is_opus {
+        tags.push(("R128_TRACK_GAIN", track.r128_gain().to_string()));
+        if let Some(album) = album {
+            tags.push(("R128_ALBUM_GAIN", album.r128_gain().to_string()));
+        }
+    } else {
+        tags.push(("REPLAYGAIN_TRACK_GAIN", format!("{:.2} dB", track.gain_db(reference_lufs))));
+        tags.push(("REPLAYGAIN_TRACK_PEAK", format!("{:.6}", track.peak_linear())));
+        if let Some(album) = album {
+            tags.push(("REPLAYGAIN_ALBUM_GAIN", format!("{:.2} dB", album.gain_db(reference_lufs))));
+            tags.push(("REPLAYGAIN_ALBUM_PEAK", format!("{:.6}", album.peak_linear())));
+        }
+    }
+    tags
+}
+
+struct MeasuredFile {
+    path: PathBuf,
+    loudness: Loudness,
+    duration_secs: f64,
+    album: Option<String>,
+}
+
+/// Measure every audio file staged in `job_dir` and write ReplayGain tags
+///
+/// Files in the same folder that all share one album tag are treated as an
+/// album and also get album gain/peak. Returns the report stored in the
+/// upload log's details under "loudness".
+pub async fn analyze_job(config: &Config, job_dir: &Path) -> serde_json::Value {
+    let settings = &config.loudness;
+    let ffmpeg = &config.ffmpeg;
+    let relative = |path: &Path| path.strip_prefix(job_dir).unwrap_or(path).display().to_string();
+
+    let mut measured = Vec::new();
+    let mut errors = Vec::new();
+    for file in staged_files(job_dir).into_iter().filter(|f| is_audio_file(f)) {
+        let result = async {
+            let loudness = measure_loudness(&ffmpeg.ffmpeg_path, &file).await?;
+            let probe = probe_audio(&ffmpeg.ffprobe_path, &file).await?;
+            let album = read_tags(&ffmpeg.ffprobe_path, &file).await?.remove("album");
+            anyhow::Ok(MeasuredFile {
+                duration_secs: probe.duration_secs.unwrap_or(0.0),
+                path: file.clone(),
+                loudness,
+                album: album.filter(|a| !a.trim().is_empty()),
+            })
+        }
+        .await;
+
+        match result {
+            Ok(m) => measured.push(m),
+            Err(e) => {
+                tracing::warn!("Loudness analysis failed for {}: {:#}", file.display(), e);
+                errors.push(serde_json::json!({ "file": relative(&file), "error": format!("{:#}", e) }));
+            }
+        }
+    }
+
+    // Group by folder; a folder is an album when all its files share the album tag
+    let mut folders: BTreeMap<PathBuf, Vec<&MeasuredFile>> = BTreeMap::new();
+    for m in &measured {
+        folders
+            .entry(m.path.parent().unwrap_or(job_dir).to_path_buf())
+            .or_default()
+            .push(m);
+    }
+    let mut album_values: BTreeMap<PathBuf, Loudness> = BTreeMap::new();
+    let mut albums = Vec::new();
+    if settings.album_gain {
+        for (folder, files) in &folders {
+            let name = files[0].album.as_deref();
+            if files.len() < 2 || name.is_none() || files.iter().any(|f| f.album.as_deref() != name) {
+                continue;
+            }
+            let tracks: Vec<(Loudness, f64)> = files.iter().map(|f| (f.loudness, f.duration_secs)).collect();
+            if let Some(album) = album_loudness(&tracks) {
+                albums.push(serde_json::json!({
+                    "folder": relative(folder),
+                    "album": name,
+                    "tracks": files.len(),
+                    "loudness": album,
+                    "gain_db": album.gain_db(settings.reference_lufs),
+                    "peak": album.peak_linear(),
+                }));
+                album_values.insert(folder.clone(), album);
+            }
+        }
+    }
+
+    let mut files = Vec::new();
+    for m in &measured {
+        let album = m.path.parent().and_then(|p| album_values.get(p));
+        let mut tag_error = None;
+        if settings.write_tags {
+            let tags = gain_tags(&m.path, &m.loudness, album, settings.reference_lufs);
+            if let Err(e) = write_metadata(&ffmpeg.ffmpeg_path, &m.path, &tags).await {
+                tracing::warn!("Writing ReplayGain tags failed for {}: {:#}", m.path.display(), e);
+                tag_error = Some(format!("{:#}", e));
+            }
+        }
+        files.push(serde_json::json!({
+            "file": relative(&m.path),
+            "loudness": m.loudness,
+            "gain_db": m.loudness.gain_db(settings.reference_lufs),
+            "peak": m.loudness.peak_linear(),
+            "album_gain_db": album.map(|a| a.gain_db(settings.reference_lufs)),
+            "error": tag_error,
+        }));
+    }
+    files.extend(errors);
+
+    serde_json::json!({
+        "reference_lufs": settings.reference_lufs,
+        "files": files,
+        "albums": albums,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const SUMMARY: &str = "[Parsed_ebur128_0 @ 0x5581] t: 215.4  TARGET:-23 LUFS    M: -10.2 S: -11.0     I: -12.6 LUFS\n\
+        [Parsed_ebur128_0 @ 0x5581] Summary:\n\
+        \n\
+          Integrated loudness:\n\
+            I:         -9.8 LUFS\n\
+            Threshold: -20.1 LUFS\n\
+        \n\
+          Loudness range:\n\
+            LRA:         5.4 LU\n\
+            Threshold:  -30.0 LUFS\n\
+        \n\
+          True peak:\n\
+            Peak:        0.6 dBFS\n";
+
+    fn loudness(integrated_lufs: f64, true_peak_dbtp: f64) -> Loudness {
+        Loudness { integrated_lufs, true_peak_dbtp, loudness_range_lu: None }
+    }
+
+    #[test]
+    fn parses_ebur128_summary() {
+        let l = parse_ebur128_summary(SUMMARY).unwrap();
+        assert_eq!(l.integrated_lufs, -9.8);
+        assert_eq!(l.true_peak_dbtp, 0.6);
+        assert_eq!(l.loudness_range_lu, Some(5.4));
+        assert!(parse_ebur128_summary("no summary here").is_none());
+    }
+
+    #[test]
+    fn replaygain_and_r128_values() {
+        let l = loudness(-9.8, 0.0);
+        assert!((l.gain_db(-18.0) - -8.2).abs() < 1e-9);
+        assert_eq!(l.peak_linear(), 1.0);
+        // (-23 - -9.8) * 256 = -3379.2
+        assert_eq!(l.r128_gain(), -3379);
+
+        let tags = gain_tags(Path::new("a.flac"), &l, None, -18.0);
+        assert_eq!(tags[0], ("REPLAYGAIN_TRACK_GAIN", "-8.20 dB".to_string()));
+        assert_eq!(tags[1], ("REPLAYGAIN_TRACK_PEAK", "1.000000".to_string()));
+
+        let tags = gain_tags(Path::new("a.opus"), &l, Some(&l), -18.0);
+        assert_eq!(tags, vec![("R128_TRACK_GAIN", "-3379".to_string()), ("R128_ALBUM_GAIN", "-3379".to_string())]);
+    }
+
+    #[test]
+    fn album_loudness_weights_by_duration() {
+        let same = album_loudness(&[(loudness(-14.0, -1.0), 100.0), (loudness(-14.0, -3.0), 200.0)]).unwrap();
+        assert!((same.integrated_lufs - -14.0).abs() < 1e-9);
+        assert_eq!(same.true_peak_dbtp, -1.0);
+
+        // A long quiet track pulls the album value towards it
+        let mixed = album_loudness(&[(loudness(-10.0, 0.0), 60.0), (loudness(-20.0, 0.0), 600.0)]).unwrap();
+        assert!(mixed.integrated_lufs < -15.0 && mixed.integrated_lufs > -20.0);
+
+        assert!(album_loudness(&[]).is_none());
+    }
+}
@@ -0,0 +1,344 @@
+use super::probe::probe_audio;
+use super::{is_audio_file, is_lyrics_file, staged_files, LYRICS_EXTENSIONS};
+use crate::config::{Config, LyricsOutput};
+use crate::tagging::{read_tags, write_metadata};
+use anyhow::{Context, Result};
+use serde::Deserialize;
+use std::collections::BTreeMap;
+use std::path::{Path, PathBuf};
+use std::time::Duration;
+use tokio::fs;
+
+/// Largest lyrics file accepted in an upload
+const MAX_LYRICS_BYTES: usize = 1024 * 1024;
+
+/// Search results within this many seconds of the track's duration are accepted
+const DURATION_TOLERANCE_SECS: f64 = 2.0;
+
+pub fn validate_lyrics_file(data: &[u8]) -> Result<(), String> {
+    if data.len() > MAX_LYRICS_BYTES {
+        return Err(format!("Lyrics file too large (max {} MB)", MAX_LYRICS_BYTES / 1024 / 1024));
+    }
+    if std::str::from_utf8(data).is_err() {
+        return Err("Lyrics file must be UTF-8 text".to_string());
+    }
+    Ok(())
+}
+
+/// Whether the text has LRC timestamps ("[mm:ss.xx] line")
+pub fn is_synced(text: &str) -> bool {
+    text.lines().any(|line| {
+        let Some(rest) = line.trim_start().strip_prefix('[') else {
+            return false;
+        };
+        let Some((minutes, seconds)) = rest.split_once(']').and_then(|(stamp, _)| stamp.split_once(':')) else {
+            return false;
+        };
+        minutes.parse::<u32>().is_ok() && seconds.parse::<f64>().is_ok()
+    })
+}
+
+/// Pair lyrics files with the tracks of the same folder
+/// A lyrics file matches the track with the same file stem (ignoring case);
+/// a lone lyrics file next to a lone track belongs to that track.
+pub fn pair_sidecars(lyrics: &[PathBuf], tracks: &[PathBuf]) -> Vec<(PathBuf, Option<PathBuf>)> {
+    let stem = |p: &Path| p.file_stem().map(|s| s.to_string_lossy().to_lowercase());
+    lyrics
+        .iter()
+        .map(|file| {
+            let matched = tracks
+                .iter()
+                .find(|track| stem(track) == stem(file))
+                .or_else(|| (lyrics.len() == 1 && tracks.len() == 1).then(|| &tracks[0]));
+            (file.clone(), matched.cloned())
+        })
+        .collect()
+}
+
+/// Sidecar lyrics files that belong to `track`
+pub fn sidecars(track: &Path) -> Vec<PathBuf> {
+    LYRICS_EXTENSIONS
+        .iter()
+        .map(|ext| track.with_extension(ext))
+        .filter(|p| p.is_file())
+        .collect()
+}
+
+/// A record from an LRCLIB-compatible API
+#[derive(Debug, Clone, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct LyricsRecord {
+    #[serde(default)]
+    pub synced_lyrics: Option<String>,
+    #[serde(default)]
+    pub plain_lyrics: Option<String>,
+    #[serde(default)]
+    pub instrumental: bool,
+    #[serde(default)]
+    pub duration: Option<f64>,
+}
+
+impl LyricsRecord {
+    /// Synced lyrics if available, else plain text; None for instrumentals
+    pub fn best(&self) -> Option<(&str, bool)> {
+        fn non_empty(s: &Option<String>) -> Option<&str> {
+            s.as_deref().filter(|t| !t.trim().is_empty())
+        }
+        non_empty(&self.synced_lyrics)
+            .map(|t| (t, true))
+            .or_else(|| non_empty(&self.plain_lyrics).map(|t| (t, false)))
+    }
+}
+
+/// First search result with lyrics whose duration is close to the track's
+pub fn pick_search_result(results: Vec<LyricsRecord>, duration_secs: Option<f64>) -> Option<LyricsRecord> {
+    results.into_iter().find(|r| {
+        let duration_ok = match (r.duration, duration_secs) {
+            (Some(a), Some(b)) => (a - b).abs() <= DURATION_TOLERANCE_SECS,
+            _ => true,
+        };
+        duration_ok && (r.best().is_some() || r.instrumental)
+    })
+}
+
+pub struct TrackQuery {
+    pub artist: String,
+    pub title: String,
+    pub album: Option<String>,
+    pub duration_secs: Option<f64>,
+}
+
+/// Look a track up: the exact /api/get endpoint first, then /api/search
+pub async fn fetch_lyrics(
+    client: &reqwest::Client,
+    base_url: &str,
+    query: &TrackQuery,
+) -> Result<Option<LyricsRecord>> {
+    let base_url = base_url.trim_end_matches('/');
+
+    if let (Some(album), Some(duration)) = (&query.album, query.duration_secs) {
+        let response = client
+            .get(format!("{}/api/get", base_url))
+            .query(&[
+                ("artist_name", query.artist.as_str()),
+                ("track_name", query.title.as_str()),
+                ("album_name", album.as_str()),
+                ("duration", &(duration.round() as u64).to_string()),
+            ])
+            .send()
+            .await
+            .context("Lyrics lookup failed")?;
+        if response.status().is_success() {
+            return Ok(Some(response.json().await.context("Invalid lyrics response")?));
+        }
+        if response.status() != reqwest::StatusCode::NOT_FOUND {
+            anyhow::bail!("Lyrics lookup failed: HTTP {}", response.status());
+        }
+    }
+
+    let results: Vec<LyricsRecord> = client
+        .get(format!("{}/api/search", base_url))
+        .query(&[
+            ("artist_name", query.artist.as_str()),
+            ("track_name", query.title.as_str()),
+        ])
+        .send()
+        .await
+        .context("Lyrics search failed")?
+        .error_for_status()
+        .context("Lyrics search failed")?
+        .json()
+        .await
+        .context("Invalid lyrics search response")?;
+
+    Ok(pick_search_result(results, query.duration_secs))
+}
+
+/// Write lyrics for `track` as a sidecar and/or a LYRICS tag, per config
+async fn store_lyrics(config: &Config, track: &Path, text: &str, synced: bool) -> Result<()> {
+    let output = config.lyrics.output;
+    if matches!(output, LyricsOutput::Sidecar | LyricsOutput::Both) {
+        let sidecar = track.with_extension(if synced { "lrc" } else { "txt" });
+        fs::write(&sidecar, text)
+            .await
+            .context(format!("Failed to write {}", sidecar.display()))?;
+    }
+    if matches!(output, LyricsOutput::Embed | LyricsOutput::Both) {
+        write_metadata(&config.ffmpeg.ffmpeg_path, track, &[("lyrics", text.to_string())]).await?;
+    }
+    Ok(())
+}
+
+fn has_lyrics_tag(tags: &std::collections::HashMap<String, String>) -> bool {
+    tags.keys().any(|k| k.starts_with("lyrics") || k == "unsyncedlyrics")
+}
+
+/// Pair uploaded lyrics files with their tracks and optionally look up
+/// lyrics for tracks that have none
+///
+/// Unpaired lyrics files are dropped. Paired and fetched lyrics are stored
+/// as sidecars and/or tags according to `[lyrics] output`. Returns the report
+/// stored in the upload log's details under "lyrics" (None if nothing happened).
+pub async fn process_job(config: &Config, job_dir: &Path, fetch: bool) -> Option<serde_json::Value> {
+    let relative = |p: &Path| p.strip_prefix(job_dir).unwrap_or(p).display().to_string();
+
+    let mut folders: BTreeMap<PathBuf, (Vec<PathBuf>, Vec<PathBuf>)> = BTreeMap::new();
+    for file in staged_files(job_dir) {
+        let entry = folders.entry(file.parent().unwrap_or(job_dir).to_path_buf()).or_default();
+        if is_audio_file(&file) {
+            entry.0.push(file);
+        } else if is_lyrics_file(&file) {
+            entry.1.push(file);
+        }
+    }
+    if !fetch && folders.values().all(|(_, lyrics)| lyrics.is_empty()) {
+        return None;
+    }
+
+    let mut paired = Vec::new();
+    let mut unpaired = Vec::new();
+    let mut errors = Vec::new();
+    let mut with_lyrics = Vec::new();
+    for (tracks, lyrics) in folders.values() {
+        for (file, track) in pair_sidecars(lyrics, tracks) {
+            let Some(track) = track else {
+                fs::remove_file(&file).await.ok();
+                unpaired.push(relative(&file));
+                continue;
+            };
+            let result = attach_sidecar(config, &file, &track).await;
+            match result {
+                Ok(()) => paired.push(serde_json::json!({ "lyrics": relative(&file), "track": relative(&track) })),
+                Err(e) => errors.push(serde_json::json!({ "file": relative(&file), "error": format!("{:#}", e) })),
+            }
+            with_lyrics.push(track);
+        }
+    }
+
+    let mut fetched = Vec::new();
+    let mut not_found = Vec::new();
+    if fetch {
+        let client = reqwest::Client::builder()
+            .timeout(Duration::from_secs(config.lyrics.timeout_secs))
+            .user_agent(concat!(env!("CARGO_PKG_NAME"), "/", env!("CARGO_PKG_VERSION")))
+            .build();
+        let client = match client {
+            Ok(client) => client,
+            Err(e) => {
+                tracing::warn!("Cannot create HTTP client for lyrics: {}", e);
+                return Some(serde_json::json!({ "error": e.to_string() }));
+            }
+        };
+
+        let tracks = folders.values().flat_map(|(tracks, _)| tracks);
+        for track in tracks.filter(|t| !with_lyrics.contains(t)) {
+            match fetch_for_track(config, &client, track).await {
+                Ok(Some(synced)) => fetched.push(serde_json::json!({ "file": relative(track), "synced": synced })),
+                Ok(None) => not_found.push(relative(track)),
+                Err(e) => {
+                    tracing::warn!("Lyrics lookup failed for {}: {:#}", track.display(), e);
+                    errors.push(serde_json::json!({ "file": relative(track), "error": format!("{:#}", e) }));
+                }
+            }
+        }
+    }
+
+    Some(serde_json::json!({
+        "paired": paired,
+        "unpaired": unpaired,
+        "fetched": fetched,
+        "not_found": not_found,
+        "errors": errors,
+    }))
+}
+
+/// Rename an uploaded lyrics file after its track (and embed it, per config)
+async fn attach_sidecar(config: &Config, file: &Path, track: &Path) -> Result<()> {
+    let text = fs::read_to_string(file).await?;
+    let ext = if is_synced(&text) { "lrc" } else { "txt" };
+    let sidecar = track.with_extension(ext);
+
+    if matches!(config.lyrics.output, LyricsOutput::Embed | LyricsOutput::Both) {
+        write_metadata(&config.ffmpeg.ffmpeg_path, track, &[("lyrics", text.clone())]).await?;
+    }
+    if config.lyrics.output == LyricsOutput::Embed {
+        fs::remove_file(file).await?;
+    } else if sidecar != file {
+        fs::rename(file, &sidecar).await?;
+    }
+    Ok(())
+}
+
+/// Fetch and store lyrics for one track; Some(synced) when lyrics were stored
+async fn fetch_for_track(config: &Config, client: &reqwest::Client, track: &Path) -> Result<Option<bool>> {
+    let ffmpeg = &config.ffmpeg;
+    let mut tags = read_tags(&ffmpeg.ffprobe_path, track).await?;
+    if has_lyrics_tag(&tags) {
+        return Ok(None);
+    }
+    let (Some(artist), Some(title)) = (tags.remove("artist"), tags.remove("title")) else {
+        return Ok(None);
+    };
+    let duration_secs = probe_audio(&ffmpeg.ffprobe_path, track).await.ok().and_then(|p| p.duration_secs);
+
+    let query = TrackQuery {
+        artist,
+        title,
+        album: tags.remove("album"),
+        duration_secs,
+    };
+    let Some(record) = fetch_lyrics(client, &config.lyrics.base_url, &query).await? else {
+        return Ok(None);
+    };
+    let Some((text, synced)) = record.best() else {
+        return Ok(None);
+    };
+
+    store_lyrics(config, track, text, synced).await?;
+    Ok(Some(synced))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn detects_synced_lyrics() {
+        assert!(is_synced("[ar:Someone]\n[00:12.34] First line\n[00:15.00] Second"));
+        assert!(is_synced("[1:02] Short stamp"));
+        assert!(!is_synced("Just words\n[Chorus]\nMore words"));
+    }
+
+    #[test]
+    fn pairs_by_stem_or_single_track() {
+        let tracks = vec![PathBuf::from("a/01 Song.flac"), PathBuf::from("a/02 Other.flac")];
+        let lyrics = vec![PathBuf::from("a/01 song.LRC"), PathBuf::from("a/notes.txt")];
+        let pairs = pair_sidecars(&lyrics, &tracks);
+        assert_eq!(pairs[0].1.as_deref(), Some(Path::new("a/01 Song.flac")));
+        assert_eq!(pairs[1].1, None);
+
+        // One track and one lyrics file always belong together
+        let pairs = pair_sidecars(&[PathBuf::from("lyrics.txt")], &[PathBuf::from("track.mp3")]);
+        assert_eq!(pairs[0].1.as_deref(), Some(Path::new("track.mp3")));
+    }
+
+    #[test]
+    fn picks_search_result_by_duration() {
+        let results: Vec<LyricsRecord> = serde_json::from_str(
+            r#"[
+                {"id": 1, "duration": 300, "syncedLyrics": "[00:01.00] wrong", "plainLyrics": "wrong"},
+                {"id": 2, "duration": 201, "syncedLyrics": null, "plainLyrics": "right"},
+                {"id": 3, "duration": 200, "syncedLyrics": "[00:01.00] also right"}
+            ]"#,
+        )
+        .unwrap();
+
+        let record = pick_search_result(results.clone(), Some(200.4)).unwrap();
+        assert_eq!(record.best(), Some(("right", false)));
+        assert!(pick_search_result(results, Some(100.0)).is_none());
+
+        let synced: LyricsRecord =
+            serde_json::from_str(r#"{"syncedLyrics": "[00:01.00] a", "plainLyrics": "a", "instrumental": false}"#).unwrap();
+        assert_eq!(synced.best(), Some(("[00:01.00] a", true)));
+    }
+}
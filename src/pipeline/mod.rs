@@ -0,0 +1,402 @@
+pub mod cover_art;
+pub mod duplicates;
+pub mod fingerprint;
+pub mod loudness;
+pub mod lyrics;
+pub mod probe;
+pub mod spectral;
+pub mod transcode;
+pub mod validate;
+
+use anyhow::{Context, Result};
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+use tokio::fs;
+
+/// List the files staged in a job directory (recursively)
+/// Hidden files (leftovers from tagging, tool state) are skipped
+pub fn staged_files(job_dir: &Path) -> Vec<PathBuf> {
+    walkdir::WalkDir::new(job_dir)
+        .into_iter()
+        .filter_map(|e| e.ok())
+        .filter(|e| e.file_type().is_file())
+        .filter(|e| !e.file_name().to_string_lossy().starts_with('.'))
+        .map(|e| e.into_path())
+        .collect()
+}
+
+/// Deep-validate the staged audio files, deleting the ones that fail
+/// Rejections are stored in the upload log's details under "validation".
+/// Errors when no valid audio file is left.
+pub async fn validate_staged_files(
+    state: &Arc<crate::AppState>,
+    job_dir: &Path,
+    log_id: i32,
+) -> Result<validate::ValidationReport> {
+    if !state.config.validation.enabled {
+        return Ok(validate::ValidationReport::default());
+    }
+
+    let report = validate::validate_job(&state.config, job_dir).await;
+    if !report.rejected.is_empty() {
+        state
+            .db
+            .add_upload_log_detail(log_id, "validation", &report.to_json())
+            .await?;
+        if report.accepted == 0 {
+            anyhow::bail!("No valid audio files: {}", report.summary());
+        }
+    }
+
+    Ok(report)
+}
+
+/// The job being processed by the post-processing stages
+pub struct JobContext<'a> {
+    /// Owner of the job
+    pub user_id: &'a str,
+    pub log_id: i32,
+    pub job_dir: &'a Path,
+    /// The owner's music directory the job is imported into
+    pub music_dir: &'a Path,
+}
+
+/// Post-processing stages run on every job before it is imported
+/// Stage reports are stored in the upload log's details under the stage name
+pub async fn run_post_processing(state: &Arc<crate::AppState>, job: &JobContext<'_>) -> Result<()> {
+    let (job_dir, log_id) = (job.job_dir, job.log_id);
+
+    // Spectral analysis runs first so it sees the files as they were uploaded
+    let spectral_enabled = state
+        .db
+        .get_spectral_enabled(&state.config)
+        .await
+        .unwrap_or(state.config.spectral.enabled);
+
+    if spectral_enabled {
+        let report = spectral::analyze_job(state, job).await;
+        state
+            .db
+            .add_upload_log_detail(log_id, "spectral", &report)
+            .await?;
+    }
+
+    // Check database for transcode_enabled setting (overrides config file)
+    let transcode_enabled = state
+        .db
+        .get_transcode_enabled(&state.config)
+        .await
+        .unwrap_or(state.config.transcode.enabled);
+
+    if transcode_enabled {
+        let report = transcode::transcode_job(&state.config, job_dir).await;
+        state
+            .db
+            .add_upload_log_detail(log_id, "transcode", &report.to_json())
+            .await?;
+    }
+
+    // Check database for cover_art_enabled setting (overrides config file)
+    let cover_art_enabled = state
+        .db
+        .get_cover_art_enabled(&state.config)
+        .await
+        .unwrap_or(state.config.cover_art.enabled);
+
+    if cover_art_enabled {
+        let report = cover_art::process_job(&state.config, job_dir).await;
+        state
+            .db
+            .add_upload_log_detail(log_id, "cover_art", &report)
+            .await?;
+    }
+
+    // Lyrics files are always paired with their tracks; lookups are opt-in
+    let lyrics_fetch_enabled = state
+        .db
+        .get_lyrics_fetch_enabled(&state.config)
+        .await
+        .unwrap_or(state.config.lyrics.fetch);
+
+    if let Some(report) = lyrics::process_job(&state.config, job_dir, lyrics_fetch_enabled).await {
+        state
+            .db
+            .add_upload_log_detail(log_id, "lyrics", &report)
+            .await?;
+    }
+
+    // Loudness runs last so the tags end up in the files that get imported
+    let loudness_enabled = state
+        .db
+        .get_loudness_enabled(&state.config)
+        .await
+        .unwrap_or(state.config.loudness.enabled);
+
+    if loudness_enabled {
+        let report = loudness::analyze_job(&state.config, job_dir).await;
+        state
+            .db
+            .add_upload_log_detail(log_id, "loudness", &report)
+            .await?;
+    }
+
+    Ok(())
+}
+
+/// Move everything staged in the job directory into the user's music directory
+///
+/// With Ferric enabled the job directory is handed to Ferric as its input,
+/// otherwise files are moved directly, keeping their folder structure
+/// relative to `job_dir` (e.g. "Artist/Album/01 - Track.opus").
+/// With duplicate detection enabled, files already in the user's library are
+/// handled by the collision rule first; replaced library files are only
+/// deleted once the import succeeded. Direct moves are recorded in the
+/// library (Ferric picks its own layout, so its paths aren't known).
+/// Returns the imported files, relative to `job_dir`.
+pub async fn import_job_dir(state: &Arc<crate::AppState>, job: &JobContext<'_>) -> Result<Vec<PathBuf>> {
+    let (job_dir, music_dir) = (job.job_dir, job.music_dir);
+
+    // Check database for duplicates_enabled setting (overrides config file)
+    let duplicates_enabled = state
+        .db
+        .get_duplicates_enabled(&state.config)
+        .await
+        .unwrap_or(state.config.duplicates.enabled);
+
+    let duplicates = if duplicates_enabled {
+        let check = duplicates::check_job(state, job).await;
+        state
+            .db
+            .add_upload_log_detail(job.log_id, "duplicates", &check.to_json(state.config.duplicates.on_duplicate))
+            .await?;
+        Some(check)
+    } else {
+        None
+    };
+
+    let files = staged_files(job_dir);
+
+    // Check database for ferric_enabled setting (overrides config file)
+    let ferric_enabled = state
+        .db
+        .get_ferric_enabled(&state.config)
+        .await
+        .unwrap_or(state.config.paths.ferric_enabled);
+
+    if ferric_enabled {
+        // Call Ferric to process the files in the job dir
+        tracing::info!("Ferric enabled: processing files");
+        let output = tokio::process::Command::new(&state.config.paths.ferric_path)
+            .arg("--input-dir")
+            .arg(job_dir)
+            .arg("--output-dir")
+            .arg(music_dir)
+            .output()
+            .await?;
+
+        if !output.status.success() {
+            let stderr = String::from_utf8_lossy(&output.stderr);
+            anyhow::bail!("Ferric processing failed: {}", stderr);
+        }
+    } else {
+        // Ferric disabled: just move files directly to music_dir
+        tracing::info!("Ferric disabled: moving files directly to music directory");
+        for file in &files {
+            let relative = file.strip_prefix(job_dir).unwrap_or(file);
+            let dest = music_dir.join(relative);
+            if let Some(parent) = dest.parent() {
+                fs::create_dir_all(parent)
+                    .await
+                    .context(format!("Failed to create {}", parent.display()))?;
+            }
+            move_file(file, &dest).await?;
+        }
+    }
+
+    let imported: Vec<PathBuf> = files
+        .iter()
+        .map(|f| f.strip_prefix(job_dir).unwrap_or(f).to_path_buf())
+        .collect();
+
+    if let Some(check) = duplicates {
+        if ferric_enabled {
+            duplicates::remove_replaced(state, job, &check.replaced, &[]).await;
+        } else {
+            duplicates::remove_replaced(state, job, &check.replaced, &imported).await;
+            duplicates::record_imports(state, job, &check.files).await?;
+        }
+    }
+
+    Ok(imported)
+}
+
+/// Move a file, falling back to copy+remove for cross-filesystem moves
+pub async fn move_file(from: &Path, to: &Path) -> Result<()> {
+    if fs::rename(from, to).await.is_err() {
+        fs::copy(from, to)
+            .await
+            .context(format!("Failed to copy {} to {}", from.display(), to.display()))?;
+        fs::remove_file(from).await?;
+    }
+    Ok(())
+}
+
+/// File extensions treated as audio by the processing stages
+pub const AUDIO_EXTENSIONS: &[&str] = &[
+    "mp3", "flac", "ogg", "oga", "opus", "m4a", "mp4", "aac", "wav", "aif", "aiff", "wma", "webm",
+    "mka",
+];
+
+pub fn is_audio_file(path: &Path) -> bool {
+    path.extension()
+        .and_then(|e| e.to_str())
+        .map(|e| AUDIO_EXTENSIONS.contains(&e.to_lowercase().as_str()))
+        .unwrap_or(false)
+}
+
+/// Whether the file has one of the configured upload extensions (ignoring case)
+pub fn is_allowed_audio(allowed_extensions: &[String], path: &Path) -> bool {
+    path.extension()
+        .and_then(|e| e.to_str())
+        .is_some_and(|ext| allowed_extensions.iter().any(|a| a.eq_ignore_ascii_case(ext)))
+}
+
+/// Image files accepted alongside audio as album art
+pub const IMAGE_EXTENSIONS: &[&str] = &["jpg", "jpeg", "png"];
+
+pub fn is_image_file(path: &Path) -> bool {
+    path.extension()
+        .and_then(|e| e.to_str())
+        .map(|e| IMAGE_EXTENSIONS.contains(&e.to_lowercase().as_str()))
+        .unwrap_or(false)
+}
+
+/// Lyrics sidecars: .lrc for synced lyrics, .txt for plain text
+pub const LYRICS_EXTENSIONS: &[&str] = &["lrc", "txt"];
+
+pub fn is_lyrics_file(path: &Path) -> bool {
+    path.extension()
+        .and_then(|e| e.to_str())
+        .map(|e| LYRICS_EXTENSIONS.contains(&e.to_lowercase().as_str()))
+        .unwrap_or(false)
+}
+
+/// Maximum decoded size of a user-supplied cover image
+const MAX_COVER_BYTES: usize = 10 * 1024 * 1024;
+
+/// Decode a base64 cover image (optionally a data: URL) and check it is JPEG/PNG
+pub fn decode_cover_image(encoded: &str) -> Result<Vec<u8>, String> {
+    use base64::Engine;
+
+    let encoded = encoded.trim();
+    let payload = match encoded.split_once(";base64,") {
+        Some((prefix, data)) if prefix.starts_with("data:") => data,
+        _ => encoded,
+    };
+    let data = base64::engine::general_purpose::STANDARD
+        .decode(payload.trim())
+        .map_err(|_| "cover_image is not valid base64".to_string())?;
+
+    validate_cover_image(&data)?;
+    Ok(data)
+}
+
+pub fn validate_cover_image(data: &[u8]) -> Result<(), String> {
+    if data.len() > MAX_COVER_BYTES {
+        return Err(format!(
+            "Cover image too large (max {} MB)",
+            MAX_COVER_BYTES / 1024 / 1024
+        ));
+    }
+    if crate::artwork::image_mime(data).is_none() {
+        return Err("Cover image must be a JPEG or PNG".to_string());
+    }
+    Ok(())
+}
+
+/// Write user-supplied cover art into the job dir as a hidden file
+/// (hidden so it is not imported as a regular file)
+pub async fn stage_cover_image(job_dir: &Path, data: &[u8]) -> Result<PathBuf> {
+    let ext = match crate::artwork::image_mime(data) {
+        Some("image/png") => "png",
+        _ => "jpg",
+    };
+    let path = job_dir.join(format!(".override-cover.{}", ext));
+    fs::write(&path, data).await?;
+    Ok(path)
+}
+
+/// Apply user-supplied metadata overrides (and cover art) to every audio file in the job
+/// The title override is only applied when the job contains a single track
+pub async fn apply_metadata_overrides(
+    state: &Arc<crate::AppState>,
+    job_dir: &Path,
+    overrides: &crate::models::MetadataOverrides,
+    cover: Option<&Path>,
+) -> Result<()> {
+    let files: Vec<PathBuf> = staged_files(job_dir)
+        .into_iter()
+        .filter(|f| is_audio_file(f))
+        .collect();
+    let tags = crate::tagging::TrackTags::from_overrides(overrides, files.len() == 1);
+    let ffmpeg = &state.config.ffmpeg;
+
+    for file in &files {
+        crate::tagging::write_tags(&ffmpeg.ffmpeg_path, file, &tags).await?;
+        if let Some(cover) = cover {
+            if !crate::tagging::embed_cover_art(ffmpeg, file, cover).await? {
+                tracing::warn!("Cannot embed cover art into {}", file.display());
+            }
+        }
+    }
+
+    Ok(())
+}
+
+/// Heuristic cleanup for files downloaded from YouTube:
+/// strips "(Official Video)"-style noise from the title and splits "Artist - Title".
+/// Fields the user overrode are left alone; files are renamed to the cleaned title.
+pub async fn clean_youtube_metadata(
+    state: &Arc<crate::AppState>,
+    job_dir: &Path,
+    overrides: &crate::models::MetadataOverrides,
+) -> Result<()> {
+    use crate::tagging::{clean_youtube_title, read_tags, write_tags, TrackTags};
+
+    let ffmpeg = &state.config.ffmpeg;
+    for file in staged_files(job_dir).into_iter().filter(|f| is_audio_file(f)) {
+        let existing = read_tags(&ffmpeg.ffprobe_path, &file).await?;
+        let raw_title = match existing.get("title") {
+            Some(title) => title.clone(),
+            None => file
+                .file_stem()
+                .and_then(|s| s.to_str())
+                .unwrap_or_default()
+                .to_string(),
+        };
+
+        let cleaned = clean_youtube_title(&raw_title);
+        let tags = TrackTags {
+            title: Some(cleaned.title.clone()).filter(|_| overrides.title.is_none()),
+            artist: cleaned.artist.clone().filter(|_| overrides.artist.is_none()),
+            ..TrackTags::default()
+        };
+        write_tags(&ffmpeg.ffmpeg_path, &file, &tags).await?;
+
+        let stem = match (&cleaned.artist, overrides.title.is_none()) {
+            (Some(artist), true) => format!("{} - {}", artist, cleaned.title),
+            (None, true) => cleaned.title.clone(),
+            _ => continue,
+        };
+        let stem = crate::paths::sanitize_path_component(&stem);
+        let renamed = match file.extension().and_then(|e| e.to_str()) {
+            Some(ext) => file.with_file_name(format!("{}.{}", stem, ext)),
+            None => file.with_file_name(stem),
+        };
+        if renamed != file && !renamed.exists() {
+            fs::rename(&file, &renamed).await?;
+        }
+    }
+
+    Ok(())
+}
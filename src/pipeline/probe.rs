@@ -0,0 +1,153 @@
+use anyhow::{Context, Result};
+use serde::Deserialize;
+use std::path::Path;
+
+/// Container and codec information for an audio file, as reported by ffprobe
+#[derive(Debug, Clone, PartialEq)]
+pub struct AudioProbe {
+    /// ffprobe's format name, e.g. "flac" or "mov,mp4,m4a,3gp,3g2,mj2"
+    pub format_name: String,
+    /// Codec of the first audio stream, e.g. "alac" or "pcm_s16le"
+    pub codec: String,
+    pub bitrate_kbps: Option<u32>,
+    pub duration_secs: Option<f64>,
+    /// Whether the file carries an attached picture (cover art)
+    pub has_cover: bool,
+}
+
+impl AudioProbe {
+    pub fn is_lossless(&self) -> bool {
+        codec_is_lossless(&self.codec)
+    }
+}
+
+/// Codecs that store audio without loss
+pub fn codec_is_lossless(codec: &str) -> bool {
+    codec.starts_with("pcm_")
+        || matches!(
+            codec,
+            "flac" | "alac" | "wavpack" | "ape" | "tta" | "mlp" | "truehd" | "tak"
+        )
+}
+
+/// Probe `file` with ffprobe; fails when it has no audio stream
+pub async fn probe_audio(ffprobe_path: &str, file: &Path) -> Result<AudioProbe> {
+    let output = tokio::process::Command::new(ffprobe_path)
+        .args(["-v", "error", "-of", "json"])
+        .args([
+            "-show_entries",
+            "format=format_name,bit_rate,duration:stream=codec_type,codec_name,bit_rate:stream_disposition=attached_pic",
+        ])
+        .arg(file)
+        .output()
+        .await
+        .context("Failed to run ffprobe")?;
+
+    if !output.status.success() {
+        let stderr = String::from_utf8_lossy(&output.stderr);
+        anyhow::bail!("ffprobe failed for {}: {}", file.display(), stderr.trim());
+    }
+
+    parse_probe_output(&output.stdout)
+        .with_context(|| format!("Cannot read audio stream of {}", file.display()))
+}
+
+#[derive(Debug, Default, Deserialize)]
+struct ProbeOutput {
+    #[serde(default)]
+    format: Option<ProbeFormat>,
+    #[serde(default)]
+    streams: Vec<ProbeStream>,
+}
+
+#[derive(Debug, Default, Deserialize)]
+struct ProbeFormat {
+    #[serde(default)]
+    format_name: String,
+    #[serde(default)]
+    bit_rate: Option<String>,
+    #[serde(default)]
+    duration: Option<String>,
+}
+
+#[derive(Debug, Default, Deserialize)]
+struct ProbeStream {
+    #[serde(default)]
+    codec_type: String,
+    #[serde(default)]
+    codec_name: String,
+    #[serde(default)]
+    bit_rate: Option<String>,
+    #[serde(default)]
+    disposition: Option<ProbeDisposition>,
+}
+
+#[derive(Debug, Default, Deserialize)]
+struct ProbeDisposition {
+    #[serde(default)]
+    attached_pic: u8,
+}
+
+fn parse_probe_output(json: &[u8]) -> Result<AudioProbe> {
+    let probe: ProbeOutput = serde_json::from_slice(json)?;
+    let format = probe.format.unwrap_or_default();
+
+    let audio = probe
+        .streams
+        .iter()
+        .find(|s| s.codec_type == "audio")
+        .ok_or_else(|| anyhow::anyhow!("no audio stream"))?;
+
+    // Stream bitrate is missing for some containers (e.g. Ogg); fall back to the overall rate
+    let bitrate_kbps = audio
+        .bit_rate
+        .as_deref()
+        .or(format.bit_rate.as_deref())
+        .and_then(|b| b.parse::<u64>().ok())
+        .map(|bps| (bps / 1000) as u32);
+
+    Ok(AudioProbe {
+        format_name: format.format_name,
+        codec: audio.codec_name.clone(),
+        bitrate_kbps,
+        duration_secs: format.duration.and_then(|d| d.parse().ok()),
+        has_cover: probe
+            .streams
+            .iter()
+            .any(|s| s.codec_type == "video" && s.disposition.as_ref().is_some_and(|d| d.attached_pic == 1)),
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parses_audio_stream_and_cover() {
+        let json = br#"{
+            "streams": [
+                {"codec_name": "alac", "codec_type": "audio", "bit_rate": "912000", "disposition": {"attached_pic": 0}},
+                {"codec_name": "mjpeg", "codec_type": "video", "disposition": {"attached_pic": 1}}
+            ],
+            "format": {"format_name": "mov,mp4,m4a,3gp,3g2,mj2", "duration": "215.5", "bit_rate": "950000"}
+        }"#;
+        let probe = parse_probe_output(json).unwrap();
+
+        assert_eq!(probe.codec, "alac");
+        assert_eq!(probe.bitrate_kbps, Some(912));
+        assert_eq!(probe.duration_secs, Some(215.5));
+        assert!(probe.has_cover);
+        assert!(probe.is_lossless());
+    }
+
+    #[test]
+    fn falls_back_to_format_bitrate_and_requires_audio() {
+        let json = br#"{"streams": [{"codec_name": "opus", "codec_type": "audio"}], "format": {"format_name": "ogg", "bit_rate": "128500"}}"#;
+        let probe = parse_probe_output(json).unwrap();
+        assert_eq!(probe.bitrate_kbps, Some(128));
+        assert!(!probe.is_lossless());
+
+        let json = br#"{"streams": [{"codec_name": "png", "codec_type": "video"}], "format": {"format_name": "png_pipe"}}"#;
+        assert!(parse_probe_output(json).is_err());
+    }
+}
@@ -0,0 +1,286 @@
+use super::probe::probe_audio;
+use super::{is_audio_file, move_file, staged_files, JobContext};
+use crate::models::CreateSpectralAnalysis;
+use anyhow::{Context, Result};
+use rustfft::{num_complex::Complex, FftPlanner};
+use serde::Serialize;
+use std::path::Path;
+use std::sync::Arc;
+use tokio::fs;
+
+/// Files are resampled to this rate for analysis (Nyquist 22.05 kHz covers every
+/// encoder lowpass of interest)
+pub const ANALYSIS_SAMPLE_RATE: u32 = 44100;
+const FFT_SIZE: usize = 4096;
+/// FFT bins averaged into one band (~172 Hz at 44.1 kHz)
+const BAND_BINS: usize = 16;
+/// Lossy encoders cut off between ~15 kHz (128k) and ~20.5 kHz (320k)
+const MIN_CUTOFF_HZ: f64 = 13_000.0;
+const MAX_CUTOFF_HZ: f64 = 20_500.0;
+/// Upper end of the region checked above a cutoff (CD anti-alias filters start above this)
+const TOP_HZ: f64 = 21_000.0;
+const FLOOR_DB: f64 = -140.0;
+
+/// Result of the fake-lossless check for one file
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct SpectrumAnalysis {
+    /// Frequency where the spectrum drops off, if a sharp drop was found
+    pub cutoff_hz: Option<f64>,
+    /// Level difference between the bands just below and everything above the cutoff
+    pub drop_db: f64,
+    /// 0.0 (looks genuine) to 1.0 (almost certainly a lossy transcode)
+    pub confidence: f64,
+    /// "genuine", "suspicious" or "inconclusive"
+    pub verdict: &'static str,
+}
+
+/// Look for the brick-wall lowpass lossy encoders leave in the spectrum
+///
+/// The averaged power spectrum is split into bands; for every candidate cutoff
+/// the mean level of the ~1 kHz below is compared with the mean level from the
+/// cutoff up to 21 kHz. A genuine recording rolls off gradually, a transcode
+/// falls off a cliff.
+pub fn analyze_spectrum(samples: &[f32], sample_rate: u32, threshold: f64) -> SpectrumAnalysis {
+    let inconclusive = SpectrumAnalysis {
+        cutoff_hz: None,
+        drop_db: 0.0,
+        confidence: 0.0,
+        verdict: "inconclusive",
+    };
+
+    let fft = FftPlanner::<f32>::new().plan_fft_forward(FFT_SIZE);
+    let window: Vec<f32> = (0..FFT_SIZE)
+        .map(|i| 0.5 - 0.5 * (2.0 * std::f32::consts::PI * i as f32 / FFT_SIZE as f32).cos())
+        .collect();
+
+    let mut power = vec![0f64; FFT_SIZE / 2];
+    let mut frames = 0usize;
+    let mut buffer = vec![Complex::new(0f32, 0f32); FFT_SIZE];
+    for chunk in samples.chunks_exact(FFT_SIZE) {
+        // Skip silence, it only dilutes the average
+        let rms = (chunk.iter().map(|s| s * s).sum::<f32>() / FFT_SIZE as f32).sqrt();
+        if rms < 1e-4 {
+            continue;
+        }
+        for (slot, (sample, w)) in buffer.iter_mut().zip(chunk.iter().zip(&window)) {
+            *slot = Complex::new(sample * w, 0.0);
+        }
+        fft.process(&mut buffer);
+        for (p, c) in power.iter_mut().zip(&buffer) {
+            *p += c.norm_sqr() as f64;
+        }
+        frames += 1;
+    }
+    if frames < 4 {
+        return inconclusive;
+    }
+
+    let bands: Vec<f64> = power
+        .chunks(BAND_BINS)
+        .map(|band| {
+            let mean = band.iter().sum::<f64>() / (band.len() * frames) as f64;
+            (10.0 * mean.log10()).max(FLOOR_DB)
+        })
+        .collect();
+    let band_hz = BAND_BINS as f64 * sample_rate as f64 / FFT_SIZE as f64;
+    let band_of = |hz: f64| ((hz / band_hz) as usize).min(bands.len());
+    let mean = |range: &[f64]| range.iter().sum::<f64>() / range.len().max(1) as f64;
+
+    let top = band_of(TOP_HZ);
+    let below_width = (1000.0 / band_hz).ceil() as usize;
+    let mut best: Option<(usize, f64)> = None;
+    for b in band_of(MIN_CUTOFF_HZ)..band_of(MAX_CUTOFF_HZ) {
+        // Leave one band for the transition itself
+        if b < below_width || b + 2 > top {
+            continue;
+        }
+        let drop = mean(&bands[b - below_width..b]) - mean(&bands[b + 1..top]);
+        if best.is_none_or(|(_, d)| drop > d) {
+            best = Some((b, drop));
+        }
+    }
+    let Some((band, drop_db)) = best else {
+        return inconclusive;
+    };
+
+    let cutoff_hz = band as f64 * band_hz;
+    // 20 dB is within what natural roll-off produces; 50 dB is a cliff
+    let mut confidence = ((drop_db - 20.0) / 30.0).clamp(0.0, 1.0);
+    // Cutoffs near 20 kHz are also produced by some mastering/resampling chains
+    if cutoff_hz >= 19_500.0 {
+        confidence *= 0.8;
+    }
+
+    SpectrumAnalysis {
+        cutoff_hz: Some(cutoff_hz).filter(|_| confidence > 0.0),
+        drop_db,
+        confidence,
+        verdict: if confidence >= threshold { "suspicious" } else { "genuine" },
+    }
+}
+
+/// Decode up to `max_secs` of the first audio stream as mono f32 samples
+async fn decode_mono_samples(ffmpeg_path: &str, file: &Path, max_secs: u32) -> Result<Vec<f32>> {
+    let output = tokio::process::Command::new(ffmpeg_path)
+        .args(["-v", "error", "-nostdin", "-i"])
+        .arg(file)
+        .args(["-map", "0:a:0", "-t", &max_secs.to_string(), "-ac", "1"])
+        .args(["-ar", &ANALYSIS_SAMPLE_RATE.to_string(), "-f", "f32le", "-"])
+        .output()
+        .await
+        .context("Failed to run ffmpeg")?;
+
+    if !output.status.success() {
+        let stderr = String::from_utf8_lossy(&output.stderr);
+        anyhow::bail!("ffmpeg failed to decode {}: {}", file.display(), stderr.trim());
+    }
+
+    Ok(output
+        .stdout
+        .chunks_exact(4)
+        .map(|b| f32::from_le_bytes([b[0], b[1], b[2], b[3]]))
+        .collect())
+}
+
+/// Check every lossless file in the job for a lossy-encoder cutoff
+///
+/// Results are stored in `spectral_analyses`; with quarantine enabled,
+/// suspicious files are moved out of the job so they are not imported.
+/// Returns the report stored in the upload log's details under "spectral".
+pub async fn analyze_job(state: &Arc<crate::AppState>, job: &JobContext<'_>) -> serde_json::Value {
+    let settings = &state.config.spectral;
+    let mut files = Vec::new();
+
+    for file in staged_files(job.job_dir).into_iter().filter(|f| is_audio_file(f)) {
+        let relative = file.strip_prefix(job.job_dir).unwrap_or(&file).display().to_string();
+        match analyze_file(state, job, &file, &relative).await {
+            Ok(Some(entry)) => files.push(entry),
+            Ok(None) => {}
+            Err(e) => {
+                tracing::warn!("Spectral analysis failed for {}: {:#}", relative, e);
+                files.push(serde_json::json!({ "file": relative, "error": format!("{:#}", e) }));
+            }
+        }
+    }
+
+    serde_json::json!({
+        "threshold": settings.threshold,
+        "files": files,
+    })
+}
+
+async fn analyze_file(
+    state: &Arc<crate::AppState>,
+    job: &JobContext<'_>,
+    file: &Path,
+    relative: &str,
+) -> Result<Option<serde_json::Value>> {
+    let ffmpeg = &state.config.ffmpeg;
+    let settings = &state.config.spectral;
+
+    // Only files claiming to be lossless can be fake
+    let probe = probe_audio(&ffmpeg.ffprobe_path, file).await?;
+    if !probe.is_lossless() {
+        return Ok(None);
+    }
+
+    let samples = decode_mono_samples(&ffmpeg.ffmpeg_path, file, settings.max_analysis_secs).await?;
+    let analysis = analyze_spectrum(&samples, ANALYSIS_SAMPLE_RATE, settings.threshold);
+
+    let mut quarantine_path = None;
+    if analysis.verdict == "suspicious" && settings.quarantine {
+        let dir = settings
+            .quarantine_dir
+            .clone()
+            .unwrap_or_else(|| state.config.paths.temp_dir.join("quarantine"));
+        let dest = dir.join(job.log_id.to_string()).join(relative);
+        if let Some(parent) = dest.parent() {
+            fs::create_dir_all(parent)
+                .await
+                .context(format!("Failed to create {}", parent.display()))?;
+        }
+        move_file(file, &dest).await?;
+        tracing::info!("Quarantined suspected lossy transcode {}", dest.display());
+        quarantine_path = Some(dest.display().to_string());
+    }
+
+    state
+        .db
+        .create_spectral_analysis(CreateSpectralAnalysis {
+            user_id: job.user_id.to_string(),
+            upload_log_id: job.log_id,
+            file_name: relative.to_string(),
+            codec: probe.codec.clone(),
+            cutoff_hz: analysis.cutoff_hz,
+            confidence: analysis.confidence,
+            verdict: analysis.verdict.to_string(),
+            quarantine_path: quarantine_path.clone(),
+        })
+        .await?;
+
+    Ok(Some(serde_json::json!({
+        "file": relative,
+        "codec": probe.codec,
+        "cutoff_hz": analysis.cutoff_hz,
+        "drop_db": analysis.drop_db,
+        "confidence": analysis.confidence,
+        "verdict": analysis.verdict,
+        "quarantined": quarantine_path.is_some(),
+    })))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Dense multi-tone test signal with content up to `max_hz`
+    fn tones_up_to(max_hz: f64, len: usize) -> Vec<f32> {
+        let mut seed = 12345u32;
+        let tones: Vec<(f64, f64)> = (1..)
+            .map(|i| i as f64 * 97.0)
+            .take_while(|hz| *hz <= max_hz)
+            .map(|hz| {
+                seed = seed.wrapping_mul(1_103_515_245).wrapping_add(12345);
+                (hz, (seed >> 8) as f64 / (1u32 << 24) as f64 * std::f64::consts::TAU)
+            })
+            .collect();
+        let scale = 0.5 / tones.len() as f64;
+
+        (0..len)
+            .map(|n| {
+                let t = n as f64 / ANALYSIS_SAMPLE_RATE as f64;
+                let sum: f64 = tones
+                    .iter()
+                    .map(|(hz, phase)| (std::f64::consts::TAU * hz * t + phase).sin())
+                    .sum();
+                (sum * scale) as f32
+            })
+            .collect()
+    }
+
+    #[test]
+    fn detects_128k_style_lowpass() {
+        let samples = tones_up_to(16_000.0, FFT_SIZE * 8);
+        let analysis = analyze_spectrum(&samples, ANALYSIS_SAMPLE_RATE, 0.6);
+
+        assert_eq!(analysis.verdict, "suspicious");
+        let cutoff = analysis.cutoff_hz.unwrap();
+        assert!((15_500.0..=16_500.0).contains(&cutoff), "cutoff {}", cutoff);
+        assert!(analysis.confidence > 0.9);
+    }
+
+    #[test]
+    fn full_bandwidth_signal_is_genuine() {
+        let samples = tones_up_to(21_800.0, FFT_SIZE * 8);
+        let analysis = analyze_spectrum(&samples, ANALYSIS_SAMPLE_RATE, 0.6);
+
+        assert_eq!(analysis.verdict, "genuine");
+        assert!(analysis.confidence < 0.2, "confidence {}", analysis.confidence);
+    }
+
+    #[test]
+    fn silence_is_inconclusive() {
+        let analysis = analyze_spectrum(&vec![0.0; FFT_SIZE * 8], ANALYSIS_SAMPLE_RATE, 0.6);
+        assert_eq!(analysis.verdict, "inconclusive");
+    }
+}
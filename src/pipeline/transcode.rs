@@ -0,0 +1,317 @@
+use super::probe::{probe_audio, AudioProbe};
+use super::{is_audio_file, move_file, staged_files};
+use crate::config::{Config, TranscodeRule};
+use crate::tagging::{embed_cover_art, extract_cover_art, read_tags, sibling_temp_path};
+use anyhow::{Context, Result};
+use serde::Serialize;
+use std::path::{Path, PathBuf};
+use tokio::fs;
+
+/// Outcome of the transcode stage for one staged file
+#[derive(Debug, Clone, Serialize)]
+pub struct TranscodeResult {
+    /// Path relative to the job directory
+    pub file: String,
+    pub codec: String,
+    /// "transcoded", "kept" or "failed"
+    pub action: &'static str,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub output: Option<String>,
+    pub original_bytes: u64,
+    pub output_bytes: u64,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub error: Option<String>,
+}
+
+/// Per-file results of a job, stored under "transcode" in the upload log details
+#[derive(Debug, Default)]
+pub struct TranscodeReport {
+    pub files: Vec<TranscodeResult>,
+}
+
+impl TranscodeReport {
+    pub fn to_json(&self) -> serde_json::Value {
+        let before: u64 = self.files.iter().map(|f| f.original_bytes).sum();
+        let after: u64 = self.files.iter().map(|f| f.output_bytes).sum();
+        serde_json::json!({
+            "files": self.files,
+            "bytes_before": before,
+            "bytes_after": after,
+            "saved_bytes": before as i64 - after as i64,
+        })
+    }
+}
+
+/// Output container and ffmpeg codec arguments for a rule's `to` value
+#[derive(Debug, PartialEq)]
+struct TargetFormat {
+    ext: &'static str,
+    /// ffprobe codec name of the result, used to skip no-op conversions
+    codec: &'static str,
+    codec_args: Vec<String>,
+}
+
+fn target_format(to: &str, bitrate_kbps: Option<u32>) -> Option<TargetFormat> {
+    let bitrate = |default: u32| vec!["-b:a".to_string(), format!("{}k", bitrate_kbps.unwrap_or(default))];
+    let (ext, codec, encoder, extra) = match to.to_lowercase().as_str() {
+        "flac" => ("flac", "flac", "flac", vec!["-compression_level".to_string(), "8".to_string()]),
+        "alac" => ("m4a", "alac", "alac", Vec::new()),
+        "opus" => ("opus", "opus", "libopus", bitrate(160)),
+        "mp3" => ("mp3", "mp3", "libmp3lame", bitrate(320)),
+        "m4a" | "aac" => ("m4a", "aac", "aac", bitrate(256)),
+        "ogg" | "vorbis" => ("ogg", "vorbis", "libvorbis", bitrate(256)),
+        _ => return None,
+    };
+
+    let mut codec_args = vec!["-c:a".to_string(), encoder.to_string()];
+    codec_args.extend(extra);
+    Some(TargetFormat { ext, codec, codec_args })
+}
+
+/// Whether `rule` applies to a file with this probe result and extension
+pub fn rule_matches(rule: &TranscodeRule, probe: &AudioProbe, ext: &str) -> bool {
+    let codec_match = rule.from.iter().any(|from| match from.to_lowercase().as_str() {
+        "*" | "any" => true,
+        "pcm" => probe.codec.starts_with("pcm_"),
+        "lossless" => probe.is_lossless(),
+        "lossy" => !probe.is_lossless(),
+        codec => probe.codec == codec,
+    });
+    let ext_match = rule.extensions.is_empty()
+        || rule.extensions.iter().any(|e| e.trim_start_matches('.').eq_ignore_ascii_case(ext));
+
+    // Unknown bitrates never satisfy a bitrate bound
+    let min_match = rule
+        .min_bitrate_kbps
+        .is_none_or(|min| probe.bitrate_kbps.is_some_and(|b| b >= min));
+    let max_match = rule
+        .max_bitrate_kbps
+        .is_none_or(|max| probe.bitrate_kbps.is_some_and(|b| b <= max));
+
+    codec_match && ext_match && min_match && max_match
+}
+
+pub fn find_rule<'a>(rules: &'a [TranscodeRule], probe: &AudioProbe, ext: &str) -> Option<&'a TranscodeRule> {
+    rules.iter().find(|rule| rule_matches(rule, probe, ext))
+}
+
+/// Apply the transcode rules to every audio file staged in `job_dir`
+/// A file that fails to convert is left as it is and reported as failed
+pub async fn transcode_job(config: &Config, job_dir: &Path) -> TranscodeReport {
+    let mut report = TranscodeReport::default();
+
+    for file in staged_files(job_dir).into_iter().filter(|f| is_audio_file(f)) {
+        let relative = file.strip_prefix(job_dir).unwrap_or(&file).display().to_string();
+        let original_bytes = fs::metadata(&file).await.map(|m| m.len()).unwrap_or(0);
+        let mut result = TranscodeResult {
+            file: relative,
+            codec: String::new(),
+            action: "kept",
+            output: None,
+            original_bytes,
+            output_bytes: original_bytes,
+            error: None,
+        };
+
+        if let Err(e) = transcode_file(config, job_dir, &file, &mut result).await {
+            tracing::warn!("Transcoding {} failed: {:#}", file.display(), e);
+            result.action = "failed";
+            result.error = Some(format!("{:#}", e));
+        }
+        report.files.push(result);
+    }
+
+    report
+}
+
+async fn transcode_file(
+    config: &Config,
+    job_dir: &Path,
+    file: &Path,
+    result: &mut TranscodeResult,
+) -> Result<()> {
+    let ffmpeg = &config.ffmpeg;
+    let probe = probe_audio(&ffmpeg.ffprobe_path, file).await?;
+    result.codec = probe.codec.clone();
+
+    let ext = file
+        .extension()
+        .and_then(|e| e.to_str())
+        .unwrap_or("")
+        .to_lowercase();
+    let Some(rule) = find_rule(&config.transcode.rules, &probe, &ext) else {
+        return Ok(());
+    };
+    if rule.to.eq_ignore_ascii_case("keep") {
+        return Ok(());
+    }
+
+    let target = target_format(&rule.to, rule.bitrate_kbps)
+        .ok_or_else(|| anyhow::anyhow!("unknown transcode target: {}", rule.to))?;
+    if target.codec == probe.codec && target.ext == ext {
+        return Ok(());
+    }
+
+    let output = file.with_extension(target.ext);
+    if output != file && output.exists() {
+        anyhow::bail!("{} already exists", output.display());
+    }
+
+    // Tags are passed explicitly: containers store them in different places
+    // (e.g. Ogg keeps them on the stream), so -map_metadata alone can lose them
+    let tags = read_tags(&ffmpeg.ffprobe_path, file).await?;
+    let tmp = sibling_temp_path(&output, "transcode");
+    let mut cmd = tokio::process::Command::new(&ffmpeg.ffmpeg_path);
+    cmd.args(["-y", "-v", "error", "-i"])
+        .arg(file)
+        .args(["-map", "0:a:0", "-map_metadata", "-1"])
+        .args(&target.codec_args);
+    for (key, value) in tags.iter().filter(|(k, _)| !IGNORED_TAGS.contains(&k.as_str())) {
+        cmd.arg("-metadata").arg(format!("{}={}", key, value));
+    }
+    cmd.arg(&tmp);
+
+    let ffmpeg_output = cmd.output().await.context("Failed to run ffmpeg")?;
+    if !ffmpeg_output.status.success() {
+        fs::remove_file(&tmp).await.ok();
+        let stderr = String::from_utf8_lossy(&ffmpeg_output.stderr);
+        anyhow::bail!("ffmpeg failed: {}", stderr.trim());
+    }
+
+    // Carry the cover art over (stream copy cannot move pictures between all containers)
+    if probe.has_cover {
+        let art_stem = sibling_temp_path(file, "art");
+        if let Some(cover) = extract_cover_art(&ffmpeg.ffmpeg_path, file, &art_stem).await? {
+            let embedded = embed_cover_art(ffmpeg, &tmp, &cover).await;
+            fs::remove_file(&cover).await.ok();
+            if let Err(e) = embedded {
+                fs::remove_file(&tmp).await.ok();
+                return Err(e);
+            }
+        }
+    }
+
+    fs::rename(&tmp, &output)
+        .await
+        .context(format!("Failed to write {}", output.display()))?;
+
+    if output != file {
+        dispose_original(config, job_dir, file).await?;
+    }
+
+    result.action = "transcoded";
+    result.output = Some(output.strip_prefix(job_dir).unwrap_or(&output).display().to_string());
+    result.output_bytes = fs::metadata(&output).await?.len();
+    if config.transcode.keep_originals && config.transcode.originals_dir.is_none() {
+        result.output_bytes += result.original_bytes;
+    }
+    Ok(())
+}
+
+/// Container bookkeeping tags that should not be copied to a new file
+const IGNORED_TAGS: &[&str] = &[
+    "metadata_block_picture",
+    "encoder",
+    "major_brand",
+    "minor_version",
+    "compatible_brands",
+    "handler_name",
+    "vendor_id",
+];
+
+/// Delete the original, or keep it according to the keep_originals policy
+async fn dispose_original(config: &Config, job_dir: &Path, file: &Path) -> Result<()> {
+    if !config.transcode.keep_originals {
+        fs::remove_file(file).await?;
+        return Ok(());
+    }
+
+    // Without an originals_dir the original stays in the job and is imported too
+    if let Some(originals_dir) = &config.transcode.originals_dir {
+        let dest: PathBuf = originals_dir.join(file.strip_prefix(job_dir).unwrap_or(file));
+        if let Some(parent) = dest.parent() {
+            fs::create_dir_all(parent)
+                .await
+                .context(format!("Failed to create {}", parent.display()))?;
+        }
+        move_file(file, &dest).await?;
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn probe(codec: &str, bitrate_kbps: Option<u32>) -> AudioProbe {
+        AudioProbe {
+            format_name: String::new(),
+            codec: codec.to_string(),
+            bitrate_kbps,
+            duration_secs: None,
+            has_cover: false,
+        }
+    }
+
+    fn rule(from: &str, to: &str) -> TranscodeRule {
+        TranscodeRule {
+            from: vec![from.to_string()],
+            extensions: Vec::new(),
+            min_bitrate_kbps: None,
+            max_bitrate_kbps: None,
+            to: to.to_string(),
+            bitrate_kbps: None,
+        }
+    }
+
+    #[test]
+    fn first_matching_rule_wins() {
+        let rules = vec![
+            rule("pcm", "flac"),
+            TranscodeRule { extensions: vec!["m4a".to_string()], ..rule("alac", "flac") },
+            TranscodeRule { min_bitrate_kbps: Some(320), ..rule("lossy", "keep") },
+            rule("lossy", "opus"),
+        ];
+
+        assert_eq!(find_rule(&rules, &probe("pcm_s16le", Some(1411)), "wav").unwrap().to, "flac");
+        assert_eq!(find_rule(&rules, &probe("alac", None), "m4a").unwrap().to, "flac");
+        assert!(find_rule(&rules[..3], &probe("alac", None), "caf").is_none());
+        assert_eq!(find_rule(&rules, &probe("mp3", Some(320)), "mp3").unwrap().to, "keep");
+        assert_eq!(find_rule(&rules, &probe("mp3", Some(192)), "mp3").unwrap().to, "opus");
+        // Unknown bitrate does not satisfy the 320k bound
+        assert_eq!(find_rule(&rules, &probe("aac", None), "m4a").unwrap().to, "opus");
+        assert!(find_rule(&rules[..2], &probe("flac", None), "flac").is_none());
+    }
+
+    #[test]
+    fn target_formats_map_to_encoders() {
+        let flac = target_format("FLAC", None).unwrap();
+        assert_eq!(flac.ext, "flac");
+        assert_eq!(flac.codec_args[..2], ["-c:a", "flac"]);
+
+        let opus = target_format("opus", Some(128)).unwrap();
+        assert_eq!(opus.codec_args, ["-c:a", "libopus", "-b:a", "128k"]);
+
+        assert_eq!(target_format("alac", None).unwrap().ext, "m4a");
+        assert!(target_format("wma", None).is_none());
+    }
+
+    #[test]
+    fn report_sums_sizes() {
+        let result = |before, after| TranscodeResult {
+            file: "a.wav".to_string(),
+            codec: "pcm_s16le".to_string(),
+            action: "transcoded",
+            output: None,
+            original_bytes: before,
+            output_bytes: after,
+            error: None,
+        };
+        let report = TranscodeReport { files: vec![result(1000, 600), result(500, 500)] };
+        let json = report.to_json();
+
+        assert_eq!(json["bytes_before"], 1500);
+        assert_eq!(json["bytes_after"], 1100);
+        assert_eq!(json["saved_bytes"], 400);
+    }
+}
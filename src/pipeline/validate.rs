@@ -0,0 +1,279 @@
+use super::probe::probe_audio;
+use super::{is_audio_file, staged_files};
+use crate::config::Config;
+use anyhow::{Context, Result};
+use serde::Serialize;
+use std::path::Path;
+use tokio::fs;
+use tokio::io::AsyncReadExt;
+
+/// Container formats recognised from a file's first bytes
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Container {
+    Mp3,
+    Adts,
+    Flac,
+    Ogg,
+    Mp4,
+    Wav,
+    Aiff,
+    Matroska,
+    Asf,
+}
+
+impl Container {
+    fn name(&self) -> &'static str {
+        match self {
+            Container::Mp3 => "MP3",
+            Container::Adts => "AAC (ADTS)",
+            Container::Flac => "FLAC",
+            Container::Ogg => "Ogg",
+            Container::Mp4 => "MP4/M4A",
+            Container::Wav => "WAV",
+            Container::Aiff => "AIFF",
+            Container::Matroska => "Matroska/WebM",
+            Container::Asf => "ASF/WMA",
+        }
+    }
+
+    /// Usual file extension for this container
+    pub fn extension(&self) -> &'static str {
+        match self {
+            Container::Mp3 => "mp3",
+            Container::Adts => "aac",
+            Container::Flac => "flac",
+            Container::Ogg => "ogg",
+            Container::Mp4 => "m4a",
+            Container::Wav => "wav",
+            Container::Aiff => "aiff",
+            Container::Matroska => "mka",
+            Container::Asf => "wma",
+        }
+    }
+
+    /// Substring of ffprobe's format_name for this container
+    fn ffprobe_format(&self) -> &'static str {
+        match self {
+            Container::Mp3 => "mp3",
+            Container::Adts => "aac",
+            Container::Flac => "flac",
+            Container::Ogg => "ogg",
+            Container::Mp4 => "mp4",
+            Container::Wav => "wav",
+            Container::Aiff => "aiff",
+            Container::Matroska => "matroska",
+            Container::Asf => "asf",
+        }
+    }
+}
+
+/// Identify the container from magic bytes
+pub fn sniff_container(header: &[u8]) -> Option<Container> {
+    let at = |offset: usize, magic: &[u8]| header.get(offset..offset + magic.len()) == Some(magic);
+
+    if at(0, b"fLaC") {
+        Some(Container::Flac)
+    } else if at(0, b"OggS") {
+        Some(Container::Ogg)
+    } else if at(0, b"RIFF") && at(8, b"WAVE") {
+        Some(Container::Wav)
+    } else if at(0, b"FORM") && (at(8, b"AIFF") || at(8, b"AIFC")) {
+        Some(Container::Aiff)
+    } else if at(0, &[0x1A, 0x45, 0xDF, 0xA3]) {
+        Some(Container::Matroska)
+    } else if at(0, &[0x30, 0x26, 0xB2, 0x75, 0x8E, 0x66, 0xCF, 0x11]) {
+        Some(Container::Asf)
+    } else if ["ftyp", "moov", "mdat", "free", "wide", "skip"]
+        .iter()
+        .any(|atom| at(4, atom.as_bytes()))
+    {
+        Some(Container::Mp4)
+    } else if at(0, b"ID3") {
+        Some(Container::Mp3)
+    } else {
+        match header {
+            // ADTS: 12-bit sync word with layer bits 00
+            [0xFF, b1, ..] if b1 & 0xF6 == 0xF0 => Some(Container::Adts),
+            // MPEG audio frame sync (11 bits) with a non-reserved layer
+            [0xFF, b1, ..] if b1 & 0xE0 == 0xE0 && b1 & 0x06 != 0 => Some(Container::Mp3),
+            _ => None,
+        }
+    }
+}
+
+/// Containers a file with this extension may use
+pub fn expected_containers(ext: &str) -> &'static [Container] {
+    match ext {
+        "mp3" => &[Container::Mp3],
+        "flac" => &[Container::Flac],
+        "ogg" | "oga" | "opus" => &[Container::Ogg],
+        "m4a" | "mp4" => &[Container::Mp4],
+        "aac" => &[Container::Adts, Container::Mp4],
+        "wav" => &[Container::Wav],
+        "aif" | "aiff" => &[Container::Aiff],
+        "webm" | "mka" => &[Container::Matroska],
+        "wma" => &[Container::Asf],
+        _ => &[],
+    }
+}
+
+/// Whether an audio codec is plausible for the extension
+pub fn codec_matches_extension(ext: &str, codec: &str) -> bool {
+    match ext {
+        "mp3" => codec == "mp3",
+        "flac" => codec == "flac",
+        "opus" => codec == "opus",
+        "ogg" | "oga" => matches!(codec, "vorbis" | "opus" | "flac" | "speex"),
+        "m4a" | "mp4" => matches!(codec, "aac" | "alac" | "mp3" | "ac3" | "eac3"),
+        "aac" => codec == "aac",
+        "wav" | "aif" | "aiff" => codec.starts_with("pcm_") || codec.starts_with("adpcm_"),
+        "webm" | "mka" => matches!(codec, "opus" | "vorbis" | "aac" | "flac" | "mp3"),
+        "wma" => codec.starts_with("wma"),
+        _ => false,
+    }
+}
+
+/// A staged file that failed validation
+#[derive(Debug, Clone, Serialize)]
+pub struct Rejection {
+    /// Path relative to the job directory
+    pub file: String,
+    pub reason: String,
+}
+
+#[derive(Debug, Default)]
+pub struct ValidationReport {
+    pub accepted: usize,
+    pub rejected: Vec<Rejection>,
+}
+
+impl ValidationReport {
+    pub fn to_json(&self) -> serde_json::Value {
+        serde_json::json!({
+            "accepted": self.accepted,
+            "rejected": self.rejected,
+        })
+    }
+
+    /// One line per rejected file, e.g. for an error message
+    pub fn summary(&self) -> String {
+        self.rejected
+            .iter()
+            .map(|r| format!("{}: {}", r.file, r.reason))
+            .collect::<Vec<_>>()
+            .join("; ")
+    }
+}
+
+/// Validate every audio file in `job_dir`; rejected files are deleted from the job
+pub async fn validate_job(config: &Config, job_dir: &Path) -> ValidationReport {
+    let mut report = ValidationReport::default();
+
+    for file in staged_files(job_dir).into_iter().filter(|f| is_audio_file(f)) {
+        match validate_file(config, &file).await {
+            Ok(()) => report.accepted += 1,
+            Err(e) => {
+                let relative = file.strip_prefix(job_dir).unwrap_or(&file).display().to_string();
+                tracing::warn!("Rejected {}: {:#}", relative, e);
+                fs::remove_file(&file).await.ok();
+                report.rejected.push(Rejection {
+                    file: relative,
+                    reason: format!("{:#}", e),
+                });
+            }
+        }
+    }
+
+    report
+}
+
+/// Check one file: magic bytes, ffprobe container/codec, then (optionally) a full decode
+pub async fn validate_file(config: &Config, file: &Path) -> Result<()> {
+    let ext = file
+        .extension()
+        .and_then(|e| e.to_str())
+        .unwrap_or("")
+        .to_lowercase();
+    let expected = expected_containers(&ext);
+
+    let mut header = vec![0u8; 16];
+    let mut handle = fs::File::open(file).await.context("cannot open file")?;
+    let read = handle.read(&mut header).await?;
+    header.truncate(read);
+
+    let sniffed = sniff_container(&header)
+        .ok_or_else(|| anyhow::anyhow!("not a recognised audio file (.{} extension)", ext))?;
+    if !expected.contains(&sniffed) {
+        anyhow::bail!("content is {} but the extension is .{}", sniffed.name(), ext);
+    }
+
+    let probe = probe_audio(&config.ffmpeg.ffprobe_path, file)
+        .await
+        .map_err(|_| anyhow::anyhow!("no readable audio stream"))?;
+    if !probe.format_name.contains(sniffed.ffprobe_format()) {
+        anyhow::bail!("container is {} but the extension is .{}", probe.format_name, ext);
+    }
+    if !codec_matches_extension(&ext, &probe.codec) {
+        anyhow::bail!("codec {} is not valid in a .{} file", probe.codec, ext);
+    }
+
+    if config.validation.full_decode {
+        decode_fully(&config.ffmpeg.ffmpeg_path, file).await?;
+    }
+
+    Ok(())
+}
+
+/// Decode the whole audio stream; any decoder error means the file is corrupt or truncated
+async fn decode_fully(ffmpeg_path: &str, file: &Path) -> Result<()> {
+    let output = tokio::process::Command::new(ffmpeg_path)
+        .args(["-v", "error", "-nostdin", "-i"])
+        .arg(file)
+        .args(["-map", "0:a:0", "-f", "null", "-"])
+        .output()
+        .await
+        .context("Failed to run ffmpeg")?;
+
+    let stderr = String::from_utf8_lossy(&output.stderr);
+    if !output.status.success() || !stderr.trim().is_empty() {
+        let first_error = stderr.lines().find(|l| !l.trim().is_empty()).unwrap_or("decoding failed");
+        anyhow::bail!("corrupt or truncated audio: {}", first_error.trim());
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn sniffs_common_containers() {
+        assert_eq!(sniff_container(b"fLaC\0\0\0\x22"), Some(Container::Flac));
+        assert_eq!(sniff_container(b"OggS\0\x02"), Some(Container::Ogg));
+        assert_eq!(sniff_container(b"RIFF\x24\0\0\0WAVEfmt "), Some(Container::Wav));
+        assert_eq!(sniff_container(b"FORM\0\0\0\0AIFF"), Some(Container::Aiff));
+        assert_eq!(sniff_container(b"\0\0\0\x20ftypM4A "), Some(Container::Mp4));
+        assert_eq!(sniff_container(b"ID3\x04\0"), Some(Container::Mp3));
+        assert_eq!(sniff_container(&[0xFF, 0xFB, 0x90, 0x64]), Some(Container::Mp3));
+        assert_eq!(sniff_container(&[0xFF, 0xF1, 0x50, 0x80]), Some(Container::Adts));
+        assert_eq!(sniff_container(b"MZ\x90\0\x03\0\0\0"), None);
+        assert_eq!(sniff_container(b""), None);
+    }
+
+    #[test]
+    fn extension_must_match_content() {
+        assert!(expected_containers("mp3").contains(&Container::Mp3));
+        assert!(!expected_containers("flac").contains(&Container::Mp3));
+        assert!(expected_containers("aac").contains(&Container::Mp4));
+        assert!(expected_containers("exe").is_empty());
+    }
+
+    #[test]
+    fn codecs_are_checked_per_extension() {
+        assert!(codec_matches_extension("m4a", "alac"));
+        assert!(codec_matches_extension("ogg", "opus"));
+        assert!(codec_matches_extension("wav", "pcm_s24le"));
+        assert!(!codec_matches_extension("opus", "vorbis"));
+        assert!(!codec_matches_extension("flac", "mp3"));
+    }
+}
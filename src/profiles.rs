@@ -0,0 +1,152 @@
+use crate::config::{Config, DownloadProfile};
+use crate::models::User;
+use axum::http::StatusCode;
+use std::sync::Arc;
+
+/// A download profile chosen for a job
+#[derive(Debug, Clone)]
+pub struct SelectedProfile {
+    /// None when the server defaults ([youtube]/[spotify] settings) are used
+    pub name: Option<String>,
+    pub profile: DownloadProfile,
+}
+
+/// Why a requested profile cannot be used
+#[derive(Debug, PartialEq)]
+pub enum ProfileError {
+    Unknown(String),
+    NotAllowed(String),
+}
+
+impl ProfileError {
+    pub fn status(&self) -> StatusCode {
+        match self {
+            ProfileError::Unknown(_) => StatusCode::BAD_REQUEST,
+            ProfileError::NotAllowed(_) => StatusCode::FORBIDDEN,
+        }
+    }
+}
+
+impl std::fmt::Display for ProfileError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            ProfileError::Unknown(name) => write!(f, "Unknown download profile: {}", name),
+            ProfileError::NotAllowed(name) => {
+                write!(f, "Download profile {} is not allowed on this server", name)
+            }
+        }
+    }
+}
+
+/// Pick the profile name for a job
+///
+/// An explicitly requested profile must exist and be allowed. The user's
+/// default is used when nothing was requested; a default that is no longer
+/// available falls back to the server defaults instead of failing the job.
+pub fn select_profile_name<'a>(
+    config: &Config,
+    allowed: &[String],
+    requested: Option<&'a str>,
+    user_default: Option<&'a str>,
+) -> Result<Option<&'a str>, ProfileError> {
+    let requested = requested.map(str::trim).filter(|name| !name.is_empty());
+    if let Some(name) = requested {
+        if !config.profiles.contains_key(name) {
+            return Err(ProfileError::Unknown(name.to_string()));
+        }
+        if !allowed.iter().any(|a| a == name) {
+            return Err(ProfileError::NotAllowed(name.to_string()));
+        }
+        return Ok(Some(name));
+    }
+
+    match user_default.map(str::trim).filter(|name| !name.is_empty()) {
+        Some(name) if config.profiles.contains_key(name) && allowed.iter().any(|a| a == name) => {
+            Ok(Some(name))
+        }
+        Some(name) => {
+            tracing::warn!("Default profile {} is not available, using server defaults", name);
+            Ok(None)
+        }
+        None => Ok(None),
+    }
+}
+
+/// Resolve the profile for a download request
+/// `fallback` is the profile built from the global settings of the source
+pub async fn resolve_profile(
+    state: &Arc<crate::AppState>,
+    user: &User,
+    requested: Option<&str>,
+    fallback: DownloadProfile,
+) -> Result<SelectedProfile, ProfileError> {
+    let allowed = state
+        .db
+        .get_allowed_profiles(&state.config)
+        .await
+        .unwrap_or_else(|_| state.config.profiles.keys().cloned().collect());
+
+    let name = select_profile_name(
+        &state.config,
+        &allowed,
+        requested,
+        user.default_profile.as_deref(),
+    )?;
+
+    Ok(match name {
+        Some(name) => SelectedProfile {
+            name: Some(name.to_string()),
+            profile: state.config.profiles[name].clone(),
+        },
+        None => SelectedProfile {
+            name: None,
+            profile: fallback,
+        },
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn config_with_profiles(names: &[&str]) -> Config {
+        let mut config = Config::default();
+        for name in names {
+            config.profiles.insert(
+                name.to_string(),
+                DownloadProfile {
+                    audio_format: "mp3".to_string(),
+                    ..config.legacy_youtube_profile()
+                },
+            );
+        }
+        config
+    }
+
+    #[test]
+    fn requested_profile_must_exist_and_be_allowed() {
+        let config = config_with_profiles(&["mp3", "flac"]);
+        let allowed = vec!["mp3".to_string()];
+
+        assert_eq!(select_profile_name(&config, &allowed, Some("mp3"), None), Ok(Some("mp3")));
+        assert_eq!(
+            select_profile_name(&config, &allowed, Some("flac"), None),
+            Err(ProfileError::NotAllowed("flac".to_string()))
+        );
+        assert_eq!(
+            select_profile_name(&config, &allowed, Some("wav"), None),
+            Err(ProfileError::Unknown("wav".to_string()))
+        );
+    }
+
+    #[test]
+    fn user_default_applies_only_when_available() {
+        let config = config_with_profiles(&["mp3", "flac"]);
+        let allowed = vec!["mp3".to_string()];
+
+        assert_eq!(select_profile_name(&config, &allowed, None, Some("mp3")), Ok(Some("mp3")));
+        assert_eq!(select_profile_name(&config, &allowed, Some(" "), Some("mp3")), Ok(Some("mp3")));
+        assert_eq!(select_profile_name(&config, &allowed, None, Some("flac")), Ok(None));
+        assert_eq!(select_profile_name(&config, &allowed, None, None), Ok(None));
+    }
+}
@@ -0,0 +1,70 @@
+use crate::config::RoleMapping;
+use crate::db::Database;
+use crate::models::{CreateUser, User};
+use crate::paths::sanitize_path_component;
+use anyhow::Result;
+use rand::distributions::Alphanumeric;
+use rand::Rng;
+
+/// Role for a user in `groups`: the first mapped group, else `default_role`
+pub fn map_role(mappings: &[RoleMapping], default_role: Option<&str>, groups: &[String]) -> Option<String> {
+    mappings
+        .iter()
+        .find(|m| groups.contains(&m.group))
+        .map(|m| m.role.clone())
+        .or_else(|| default_role.map(str::to_string))
+}
+
+/// Library path of a provisioned user; the username is made safe as a folder name
+pub fn library_path_for(template: &str, username: &str) -> String {
+    template.replace("{username}", &sanitize_path_component(username))
+}
+
+/// Create a user who logs in elsewhere (identity provider, auth proxy);
+/// the random password is never given out
+pub async fn create_external_user(
+    db: &Database,
+    username: &str,
+    role: &str,
+    library_path_template: Option<&str>,
+) -> Result<User> {
+    let password: String = rand::thread_rng()
+        .sample_iter(&Alphanumeric)
+        .take(32)
+        .map(char::from)
+        .collect();
+
+    db.create_user(CreateUser {
+        username: username.to_string(),
+        password,
+        role: role.to_string(),
+        library_path: library_path_template.map(|template| library_path_for(template, username)),
+    })
+    .await
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn roles_follow_the_first_mapped_group() {
+        let mappings = vec![
+            RoleMapping { group: "music-admins".to_string(), role: "admin".to_string() },
+            RoleMapping { group: "family".to_string(), role: "user".to_string() },
+        ];
+        let groups = |names: &[&str]| names.iter().map(|n| n.to_string()).collect::<Vec<_>>();
+
+        assert_eq!(map_role(&mappings, None, &groups(&["family", "music-admins"])).as_deref(), Some("admin"));
+        assert_eq!(map_role(&mappings, None, &groups(&["family"])).as_deref(), Some("user"));
+        assert_eq!(map_role(&mappings, Some("guest"), &groups(&["others"])).as_deref(), Some("guest"));
+        assert_eq!(map_role(&mappings, None, &groups(&["others"])), None);
+    }
+
+    #[test]
+    fn library_paths_only_use_safe_usernames() {
+        assert_eq!(library_path_for("/srv/music/{username}", "alice"), "/srv/music/alice");
+        assert_eq!(library_path_for("/srv/music/{username}", ".."), "/srv/music/_");
+        assert_eq!(library_path_for("/srv/music/{username}", "../etc"), "/srv/music/_etc");
+    }
+}
@@ -0,0 +1,83 @@
+use crate::models::User;
+use crate::provisioning;
+use axum::http::{HeaderMap, StatusCode};
+use std::net::SocketAddr;
+
+/// The user an auth proxy vouches for: only when the request comes straight
+/// from a trusted proxy and carries the user header. Users the role mapping
+/// refuses are a 403.
+pub async fn identify(
+    state: &crate::AppState,
+    peer: Option<SocketAddr>,
+    headers: &HeaderMap,
+) -> Result<Option<User>, StatusCode> {
+    let config = &state.config.proxy_auth;
+    if !config.enabled {
+        return Ok(None);
+    }
+    // The direct peer, not X-Forwarded-For, which any client can send
+    let trusted_proxies = &state.config.security.trusted_proxies;
+    if !peer.is_some_and(|peer| trusted_proxies.iter().any(|net| net.contains(&peer.ip()))) {
+        return Ok(None);
+    }
+    let Some(username) = header_value(headers, &config.user_header) else {
+        return Ok(None);
+    };
+    let groups: Vec<String> = header_value(headers, &config.groups_header)
+        .map(|groups| {
+            groups
+                .split(',')
+                .map(str::trim)
+                .filter(|g| !g.is_empty())
+                .map(str::to_string)
+                .collect()
+        })
+        .unwrap_or_default();
+
+    let internal = |e: anyhow::Error| {
+        tracing::error!("Proxy authentication of {} failed: {:#}", username, e);
+        StatusCode::INTERNAL_SERVER_ERROR
+    };
+    let Some(role) = provisioning::map_role(&config.role_mapping, config.default_role.as_deref(), &groups) else {
+        tracing::warn!("Proxy user {} is in none of the mapped groups", username);
+        return Err(StatusCode::FORBIDDEN);
+    };
+    if !state.db.role_exists(&role).await.map_err(internal)? {
+        return Err(internal(anyhow::anyhow!("Role {} from [proxy_auth] doesn't exist", role)));
+    }
+
+    let mut user = match state.db.get_user_by_username(username).await {
+        Ok(user) => user,
+        Err(_) if config.auto_provision => {
+            let template = config.library_path_template.as_deref();
+            match provisioning::create_external_user(&state.db, username, &role, template).await {
+                Ok(user) => {
+                    tracing::info!("Provisioned user {} for the auth proxy", username);
+                    user
+                }
+                // A parallel request may have created it first
+                Err(e) => state.db.get_user_by_username(username).await.map_err(|_| internal(e))?,
+            }
+        }
+        Err(_) => {
+            tracing::warn!("Proxy user {} has no account", username);
+            return Err(StatusCode::FORBIDDEN);
+        }
+    };
+
+    // With group mappings, the proxy decides the role
+    if !config.role_mapping.is_empty() && user.role != role {
+        state.db.update_user_role(&user.id, &role).await.map_err(internal)?;
+        user.role = role;
+    }
+
+    Ok(Some(user))
+}
+
+fn header_value<'a>(headers: &'a HeaderMap, name: &str) -> Option<&'a str> {
+    headers
+        .get(name)
+        .and_then(|v| v.to_str().ok())
+        .map(str::trim)
+        .filter(|v| !v.is_empty())
+}
@@ -0,0 +1,948 @@
+use crate::auth::{auth_middleware, require_permission, require_session, Permission};
+use crate::handlers::admin::{
+    admin_change_user_password, change_own_password, clear_lockout, change_own_username, create_user, delete_role, delete_user,
+    get_all_upload_logs, get_auth_events, get_config, get_duplicate_clusters, get_spectral_report, get_system_info, get_upload_logs,
+    get_user_directories_info, get_user_info, list_config, list_jwt_keys, list_lockouts, list_roles, list_users, rotate_jwt_key, save_role, update_config,
+    unlock_user, update_user_library_path, update_user_role,
+};
+use crate::handlers::api_tokens::{
+    admin_delete_api_token, admin_list_api_tokens, create_api_token, delete_api_token, list_api_tokens,
+};
+use crate::handlers::auth_handlers::{
+    list_sessions, login, login_passkey, login_proxy, login_totp, logout, passkey_login_options, revoke_all_sessions,
+    revoke_session,
+};
+use crate::handlers::oidc::{oidc_callback, oidc_login};
+use crate::handlers::passkeys::{
+    delete_passkey, list_passkeys, passkey_registration_options, register_passkey, rename_passkey,
+};
+use crate::handlers::profiles::{list_profiles, update_allowed_profiles, update_default_profile};
+use crate::handlers::server_import::{browse_import_root, import_from_root, list_import_roots};
+use crate::handlers::spotify::download_spotify;
+use crate::handlers::totp::{
+    disable_totp, enable_totp, get_totp_status, regenerate_recovery_codes, reset_user_totp, setup_totp,
+};
+use crate::handlers::upload::upload_files;
+use crate::handlers::url_import::import_url;
+use crate::handlers::youtube::download_youtube;
+use crate::templates::{AdminTemplate, LoginTemplate, LogsTemplate, SettingsTemplate, UploadTemplate};
+use crate::AppState;
+use axum::{
+    extract::State,
+    middleware,
+    routing::{delete, get, patch, post},
+    Router,
+};
+use std::sync::Arc;
+use tower_http::{
+    cors::{Any, CorsLayer},
+    limit::RequestBodyLimitLayer,
+    trace::TraceLayer,
+};
+
+type Routes = Router<Arc<AppState>>;
+
+/// Routes that need `permission`; rejected with 403 before the handler runs
+fn guarded(permission: Permission, routes: Routes) -> Routes {
+    routes.route_layer(middleware::from_fn_with_state(permission, require_permission))
+}
+
+/// The whole application: public routes, and authenticated route groups
+/// each guarded by the permission they need
+pub fn app(app_state: Arc<AppState>) -> Router {
+    let uploads = Router::new()
+        .merge(guarded(Permission::Upload, Router::new().route("/upload", post(upload_files))))
+        .merge(guarded(Permission::Youtube, Router::new().route("/youtube", post(download_youtube))))
+        .merge(guarded(Permission::Spotify, Router::new().route("/spotify", post(download_spotify))))
+        .merge(guarded(Permission::UrlImport, Router::new().route("/url", post(import_url))))
+        .merge(guarded(
+            Permission::ServerImport,
+            Router::new()
+                .route("/import", post(import_from_root))
+                .route("/import/roots", get(list_import_roots))
+                .route("/import/browse", get(browse_import_root)),
+        ));
+
+    // Anything a logged-in user (or any of their API tokens) may do with their own account
+    let account = Router::new()
+        .route("/progress/:session_id", get(crate::stream_progress))
+        .route("/logs", get(get_upload_logs))
+        .route("/user/info", get(get_user_info))
+        .route("/user/directories", get(get_user_directories_info))
+        .route("/user/default-profile", post(update_default_profile))
+        .route("/profiles", get(list_profiles))
+        .route("/logout", post(logout));
+
+    // Credentials: only with a login session, so a leaked API token can't take over the account
+    let credentials = Router::new()
+        .route("/user/change-password", post(change_own_password))
+        .route("/user/change-username", post(change_own_username))
+        .route("/user/sessions", get(list_sessions).delete(revoke_all_sessions))
+        .route("/user/sessions/:id", delete(revoke_session))
+        .route("/user/tokens", get(list_api_tokens).post(create_api_token))
+        .route("/user/tokens/:id", delete(delete_api_token))
+        .route("/user/totp", get(get_totp_status))
+        .route("/user/totp/setup", post(setup_totp))
+        .route("/user/totp/enable", post(enable_totp))
+        .route("/user/totp/disable", post(disable_totp))
+        .route("/user/totp/recovery-codes", post(regenerate_recovery_codes))
+        .route("/user/passkeys", get(list_passkeys).post(register_passkey))
+        .route("/user/passkeys/options", post(passkey_registration_options))
+        .route("/user/passkeys/:id", patch(rename_passkey).delete(delete_passkey))
+        .route_layer(middleware::from_fn(require_session));
+
+    let manage_users = Router::new()
+        .route("/users", get(list_users).post(create_user))
+        .route("/users/:id", delete(delete_user))
+        .route("/users/:id/password", post(admin_change_user_password))
+        .route("/users/:id/library", post(update_user_library_path))
+        .route("/users/:id/role", post(update_user_role))
+        .route("/users/:id/unlock", post(unlock_user))
+        .route("/users/:id/totp/reset", post(reset_user_totp))
+        .route("/lockouts", get(list_lockouts))
+        .route("/lockouts/:key", delete(clear_lockout))
+        .route("/auth-events", get(get_auth_events))
+        .route("/roles", get(list_roles).post(save_role))
+        .route("/roles/:name", delete(delete_role))
+        .route("/tokens", get(admin_list_api_tokens))
+        .route("/tokens/:id", delete(admin_delete_api_token));
+
+    let manage_config = Router::new()
+        .route("/config", get(list_config).post(update_config))
+        .route("/config/:key", get(get_config))
+        .route("/profiles", post(update_allowed_profiles))
+        .route("/system-info", get(get_system_info))
+        .route("/jwt-keys", get(list_jwt_keys))
+        .route("/jwt-keys/rotate", post(rotate_jwt_key));
+
+    let view_all_logs = Router::new()
+        .route("/logs", get(get_all_upload_logs))
+        .route("/spectral-report", get(get_spectral_report))
+        .route("/duplicates", get(get_duplicate_clusters));
+
+    let admin = Router::new()
+        .merge(guarded(Permission::ManageUsers, manage_users))
+        .merge(guarded(Permission::ManageConfig, manage_config))
+        .merge(guarded(Permission::ViewAllLogs, view_all_logs));
+
+    // Protected routes (require authentication)
+    let protected_routes = Router::new()
+        .nest("/api", uploads.merge(account).merge(credentials).nest("/admin", admin))
+        // Template routes (PROTECTED - require login)
+        .route("/upload", get(|| async { UploadTemplate }))
+        .route("/settings", get(|| async { SettingsTemplate }))
+        .route("/admin", get(|| async { AdminTemplate }))
+        .route("/logs", get(|| async { LogsTemplate }))
+        .layer(middleware::from_fn_with_state(app_state.clone(), auth_middleware));
+
+    // Public routes (login page, login endpoints and the single sign-on round trip)
+    let public_routes = Router::new()
+        .route("/", get(|State(state): State<Arc<AppState>>| async move { LoginTemplate::new(&state.config) }))
+        .route("/api/login", post(login))
+        .route("/api/login/totp", post(login_totp))
+        .route("/api/login/proxy", post(login_proxy))
+        .route("/api/login/passkey/options", post(passkey_login_options))
+        .route("/api/login/passkey", post(login_passkey))
+        .route("/api/oidc/login", get(oidc_login))
+        .route("/api/oidc/callback", get(oidc_callback));
+
+    // Configure CORS - only allow same-origin by default (restrictive for security)
+    // If you need to allow different origins, configure this appropriately
+    let cors = CorsLayer::new()
+        .allow_origin(Any) // In production, specify your domain(s)
+        .allow_methods([
+            axum::http::Method::GET,
+            axum::http::Method::POST,
+            axum::http::Method::DELETE,
+            axum::http::Method::PATCH,
+        ])
+        .allow_headers(Any);
+
+    // Max request body size from config (archives may be larger than single files)
+    let max_body_size = app_state
+        .config
+        .max_file_size_bytes()
+        .max(app_state.config.max_archive_size_bytes() as usize);
+
+    // Combine routes
+    Router::new()
+        .merge(protected_routes)
+        .merge(public_routes)
+        .with_state(app_state)
+        .layer(cors)
+        .layer(RequestBodyLimitLayer::new(max_body_size))
+        .layer(TraceLayer::new_for_http())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::auth::{start_session, AuthState, ClientInfo};
+    use crate::config::Config;
+    use crate::db::Database;
+    use crate::models::{CreateUser, Role};
+    use axum::body::Body;
+    use axum::extract::ConnectInfo;
+    use axum::http::{Request, StatusCode};
+    use serde_json::json;
+    use tower::ServiceExt;
+
+    /// Every guarded route with the permission it needs
+    const GUARDED: &[(&str, &str, Permission)] = &[
+        ("POST", "/api/upload", Permission::Upload),
+        ("POST", "/api/youtube", Permission::Youtube),
+        ("POST", "/api/spotify", Permission::Spotify),
+        ("POST", "/api/url", Permission::UrlImport),
+        ("POST", "/api/import", Permission::ServerImport),
+        ("GET", "/api/import/roots", Permission::ServerImport),
+        ("GET", "/api/import/browse", Permission::ServerImport),
+        ("GET", "/api/admin/users", Permission::ManageUsers),
+        ("POST", "/api/admin/users", Permission::ManageUsers),
+        ("DELETE", "/api/admin/users/missing", Permission::ManageUsers),
+        ("POST", "/api/admin/users/missing/password", Permission::ManageUsers),
+        ("POST", "/api/admin/users/missing/library", Permission::ManageUsers),
+        ("POST", "/api/admin/users/missing/role", Permission::ManageUsers),
+        ("POST", "/api/admin/users/missing/unlock", Permission::ManageUsers),
+        ("POST", "/api/admin/users/missing/totp/reset", Permission::ManageUsers),
+        ("GET", "/api/admin/lockouts", Permission::ManageUsers),
+        ("DELETE", "/api/admin/lockouts/missing", Permission::ManageUsers),
+        ("GET", "/api/admin/auth-events", Permission::ManageUsers),
+        ("GET", "/api/admin/roles", Permission::ManageUsers),
+        ("POST", "/api/admin/roles", Permission::ManageUsers),
+        ("DELETE", "/api/admin/roles/missing", Permission::ManageUsers),
+        ("GET", "/api/admin/tokens", Permission::ManageUsers),
+        ("DELETE", "/api/admin/tokens/missing", Permission::ManageUsers),
+        ("GET", "/api/admin/config", Permission::ManageConfig),
+        ("POST", "/api/admin/config", Permission::ManageConfig),
+        ("GET", "/api/admin/config/missing", Permission::ManageConfig),
+        ("POST", "/api/admin/profiles", Permission::ManageConfig),
+        ("GET", "/api/admin/system-info", Permission::ManageConfig),
+        ("GET", "/api/admin/jwt-keys", Permission::ManageConfig),
+        ("POST", "/api/admin/jwt-keys/rotate", Permission::ManageConfig),
+        ("GET", "/api/admin/logs", Permission::ViewAllLogs),
+        ("GET", "/api/admin/spectral-report", Permission::ViewAllLogs),
+        ("GET", "/api/admin/duplicates", Permission::ViewAllLogs),
+    ];
+
+    async fn test_state() -> Arc<AppState> {
+        test_state_with(Config::default()).await
+    }
+
+    async fn test_state_with(config: Config) -> Arc<AppState> {
+        let dir = std::env::temp_dir().join(format!("routes-test-{}", uuid::Uuid::new_v4()));
+        std::fs::create_dir_all(&dir).unwrap();
+        let db = Database::new(&format!("sqlite:{}", dir.join("test.db").display()), 1)
+            .await
+            .unwrap();
+        Arc::new(AppState {
+            db,
+            config,
+            auth: AuthState::new("test-secret".to_string(), 1),
+            progress_store: crate::progress::create_progress_store(),
+        })
+    }
+
+    /// Token of a new user whose role has exactly `permissions`
+    async fn token_with(state: &AppState, name: &str, permissions: Vec<Permission>) -> String {
+        let role = Role {
+            name: name.to_string(),
+            description: String::new(),
+            permissions,
+        };
+        state.db.save_role(&role).await.unwrap();
+        let user = state
+            .db
+            .create_user(CreateUser {
+                username: name.to_string(),
+                password: "password123".to_string(),
+                role: role.name,
+                library_path: None,
+            })
+            .await
+            .unwrap();
+        start_session(state, &user, &ClientInfo::default()).await.unwrap()
+    }
+
+    async fn status(app: &Router, method: &str, uri: &str, token: Option<&str>) -> StatusCode {
+        let mut request = Request::builder().method(method).uri(uri);
+        if let Some(token) = token {
+            request = request.header("Authorization", format!("Bearer {}", token));
+        }
+        app.clone()
+            .oneshot(request.body(Body::empty()).unwrap())
+            .await
+            .unwrap()
+            .status()
+    }
+
+    #[tokio::test]
+    async fn every_guarded_route_requires_its_permission() {
+        let state = test_state().await;
+        let app = app(state.clone());
+
+        for permission in Permission::ALL {
+            let others = Permission::ALL.into_iter().filter(|p| *p != permission).collect();
+            let without = token_with(&state, &format!("without_{}", permission.as_str()), others).await;
+            let only = token_with(&state, &format!("only_{}", permission.as_str()), vec![permission]).await;
+
+            for (method, uri, _) in GUARDED.iter().filter(|(_, _, p)| *p == permission) {
+                assert_eq!(status(&app, method, uri, None).await, StatusCode::UNAUTHORIZED, "{} {}", method, uri);
+                assert_eq!(status(&app, method, uri, Some(&without)).await, StatusCode::FORBIDDEN, "{} {}", method, uri);
+                // With the permission the handler runs (and fails on the empty request, if at all)
+                let allowed = status(&app, method, uri, Some(&only)).await;
+                assert!(
+                    allowed != StatusCode::FORBIDDEN && allowed != StatusCode::UNAUTHORIZED,
+                    "{} {} returned {} with {}",
+                    method,
+                    uri,
+                    allowed,
+                    permission.as_str()
+                );
+            }
+        }
+    }
+
+    #[tokio::test]
+    async fn account_routes_need_no_permission() {
+        let state = test_state().await;
+        let app = app(state.clone());
+        let token = token_with(&state, "nothing", Vec::new()).await;
+
+        for uri in ["/api/logs", "/api/user/info", "/api/profiles"] {
+            assert_eq!(status(&app, "GET", uri, Some(&token)).await, StatusCode::OK, "{}", uri);
+        }
+        assert_eq!(status(&app, "GET", "/api/logs", Some("not-a-token")).await, StatusCode::UNAUTHORIZED);
+    }
+
+    #[tokio::test]
+    async fn revoked_sessions_are_rejected() {
+        let state = test_state().await;
+        let app = app(state.clone());
+        let first = token_with(&state, "traveller", Vec::new()).await;
+        let user = state.db.get_user_by_username("traveller").await.unwrap();
+        let second = start_session(&state, &user, &ClientInfo::default()).await.unwrap();
+        let third = start_session(&state, &user, &ClientInfo::default()).await.unwrap();
+
+        // Logging out ends only that session
+        assert_eq!(status(&app, "POST", "/api/logout", Some(&first)).await, StatusCode::OK);
+        assert_eq!(status(&app, "GET", "/api/user/info", Some(&first)).await, StatusCode::UNAUTHORIZED);
+        assert_eq!(status(&app, "GET", "/api/user/info", Some(&second)).await, StatusCode::OK);
+
+        // Changing the password ends every other session
+        let change = Request::post("/api/user/change-password")
+            .header("Authorization", format!("Bearer {}", second))
+            .header("Content-Type", "application/json")
+            .body(Body::from(r#"{"old_password":"password123","new_password":"password456"}"#))
+            .unwrap();
+        assert_eq!(app.clone().oneshot(change).await.unwrap().status(), StatusCode::OK);
+        assert_eq!(status(&app, "GET", "/api/user/info", Some(&third)).await, StatusCode::UNAUTHORIZED);
+        assert_eq!(status(&app, "GET", "/api/user/info", Some(&second)).await, StatusCode::OK);
+
+        // "Log out everywhere" ends the rest
+        assert_eq!(status(&app, "DELETE", "/api/user/sessions", Some(&second)).await, StatusCode::OK);
+        assert_eq!(status(&app, "GET", "/api/user/info", Some(&second)).await, StatusCode::UNAUTHORIZED);
+
+        // Deleting the user removes their sessions
+        start_session(&state, &user, &ClientInfo::default()).await.unwrap();
+        state.db.delete_user(&user.id).await.unwrap();
+        assert!(state.db.list_sessions(&user.id).await.unwrap().is_empty());
+    }
+
+    async fn send_json(app: &Router, method: &str, uri: &str, token: &str, body: &str) -> (StatusCode, serde_json::Value) {
+        let request = Request::builder()
+            .method(method)
+            .uri(uri)
+            .header("Authorization", format!("Bearer {}", token))
+            .header("Content-Type", "application/json")
+            .body(Body::from(body.to_string()))
+            .unwrap();
+        let response = app.clone().oneshot(request).await.unwrap();
+        let status = response.status();
+        let bytes = axum::body::to_bytes(response.into_body(), usize::MAX).await.unwrap();
+        (status, serde_json::from_slice(&bytes).unwrap_or_default())
+    }
+
+    #[tokio::test]
+    async fn api_tokens_are_limited_to_their_scopes() {
+        let state = test_state().await;
+        let app = app(state.clone());
+        let session = token_with(&state, "scripter", vec![Permission::Upload, Permission::ViewAllLogs]).await;
+
+        let (code, _) = send_json(&app, "POST", "/api/user/tokens", &session, r#"{"name":"x","scopes":["manage_users"]}"#).await;
+        assert_eq!(code, StatusCode::BAD_REQUEST);
+        let body = r#"{"name":"x","expires_in_days":4294967295}"#;
+        assert_eq!(send_json(&app, "POST", "/api/user/tokens", &session, body).await.0, StatusCode::BAD_REQUEST);
+        let (code, created) = send_json(&app, "POST", "/api/user/tokens", &session, r#"{"name":"uploads","scopes":["upload"]}"#).await;
+        assert_eq!(code, StatusCode::OK);
+        let api_token = created["token"].as_str().unwrap().to_string();
+
+        assert_eq!(status(&app, "GET", "/api/logs", Some(&api_token)).await, StatusCode::OK);
+        assert_eq!(status(&app, "GET", "/api/admin/logs", Some(&api_token)).await, StatusCode::FORBIDDEN);
+        let upload = status(&app, "POST", "/api/upload", Some(&api_token)).await;
+        assert!(upload != StatusCode::FORBIDDEN && upload != StatusCode::UNAUTHORIZED);
+        // Tokens can't manage credentials, including other tokens
+        assert_eq!(status(&app, "GET", "/api/user/tokens", Some(&api_token)).await, StatusCode::FORBIDDEN);
+
+        // Only the hash is stored, and use is tracked
+        let (_, listed) = send_json(&app, "GET", "/api/user/tokens", &session, "").await;
+        let listed = &listed["tokens"][0];
+        assert!(api_token.starts_with(listed["prefix"].as_str().unwrap()));
+        assert!(!listed.to_string().contains(&api_token));
+        assert!(!listed["last_used_at"].is_null());
+
+        let id = listed["id"].as_str().unwrap();
+        assert_eq!(status(&app, "DELETE", &format!("/api/user/tokens/{}", id), Some(&session)).await, StatusCode::OK);
+        assert_eq!(status(&app, "GET", "/api/logs", Some(&api_token)).await, StatusCode::UNAUTHORIZED);
+    }
+
+    #[tokio::test]
+    async fn expired_api_tokens_are_rejected() {
+        let state = test_state().await;
+        let app = app(state.clone());
+        token_with(&state, "lapsed", Vec::new()).await;
+        let user = state.db.get_user_by_username("lapsed").await.unwrap();
+        let token = crate::auth::generate_api_token();
+        let hash = crate::auth::hash_api_token(&token);
+        state.db.create_api_token(&user.id, "old", &hash, "j3s_", None, Some(1)).await.unwrap();
+
+        assert_eq!(status(&app, "GET", "/api/logs", Some(&token)).await, StatusCode::UNAUTHORIZED);
+    }
+
+    async fn login_status(app: &Router, username: &str, password: &str) -> StatusCode {
+        let body = json!({ "username": username, "password": password }).to_string();
+        let request = Request::post("/api/login")
+            .header("Content-Type", "application/json")
+            .body(Body::from(body))
+            .unwrap();
+        app.clone().oneshot(request).await.unwrap().status()
+    }
+
+    #[tokio::test]
+    async fn failed_logins_back_off_until_unlocked() {
+        // Locked out after a single failure, so the test doesn't depend on timing
+        let mut config = Config::default();
+        config.security.login_throttle.max_account_failures = 1;
+        let state = test_state_with(config).await;
+        let app = app(state.clone());
+        let admin = token_with(&state, "unlocker", vec![Permission::ManageUsers]).await;
+        token_with(&state, "guessed", Vec::new()).await;
+        let user = state.db.get_user_by_username("guessed").await.unwrap();
+
+        assert_eq!(login_status(&app, "guessed", "wrong").await, StatusCode::UNAUTHORIZED);
+        // Even the right password is refused while locked out
+        assert_eq!(login_status(&app, "guessed", "password123").await, StatusCode::TOO_MANY_REQUESTS);
+
+        let unlock = format!("/api/admin/users/{}/unlock", user.id);
+        assert_eq!(status(&app, "POST", &unlock, Some(&admin)).await, StatusCode::OK);
+        assert_eq!(login_status(&app, "guessed", "password123").await, StatusCode::OK);
+
+        let (_, events) = send_json(&app, "GET", "/api/admin/auth-events", &admin, "").await;
+        let reasons: Vec<&str> = events["events"]
+            .as_array()
+            .unwrap()
+            .iter()
+            .map(|e| e["reason"].as_str().unwrap())
+            .collect();
+        assert_eq!(reasons, ["password", "throttled", "invalid_credentials"]);
+    }
+
+    fn current_code(secret: &str) -> String {
+        use totp_rs::{Algorithm, Secret, TOTP};
+        let bytes = Secret::Encoded(secret.to_string()).to_bytes().unwrap();
+        TOTP::new(Algorithm::SHA1, 6, 1, 30, bytes, None, "test".to_string())
+            .unwrap()
+            .generate_current()
+            .unwrap()
+    }
+
+    async fn login_json(app: &Router, uri: &str, body: serde_json::Value) -> (StatusCode, serde_json::Value) {
+        let request = Request::post(uri)
+            .header("Content-Type", "application/json")
+            .body(Body::from(body.to_string()))
+            .unwrap();
+        let response = app.clone().oneshot(request).await.unwrap();
+        let status = response.status();
+        let bytes = axum::body::to_bytes(response.into_body(), usize::MAX).await.unwrap();
+        (status, serde_json::from_slice(&bytes).unwrap_or_default())
+    }
+
+    #[tokio::test]
+    async fn totp_makes_login_two_steps() {
+        let state = test_state().await;
+        let app = app(state.clone());
+        let session = token_with(&state, "careful", Vec::new()).await;
+
+        let (_, setup) = send_json(&app, "POST", "/api/user/totp/setup", &session, "").await;
+        let secret = setup["secret"].as_str().unwrap();
+        let code = current_code(secret);
+        let (code_status, enabled) =
+            send_json(&app, "POST", "/api/user/totp/enable", &session, &json!({ "code": code }).to_string()).await;
+        assert_eq!(code_status, StatusCode::OK);
+        let recovery = enabled["recovery_codes"][0].as_str().unwrap().to_string();
+
+        // The password alone only gets a challenge
+        let credentials = json!({ "username": "careful", "password": "password123" });
+        let (code_status, first) = login_json(&app, "/api/login", credentials.clone()).await;
+        assert_eq!(code_status, StatusCode::OK);
+        assert!(first["token"].is_null());
+        let challenge = first["challenge"].as_str().unwrap();
+
+        // The code used for enrollment can't be replayed; a recovery code works once
+        let (code_status, _) = login_json(&app, "/api/login/totp", json!({ "challenge": challenge, "code": code })).await;
+        assert_eq!(code_status, StatusCode::UNAUTHORIZED);
+        // Skip the backoff that failure started
+        state.db.clear_login_failures(&crate::login_throttle::account_key("careful")).await.unwrap();
+        let (code_status, second) =
+            login_json(&app, "/api/login/totp", json!({ "challenge": challenge, "code": recovery })).await;
+        assert_eq!(code_status, StatusCode::OK);
+        assert!(second["token"].is_string());
+
+        let (_, again) = login_json(&app, "/api/login", credentials).await;
+        let challenge = again["challenge"].as_str().unwrap();
+        let (code_status, _) =
+            login_json(&app, "/api/login/totp", json!({ "challenge": challenge, "code": recovery })).await;
+        assert_eq!(code_status, StatusCode::UNAUTHORIZED);
+    }
+
+    #[tokio::test]
+    async fn admin_permissions_can_require_totp() {
+        let mut config = Config::default();
+        config.security.require_admin_2fa = true;
+        let state = test_state_with(config).await;
+        let app = app(state.clone());
+        let admin = token_with(&state, "boss", vec![Permission::Upload, Permission::ManageUsers]).await;
+
+        assert_eq!(status(&app, "GET", "/api/admin/users", Some(&admin)).await, StatusCode::FORBIDDEN);
+        let upload = status(&app, "POST", "/api/upload", Some(&admin)).await;
+        assert!(upload != StatusCode::FORBIDDEN && upload != StatusCode::UNAUTHORIZED);
+
+        let (_, setup) = send_json(&app, "POST", "/api/user/totp/setup", &admin, "").await;
+        let code = current_code(setup["secret"].as_str().unwrap());
+        send_json(&app, "POST", "/api/user/totp/enable", &admin, &json!({ "code": code }).to_string()).await;
+        assert_eq!(status(&app, "GET", "/api/admin/users", Some(&admin)).await, StatusCode::OK);
+    }
+
+    /// What the mock identity provider expects and puts in its next ID token
+    #[derive(Default)]
+    struct MockLogin {
+        nonce: String,
+        pkce_challenge: String,
+        claims: serde_json::Value,
+    }
+
+    /// A local OIDC provider (discovery, JWKS and token endpoint); returns its issuer URL
+    async fn mock_provider() -> (String, Arc<std::sync::Mutex<MockLogin>>) {
+        use axum::extract::Form;
+        use axum::Json;
+        use std::collections::HashMap;
+
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let issuer = format!("http://{}", listener.local_addr().unwrap());
+        let login = Arc::new(std::sync::Mutex::new(MockLogin::default()));
+
+        let discovery = json!({
+            "issuer": issuer,
+            "authorization_endpoint": format!("{}/authorize", issuer),
+            "token_endpoint": format!("{}/token", issuer),
+            "jwks_uri": format!("{}/jwks", issuer),
+        });
+        let token_login = login.clone();
+        let token_issuer = issuer.clone();
+        let provider = Router::new()
+            .route("/.well-known/openid-configuration", get(move || async move { Json(discovery) }))
+            .route("/jwks", get(|| async { Json(crate::oidc::tests::test_jwks()) }))
+            .route(
+                "/token",
+                post(move |Form(form): Form<HashMap<String, String>>| async move {
+                    let login = token_login.lock().unwrap();
+                    let verifier = form.get("code_verifier").map(String::as_str).unwrap_or_default();
+                    if form.get("code").map(String::as_str) != Some("good-code")
+                        || crate::oidc::pkce_challenge(verifier) != login.pkce_challenge
+                    {
+                        return (StatusCode::BAD_REQUEST, Json(json!({ "error": "invalid_grant" })));
+                    }
+                    let mut claims = login.claims.clone();
+                    claims["iss"] = json!(token_issuer);
+                    claims["aud"] = json!("music");
+                    claims["exp"] = json!(chrono::Utc::now().timestamp() + 60);
+                    claims["nonce"] = json!(login.nonce);
+                    let id_token = crate::oidc::tests::sign(&claims);
+                    (StatusCode::OK, Json(json!({ "access_token": "x", "token_type": "Bearer", "id_token": id_token })))
+                }),
+            );
+        tokio::spawn(async move { axum::serve(listener, provider).await.unwrap() });
+
+        (issuer, login)
+    }
+
+    fn sso_config(issuer: &str) -> Config {
+        let mut config = Config::default();
+        config.oidc.enabled = true;
+        config.oidc.issuer_url = issuer.to_string();
+        config.oidc.client_id = "music".to_string();
+        config.oidc.client_secret = "s3cret".to_string();
+        config.oidc.redirect_url = "http://localhost/api/oidc/callback".to_string();
+        config
+    }
+
+    /// Go through a single sign-on login, the provider vouching for `claims`;
+    /// `nonce` overrides the one the provider puts in the ID token
+    async fn sso_login(
+        app: &Router,
+        mock: &std::sync::Mutex<MockLogin>,
+        claims: serde_json::Value,
+        nonce: Option<&str>,
+    ) -> axum::response::Response {
+        let request = Request::get("/api/oidc/login").body(Body::empty()).unwrap();
+        let response = app.clone().oneshot(request).await.unwrap();
+        assert_eq!(response.status(), StatusCode::SEE_OTHER);
+        let location = response.headers()["location"].to_str().unwrap();
+        let params: std::collections::HashMap<_, _> =
+            reqwest::Url::parse(location).unwrap().query_pairs().into_owned().collect();
+        assert_eq!(params["code_challenge_method"], "S256");
+
+        *mock.lock().unwrap() = MockLogin {
+            nonce: nonce.unwrap_or(&params["nonce"]).to_string(),
+            pkce_challenge: params["code_challenge"].clone(),
+            claims,
+        };
+        let state_cookie = response.headers()["set-cookie"].to_str().unwrap().split(';').next().unwrap();
+        let callback = format!("/api/oidc/callback?code=good-code&state={}", params["state"]);
+        let request = Request::get(callback.as_str()).header("cookie", state_cookie).body(Body::empty()).unwrap();
+        app.clone().oneshot(request).await.unwrap()
+    }
+
+    /// The session token of a finished login, from its cookie
+    fn session_cookie(response: &axum::response::Response) -> String {
+        let cookie = response.headers()["set-cookie"].to_str().unwrap();
+        cookie.trim_start_matches("token=").split(';').next().unwrap().to_string()
+    }
+
+    fn login_error(response: &axum::response::Response) -> Option<String> {
+        let location = response.headers().get("location")?.to_str().unwrap();
+        location.strip_prefix("/?error=").map(str::to_string)
+    }
+
+    #[tokio::test]
+    async fn sso_provisions_users_and_maps_groups_to_roles() {
+        let (issuer, mock) = mock_provider().await;
+        let mut config = sso_config(&issuer);
+        config.oidc.role_mapping = vec![crate::config::RoleMapping {
+            group: "music-admins".to_string(),
+            role: "admin".to_string(),
+        }];
+        config.oidc.library_path_template = Some("/srv/music/{username}".to_string());
+        let state = test_state_with(config).await;
+        let app = app(state.clone());
+
+        let claims = json!({ "sub": "1", "preferred_username": "sso-user", "groups": ["music-admins"] });
+        let response = sso_login(&app, &mock, claims, None).await;
+        assert_eq!(response.status(), StatusCode::OK);
+        let token = session_cookie(&response);
+        assert_eq!(status(&app, "GET", "/api/admin/users", Some(&token)).await, StatusCode::OK);
+        let user = state.db.get_user_by_username("sso-user").await.unwrap();
+        assert_eq!(user.role, "admin");
+        assert_eq!(user.library_path.as_deref(), Some("/srv/music/sso-user"));
+
+        // Leaving the group takes the role away at the next login
+        let claims = json!({ "sub": "1", "preferred_username": "sso-user", "groups": [] });
+        let response = sso_login(&app, &mock, claims, None).await;
+        assert_eq!(response.status(), StatusCode::OK);
+        let again = state.db.get_user_by_username("sso-user").await.unwrap();
+        assert_eq!((again.id, again.role.as_str()), (user.id, "user"));
+
+        // Tokens for another login are refused
+        let claims = json!({ "sub": "1", "preferred_username": "sso-user" });
+        let response = sso_login(&app, &mock, claims, Some("stolen")).await;
+        assert!(login_error(&response).is_some());
+    }
+
+    #[tokio::test]
+    async fn sso_links_existing_accounts_only_by_match_claim() {
+        let (issuer, mock) = mock_provider().await;
+        let mut config = sso_config(&issuer);
+        config.oidc.default_role = None;
+        config.oidc.role_mapping = vec![crate::config::RoleMapping {
+            group: "family".to_string(),
+            role: "user".to_string(),
+        }];
+        config.security.password_login = false;
+        let state = test_state_with(config).await;
+        let app = app(state.clone());
+        token_with(&state, "alice", Vec::new()).await;
+
+        let (code, _) = login_json(&app, "/api/login", json!({ "username": "alice", "password": "password123" })).await;
+        assert_eq!(code, StatusCode::FORBIDDEN);
+
+        // Not in a mapped group, and a same-named local account isn't taken over
+        let response = sso_login(&app, &mock, json!({ "sub": "a", "preferred_username": "alice" }), None).await;
+        assert!(login_error(&response).is_some());
+        let claims = json!({ "sub": "a", "preferred_username": "alice", "groups": ["family"] });
+        let response = sso_login(&app, &mock, claims.clone(), None).await;
+        assert!(login_error(&response).unwrap().contains("already"));
+
+        let mut config = state.config.clone();
+        config.oidc.match_claim = Some("preferred_username".to_string());
+        let linked = Arc::new(AppState { config, ..(*state).clone() });
+        let response = sso_login(&super::app(linked), &mock, claims, None).await;
+        assert_eq!(response.status(), StatusCode::OK);
+        let alice = state.db.get_user_by_username("alice").await.unwrap();
+        assert_eq!(state.db.get_user_by_oidc_subject(&format!("{}|a", issuer)).await.unwrap().unwrap().id, alice.id);
+    }
+
+    #[tokio::test]
+    async fn sso_callback_only_finishes_in_the_browser_that_started_it() {
+        let (issuer, mock) = mock_provider().await;
+        let state = test_state_with(sso_config(&issuer)).await;
+        let app = app(state.clone());
+
+        // An attacker starts a login and sends the victim its callback URL
+        let request = Request::get("/api/oidc/login").body(Body::empty()).unwrap();
+        let response = app.clone().oneshot(request).await.unwrap();
+        let location = response.headers()["location"].to_str().unwrap();
+        let params: std::collections::HashMap<_, _> =
+            reqwest::Url::parse(location).unwrap().query_pairs().into_owned().collect();
+        mock.lock().unwrap().pkce_challenge = params["code_challenge"].clone();
+        mock.lock().unwrap().nonce = params["nonce"].clone();
+        mock.lock().unwrap().claims = json!({ "sub": "1", "preferred_username": "attacker" });
+
+        let callback = format!("/api/oidc/callback?code=good-code&state={}", params["state"]);
+        for cookie in [None, Some("oidc_state=other")] {
+            let mut request = Request::get(callback.as_str());
+            if let Some(cookie) = cookie {
+                request = request.header("cookie", cookie);
+            }
+            let response = app.clone().oneshot(request.body(Body::empty()).unwrap()).await.unwrap();
+            assert!(login_error(&response).is_some());
+        }
+        assert!(state.db.get_user_by_username("attacker").await.is_err());
+    }
+
+    /// A request as the auth proxy (or anyone else at `peer`) sends it
+    async fn proxied(app: &Router, method: &str, uri: &str, peer: &str, user: &str, groups: &str, token: Option<&str>) -> axum::response::Response {
+        let mut request = Request::builder()
+            .method(method)
+            .uri(uri)
+            .header("Remote-User", user)
+            .header("Remote-Groups", groups);
+        if let Some(token) = token {
+            request = request.header("Authorization", format!("Bearer {}", token));
+        }
+        let mut request = request.body(Body::empty()).unwrap();
+        request.extensions_mut().insert(ConnectInfo(peer.parse::<std::net::SocketAddr>().unwrap()));
+        app.clone().oneshot(request).await.unwrap()
+    }
+
+    #[tokio::test]
+    async fn proxy_headers_are_only_trusted_from_the_proxy() {
+        let mut config = Config::default();
+        config.security.trusted_proxies = vec!["10.0.0.0/8".parse().unwrap()];
+        config.proxy_auth.enabled = true;
+        config.proxy_auth.role_mapping = vec![crate::config::RoleMapping {
+            group: "music-admins".to_string(),
+            role: "admin".to_string(),
+        }];
+        let state = test_state_with(config).await;
+        let app = app(state.clone());
+
+        // Anyone else sending the headers is just not logged in
+        let response = proxied(&app, "GET", "/api/admin/users", "192.0.2.7:4000", "mallory", "music-admins", None).await;
+        assert_eq!(response.status(), StatusCode::UNAUTHORIZED);
+        assert!(state.db.get_user_by_username("mallory").await.is_err());
+
+        let response = proxied(&app, "GET", "/api/admin/users", "10.0.0.2:4000", "proxied", "family, music-admins", None).await;
+        assert_eq!(response.status(), StatusCode::OK);
+        assert_eq!(state.db.get_user_by_username("proxied").await.unwrap().role, "admin");
+
+        // Leaving the group takes the role away at once
+        let response = proxied(&app, "GET", "/api/admin/users", "10.0.0.2:4000", "proxied", "family", None).await;
+        assert_eq!(response.status(), StatusCode::FORBIDDEN);
+        assert_eq!(state.db.get_user_by_username("proxied").await.unwrap().role, "user");
+
+        // Session features need a login session of the same user
+        let response = proxied(&app, "GET", "/api/user/sessions", "10.0.0.2:4000", "proxied", "", None).await;
+        assert_eq!(response.status(), StatusCode::FORBIDDEN);
+        let response = proxied(&app, "POST", "/api/login/proxy", "10.0.0.2:4000", "proxied", "", None).await;
+        assert_eq!(response.status(), StatusCode::OK);
+        let token = session_cookie(&response);
+        let response = proxied(&app, "GET", "/api/user/sessions", "10.0.0.2:4000", "proxied", "", Some(&token)).await;
+        assert_eq!(response.status(), StatusCode::OK);
+        let other = token_with(&state, "other", Vec::new()).await;
+        let response = proxied(&app, "GET", "/api/user/sessions", "10.0.0.2:4000", "proxied", "", Some(&other)).await;
+        assert_eq!(response.status(), StatusCode::FORBIDDEN);
+
+        let response = proxied(&app, "POST", "/api/login/proxy", "192.0.2.7:4000", "proxied", "", None).await;
+        assert_eq!(response.status(), StatusCode::UNAUTHORIZED);
+    }
+
+    #[tokio::test]
+    async fn passkeys_log_in_alone_or_as_second_factor() {
+        let config = Config {
+            passkeys: crate::webauthn::tests::test_config(),
+            ..Config::default()
+        };
+        let state = test_state_with(config).await;
+        let app = app(state.clone());
+        let token = token_with(&state, "keyholder", Vec::new()).await;
+        let mut authenticator = crate::webauthn::tests::TestAuthenticator::new(&state.config.passkeys);
+
+        let (code, options) = send_json(&app, "POST", "/api/user/passkeys/options", &token, "{}").await;
+        assert_eq!(code, StatusCode::OK);
+        let registration = json!({
+            "name": "Phone",
+            "credential": authenticator.registration_json(options["challenge"].as_str().unwrap())
+        });
+        let (code, _) = send_json(&app, "POST", "/api/user/passkeys", &token, &registration.to_string()).await;
+        assert_eq!(code, StatusCode::OK);
+        let (code, _) = send_json(&app, "POST", "/api/user/passkeys", &token, &registration.to_string()).await;
+        assert_eq!(code, StatusCode::BAD_REQUEST);
+
+        // On its own, the passkey has to verify the user
+        let (_, options) = login_json(&app, "/api/login/passkey/options", json!({})).await;
+        let challenge = options["challenge"].as_str().unwrap().to_string();
+        let credential = authenticator.assertion_json(&challenge, false);
+        let (code, _) = login_json(&app, "/api/login/passkey", json!({ "credential": credential })).await;
+        assert_eq!(code, StatusCode::UNAUTHORIZED);
+        state.db.clear_login_failures(&crate::login_throttle::account_key("keyholder")).await.unwrap();
+        let (_, options) = login_json(&app, "/api/login/passkey/options", json!({})).await;
+        let credential = authenticator.assertion_json(options["challenge"].as_str().unwrap(), true);
+        let (code, body) = login_json(&app, "/api/login/passkey", json!({ "credential": credential.clone() })).await;
+        assert_eq!((code, body["username"].as_str()), (StatusCode::OK, Some("keyholder")));
+        let (code, _) = login_json(&app, "/api/login/passkey", json!({ "credential": credential })).await;
+        assert_eq!(code, StatusCode::UNAUTHORIZED);
+
+        // With a passkey, the password is only the first step
+        let credentials = json!({ "username": "keyholder", "password": "password123" });
+        let (_, first) = login_json(&app, "/api/login", credentials).await;
+        assert_eq!((first["totp"].as_bool(), first["passkey"].as_bool()), (Some(false), Some(true)));
+        let (_, options) = login_json(&app, "/api/login/passkey/options", json!({ "challenge": first["challenge"] })).await;
+        assert_eq!(options["allowCredentials"].as_array().unwrap().len(), 1);
+        let credential = authenticator.assertion_json(options["challenge"].as_str().unwrap(), false);
+        let body = json!({ "challenge": first["challenge"], "credential": credential });
+        let (code, login) = login_json(&app, "/api/login/passkey", body).await;
+        assert_eq!(code, StatusCode::OK);
+        let session = login["token"].as_str().unwrap();
+        let (code, list) = send_json(&app, "GET", "/api/user/passkeys", session, "").await;
+        assert_eq!((code, list["passkeys"][0]["name"].as_str()), (StatusCode::OK, Some("Phone")));
+    }
+
+    #[cfg(unix)]
+    #[tokio::test]
+    async fn move_imports_keep_sources_that_were_not_imported() {
+        use std::os::unix::fs::PermissionsExt;
+
+        let temp = std::env::temp_dir().join(format!("move-import-{}", uuid::Uuid::new_v4()));
+        let root = temp.join("inbox");
+        std::fs::create_dir_all(root.join("Album")).unwrap();
+        std::fs::write(root.join("Album/01.flac"), b"fLaC good").unwrap();
+        std::fs::write(root.join("Album/02.flac"), b"corrupt").unwrap();
+        // Stands in for ffprobe: every file that gets that far is a FLAC track
+        let ffprobe = temp.join("ffprobe");
+        let probe = r#"{"format":{"format_name":"flac"},"streams":[{"codec_type":"audio","codec_name":"flac"}]}"#;
+        std::fs::write(&ffprobe, format!("#!/bin/sh\necho '{}'\n", probe)).unwrap();
+        std::fs::set_permissions(&ffprobe, std::fs::Permissions::from_mode(0o755)).unwrap();
+
+        let mut config = Config::default();
+        config.paths.music_dir = temp.join("music");
+        config.paths.temp_dir = temp.join("tmp");
+        config.ffmpeg.ffprobe_path = ffprobe.display().to_string();
+        config.validation.full_decode = false;
+        config.cover_art.enabled = false;
+        config.import_roots.insert(
+            "inbox".to_string(),
+            crate::config::ImportRoot {
+                path: root.clone(),
+                description: String::new(),
+                users: Vec::new(),
+                allow_move: true,
+            },
+        );
+        let state = test_state_with(config).await;
+        let app = app(state.clone());
+        let token = token_with(&state, "mover", vec![Permission::ServerImport]).await;
+
+        let body = r#"{"root":"inbox","paths":["Album"],"mode":"move"}"#;
+        let (code, response) = send_json(&app, "POST", "/api/import", &token, body).await;
+        assert_eq!(code, StatusCode::OK, "{}", response);
+        assert!(temp.join("music/Album/01.flac").exists());
+        assert!(!root.join("Album/01.flac").exists());
+        // The corrupt file was rejected, so its original stays
+        assert_eq!(std::fs::read(root.join("Album/02.flac")).unwrap(), b"corrupt");
+
+        std::fs::remove_dir_all(&temp).ok();
+    }
+
+    #[tokio::test]
+    async fn usernames_named_in_the_config_cant_be_taken() {
+        let temp = std::env::temp_dir().join(format!("reserved-names-{}", uuid::Uuid::new_v4()));
+        std::fs::create_dir_all(temp.join("inboxes/dana")).unwrap();
+        let mut config = Config::default();
+        config.import_roots.insert(
+            "archive".to_string(),
+            crate::config::ImportRoot {
+                path: temp.clone(),
+                description: String::new(),
+                users: vec!["archivist".to_string()],
+                allow_move: false,
+            },
+        );
+        config.watch.folders.insert(
+            "inboxes".to_string(),
+            crate::config::WatchFolder {
+                path: temp.join("inboxes"),
+                user: None,
+            },
+        );
+        let state = test_state_with(config).await;
+        let app = app(state.clone());
+        let token = token_with(&state, "mallory", vec![Permission::ServerImport]).await;
+
+        for name in ["archivist", "dana"] {
+            let body = json!({ "new_username": name }).to_string();
+            let (code, _) = send_json(&app, "POST", "/api/user/change-username", &token, &body).await;
+            assert_eq!(code, StatusCode::CONFLICT, "{}", name);
+        }
+        let (code, _) = send_json(&app, "POST", "/api/user/change-username", &token, r#"{"new_username":"mallory2"}"#).await;
+        assert_eq!(code, StatusCode::OK);
+
+        std::fs::remove_dir_all(&temp).ok();
+    }
+
+    #[cfg(unix)]
+    #[tokio::test]
+    async fn partial_spotdl_failures_are_noted_without_the_fallback() {
+        use std::os::unix::fs::PermissionsExt;
+
+        let temp = std::env::temp_dir().join(format!("spotdl-partial-{}", uuid::Uuid::new_v4()));
+        std::fs::create_dir_all(&temp).unwrap();
+        // Downloads one track of the album, then fails on the next
+        let spotdl = temp.join("spotdl");
+        let script = "#!/bin/sh\nprintf audio > \"$(dirname \"$4\")/Artist - One.opus\"\necho 'LookupError: Two' >&2\nexit 1\n";
+        std::fs::write(&spotdl, script).unwrap();
+        std::fs::set_permissions(&spotdl, std::fs::Permissions::from_mode(0o755)).unwrap();
+
+        let mut config = Config::default();
+        config.paths.music_dir = temp.join("music");
+        config.paths.temp_dir = temp.join("tmp");
+        config.spotify.enabled = true;
+        config.spotify.spotdl_path = spotdl.display().to_string();
+        config.validation.enabled = false;
+        config.cover_art.enabled = false;
+        let state = test_state_with(config).await;
+        let app = app(state.clone());
+        let token = token_with(&state, "listener", vec![Permission::Spotify]).await;
+
+        let body = r#"{"url":"https://open.spotify.com/album/abc","youtube_fallback":false}"#;
+        let (code, response) = send_json(&app, "POST", "/api/spotify", &token, body).await;
+        assert_eq!(code, StatusCode::OK, "{}", response);
+        assert!(response["message"].as_str().unwrap().contains("some tracks may be missing"));
+        assert!(temp.join("music/Artist - One.opus").exists());
+
+        let user = state.db.get_user_by_username("listener").await.unwrap();
+        let log = &state.db.get_upload_logs(Some(&user.id), 1).await.unwrap()[0];
+        assert_eq!(log.status, "completed");
+        assert!(log.error_message.as_deref().unwrap().contains("LookupError: Two"));
+
+        std::fs::remove_dir_all(&temp).ok();
+    }
+}
@@ -0,0 +1,428 @@
+use crate::config::FfmpegConfig;
+use crate::models::MetadataOverrides;
+use anyhow::{Context, Result};
+use base64::Engine;
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use std::path::Path;
+use tokio::fs;
+
+/// Metadata that can be written into an audio file
+/// Fields left as None are not touched in the file
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+pub struct TrackTags {
+    pub title: Option<String>,
+    pub artist: Option<String>,
+    pub album: Option<String>,
+    pub album_artist: Option<String>,
+    pub year: Option<String>,
+    pub genre: Option<String>,
+    pub track_number: Option<u32>,
+    pub track_total: Option<u32>,
+    pub disc_number: Option<u32>,
+}
+
+impl TrackTags {
+    /// Tags from user-supplied overrides
+    /// The title is only applied when the job produced a single track
+    pub fn from_overrides(overrides: &MetadataOverrides, include_title: bool) -> Self {
+        Self {
+            title: overrides.title.clone().filter(|_| include_title),
+            artist: overrides.artist.clone(),
+            album: overrides.album.clone(),
+            album_artist: overrides.album_artist.clone(),
+            year: overrides.year.clone(),
+            genre: overrides.genre.clone(),
+            ..Self::default()
+        }
+    }
+
+    /// Fill in every field set in `other`, keeping ours otherwise
+    pub fn merged_with(&self, other: &TrackTags) -> Self {
+        Self {
+            title: other.title.clone().or_else(|| self.title.clone()),
+            artist: other.artist.clone().or_else(|| self.artist.clone()),
+            album: other.album.clone().or_else(|| self.album.clone()),
+            album_artist: other.album_artist.clone().or_else(|| self.album_artist.clone()),
+            year: other.year.clone().or_else(|| self.year.clone()),
+            genre: other.genre.clone().or_else(|| self.genre.clone()),
+            track_number: other.track_number.or(self.track_number),
+            track_total: other.track_total.or(self.track_total),
+            disc_number: other.disc_number.or(self.disc_number),
+        }
+    }
+
+    /// Key/value pairs using ffmpeg's generic metadata names
+    /// (ffmpeg maps these to ID3, Vorbis comments and MP4 atoms)
+    pub fn ffmpeg_metadata(&self) -> Vec<(&'static str, String)> {
+        let mut pairs = Vec::new();
+        let mut push = |key: &'static str, value: &Option<String>| {
+            if let Some(v) = value.as_deref().map(str::trim).filter(|v| !v.is_empty()) {
+                pairs.push((key, v.to_string()));
+            }
+        };
+        push("title", &self.title);
+        push("artist", &self.artist);
+        push("album", &self.album);
+        push("album_artist", &self.album_artist);
+        push("date", &self.year);
+        push("genre", &self.genre);
+
+        if let Some(track) = self.track_number {
+            let value = match self.track_total {
+                Some(total) => format!("{}/{}", track, total),
+                None => track.to_string(),
+            };
+            pairs.push(("track", value));
+        }
+        if let Some(disc) = self.disc_number {
+            pairs.push(("disc", disc.to_string()));
+        }
+
+        pairs
+    }
+}
+
+/// Write tags into an audio file using ffmpeg (stream copy, no re-encode)
+pub async fn write_tags(ffmpeg_path: &str, file: &Path, tags: &TrackTags) -> Result<()> {
+    write_metadata(ffmpeg_path, file, &tags.ffmpeg_metadata()).await
+}
+
+/// Write arbitrary metadata keys (e.g. REPLAYGAIN_TRACK_GAIN) into an audio file
+/// ffmpeg cannot edit in place, so the result is written to a sibling file
+/// and then renamed over the original
+pub async fn write_metadata(ffmpeg_path: &str, file: &Path, metadata: &[(&str, String)]) -> Result<()> {
+    if metadata.is_empty() {
+        return Ok(());
+    }
+
+    let tmp = sibling_temp_path(file, "tagging");
+    let mut cmd = tokio::process::Command::new(ffmpeg_path);
+    cmd.arg("-y")
+        .arg("-v")
+        .arg("error")
+        .arg("-i")
+        .arg(file)
+        .args(["-map", "0", "-c", "copy", "-map_metadata", "0"]);
+    // The MP4 muxer drops keys it has no atom for unless asked to keep them
+    if matches!(
+        file.extension().and_then(|e| e.to_str()).map(str::to_lowercase).as_deref(),
+        Some("m4a" | "mp4")
+    ) {
+        cmd.args(["-movflags", "use_metadata_tags"]);
+    }
+    for (key, value) in metadata {
+        cmd.arg("-metadata").arg(format!("{}={}", key, value));
+    }
+    cmd.arg(&tmp);
+
+    let output = cmd.output().await.context("Failed to run ffmpeg")?;
+    if !output.status.success() {
+        fs::remove_file(&tmp).await.ok();
+        let stderr = String::from_utf8_lossy(&output.stderr);
+        anyhow::bail!("ffmpeg tagging failed for {}: {}", file.display(), stderr);
+    }
+
+    fs::rename(&tmp, file)
+        .await
+        .context(format!("Failed to replace {}", file.display()))?;
+
+    Ok(())
+}
+
+/// Hidden temp path next to `file` that keeps the original extension
+/// (ffmpeg picks the output container from the extension)
+pub fn sibling_temp_path(file: &Path, purpose: &str) -> std::path::PathBuf {
+    let stem = file
+        .file_stem()
+        .and_then(|s| s.to_str())
+        .unwrap_or("file");
+    let name = match file.extension().and_then(|e| e.to_str()) {
+        Some(ext) => format!(".{}.{}.{}", stem, purpose, ext),
+        None => format!(".{}.{}", stem, purpose),
+    };
+    file.with_file_name(name)
+}
+
+/// Read the tags of an audio file with ffprobe
+/// Container and audio stream tags are merged; keys are lowercased
+pub async fn read_tags(ffprobe_path: &str, file: &Path) -> Result<HashMap<String, String>> {
+    let output = tokio::process::Command::new(ffprobe_path)
+        .args(["-v", "error", "-of", "json", "-show_entries", "format_tags:stream_tags"])
+        .args(["-select_streams", "a:0"])
+        .arg(file)
+        .output()
+        .await
+        .context("Failed to run ffprobe")?;
+
+    if !output.status.success() {
+        let stderr = String::from_utf8_lossy(&output.stderr);
+        anyhow::bail!("ffprobe failed for {}: {}", file.display(), stderr);
+    }
+
+    let probe: ProbeTags = serde_json::from_slice(&output.stdout)?;
+    let mut tags = HashMap::new();
+    let stream_tags = probe.streams.into_iter().flat_map(|s| s.tags);
+    for (key, value) in probe.format.map(|f| f.tags).unwrap_or_default().into_iter().chain(stream_tags) {
+        tags.insert(key.to_lowercase(), value);
+    }
+    Ok(tags)
+}
+
+#[derive(Debug, Default, Deserialize)]
+struct ProbeTags {
+    #[serde(default)]
+    format: Option<ProbeTagSection>,
+    #[serde(default)]
+    streams: Vec<ProbeTagSection>,
+}
+
+#[derive(Debug, Default, Deserialize)]
+struct ProbeTagSection {
+    #[serde(default)]
+    tags: HashMap<String, String>,
+}
+
+/// Embed `cover` (JPEG/PNG) as front cover art, replacing any existing art
+///
+/// Ogg/Opus files store art as a METADATA_BLOCK_PICTURE comment, which is
+/// passed to ffmpeg through an ffmetadata file since it is too large for argv.
+/// Other formats get an attached picture stream.
+/// Returns false for formats that cannot carry cover art (e.g. WAV).
+pub async fn embed_cover_art(config: &FfmpegConfig, file: &Path, cover: &Path) -> Result<bool> {
+    let ext = file
+        .extension()
+        .and_then(|e| e.to_str())
+        .unwrap_or("")
+        .to_lowercase();
+    let tmp = sibling_temp_path(file, "cover");
+
+    let mut cmd = tokio::process::Command::new(&config.ffmpeg_path);
+    cmd.args(["-y", "-v", "error", "-i"]).arg(file);
+
+    let meta_file = sibling_temp_path(file, "ffmetadata").with_extension("txt");
+    match ext.as_str() {
+        "opus" | "ogg" | "oga" => {
+            let image = fs::read(cover).await?;
+            let block = crate::artwork::flac_picture_block(&image)
+                .ok_or_else(|| anyhow::anyhow!("Cover art must be a JPEG or PNG image"))?;
+
+            // Keep the existing stream comments, replacing any previous picture
+            let mut stream_tags: Vec<(String, String)> = read_tags(&config.ffprobe_path, file)
+                .await?
+                .into_iter()
+                .filter(|(k, _)| k != "metadata_block_picture" && k != "encoder")
+                .collect();
+            stream_tags.push((
+                "METADATA_BLOCK_PICTURE".to_string(),
+                base64::engine::general_purpose::STANDARD.encode(block),
+            ));
+            fs::write(&meta_file, ffmetadata_stream_section(&stream_tags)).await?;
+
+            cmd.args(["-f", "ffmetadata", "-i"])
+                .arg(&meta_file)
+                .args(["-map", "0:a", "-c", "copy", "-map_metadata", "0"])
+                .args(["-map_metadata:s:a:0", "1:s:0"]);
+        }
+        "mp3" | "m4a" | "mp4" | "flac" => {
+            cmd.arg("-i")
+                .arg(cover)
+                .args(["-map", "0:a", "-map", "1:0", "-c", "copy", "-map_metadata", "0"])
+                .args(["-disposition:v:0", "attached_pic"]);
+            if ext == "mp3" {
+                cmd.args(["-id3v2_version", "3"]);
+            }
+        }
+        _ => return Ok(false),
+    }
+    cmd.arg(&tmp);
+
+    let output = cmd.output().await.context("Failed to run ffmpeg");
+    fs::remove_file(&meta_file).await.ok();
+    let output = output?;
+    if !output.status.success() {
+        fs::remove_file(&tmp).await.ok();
+        let stderr = String::from_utf8_lossy(&output.stderr);
+        anyhow::bail!("ffmpeg failed to embed cover art in {}: {}", file.display(), stderr);
+    }
+
+    fs::rename(&tmp, file)
+        .await
+        .context(format!("Failed to replace {}", file.display()))?;
+
+    Ok(true)
+}
+
+/// Copy the embedded cover art of `file` next to `dest_stem` without re-encoding
+/// The extension (.jpg/.png) is chosen from the image data.
+/// Returns None when the file has no JPEG/PNG cover.
+pub async fn extract_cover_art(
+    ffmpeg_path: &str,
+    file: &Path,
+    dest_stem: &Path,
+) -> Result<Option<std::path::PathBuf>> {
+    let raw = dest_stem.with_extension("img");
+    let output = tokio::process::Command::new(ffmpeg_path)
+        .args(["-y", "-v", "error", "-i"])
+        .arg(file)
+        .args(["-map", "0:v:0", "-c", "copy", "-frames:v", "1", "-f", "image2"])
+        .arg(&raw)
+        .output()
+        .await
+        .context("Failed to run ffmpeg")?;
+
+    let ext = match fs::read(&raw).await {
+        Ok(data) if output.status.success() => match crate::artwork::image_mime(&data) {
+            Some("image/png") => "png",
+            Some(_) => "jpg",
+            None => {
+                fs::remove_file(&raw).await.ok();
+                return Ok(None);
+            }
+        },
+        _ => {
+            fs::remove_file(&raw).await.ok();
+            return Ok(None);
+        }
+    };
+
+    let dest = dest_stem.with_extension(ext);
+    fs::rename(&raw, &dest).await?;
+    Ok(Some(dest))
+}
+
+/// ffmetadata file with a single [STREAM] section
+fn ffmetadata_stream_section(tags: &[(String, String)]) -> String {
+    let escape = |s: &str| {
+        let mut out = String::with_capacity(s.len());
+        for c in s.chars() {
+            if matches!(c, '=' | ';' | '#' | '\\' | '\n') {
+                out.push('\\');
+            }
+            out.push(c);
+        }
+        out
+    };
+
+    let mut content = String::from(";FFMETADATA1\n[STREAM]\n");
+    for (key, value) in tags {
+        content.push_str(&format!("{}={}\n", escape(key), escape(value)));
+    }
+    content
+}
+
+/// Result of cleaning up a YouTube video title
+#[derive(Debug, Clone, PartialEq)]
+pub struct CleanTitle {
+    pub artist: Option<String>,
+    pub title: String,
+}
+
+/// Bracketed suffixes that describe the video rather than the song
+const TITLE_NOISE: &[&str] = &[
+    "official video",
+    "official music video",
+    "official audio",
+    "official lyric video",
+    "official lyrics video",
+    "official visualizer",
+    "official hd video",
+    "music video",
+    "lyric video",
+    "lyrics video",
+    "lyrics",
+    "lyric",
+    "audio",
+    "visualizer",
+    "video",
+    "hd",
+    "hq",
+    "4k",
+    "official",
+    "explicit",
+    "clean",
+];
+
+/// Heuristic cleanup for YouTube titles:
+/// strips "(Official Video)", "[Lyrics]" and similar, and splits "Artist - Title"
+pub fn clean_youtube_title(raw: &str) -> CleanTitle {
+    let mut title = String::with_capacity(raw.len());
+    let mut rest = raw;
+
+    // Drop bracketed groups whose content is pure noise, keep e.g. "(Remix)"
+    while let Some(open_idx) = rest.find(['(', '[']) {
+        let close = if rest.as_bytes()[open_idx] == b'(' { ')' } else { ']' };
+        let Some(close_rel) = rest[open_idx..].find(close) else {
+            break;
+        };
+        let close_idx = open_idx + close_rel;
+        let inner = rest[open_idx + 1..close_idx].trim().to_lowercase();
+
+        title.push_str(&rest[..open_idx]);
+        if !TITLE_NOISE.contains(&inner.as_str()) {
+            title.push_str(&rest[open_idx..=close_idx]);
+        }
+        rest = &rest[close_idx + 1..];
+    }
+    title.push_str(rest);
+
+    let title = title.split_whitespace().collect::<Vec<_>>().join(" ");
+
+    for separator in [" - ", " – ", " — ", " | "] {
+        if let Some((artist, song)) = title.split_once(separator) {
+            let (artist, song) = (artist.trim(), song.trim());
+            if !artist.is_empty() && !song.is_empty() {
+                return CleanTitle {
+                    artist: Some(artist.to_string()),
+                    title: song.to_string(),
+                };
+            }
+        }
+    }
+
+    CleanTitle {
+        artist: None,
+        title: if title.is_empty() { raw.trim().to_string() } else { title },
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn clean_title_strips_noise_and_splits_artist() {
+        let cleaned = clean_youtube_title("Daft Punk - One More Time (Official Video) [HD]");
+        assert_eq!(cleaned.artist.as_deref(), Some("Daft Punk"));
+        assert_eq!(cleaned.title, "One More Time");
+
+        let cleaned = clean_youtube_title("Song Name [Lyrics]");
+        assert_eq!(cleaned.artist, None);
+        assert_eq!(cleaned.title, "Song Name");
+    }
+
+    #[test]
+    fn clean_title_keeps_meaningful_brackets() {
+        let cleaned = clean_youtube_title("Artist - Track (Extended Mix) (Official Audio)");
+        assert_eq!(cleaned.title, "Track (Extended Mix)");
+    }
+
+    #[test]
+    fn override_title_only_for_single_tracks() {
+        let overrides = MetadataOverrides {
+            title: Some("Title".into()),
+            album: Some("Album".into()),
+            ..MetadataOverrides::default()
+        };
+
+        assert_eq!(TrackTags::from_overrides(&overrides, true).title.as_deref(), Some("Title"));
+        assert_eq!(TrackTags::from_overrides(&overrides, false).title, None);
+        assert_eq!(TrackTags::from_overrides(&overrides, false).album.as_deref(), Some("Album"));
+    }
+
+    #[test]
+    fn ffmetadata_values_are_escaped() {
+        let content = ffmetadata_stream_section(&[("TITLE".into(), "a=b;c".into())]);
+        assert_eq!(content, ";FFMETADATA1\n[STREAM]\nTITLE=a\\=b\\;c\n");
+    }
+}
@@ -2,7 +2,32 @@ use askama_axum::Template;
 
 #[derive(Template)]
 #[template(path = "login.html")]
-pub struct LoginTemplate;
+pub struct LoginTemplate {
+    pub password_login: bool,
+    /// Button label when single sign-on is enabled
+    pub sso_label: Option<String>,
+    /// Pick up a session from the auth proxy's headers on load
+    pub proxy_auth: bool,
+    pub passkeys: bool,
+}
+
+impl LoginTemplate {
+    pub fn new(config: &crate::config::Config) -> Self {
+        Self {
+            password_login: config.security.password_login,
+            sso_label: config.oidc.enabled.then(|| config.oidc.button_label.clone()),
+            proxy_auth: config.proxy_auth.enabled,
+            passkeys: config.passkeys.enabled,
+        }
+    }
+}
+
+/// Hands a single sign-on login (the login response JSON) to the browser
+#[derive(Template)]
+#[template(path = "sso.html")]
+pub struct SsoTemplate {
+    pub login: String,
+}
 
 #[derive(Template)]
 #[template(path = "upload.html")]
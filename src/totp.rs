@@ -0,0 +1,117 @@
+use anyhow::{anyhow, Result};
+use qrcode::render::svg;
+use qrcode::QrCode;
+use rand::Rng;
+use sha2::{Digest, Sha256};
+use totp_rs::{Algorithm, Secret, TOTP};
+
+/// Shown as the account's issuer in authenticator apps
+const ISSUER: &str = "Music Upload";
+/// Seconds per code (RFC 6238 default, what every authenticator app assumes)
+const STEP: u64 = 30;
+/// Codes from one step before or after are accepted, for clock drift
+const SKEW: i64 = 1;
+pub const RECOVERY_CODE_COUNT: usize = 10;
+
+fn totp(secret: &str, username: &str) -> Result<TOTP> {
+    let bytes = Secret::Encoded(secret.to_string())
+        .to_bytes()
+        .map_err(|e| anyhow!("Invalid TOTP secret: {:?}", e))?;
+    // The account name can't contain ':' in the otpauth URI
+    TOTP::new(Algorithm::SHA1, 6, 1, STEP, bytes, Some(ISSUER.to_string()), username.replace(':', "_"))
+        .map_err(|e| anyhow!("Invalid TOTP parameters: {}", e))
+}
+
+/// A new random base32 secret (160 bits)
+pub fn generate_secret() -> String {
+    Secret::generate_secret().to_encoded().to_string()
+}
+
+/// `otpauth://` URI for authenticator apps
+pub fn provisioning_uri(secret: &str, username: &str) -> Result<String> {
+    Ok(totp(secret, username)?.get_url())
+}
+
+/// The provisioning URI as an SVG QR code
+pub fn qr_svg(uri: &str) -> Result<String> {
+    let code = QrCode::new(uri.as_bytes())?;
+    Ok(code.render::<svg::Color>().min_dimensions(200, 200).build())
+}
+
+/// The time step `code` is valid for, if it is valid now and newer than
+/// `last_step` (so a code can't be used twice)
+pub fn verify(secret: &str, code: &str, now: u64, last_step: Option<i64>) -> Option<i64> {
+    let totp = totp(secret, "").ok()?;
+    let code = code.trim();
+    let current = (now / STEP) as i64;
+    (current - SKEW..=current + SKEW)
+        .filter(|step| *step >= 0 && last_step.is_none_or(|last| *step > last))
+        .find(|step| totp.generate(*step as u64 * STEP) == code)
+}
+
+/// Fresh one-time recovery codes, like `k3f9x-2mq7p`
+pub fn generate_recovery_codes() -> Vec<String> {
+    const CHARSET: &[u8] = b"abcdefghjkmnpqrstuvwxyz23456789";
+    let mut rng = rand::thread_rng();
+    (0..RECOVERY_CODE_COUNT)
+        .map(|_| {
+            let chars: String = (0..10).map(|_| CHARSET[rng.gen_range(0..CHARSET.len())] as char).collect();
+            format!("{}-{}", &chars[..5], &chars[5..])
+        })
+        .collect()
+}
+
+/// Recovery codes are random, so a plain SHA-256 is enough; case and
+/// separators are ignored when they are typed back in
+pub fn hash_recovery_code(code: &str) -> String {
+    let normalized: String = code
+        .chars()
+        .filter(|c| c.is_ascii_alphanumeric())
+        .map(|c| c.to_ascii_lowercase())
+        .collect();
+    Sha256::digest(normalized.as_bytes()).iter().map(|b| format!("{:02x}", b)).collect()
+}
+
+/// Whether `code` looks like a TOTP code rather than a recovery code
+pub fn is_totp_code(code: &str) -> bool {
+    let code = code.trim();
+    code.len() == 6 && code.chars().all(|c| c.is_ascii_digit())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // RFC 6238 test key ("12345678901234567890"), SHA-1, truncated to 6 digits
+    const SECRET: &str = "GEZDGNBVGY3TQOJQGEZDGNBVGY3TQOJQ";
+
+    #[test]
+    fn matches_rfc_6238_vectors() {
+        assert_eq!(verify(SECRET, "287082", 59, None), Some(1));
+        assert_eq!(verify(SECRET, "081804", 1111111109, None), Some(37037036));
+        assert_eq!(verify(SECRET, "000000", 59, None), None);
+    }
+
+    #[test]
+    fn accepts_drift_but_not_reuse() {
+        // One step late is fine, two is not
+        assert_eq!(verify(SECRET, "287082", 89, None), Some(1));
+        assert_eq!(verify(SECRET, "287082", 119, None), None);
+        assert_eq!(verify(SECRET, "287082", 59, Some(1)), None);
+    }
+
+    #[test]
+    fn recovery_codes_are_normalized() {
+        let codes = generate_recovery_codes();
+        assert_eq!(codes.len(), RECOVERY_CODE_COUNT);
+        assert!(!is_totp_code(&codes[0]));
+        assert_eq!(hash_recovery_code(&codes[0]), hash_recovery_code(&codes[0].to_uppercase().replace('-', " ")));
+    }
+
+    #[test]
+    fn provisioning_uri_names_issuer_and_account() {
+        let uri = provisioning_uri(&generate_secret(), "dj:alice").unwrap();
+        assert!(uri.starts_with("otpauth://totp/Music%20Upload:dj_alice?"), "{}", uri);
+        assert!(qr_svg(&uri).unwrap().contains("<svg"));
+    }
+}
@@ -0,0 +1,246 @@
+use crate::tagging::TrackTags;
+use anyhow::{Context, Result};
+use serde::Deserialize;
+use std::path::{Path, PathBuf};
+
+/// One track inside a longer recording
+#[derive(Debug, Clone, PartialEq)]
+pub struct TrackSegment {
+    pub title: String,
+    pub start_secs: f64,
+    /// None means "until the end of the file"
+    pub end_secs: Option<f64>,
+}
+
+/// A chapter as reported by yt-dlp's info JSON
+#[derive(Debug, Clone, Deserialize)]
+pub struct Chapter {
+    pub start_time: f64,
+    #[serde(default)]
+    pub end_time: Option<f64>,
+    #[serde(default)]
+    pub title: String,
+}
+
+/// Convert yt-dlp chapters into track segments
+pub fn segments_from_chapters(chapters: &[Chapter]) -> Vec<TrackSegment> {
+    chapters
+        .iter()
+        .enumerate()
+        .map(|(i, chapter)| TrackSegment {
+            title: if chapter.title.trim().is_empty() {
+                format!("Track {}", i + 1)
+            } else {
+                chapter.title.trim().to_string()
+            },
+            start_secs: chapter.start_time,
+            end_secs: chapter.end_time,
+        })
+        .collect()
+}
+
+/// Parse a pasted tracklist with one "timestamp title" entry per line
+///
+/// Accepts the usual YouTube description formats, e.g.
+/// `00:00 Intro`, `3:45 - Song`, `[1:02:03] Song`, `1. Song 4:10`.
+/// Lines without a timestamp are ignored. Each track ends where the next begins.
+pub fn parse_tracklist(text: &str) -> Result<Vec<TrackSegment>> {
+    let mut entries: Vec<(f64, String)> = Vec::new();
+
+    for line in text.lines() {
+        let tokens: Vec<&str> = line.split_whitespace().collect();
+        let Some((index, start)) = tokens
+            .iter()
+            .enumerate()
+            .find_map(|(i, token)| parse_timestamp(token).map(|secs| (i, secs)))
+        else {
+            continue;
+        };
+
+        let mut rest: Vec<&str> = tokens
+            .iter()
+            .enumerate()
+            .filter(|(i, _)| *i != index)
+            .map(|(_, t)| *t)
+            .collect();
+
+        // Drop leading track numbering such as "1." or "01)"
+        if let Some(first) = rest.first() {
+            let digits = first.trim_end_matches(['.', ')']);
+            if digits.len() < first.len() && digits.chars().all(|c| c.is_ascii_digit()) {
+                rest.remove(0);
+            }
+        }
+
+        let title = rest
+            .join(" ")
+            .trim_matches(|c: char| c.is_whitespace() || "-–—:|".contains(c))
+            .to_string();
+        let title = if title.is_empty() {
+            format!("Track {}", entries.len() + 1)
+        } else {
+            title
+        };
+        entries.push((start, title));
+    }
+
+    if entries.is_empty() {
+        anyhow::bail!("Tracklist does not contain any timestamps");
+    }
+
+    if entries.windows(2).any(|pair| pair[1].0 <= pair[0].0) {
+        anyhow::bail!("Tracklist timestamps must be in increasing order");
+    }
+
+    let starts: Vec<f64> = entries.iter().map(|(start, _)| *start).collect();
+    Ok(entries
+        .into_iter()
+        .enumerate()
+        .map(|(i, (start, title))| TrackSegment {
+            title,
+            start_secs: start,
+            end_secs: starts.get(i + 1).copied(),
+        })
+        .collect())
+}
+
+/// Parse "m:ss", "mm:ss" or "h:mm:ss", ignoring surrounding brackets
+fn parse_timestamp(token: &str) -> Option<f64> {
+    let token = token.trim_matches(|c: char| "[]()".contains(c));
+    let parts: Vec<&str> = token.split(':').collect();
+    if !(2..=3).contains(&parts.len()) {
+        return None;
+    }
+    if parts
+        .iter()
+        .any(|p| p.is_empty() || p.len() > 2 || !p.chars().all(|c| c.is_ascii_digit()))
+    {
+        return None;
+    }
+
+    let values: Vec<u64> = parts.iter().filter_map(|p| p.parse().ok()).collect();
+    let (h, m, s) = match values.as_slice() {
+        [m, s] => (0, *m, *s),
+        [h, m, s] => (*h, *m, *s),
+        _ => return None,
+    };
+    if s >= 60 || (values.len() == 3 && m >= 60) {
+        return None;
+    }
+
+    Some((h * 3600 + m * 60 + s) as f64)
+}
+
+/// Cut `source` into one file per segment using ffmpeg stream copy
+///
+/// Files are written as "NN - Title.ext" into `output_dir` and tagged with
+/// `album_tags` plus the segment's title and track number. Global metadata of
+/// the source (including embedded cover art) is carried over to every track.
+pub async fn split_into_tracks(
+    ffmpeg_path: &str,
+    source: &Path,
+    segments: &[TrackSegment],
+    album_tags: &TrackTags,
+    output_dir: &Path,
+) -> Result<Vec<PathBuf>> {
+    let ext = source
+        .extension()
+        .and_then(|e| e.to_str())
+        .unwrap_or("opus");
+    let total = segments.len() as u32;
+    let mut outputs = Vec::with_capacity(segments.len());
+
+    for (i, segment) in segments.iter().enumerate() {
+        let number = i as u32 + 1;
+        let file_name = format!(
+            "{:02} - {}.{}",
+            number,
+            crate::paths::sanitize_path_component(&segment.title),
+            ext
+        );
+        let output = output_dir.join(file_name);
+
+        let tags = TrackTags {
+            title: Some(segment.title.clone()),
+            track_number: Some(number),
+            track_total: Some(total),
+            ..album_tags.clone()
+        };
+
+        let mut cmd = tokio::process::Command::new(ffmpeg_path);
+        cmd.args(["-y", "-v", "error", "-i"])
+            .arg(source)
+            .arg("-ss")
+            .arg(format!("{:.3}", segment.start_secs));
+        if let Some(end) = segment.end_secs {
+            cmd.arg("-to").arg(format!("{:.3}", end));
+        }
+        cmd.args(["-map", "0", "-c", "copy", "-map_metadata", "0", "-map_chapters", "-1"]);
+        for (key, value) in tags.ffmpeg_metadata() {
+            cmd.arg("-metadata").arg(format!("{}={}", key, value));
+        }
+        cmd.arg(&output);
+
+        let result = cmd.output().await.context("Failed to run ffmpeg")?;
+        if !result.status.success() {
+            let stderr = String::from_utf8_lossy(&result.stderr);
+            anyhow::bail!("ffmpeg failed to cut track {}: {}", number, stderr);
+        }
+
+        outputs.push(output);
+    }
+
+    Ok(outputs)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parses_common_tracklist_formats() {
+        let text = "Tracklist:\n\
+                    00:00 Intro\n\
+                    3:45 - Second Song\n\
+                    2. Third Song [12:01]\n\
+                    1:02:03 – Finale\n";
+        let segments = parse_tracklist(text).unwrap();
+
+        assert_eq!(segments.len(), 4);
+        assert_eq!(segments[0].title, "Intro");
+        assert_eq!(segments[0].end_secs, Some(225.0));
+        assert_eq!(segments[1].title, "Second Song");
+        assert_eq!(segments[2].title, "Third Song");
+        assert_eq!(segments[2].start_secs, 721.0);
+        assert_eq!(segments[3].title, "Finale");
+        assert_eq!(segments[3].start_secs, 3723.0);
+        assert_eq!(segments[3].end_secs, None);
+    }
+
+    #[test]
+    fn rejects_tracklist_without_timestamps_or_out_of_order() {
+        assert!(parse_tracklist("just some text").is_err());
+        assert!(parse_tracklist("05:00 B\n01:00 A").is_err());
+    }
+
+    #[test]
+    fn timestamp_parsing_is_strict() {
+        assert_eq!(parse_timestamp("(4:05)"), Some(245.0));
+        assert_eq!(parse_timestamp("4:75"), None);
+        assert_eq!(parse_timestamp("2024:01"), None);
+        assert_eq!(parse_timestamp("Song"), None);
+    }
+
+    #[test]
+    fn chapters_without_titles_get_numbered() {
+        let chapters = vec![
+            Chapter { start_time: 0.0, end_time: Some(100.0), title: "Opening".into() },
+            Chapter { start_time: 100.0, end_time: Some(200.0), title: " ".into() },
+        ];
+        let segments = segments_from_chapters(&chapters);
+
+        assert_eq!(segments[0].title, "Opening");
+        assert_eq!(segments[1].title, "Track 2");
+        assert_eq!(segments[1].end_secs, Some(200.0));
+    }
+}
@@ -0,0 +1,534 @@
+use crate::config::WatchConfig;
+use crate::import_roots::remove_sources;
+use crate::models::CreateUploadLog;
+use crate::paths::{ensure_directory_exists, get_user_directories};
+use crate::pipeline::{
+    import_job_dir, is_allowed_audio, is_image_file, is_lyrics_file, run_post_processing, validate_staged_files,
+    JobContext,
+};
+use anyhow::{Context, Result};
+use notify::{RecommendedWatcher, RecursiveMode, Watcher};
+use serde_json::json;
+use std::collections::{BTreeMap, HashMap};
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+use std::time::{Duration, Instant, SystemTime};
+use tokio::sync::mpsc;
+
+/// Subfolder of an inbox that rejected files are moved to
+pub const ERRORS_DIR: &str = "errors";
+
+/// Start watching the configured drop folders (if enabled)
+pub fn spawn(state: Arc<crate::AppState>) {
+    let settings = &state.config.watch;
+    if !settings.enabled || settings.folders.is_empty() {
+        return;
+    }
+    tokio::spawn(run(state));
+}
+
+async fn run(state: Arc<crate::AppState>) {
+    let settings = state.config.watch.clone();
+    let (tx, mut rx) = mpsc::channel(1);
+    // Kept alive for as long as the loop runs; None means polling only
+    let _watcher = if settings.force_polling {
+        None
+    } else {
+        match start_watcher(&settings, tx) {
+            Ok(watcher) => Some(watcher),
+            Err(e) => {
+                tracing::warn!("inotify unavailable ({:#}), polling drop folders every {}s", e, settings.poll_interval_secs);
+                None
+            }
+        }
+    };
+
+    let stable = Duration::from_secs(settings.stable_secs);
+    let poll_interval = Duration::from_secs(settings.poll_interval_secs.max(1));
+    // While files are settling they are re-checked more often than the poll interval
+    let settle_interval = Duration::from_secs((settings.stable_secs / 2).clamp(1, settings.poll_interval_secs.max(1)));
+    let mut tracker = Tracker::default();
+
+    loop {
+        let snapshot = {
+            let settings = settings.clone();
+            tokio::task::spawn_blocking(move || snapshot(&settings)).await.unwrap_or_default()
+        };
+        for group in tracker.update(snapshot, Instant::now(), stable) {
+            let files: Vec<PathBuf> = group.files.iter().map(|f| group.inbox.join(f)).collect();
+            if let Err(e) = ingest_group(&state, &group).await {
+                tracing::error!("Failed to import {}: {:#}", group.inbox.join(&group.top).display(), e);
+            }
+            // Anything still in place (e.g. couldn't be moved) is left alone until it changes
+            tracker.ignore(files.into_iter().filter(|f| f.exists()));
+        }
+
+        let wait = if tracker.has_pending() { settle_interval } else { poll_interval };
+        tokio::select! {
+            _ = rx.recv() => {}
+            _ = tokio::time::sleep(wait) => {}
+        }
+    }
+}
+
+/// inotify watcher that wakes the loop on any change in the drop folders
+fn start_watcher(settings: &WatchConfig, tx: mpsc::Sender<()>) -> Result<RecommendedWatcher> {
+    let mut watcher = notify::recommended_watcher(move |event: notify::Result<notify::Event>| {
+        if event.is_ok() {
+            // A wake-up is already queued if the channel is full
+            tx.try_send(()).ok();
+        }
+    })?;
+    for folder in settings.folders.values() {
+        watcher
+            .watch(&folder.path, RecursiveMode::Recursive)
+            .with_context(|| format!("Failed to watch {}", folder.path.display()))?;
+    }
+    Ok(watcher)
+}
+
+/// A file seen in an inbox
+#[derive(Debug, Clone, PartialEq)]
+pub struct SeenFile {
+    pub username: String,
+    pub inbox: PathBuf,
+    /// Path relative to the inbox
+    pub relative: PathBuf,
+    pub size: u64,
+    pub modified: Option<SystemTime>,
+}
+
+/// Files that are imported together: a top-level file or folder of an inbox
+#[derive(Debug, Clone, PartialEq)]
+pub struct Group {
+    pub username: String,
+    pub inbox: PathBuf,
+    /// First component of the files' paths
+    pub top: PathBuf,
+    /// Paths relative to the inbox
+    pub files: Vec<PathBuf>,
+}
+
+/// Files still being written by a sync tool or a browser
+fn is_in_progress(name: &str) -> bool {
+    name.starts_with('.') || name.starts_with("~syncthing~") || name.ends_with(".part") || name.ends_with(".tmp")
+}
+
+/// List every file in the drop folders, with its owner
+/// Hidden and in-progress files and the errors/ subfolders are left out.
+fn snapshot(settings: &WatchConfig) -> Vec<SeenFile> {
+    let mut inboxes = Vec::new();
+    for folder in settings.folders.values() {
+        match &folder.user {
+            Some(user) => inboxes.push((user.clone(), folder.path.clone())),
+            None => {
+                let Ok(entries) = fs::read_dir(&folder.path) else {
+                    continue;
+                };
+                for entry in entries.flatten() {
+                    let name = entry.file_name().to_string_lossy().to_string();
+                    if entry.file_type().is_ok_and(|t| t.is_dir()) && !name.starts_with('.') {
+                        inboxes.push((name, entry.path()));
+                    }
+                }
+            }
+        }
+    }
+
+    let mut files = Vec::new();
+    for (username, inbox) in inboxes {
+        let walker = walkdir::WalkDir::new(&inbox).min_depth(1).into_iter().filter_entry(|e| {
+            let name = e.file_name().to_string_lossy();
+            let errors_dir = e.depth() == 1 && e.file_type().is_dir() && name == ERRORS_DIR;
+            !is_in_progress(&name) && !errors_dir
+        });
+        for entry in walker.flatten().filter(|e| e.file_type().is_file()) {
+            let Ok(metadata) = entry.metadata() else {
+                continue;
+            };
+            files.push(SeenFile {
+                username: username.clone(),
+                inbox: inbox.clone(),
+                relative: entry.path().strip_prefix(&inbox).unwrap_or(entry.path()).to_path_buf(),
+                size: metadata.len(),
+                modified: metadata.modified().ok(),
+            });
+        }
+    }
+    files
+}
+
+/// Decides when files have settled: their size and modification time
+/// must stay the same for the stable period
+#[derive(Debug, Default)]
+pub struct Tracker {
+    /// Last seen state of each file and since when it has been unchanged
+    files: HashMap<PathBuf, (SeenFile, Instant)>,
+    /// Files that couldn't be imported or moved away, with the state they had
+    ignored: HashMap<PathBuf, (u64, Option<SystemTime>)>,
+}
+
+impl Tracker {
+    /// Record a snapshot and return the groups whose files have all settled
+    /// (those are forgotten, so they are returned once)
+    pub fn update(&mut self, snapshot: Vec<SeenFile>, now: Instant, stable: Duration) -> Vec<Group> {
+        let mut current = HashMap::new();
+        for file in snapshot {
+            let path = file.inbox.join(&file.relative);
+            if self.ignored.get(&path) == Some(&(file.size, file.modified)) {
+                current.insert(path, None);
+                continue;
+            }
+            let since = match self.files.get(&path) {
+                Some((seen, since)) if seen.size == file.size && seen.modified == file.modified => *since,
+                _ => now,
+            };
+            current.insert(path, Some((file, since)));
+        }
+        // Forget files that are gone (and ignored ones that changed or are gone)
+        self.ignored.retain(|path, _| matches!(current.get(path), Some(None)));
+        self.files = current.into_iter().filter_map(|(path, seen)| Some((path, seen?))).collect();
+
+        let mut groups: BTreeMap<(PathBuf, PathBuf), (Group, bool)> = BTreeMap::new();
+        for (file, since) in self.files.values() {
+            let top = file.relative.iter().next().map(PathBuf::from).unwrap_or_default();
+            let (group, settled) = groups.entry((file.inbox.clone(), top.clone())).or_insert_with(|| {
+                let group = Group {
+                    username: file.username.clone(),
+                    inbox: file.inbox.clone(),
+                    top,
+                    files: Vec::new(),
+                };
+                (group, true)
+            });
+            group.files.push(file.relative.clone());
+            *settled &= now.duration_since(*since) >= stable;
+        }
+
+        let ready: Vec<Group> = groups
+            .into_values()
+            .filter(|(_, settled)| *settled)
+            .map(|(mut group, _)| {
+                group.files.sort();
+                group
+            })
+            .collect();
+        for group in &ready {
+            for file in &group.files {
+                self.files.remove(&group.inbox.join(file));
+            }
+        }
+        ready
+    }
+
+    /// Skip these files until they change
+    pub fn ignore(&mut self, paths: impl IntoIterator<Item = PathBuf>) {
+        for path in paths {
+            if let Ok(metadata) = fs::metadata(&path) {
+                self.ignored.insert(path, (metadata.len(), metadata.modified().ok()));
+            }
+        }
+    }
+
+    /// Whether some files are still settling
+    pub fn has_pending(&self) -> bool {
+        !self.files.is_empty()
+    }
+}
+
+/// Run a settled group through the upload pipeline
+/// Accepted files are removed from the inbox, rejected ones moved to errors/.
+async fn ingest_group(state: &Arc<crate::AppState>, group: &Group) -> Result<()> {
+    let source = group.inbox.join(&group.top);
+    let user = match state.db.get_user_by_username(&group.username).await {
+        Ok(user) => user,
+        Err(_) => {
+            tracing::warn!("Drop folder {} belongs to unknown user {}", group.inbox.display(), group.username);
+            return Ok(());
+        }
+    };
+    let (music_dir, temp_dir) = get_user_directories(&state.config, &user.library_path).await?;
+
+    tracing::info!("Importing {} from drop folder for {}", source.display(), user.username);
+
+    let log_id = state
+        .db
+        .create_upload_log(CreateUploadLog {
+            user_id: user.id.clone(),
+            upload_type: "watch".to_string(),
+            source: source.display().to_string(),
+        })
+        .await?;
+    state.db.update_upload_log_status(log_id, "processing", None, None).await?;
+
+    let job_dir = temp_dir.join(format!("watch-{}", uuid::Uuid::new_v4()));
+
+    // Originals stay in the inbox until the outcome is known
+    let allowed = &state.config.upload.allowed_extensions;
+    let mut staged = Vec::new();
+    let mut rejected: Vec<(PathBuf, String)> = Vec::new();
+    let mut audio = 0;
+
+    // Staging failures take the same path as processing failures
+    let result = async {
+        ensure_directory_exists(&job_dir).await?;
+        for file in &group.files {
+            if is_allowed_audio(allowed, file) {
+                audio += 1;
+            } else if !is_image_file(file) && !is_lyrics_file(file) {
+                rejected.push((file.clone(), "Unsupported file type".to_string()));
+                continue;
+            }
+            let target = job_dir.join(file);
+            if let Some(parent) = target.parent() {
+                tokio::fs::create_dir_all(parent).await?;
+            }
+            tokio::fs::copy(group.inbox.join(file), &target)
+                .await
+                .with_context(|| format!("Failed to copy {}", file.display()))?;
+            staged.push(file.clone());
+        }
+
+        if audio == 0 {
+            anyhow::bail!("No audio files");
+        }
+        let job = JobContext {
+            user_id: &user.id,
+            log_id,
+            job_dir: &job_dir,
+            music_dir: &music_dir,
+        };
+        let validation = validate_staged_files(state, &job_dir, log_id).await?;
+        run_post_processing(state, &job).await?;
+        import_job_dir(state, &job).await?;
+        Ok(validation)
+    }
+    .await;
+    tokio::fs::remove_dir_all(&job_dir).await.ok();
+
+    let outcome = match result {
+        Ok(validation) => {
+            rejected.extend(validation.rejected.iter().map(|r| (PathBuf::from(&r.file), r.reason.clone())));
+            let imported: Vec<PathBuf> = staged
+                .iter()
+                .filter(|f| !rejected.iter().any(|(r, _)| r == *f))
+                .map(|f| group.inbox.join(f))
+                .collect();
+            Ok(((audio - validation.rejected.len()) as i32, imported))
+        }
+        // Nothing was imported: every file goes to errors/ with the job's error
+        Err(e) => {
+            let reason = format!("{:#}", e);
+            rejected = group
+                .files
+                .iter()
+                .map(|f| {
+                    let own = rejected.iter().find(|(r, _)| r == f).map(|(_, reason)| reason.clone());
+                    (f.clone(), own.unwrap_or_else(|| reason.clone()))
+                })
+                .collect();
+            Err(reason)
+        }
+    };
+
+    let errors = group.inbox.join(ERRORS_DIR);
+    let moved = {
+        let (inbox, errors, rejected) = (group.inbox.clone(), errors.clone(), rejected.clone());
+        tokio::task::spawn_blocking(move || move_to_errors(&inbox, &errors, &rejected)).await?
+    };
+    if let Err(e) = &moved {
+        tracing::error!("Failed to move rejected files to {}: {:#}", errors.display(), e);
+    }
+    if !rejected.is_empty() {
+        let files: Vec<_> = rejected
+            .iter()
+            .map(|(file, reason)| json!({ "file": file.display().to_string(), "reason": reason }))
+            .collect();
+        state
+            .db
+            .add_upload_log_detail(log_id, "watch", &json!({ "errors_dir": errors.display().to_string(), "rejected": files }))
+            .await
+            .ok();
+    }
+
+    match outcome {
+        Ok((file_count, imported)) => {
+            let selection = vec![source];
+            tokio::task::spawn_blocking(move || remove_sources(&selection, &imported)).await??;
+            let note = (!rejected.is_empty()).then(|| {
+                let reasons: Vec<_> = rejected.iter().map(|(f, r)| format!("{}: {}", f.display(), r)).collect();
+                format!("Rejected {} file(s): {}", rejected.len(), reasons.join("; "))
+            });
+            state.db.update_upload_log_status(log_id, "completed", Some(file_count), note).await?;
+        }
+        Err(reason) => {
+            state
+                .db
+                .update_upload_log_status(log_id, "failed", Some(0), Some(format!("Processing failed: {}", reason)))
+                .await?;
+        }
+    }
+    Ok(())
+}
+
+/// Move files (relative to the inbox) into the errors folder, each with a
+/// `<name>.reason.txt` explaining why; existing names get a numeric suffix
+fn move_to_errors(inbox: &Path, errors: &Path, rejected: &[(PathBuf, String)]) -> Result<()> {
+    for (file, reason) in rejected {
+        let target = unique_path(&errors.join(file));
+        if let Some(parent) = target.parent() {
+            fs::create_dir_all(parent)?;
+        }
+        let from = inbox.join(file);
+        if fs::rename(&from, &target).is_err() {
+            fs::copy(&from, &target).with_context(|| format!("Failed to move {}", file.display()))?;
+            fs::remove_file(&from)?;
+        }
+        let mut reason_file = target.clone().into_os_string();
+        reason_file.push(".reason.txt");
+        fs::write(reason_file, format!("{}\n", reason))?;
+    }
+
+    // Drop the folders left empty
+    for (file, _) in rejected {
+        let mut dir = inbox.join(file);
+        while dir.pop() && dir != inbox {
+            if fs::remove_dir(&dir).is_err() {
+                break;
+            }
+        }
+    }
+    Ok(())
+}
+
+/// `path`, or `name (2).ext`, `name (3).ext`, ... if it already exists
+fn unique_path(path: &Path) -> PathBuf {
+    if !path.exists() {
+        return path.to_path_buf();
+    }
+    let stem = path.file_stem().unwrap_or_default().to_string_lossy().to_string();
+    let ext = path.extension().map(|e| format!(".{}", e.to_string_lossy())).unwrap_or_default();
+    (2..)
+        .map(|n| path.with_file_name(format!("{} ({}){}", stem, n, ext)))
+        .find(|p| !p.exists())
+        .unwrap_or_else(|| path.to_path_buf())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn seen(relative: &str, size: u64) -> SeenFile {
+        SeenFile {
+            username: "alice".to_string(),
+            inbox: PathBuf::from("/inbox/alice"),
+            relative: PathBuf::from(relative),
+            size,
+            modified: None,
+        }
+    }
+
+    #[test]
+    fn groups_are_ready_once_every_file_settled() {
+        let stable = Duration::from_secs(30);
+        let start = Instant::now();
+        let mut tracker = Tracker::default();
+
+        let first = vec![seen("Album/01.flac", 100), seen("Album/02.flac", 50), seen("single.mp3", 10)];
+        assert!(tracker.update(first, start, stable).is_empty());
+
+        // 02.flac is still growing, so only the single is ready
+        let second = vec![seen("Album/01.flac", 100), seen("Album/02.flac", 80), seen("single.mp3", 10)];
+        let ready = tracker.update(second, start + Duration::from_secs(31), stable);
+        assert_eq!(ready.len(), 1);
+        assert_eq!(ready[0].top, PathBuf::from("single.mp3"));
+        assert!(tracker.has_pending());
+
+        let third = vec![seen("Album/01.flac", 100), seen("Album/02.flac", 80)];
+        let ready = tracker.update(third, start + Duration::from_secs(62), stable);
+        assert_eq!(ready.len(), 1);
+        assert_eq!(ready[0].files, [PathBuf::from("Album/01.flac"), PathBuf::from("Album/02.flac")]);
+        assert!(!tracker.has_pending());
+    }
+
+    #[test]
+    fn skips_in_progress_files() {
+        assert!(is_in_progress(".syncthing.01.flac.tmp"));
+        assert!(is_in_progress("~syncthing~01.flac.tmp"));
+        assert!(is_in_progress("01.flac.part"));
+        assert!(!is_in_progress("01.flac"));
+    }
+
+    #[test]
+    fn rejected_files_get_a_reason_file() {
+        let temp = std::env::temp_dir().join(format!("watch-errors-{}", uuid::Uuid::new_v4()));
+        let inbox = temp.join("alice");
+        fs::create_dir_all(inbox.join("Album")).unwrap();
+        fs::write(inbox.join("Album/info.nfo"), b"nfo").unwrap();
+        let errors = inbox.join(ERRORS_DIR);
+        fs::create_dir_all(errors.join("Album")).unwrap();
+        fs::write(errors.join("Album/info.nfo"), b"older").unwrap();
+
+        let rejected = vec![(PathBuf::from("Album/info.nfo"), "Unsupported file type".to_string())];
+        move_to_errors(&inbox, &errors, &rejected).unwrap();
+
+        assert_eq!(fs::read(errors.join("Album/info (2).nfo")).unwrap(), b"nfo");
+        assert_eq!(fs::read_to_string(errors.join("Album/info (2).nfo.reason.txt")).unwrap(), "Unsupported file type\n");
+        assert!(!inbox.join("Album").exists());
+
+        fs::remove_dir_all(&temp).ok();
+    }
+
+    #[tokio::test]
+    async fn staging_failures_fail_the_log_and_clean_up() {
+        use crate::auth::AuthState;
+        use crate::config::Config;
+        use crate::models::CreateUser;
+
+        let temp = std::env::temp_dir().join(format!("watch-staging-{}", uuid::Uuid::new_v4()));
+        let inbox = temp.join("inbox");
+        fs::create_dir_all(inbox.join("Album")).unwrap();
+        fs::write(inbox.join("Album/01.flac"), b"audio").unwrap();
+
+        let mut config = Config::default();
+        config.paths.music_dir = temp.join("music");
+        config.paths.temp_dir = temp.join("tmp");
+        let state = Arc::new(crate::AppState {
+            db: crate::db::Database::new(&format!("sqlite:{}", temp.join("test.db").display()), 1)
+                .await
+                .unwrap(),
+            config,
+            auth: AuthState::new("test-secret".to_string(), 1),
+            progress_store: crate::progress::create_progress_store(),
+        });
+        let user = state
+            .db
+            .create_user(CreateUser {
+                username: "alice".to_string(),
+                password: "password123".to_string(),
+                role: "user".to_string(),
+                library_path: None,
+            })
+            .await
+            .unwrap();
+
+        // 02.flac disappeared between the scan and the import
+        let group = Group {
+            username: "alice".to_string(),
+            inbox: inbox.clone(),
+            top: PathBuf::from("Album"),
+            files: vec![PathBuf::from("Album/01.flac"), PathBuf::from("Album/02.flac")],
+        };
+        ingest_group(&state, &group).await.unwrap();
+
+        let logs = state.db.get_upload_logs(Some(&user.id), 10).await.unwrap();
+        assert_eq!(logs[0].status, "failed");
+        assert!(logs[0].error_message.as_deref().unwrap().contains("Album/02.flac"));
+        let leftovers = fs::read_dir(temp.join("tmp")).unwrap().flatten();
+        assert!(!leftovers.into_iter().any(|e| e.file_name().to_string_lossy().starts_with("watch-")));
+        assert!(inbox.join(ERRORS_DIR).join("Album/01.flac").exists());
+
+        fs::remove_dir_all(&temp).ok();
+    }
+}
@@ -0,0 +1,401 @@
+// Passkey (WebAuthn) registration and login checks. Attestation statements
+// aren't verified (options ask for `none`), so any authenticator is accepted;
+// ES256, EdDSA and RS256 keys are supported.
+
+use crate::config::PasskeyConfig;
+use anyhow::{bail, Context, Result};
+use base64::engine::general_purpose::URL_SAFE_NO_PAD;
+use base64::Engine;
+use ciborium::value::Value;
+use ring::signature::{self, RsaPublicKeyComponents, UnparsedPublicKey};
+use serde::Deserialize;
+use serde_json::json;
+use sha2::{Digest, Sha256};
+
+/// How long the browser has to answer a challenge
+pub const CHALLENGE_SECS: i64 = 300;
+
+/// COSE algorithms, in order of preference
+const ES256: i64 = -7;
+const EDDSA: i64 = -8;
+const RS256: i64 = -257;
+
+/// Authenticator data flags
+const USER_PRESENT: u8 = 0x01;
+const USER_VERIFIED: u8 = 0x04;
+const ATTESTED_CREDENTIAL: u8 = 0x40;
+
+/// The browser's `clientDataJSON`
+#[derive(Debug, Deserialize)]
+pub struct ClientData {
+    #[serde(rename = "type")]
+    pub kind: String,
+    /// base64url, as sent in the options
+    pub challenge: String,
+    pub origin: String,
+}
+
+impl ClientData {
+    /// Parse and check the client data of a `webauthn.create` or `webauthn.get` ceremony;
+    /// the challenge is left for the caller to look up
+    pub fn parse(config: &PasskeyConfig, json: &[u8], kind: &str) -> Result<Self> {
+        let client_data: ClientData = serde_json::from_slice(json).context("Invalid client data")?;
+        if client_data.kind != kind {
+            bail!("Expected a {} ceremony, got {}", kind, client_data.kind);
+        }
+        if client_data.origin != config.origin {
+            bail!("Passkey used on {}, expected {}", client_data.origin, config.origin);
+        }
+        Ok(client_data)
+    }
+}
+
+/// A passkey the browser just created
+#[derive(Debug)]
+pub struct NewCredential {
+    /// base64url
+    pub credential_id: String,
+    /// COSE_Key
+    pub public_key: Vec<u8>,
+    pub sign_count: u32,
+}
+
+/// Options for `navigator.credentials.create()`; binary fields are base64url
+pub fn creation_options(
+    config: &PasskeyConfig,
+    challenge: &str,
+    user_id: &str,
+    username: &str,
+    existing: &[String],
+) -> serde_json::Value {
+    let algorithms: Vec<_> = [ES256, EDDSA, RS256]
+        .iter()
+        .map(|alg| json!({ "type": "public-key", "alg": alg }))
+        .collect();
+    json!({
+        "challenge": challenge,
+        "rp": { "id": config.rp_id, "name": config.rp_name },
+        "user": {
+            "id": URL_SAFE_NO_PAD.encode(user_id),
+            "name": username,
+            "displayName": username
+        },
+        "pubKeyCredParams": algorithms,
+        "timeout": CHALLENGE_SECS * 1000,
+        "attestation": "none",
+        "authenticatorSelection": {
+            "residentKey": "preferred",
+            "userVerification": "preferred"
+        },
+        "excludeCredentials": descriptors(existing)
+    })
+}
+
+/// Options for `navigator.credentials.get()`; an empty `allowed` lets the
+/// browser offer any passkey for the site
+pub fn request_options(config: &PasskeyConfig, challenge: &str, allowed: &[String], user_verification: bool) -> serde_json::Value {
+    json!({
+        "challenge": challenge,
+        "rpId": config.rp_id,
+        "timeout": CHALLENGE_SECS * 1000,
+        "userVerification": if user_verification { "required" } else { "preferred" },
+        "allowCredentials": descriptors(allowed)
+    })
+}
+
+fn descriptors(credential_ids: &[String]) -> Vec<serde_json::Value> {
+    credential_ids
+        .iter()
+        .map(|id| json!({ "type": "public-key", "id": id }))
+        .collect()
+}
+
+/// The credential in an attestation object (client data checked by [`ClientData::parse`])
+pub fn verify_registration(config: &PasskeyConfig, attestation_object: &[u8]) -> Result<NewCredential> {
+    let attestation: Value = ciborium::de::from_reader(attestation_object).context("Invalid attestation object")?;
+    let auth_data = map_entry(&attestation, &Value::Text("authData".to_string()))
+        .and_then(Value::as_bytes)
+        .context("Attestation object has no authenticator data")?;
+
+    let data = AuthenticatorData::parse(config, auth_data, false)?;
+    if data.flags & ATTESTED_CREDENTIAL == 0 {
+        bail!("Authenticator data has no credential");
+    }
+    // AAGUID, credential ID length, credential ID, then the COSE key
+    let rest = data.rest;
+    if rest.len() < 18 {
+        bail!("Truncated credential data");
+    }
+    let id_len = u16::from_be_bytes([rest[16], rest[17]]) as usize;
+    let credential_id = rest.get(18..18 + id_len).context("Truncated credential ID")?;
+    let key_and_extensions = &rest[18 + id_len..];
+    let mut reader = key_and_extensions;
+    let _: Value = ciborium::de::from_reader(&mut reader).context("Invalid credential public key")?;
+    let public_key = key_and_extensions[..key_and_extensions.len() - reader.len()].to_vec();
+    // Refuse keys that could never be checked
+    PublicKey::from_cose(&public_key)?;
+
+    Ok(NewCredential {
+        credential_id: URL_SAFE_NO_PAD.encode(credential_id),
+        public_key,
+        sign_count: data.sign_count,
+    })
+}
+
+/// Check a login assertion against the stored key and counter; returns the new counter
+pub fn verify_assertion(
+    config: &PasskeyConfig,
+    client_data_json: &[u8],
+    authenticator_data: &[u8],
+    signature: &[u8],
+    public_key: &[u8],
+    stored_sign_count: u32,
+    user_verification: bool,
+) -> Result<u32> {
+    let data = AuthenticatorData::parse(config, authenticator_data, user_verification)?;
+
+    let mut message = authenticator_data.to_vec();
+    message.extend_from_slice(&Sha256::digest(client_data_json));
+    PublicKey::from_cose(public_key)?.verify(&message, signature)?;
+
+    // Authenticators without a counter always send 0
+    if (data.sign_count != 0 || stored_sign_count != 0) && data.sign_count <= stored_sign_count {
+        bail!("Signature counter went back; the passkey may have been cloned");
+    }
+    Ok(data.sign_count)
+}
+
+struct AuthenticatorData<'a> {
+    flags: u8,
+    sign_count: u32,
+    /// Attested credential data and extensions
+    rest: &'a [u8],
+}
+
+impl<'a> AuthenticatorData<'a> {
+    fn parse(config: &PasskeyConfig, data: &'a [u8], user_verification: bool) -> Result<Self> {
+        if data.len() < 37 {
+            bail!("Truncated authenticator data");
+        }
+        if data[..32] != Sha256::digest(config.rp_id.as_bytes())[..] {
+            bail!("Passkey belongs to another site");
+        }
+        let flags = data[32];
+        if flags & USER_PRESENT == 0 {
+            bail!("User presence wasn't confirmed");
+        }
+        if user_verification && flags & USER_VERIFIED == 0 {
+            bail!("User wasn't verified");
+        }
+        Ok(Self {
+            flags,
+            sign_count: u32::from_be_bytes([data[33], data[34], data[35], data[36]]),
+            rest: &data[37..],
+        })
+    }
+}
+
+enum PublicKey {
+    Es256(Vec<u8>),
+    EdDsa(Vec<u8>),
+    Rs256 { n: Vec<u8>, e: Vec<u8> },
+}
+
+impl PublicKey {
+    fn from_cose(bytes: &[u8]) -> Result<Self> {
+        let key: Value = ciborium::de::from_reader(bytes).context("Invalid COSE key")?;
+        let param = |label: i64| map_entry(&key, &Value::Integer(label.into()));
+        let int = |label: i64| param(label).and_then(Value::as_integer).map(i128::from);
+        let bytes = |label: i64| param(label).and_then(Value::as_bytes).cloned().context("Incomplete COSE key");
+
+        // kty 2: EC2 (crv 1: P-256), 1: OKP (crv 6: Ed25519), 3: RSA
+        match (int(1), int(3), int(-1)) {
+            (Some(2), Some(alg), Some(1)) if alg == ES256 as i128 => {
+                let mut point = vec![0x04];
+                point.extend(bytes(-2)?);
+                point.extend(bytes(-3)?);
+                Ok(Self::Es256(point))
+            }
+            (Some(1), Some(alg), Some(6)) if alg == EDDSA as i128 => Ok(Self::EdDsa(bytes(-2)?)),
+            (Some(3), Some(alg), _) if alg == RS256 as i128 => Ok(Self::Rs256 { n: bytes(-1)?, e: bytes(-2)? }),
+            (kty, alg, _) => bail!("Unsupported passkey type (kty {:?}, alg {:?})", kty, alg),
+        }
+    }
+
+    fn verify(&self, message: &[u8], signature: &[u8]) -> Result<()> {
+        let result = match self {
+            Self::Es256(point) => UnparsedPublicKey::new(&signature::ECDSA_P256_SHA256_ASN1, point).verify(message, signature),
+            Self::EdDsa(key) => UnparsedPublicKey::new(&signature::ED25519, key).verify(message, signature),
+            Self::Rs256 { n, e } => {
+                RsaPublicKeyComponents { n, e }.verify(&signature::RSA_PKCS1_2048_8192_SHA256, message, signature)
+            }
+        };
+        result.map_err(|_| anyhow::anyhow!("Invalid passkey signature"))
+    }
+}
+
+fn map_entry<'a>(map: &'a Value, key: &Value) -> Option<&'a Value> {
+    map.as_map()?.iter().find(|(k, _)| k == key).map(|(_, v)| v)
+}
+
+#[cfg(test)]
+pub(crate) mod tests {
+    use super::*;
+    use ring::rand::SystemRandom;
+    use ring::signature::{EcdsaKeyPair, Ed25519KeyPair, KeyPair, ECDSA_P256_SHA256_ASN1_SIGNING};
+
+    pub fn test_config() -> PasskeyConfig {
+        PasskeyConfig {
+            enabled: true,
+            rp_id: "music.example.com".to_string(),
+            origin: "https://music.example.com".to_string(),
+            ..PasskeyConfig::default()
+        }
+    }
+
+    fn cbor(value: &Value) -> Vec<u8> {
+        let mut bytes = Vec::new();
+        ciborium::ser::into_writer(value, &mut bytes).unwrap();
+        bytes
+    }
+
+    fn cose_map(entries: Vec<(i64, Value)>) -> Vec<u8> {
+        cbor(&Value::Map(entries.into_iter().map(|(k, v)| (Value::Integer(k.into()), v)).collect()))
+    }
+
+    /// A software ES256 authenticator, also used by the routes tests
+    pub struct TestAuthenticator {
+        key: EcdsaKeyPair,
+        pub credential_id: Vec<u8>,
+        sign_count: u32,
+        config: PasskeyConfig,
+    }
+
+    impl TestAuthenticator {
+        pub fn new(config: &PasskeyConfig) -> Self {
+            let rng = SystemRandom::new();
+            let pkcs8 = EcdsaKeyPair::generate_pkcs8(&ECDSA_P256_SHA256_ASN1_SIGNING, &rng).unwrap();
+            let key = EcdsaKeyPair::from_pkcs8(&ECDSA_P256_SHA256_ASN1_SIGNING, pkcs8.as_ref(), &rng).unwrap();
+            Self {
+                key,
+                credential_id: rand::random::<[u8; 16]>().to_vec(),
+                sign_count: 0,
+                config: config.clone(),
+            }
+        }
+
+        pub fn client_data(&self, kind: &str, challenge: &str) -> Vec<u8> {
+            json!({ "type": kind, "challenge": challenge, "origin": self.config.origin })
+                .to_string()
+                .into_bytes()
+        }
+
+        fn authenticator_data(&mut self, flags: u8) -> Vec<u8> {
+            self.sign_count += 1;
+            let mut data = Sha256::digest(self.config.rp_id.as_bytes()).to_vec();
+            data.push(flags);
+            data.extend(self.sign_count.to_be_bytes());
+            data
+        }
+
+        /// The attestation object of a new credential
+        pub fn attestation_object(&mut self) -> Vec<u8> {
+            let point = self.key.public_key().as_ref();
+            let cose_key = cose_map(vec![
+                (1, Value::Integer(2.into())),
+                (3, Value::Integer(ES256.into())),
+                (-1, Value::Integer(1.into())),
+                (-2, Value::Bytes(point[1..33].to_vec())),
+                (-3, Value::Bytes(point[33..].to_vec())),
+            ]);
+            let mut auth_data = self.authenticator_data(USER_PRESENT | USER_VERIFIED | ATTESTED_CREDENTIAL);
+            auth_data.extend([0u8; 16]);
+            auth_data.extend((self.credential_id.len() as u16).to_be_bytes());
+            auth_data.extend(&self.credential_id);
+            auth_data.extend(cose_key);
+            cbor(&Value::Map(vec![
+                (Value::Text("fmt".to_string()), Value::Text("none".to_string())),
+                (Value::Text("attStmt".to_string()), Value::Map(Vec::new())),
+                (Value::Text("authData".to_string()), Value::Bytes(auth_data)),
+            ]))
+        }
+
+        /// Client data, authenticator data and signature of a login
+        pub fn assert(&mut self, challenge: &str, verified: bool) -> (Vec<u8>, Vec<u8>, Vec<u8>) {
+            let client_data = self.client_data("webauthn.get", challenge);
+            let auth_data = self.authenticator_data(if verified { USER_PRESENT | USER_VERIFIED } else { USER_PRESENT });
+            let mut message = auth_data.clone();
+            message.extend_from_slice(&Sha256::digest(&client_data));
+            let signature = self.key.sign(&SystemRandom::new(), &message).unwrap().as_ref().to_vec();
+            (client_data, auth_data, signature)
+        }
+
+        /// Request body of a registration
+        pub fn registration_json(&mut self, challenge: &str) -> serde_json::Value {
+            json!({
+                "client_data_json": URL_SAFE_NO_PAD.encode(self.client_data("webauthn.create", challenge)),
+                "attestation_object": URL_SAFE_NO_PAD.encode(self.attestation_object())
+            })
+        }
+
+        /// Request body of a login
+        pub fn assertion_json(&mut self, challenge: &str, verified: bool) -> serde_json::Value {
+            let (client_data, auth_data, signature) = self.assert(challenge, verified);
+            json!({
+                "credential_id": URL_SAFE_NO_PAD.encode(&self.credential_id),
+                "client_data_json": URL_SAFE_NO_PAD.encode(client_data),
+                "authenticator_data": URL_SAFE_NO_PAD.encode(auth_data),
+                "signature": URL_SAFE_NO_PAD.encode(signature)
+            })
+        }
+    }
+
+    #[test]
+    fn registered_passkeys_sign_logins() {
+        let config = test_config();
+        let mut authenticator = TestAuthenticator::new(&config);
+        let credential = verify_registration(&config, &authenticator.attestation_object()).unwrap();
+        assert_eq!(credential.credential_id, URL_SAFE_NO_PAD.encode(&authenticator.credential_id));
+        assert_eq!(credential.sign_count, 1);
+
+        let (client_data, auth_data, signature) = authenticator.assert("c1", true);
+        let client = ClientData::parse(&config, &client_data, "webauthn.get").unwrap();
+        assert_eq!(client.challenge, "c1");
+        let count = verify_assertion(&config, &client_data, &auth_data, &signature, &credential.public_key, 1, true).unwrap();
+        assert_eq!(count, 2);
+
+        // A replayed counter, a tampered message, and a missing verification are refused
+        assert!(verify_assertion(&config, &client_data, &auth_data, &signature, &credential.public_key, 2, true).is_err());
+        let mut tampered = client_data.clone();
+        tampered.push(b' ');
+        assert!(verify_assertion(&config, &tampered, &auth_data, &signature, &credential.public_key, 1, true).is_err());
+        let (client_data, auth_data, signature) = authenticator.assert("c2", false);
+        assert!(verify_assertion(&config, &client_data, &auth_data, &signature, &credential.public_key, 2, true).is_err());
+        assert!(verify_assertion(&config, &client_data, &auth_data, &signature, &credential.public_key, 2, false).is_ok());
+
+        // Other sites' passkeys and pages
+        let other = PasskeyConfig { rp_id: "example.org".to_string(), origin: "https://example.org".to_string(), ..config.clone() };
+        assert!(verify_registration(&other, &authenticator.attestation_object()).is_err());
+        assert!(ClientData::parse(&other, &client_data, "webauthn.get").is_err());
+        assert!(ClientData::parse(&config, &client_data, "webauthn.create").is_err());
+    }
+
+    #[test]
+    fn ed25519_keys_are_supported() {
+        let rng = SystemRandom::new();
+        let pkcs8 = Ed25519KeyPair::generate_pkcs8(&rng).unwrap();
+        let key = Ed25519KeyPair::from_pkcs8(pkcs8.as_ref()).unwrap();
+        let cose_key = cose_map(vec![
+            (1, Value::Integer(1.into())),
+            (3, Value::Integer(EDDSA.into())),
+            (-1, Value::Integer(6.into())),
+            (-2, Value::Bytes(key.public_key().as_ref().to_vec())),
+        ]);
+        let public_key = PublicKey::from_cose(&cose_key).unwrap();
+        assert!(public_key.verify(b"hello", key.sign(b"hello").as_ref()).is_ok());
+        assert!(public_key.verify(b"hullo", key.sign(b"hello").as_ref()).is_err());
+
+        let unsupported = cose_map(vec![(1, Value::Integer(2.into())), (3, Value::Integer((-35).into()))]);
+        assert!(PublicKey::from_cose(&unsupported).is_err());
+    }
+}